@@ -0,0 +1,9 @@
+-- Add migration script here
+CREATE TABLE photo_metadata (
+    post_id UUID NOT NULL REFERENCES posts (id) ON DELETE CASCADE,
+    path TEXT NOT NULL,
+    captured_at TIMESTAMPTZ,
+    latitude DOUBLE PRECISION,
+    longitude DOUBLE PRECISION,
+    PRIMARY KEY (post_id, path)
+);
@@ -0,0 +1,10 @@
+-- Add migration script here
+CREATE TABLE image_attachments (
+    post_id UUID NOT NULL REFERENCES posts (id) ON DELETE CASCADE,
+    path TEXT NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    dominant_color TEXT NOT NULL,
+    blurhash TEXT NOT NULL,
+    PRIMARY KEY (post_id, path)
+);
@@ -0,0 +1,9 @@
+-- Post blobs written ahead of the transaction making a post refer to them, see StagedWrite
+CREATE TABLE blob_journal (
+    id UUID PRIMARY KEY,
+    post_id UUID NOT NULL,
+    new_blob TEXT NOT NULL,
+    old_blob TEXT,
+    committed BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
@@ -0,0 +1,6 @@
+-- Results of the blob integrity scrub, the latest one is served to admins
+CREATE TABLE scrub_reports (
+    id UUID PRIMARY KEY,
+    finished_at timestamptz NOT NULL,
+    report JSONB NOT NULL
+);
@@ -0,0 +1,13 @@
+-- Resumable uploads in progress, their bytes are kept as chunks in the blob storage
+CREATE TABLE uploads (
+    id UUID PRIMARY KEY,
+    file_name TEXT NOT NULL,
+    length BIGINT NOT NULL,
+    upload_offset BIGINT NOT NULL DEFAULT 0,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    expires_at timestamptz NOT NULL,
+    -- INFO: the request appending to an upload claims it for a while, instead of keeping its
+    -- row locked
+    claimed_by UUID,
+    claimed_until timestamptz
+);
@@ -0,0 +1,8 @@
+-- Bytes taken by the stored files of each post, by the type of file they were uploaded as
+CREATE TABLE post_storage_usage (
+    post_id UUID NOT NULL REFERENCES posts (id) ON DELETE CASCADE,
+    mime TEXT NOT NULL,
+    files INTEGER NOT NULL,
+    bytes BIGINT NOT NULL,
+    PRIMARY KEY (post_id, mime)
+);
@@ -0,0 +1,15 @@
+-- Moves of the blob storage to another backend, reads follow the target once switched
+CREATE TABLE blob_migrations (
+    target TEXT PRIMARY KEY,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    switched_at TIMESTAMPTZ
+);
+
+-- Keys copied to the target and verified, skipped when an interrupted migration resumes
+CREATE TABLE blob_migration_keys (
+    target TEXT NOT NULL REFERENCES blob_migrations (target) ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    sha256 TEXT NOT NULL,
+    copied_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (target, key)
+);
@@ -0,0 +1,15 @@
+-- Accounts wait for their email address to be confirmed, the ones created before are confirmed
+ALTER TABLE users
+    ADD COLUMN status TEXT NOT NULL DEFAULT 'confirmed',
+    ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+ALTER TABLE users ALTER COLUMN status SET DEFAULT 'pending_confirmation';
+
+-- Single-use confirmation links, only the SHA-256 of their token is kept. `kind` tells what
+-- `subject_id` confirms, as accounts and subscriptions share them
+CREATE TABLE confirmation_tokens (
+    token_hash TEXT PRIMARY KEY,
+    kind TEXT NOT NULL,
+    subject_id UUID NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX confirmation_tokens_subject ON confirmation_tokens (kind, subject_id);
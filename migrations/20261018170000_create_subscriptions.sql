@@ -0,0 +1,10 @@
+-- Newsletter subscribers, who get an email whenever a post is published
+CREATE TABLE subscriptions (
+    id UUID PRIMARY KEY,
+    email TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending_confirmation',
+    -- INFO: only allows unsubscribing and goes in every newsletter, so it is kept in clear
+    unsubscribe_token TEXT NOT NULL UNIQUE,
+    subscribed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
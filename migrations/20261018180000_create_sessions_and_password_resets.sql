@@ -0,0 +1,15 @@
+-- Signed-in sessions, only the SHA-256 of their bearer token is kept
+CREATE TABLE user_sessions (
+    token_hash TEXT PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL
+);
+CREATE INDEX user_sessions_user_id ON user_sessions (user_id);
+
+-- Single-use password reset links, only the SHA-256 of their token is kept
+CREATE TABLE password_reset_tokens (
+    token_hash TEXT PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
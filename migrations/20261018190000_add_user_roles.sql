@@ -0,0 +1,10 @@
+-- Roles of users, the accounts created before are the site owners
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'admin';
+ALTER TABLE users ALTER COLUMN role SET DEFAULT 'viewer';
+
+-- Who uploaded a post, authors may only edit their own posts
+ALTER TABLE posts ADD COLUMN owner_id UUID REFERENCES users (id) ON DELETE SET NULL;
+
+-- Uploads only serve the user who created them. The ones created before have no owner and are
+-- left to expire, as are the uploads of a removed user so their chunks get purged with them.
+ALTER TABLE uploads ADD COLUMN owner_id UUID REFERENCES users (id) ON DELETE SET NULL;
@@ -0,0 +1,20 @@
+-- TOTP second factor, enabled once a code from the pending secret was verified
+ALTER TABLE users
+    ADD COLUMN totp_secret TEXT,
+    ADD COLUMN totp_pending_secret TEXT,
+    ADD COLUMN totp_last_step BIGINT;
+
+-- Single-use codes replacing a lost authenticator, only their SHA-256 is kept
+CREATE TABLE recovery_codes (
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    PRIMARY KEY (user_id, code_hash)
+);
+
+-- Logins waiting for their second factor, only the SHA-256 of their token is kept
+CREATE TABLE login_challenges (
+    token_hash TEXT PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    attempts INT NOT NULL DEFAULT 0
+);
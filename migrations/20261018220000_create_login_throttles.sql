@@ -0,0 +1,10 @@
+-- Failed logins in a row per account and per address, and how long they have to wait
+CREATE TABLE login_throttles (
+    kind TEXT NOT NULL,
+    key TEXT NOT NULL,
+    failures INTEGER NOT NULL,
+    last_failure_at TIMESTAMPTZ NOT NULL,
+    blocked_until TIMESTAMPTZ,
+    locked BOOLEAN NOT NULL DEFAULT FALSE,
+    PRIMARY KEY (kind, key)
+);
@@ -0,0 +1,9 @@
+-- Who a post is credited to, the uploader unless the front matter names someone else
+ALTER TABLE posts ADD COLUMN author_id UUID REFERENCES users (id) ON DELETE SET NULL;
+UPDATE posts SET author_id = owner_id;
+CREATE INDEX posts_author_id ON posts (author_id);
+
+-- Public profiles of authors, the avatar being a blob of its own
+ALTER TABLE users ADD COLUMN display_name TEXT;
+ALTER TABLE users ADD COLUMN bio TEXT;
+ALTER TABLE users ADD COLUMN avatar_blob TEXT;
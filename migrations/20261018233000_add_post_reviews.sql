@@ -0,0 +1,39 @@
+-- Posts of authors who may not publish wait for a reviewer, posts so far are all published
+ALTER TABLE posts ADD COLUMN status TEXT NOT NULL DEFAULT 'published';
+ALTER TABLE posts ADD COLUMN revision INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE posts ADD COLUMN reviewer_id UUID REFERENCES users (id) ON DELETE SET NULL;
+ALTER TABLE posts ADD COLUMN published_at TIMESTAMPTZ;
+UPDATE posts SET published_at = date;
+CREATE INDEX posts_status ON posts (status);
+CREATE INDEX posts_published_at ON posts (published_at);
+
+-- A revision of a published post waits for its review next to the published one, which readers
+-- are served until the revision is approved and takes its place
+ALTER TABLE posts ADD COLUMN pending_blob TEXT;
+ALTER TABLE posts ADD COLUMN pending_title TEXT;
+ALTER TABLE posts ADD COLUMN pending_slug TEXT;
+ALTER TABLE posts ADD COLUMN pending_author_id UUID REFERENCES users (id) ON DELETE SET NULL;
+
+-- The images, storage usage and photo metadata of a pending revision are kept apart from the
+-- ones of the published revision
+ALTER TABLE image_attachments ADD COLUMN pending BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE image_attachments DROP CONSTRAINT image_attachments_pkey;
+ALTER TABLE image_attachments ADD PRIMARY KEY (post_id, pending, path);
+ALTER TABLE post_storage_usage ADD COLUMN pending BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE post_storage_usage DROP CONSTRAINT post_storage_usage_pkey;
+ALTER TABLE post_storage_usage ADD PRIMARY KEY (post_id, pending, mime);
+ALTER TABLE photo_metadata ADD COLUMN pending BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE photo_metadata DROP CONSTRAINT photo_metadata_pkey;
+ALTER TABLE photo_metadata ADD PRIMARY KEY (post_id, pending, path);
+
+-- Review comments, on the revision they were written about, anchored to a line of it or not
+CREATE TABLE review_comments (
+    id UUID PRIMARY KEY,
+    post_id UUID NOT NULL REFERENCES posts (id) ON DELETE CASCADE,
+    revision INTEGER NOT NULL,
+    author_id UUID REFERENCES users (id) ON DELETE SET NULL,
+    line INTEGER,
+    body TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX review_comments_post_id ON review_comments (post_id, revision);
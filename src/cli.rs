@@ -0,0 +1,146 @@
+use anyhow::Context;
+use clap::{Args, Parser, Subcommand};
+
+use crate::components::audit::RequestOrigin;
+use crate::components::blob_migration::migrate_blobs;
+use crate::components::blob_storage::{GcOptions, OrphanAction, DEFAULT_GRACE_PERIOD_SECS};
+use crate::components::secrets::SecretSealer;
+use crate::configuration::Settings;
+use crate::domain::roles::Role;
+use crate::routes::{change_role, live_post_blobs, reseal_totp_secrets, scrub_blob_storage};
+use crate::startup::prepare::{prepare_blob_storage, prepare_db_pool};
+
+#[derive(Debug, Parser)]
+#[command(version, about = "The pine tails blog backend")]
+pub struct Cli {
+    /// Serves the API when omitted.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Serve the API.
+    Serve,
+    /// Report blobs no post refers to and posts whose blob is missing, then collect the orphans.
+    Gc(GcArgs),
+    /// Re-hash every stored file and report the posts with missing or corrupted files.
+    Scrub,
+    /// Re-encrypt every stored file that is not encrypted with the current encryption key, and
+    /// the TOTP secrets.
+    RotateKey,
+    /// Copy every stored file to the configured `migrate_to` backend, then read from it.
+    MigrateStorage(MigrateStorageArgs),
+    /// Set the role of a user, such as making the first account an admin.
+    SetRole(SetRoleArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct SetRoleArgs {
+    username: String,
+    /// One of `admin`, `editor`, `author` or `viewer`.
+    role: Role,
+}
+
+#[derive(Debug, Args)]
+pub struct MigrateStorageArgs {
+    /// Copy and verify the files, but keep reading from the current backend.
+    #[arg(long)]
+    no_switch: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct GcArgs {
+    /// Only report what would be collected.
+    #[arg(long)]
+    dry_run: bool,
+    /// Either `quarantine` or `delete` the orphans.
+    #[arg(long, default_value = "quarantine")]
+    action: OrphanAction,
+    /// Leave orphans written within this many seconds alone.
+    #[arg(long, default_value_t = DEFAULT_GRACE_PERIOD_SECS)]
+    grace_period_secs: u64,
+}
+
+impl From<GcArgs> for GcOptions {
+    fn from(args: GcArgs) -> Self {
+        Self {
+            dry_run: args.dry_run,
+            action: args.action,
+            grace_period_secs: args.grace_period_secs,
+        }
+    }
+}
+
+pub async fn collect_garbage(config: &Settings, args: GcArgs) -> anyhow::Result<()> {
+    let pool = prepare_db_pool(config);
+    let blob_storage = prepare_blob_storage(config).context("Failed to open blob storage")?;
+
+    let live_blobs = live_post_blobs(&pool)
+        .await
+        .context("Failed to fetch the blobs of posts")?;
+    let report = blob_storage
+        .collect_garbage(&live_blobs, &args.into())
+        .await
+        .context("Failed to collect blob garbage")?;
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+pub async fn scrub(config: &Settings) -> anyhow::Result<()> {
+    let pool = prepare_db_pool(config);
+    let blob_storage = prepare_blob_storage(config).context("Failed to open blob storage")?;
+
+    let report = scrub_blob_storage(&pool, &blob_storage).await?;
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+pub async fn migrate_storage(config: &Settings, args: MigrateStorageArgs) -> anyhow::Result<()> {
+    let pool = prepare_db_pool(config);
+    let blob_storage = prepare_blob_storage(config).context("Failed to open blob storage")?;
+    let migration = blob_storage
+        .migration()
+        .context("No `migrate_to` backend is configured")?;
+
+    let report = migrate_blobs(&pool, migration, !args.no_switch).await?;
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+pub async fn rotate_key(config: &Settings) -> anyhow::Result<()> {
+    let blob_storage = prepare_blob_storage(config).context("Failed to open blob storage")?;
+
+    let report = blob_storage
+        .rotate_encryption_key()
+        .await
+        .context("Failed to rotate the encryption key")?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+
+    let pool = prepare_db_pool(config);
+    let sealer = SecretSealer::try_from(&config.blob_storage)?;
+    let resealed = reseal_totp_secrets(&pool, &sealer)
+        .await
+        .context("Failed to reseal the TOTP secrets")?;
+    println!("Resealed {resealed} TOTP secrets");
+    Ok(())
+}
+
+pub async fn set_role(config: &Settings, args: SetRoleArgs) -> anyhow::Result<()> {
+    let pool = prepare_db_pool(config);
+
+    let user_id = sqlx::query_scalar!("SELECT id FROM users WHERE username = $1", args.username)
+        .fetch_optional(&pool)
+        .await
+        .context("Failed to fetch the user")?
+        .with_context(|| format!("No user is named {}", args.username))?;
+    change_role(&pool, user_id, args.role, None, &RequestOrigin::default())
+        .await
+        .map_err(|e| anyhow::anyhow!("{e}"))?;
+
+    println!("{} is now {}", args.username, args.role);
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use actix_web::{FromRequest, HttpRequest};
+use anyhow::Context;
+use sqlx::PgExecutor;
+use tracing_actix_web::RequestId;
+use uuid::Uuid;
+
+use std::future::{ready, Ready};
+
+use crate::domain::audit::AuditAction;
+
+/// Where a request came from, for the audit log.
+#[derive(Debug, Clone, Default)]
+pub struct RequestOrigin {
+    /// The id the request is logged with.
+    pub request_id: Option<Uuid>,
+    /// The peer address. `Forwarded` headers are left alone, anyone could set them.
+    pub ip: Option<String>,
+}
+
+impl RequestOrigin {
+    pub fn of(req: &HttpRequest) -> Self {
+        Self {
+            request_id: RequestId::extract(req).into_inner().ok().map(Uuid::from),
+            ip: req.peer_addr().map(|addr| addr.ip().to_string()),
+        }
+    }
+}
+
+impl FromRequest for RequestOrigin {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
+        ready(Ok(Self::of(req)))
+    }
+}
+
+/// One entry of the audit log.
+#[derive(Debug)]
+pub struct AuditEvent {
+    pub action: AuditAction,
+    pub actor_id: Option<Uuid>,
+    /// The post, user or subscription acted on.
+    pub target_id: Option<Uuid>,
+    /// What changed, usually from [`crate::domain::audit::json_diff`].
+    pub diff: serde_json::Value,
+}
+
+/// Appends `event` to the audit log, within the transaction of the change when there is one.
+pub async fn record_audit(
+    executor: impl PgExecutor<'_>,
+    origin: &RequestOrigin,
+    event: AuditEvent,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_log (action, actor_id, target_id, request_id, ip, diff)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        event.action.as_str(),
+        event.actor_id,
+        event.target_id,
+        origin.request_id,
+        origin.ip,
+        event.diff,
+    )
+    .execute(executor)
+    .await
+    .context(format!(
+        "Failed to record {} in the audit log",
+        event.action.as_str()
+    ))?;
+    Ok(())
+}
@@ -0,0 +1,174 @@
+use anyhow::Context;
+use sqlx::PgPool;
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use crate::components::blob_storage::{BlobStorage, MirroredBackend};
+
+/// How often a running server checks whether the migration switched.
+const SWITCH_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FailedKey {
+    pub key: String,
+    pub error: String,
+}
+
+/// What one run of [`migrate_blobs`] did.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct MigrationReport {
+    pub target: String,
+    /// Keys copied and verified by this run.
+    pub copied: usize,
+    /// Keys copied and verified by an earlier run.
+    pub already_copied: usize,
+    /// Keys removed before they could be copied.
+    pub vanished: usize,
+    pub failed: Vec<FailedKey>,
+    /// Whether reads now go to the target.
+    pub switched: bool,
+}
+
+/// Copies every key of the source of `migration` to its target, checking each copy against the
+/// source, then switches reads to the target unless `switch` is off or a key failed.
+///
+/// Verified keys are recorded, so running it again after an interruption resumes where it
+/// stopped. Keys written meanwhile are mirrored by the servers, as long as they run with the
+/// migration configured.
+pub async fn migrate_blobs(
+    pool: &PgPool,
+    migration: &MirroredBackend,
+    switch: bool,
+) -> anyhow::Result<MigrationReport> {
+    let target = &migration.target_name;
+    sqlx::query!(
+        "INSERT INTO blob_migrations (target) VALUES ($1) ON CONFLICT DO NOTHING",
+        target
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the migration")?;
+
+    let copied: HashSet<String> = sqlx::query_scalar!(
+        "SELECT key FROM blob_migration_keys WHERE target = $1",
+        target
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the keys already copied")?
+    .into_iter()
+    .collect();
+
+    let mut report = MigrationReport {
+        target: target.clone(),
+        ..Default::default()
+    };
+    // INFO: a key renamed on the source alone shows up under its new name, so the source is
+    // listed again until every key it holds was seen
+    let mut seen = HashSet::new();
+    loop {
+        let keys: Vec<String> = migration
+            .source_keys()
+            .await
+            .context("Failed to list the keys to copy")?
+            .into_iter()
+            .filter(|key| !seen.contains(key))
+            .collect();
+        if keys.is_empty() {
+            break;
+        }
+
+        for key in keys {
+            seen.insert(key.clone());
+            if copied.contains(&key) {
+                report.already_copied += 1;
+                continue;
+            }
+
+            match migration.copy_key(&key).await {
+                Ok(Some(sha256)) => {
+                    sqlx::query!(
+                        r#"
+                        INSERT INTO blob_migration_keys (target, key, sha256)
+                        VALUES ($1, $2, $3)
+                        ON CONFLICT (target, key) DO UPDATE SET sha256 = $3, copied_at = now()
+                        "#,
+                        target,
+                        key,
+                        sha256,
+                    )
+                    .execute(pool)
+                    .await
+                    .context("Failed to record a copied key")?;
+                    report.copied += 1;
+                }
+                Ok(None) => report.vanished += 1,
+                Err(e) => {
+                    tracing::warn!("Failed to copy {key}: {e}");
+                    report.failed.push(FailedKey {
+                        key,
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+    }
+
+    if switch && report.failed.is_empty() {
+        sqlx::query!(
+            r#"
+            UPDATE blob_migrations SET switched_at = COALESCE(switched_at, now())
+            WHERE target = $1
+            "#,
+            target
+        )
+        .execute(pool)
+        .await
+        .context("Failed to switch to the migration target")?;
+        migration.switch();
+    }
+    report.switched = migration.is_switched();
+
+    Ok(report)
+}
+
+/// Points the reads of `blob_storage` to the migration target once the migration switched.
+/// Returns whether it did, which is always false without a migration.
+pub async fn follow_blob_migration(
+    pool: &PgPool,
+    blob_storage: &BlobStorage,
+) -> anyhow::Result<bool> {
+    let Some(migration) = blob_storage.migration() else {
+        return Ok(false);
+    };
+
+    let switched = sqlx::query_scalar!(
+        "SELECT switched_at FROM blob_migrations WHERE target = $1",
+        migration.target_name
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the migration state")?
+    .flatten()
+    .is_some();
+
+    if switched && !migration.is_switched() {
+        tracing::info!("Blob storage switched to {}", migration.target_name);
+        migration.switch();
+    }
+    Ok(switched)
+}
+
+/// Keeps following the migration of a running server until it switched.
+pub async fn watch_blob_migration(pool: PgPool, blob_storage: BlobStorage) {
+    let mut interval = tokio::time::interval(SWITCH_POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        match follow_blob_migration(&pool, &blob_storage).await {
+            Ok(true) => return,
+            Ok(false) => {}
+            Err(e) => tracing::error!("{e:?}"),
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, BoxStream, StreamExt};
+use uuid::Uuid;
+
+use std::path::PathBuf;
+
+use super::objects::INCOMING_PREFIX;
+
+/// Where the bytes of the blob storage live.
+///
+/// Keys are `/` separated relative paths such as `posts/<blob>/manifest.json`, none of their
+/// segments start with a `.`. Writes are atomic: a reader sees either the previous value of a key
+/// or the complete new one.
+#[async_trait::async_trait]
+pub trait StorageBackend: Send + Sync + std::fmt::Debug {
+    /// Prepares the backend before first use, such as creating the base directory.
+    fn init(&self) -> std::io::Result<()>;
+
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> std::io::Result<()>;
+
+    /// Writes `key` only when it does not exist yet, telling whether it was written. Checking and
+    /// writing is a single step for every instance sharing the backend, so it can serve as a lock.
+    async fn put_if_absent(&self, key: &str, bytes: Vec<u8>) -> std::io::Result<bool>;
+
+    /// Replaces `key` only while it holds `expected`, telling whether it was replaced. Backends
+    /// able to compare and swap in a single step should, by default another instance may write
+    /// the key in between.
+    async fn put_if_equals(
+        &self,
+        key: &str,
+        expected: &[u8],
+        bytes: Vec<u8>,
+    ) -> std::io::Result<bool> {
+        match self.get(key).await {
+            Ok(current) if current == expected => self.put(key, bytes).await.map(|()| true),
+            Ok(_) => Ok(false),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Removes `key` only while it holds `expected`, telling whether it was removed. Like
+    /// [`Self::put_if_equals`], backends able to do it in a single step should.
+    async fn delete_if_equals(&self, key: &str, expected: &[u8]) -> std::io::Result<bool> {
+        match self.get(key).await {
+            Ok(current) if current == expected => self.delete(key).await.map(|()| true),
+            Ok(_) => Ok(false),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Starts writing `key` piece by piece, for values too large to be held in memory. Nothing is
+    /// visible under `key` before [`BlobWriter::finish`].
+    async fn writer(&self, key: &str) -> std::io::Result<Box<dyn BlobWriter>>;
+
+    /// Reads a stored value, failing with [`std::io::ErrorKind::NotFound`] for unknown keys.
+    async fn get(&self, key: &str) -> std::io::Result<Vec<u8>>;
+
+    /// Reads a stored value piece by piece, for values too large to be held in memory. Fails like
+    /// [`Self::get`] for unknown keys.
+    async fn stream(
+        &self,
+        key: &str,
+    ) -> std::io::Result<BoxStream<'static, std::io::Result<Bytes>>> {
+        let bytes = self.get(key).await?;
+        Ok(stream::once(async { Ok(Bytes::from(bytes)) }).boxed())
+    }
+
+    async fn exists(&self, key: &str) -> std::io::Result<bool>;
+
+    /// When `key` was last written, failing with [`std::io::ErrorKind::NotFound`] for unknown
+    /// keys.
+    async fn last_modified(&self, key: &str) -> std::io::Result<DateTime<Utc>>;
+
+    /// Lists every key under `prefix`, recursively.
+    async fn list(&self, prefix: &str) -> std::io::Result<Vec<String>>;
+
+    /// Removes a key, removing an unknown key is not an error.
+    async fn delete(&self, key: &str) -> std::io::Result<()>;
+
+    /// Moves the value of `from` to `to`, replacing it. Backends able to do it atomically should.
+    async fn rename(&self, from: &str, to: &str) -> std::io::Result<()> {
+        let bytes = self.get(from).await?;
+        self.put(to, bytes).await?;
+        self.delete(from).await
+    }
+
+    /// Starts a [`BackendTransaction`], whose writes and removals are only applied once
+    /// committed.
+    async fn begin(&self) -> std::io::Result<Box<dyn BackendTransaction + '_>> {
+        Ok(Box::new(StagedTransaction {
+            backend: self,
+            operations: Vec::new(),
+        }))
+    }
+
+    /// Path of the file behind `key` when it lives on the local disk, so it can be served
+    /// directly with range requests and caching headers.
+    fn local_path(&self, _key: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// A value being written in pieces, see [`StorageBackend::writer`]. Dropping it without finishing
+/// abandons what was written.
+#[async_trait::async_trait]
+pub trait BlobWriter: Send {
+    async fn write(&mut self, bytes: Bytes) -> std::io::Result<()>;
+
+    /// Makes every written byte visible under the key at once.
+    async fn finish(self: Box<Self>) -> std::io::Result<()>;
+
+    async fn abort(self: Box<Self>) -> std::io::Result<()>;
+}
+
+/// Writes and removals applied together by [`BackendTransaction::commit`], or not at all on
+/// [`BackendTransaction::rollback`]. Dropping it without committing rolls it back, but for written
+/// values left to the garbage collector.
+#[async_trait::async_trait]
+pub trait BackendTransaction: Send {
+    async fn put(&mut self, key: &str, bytes: Vec<u8>) -> std::io::Result<()>;
+
+    async fn delete(&mut self, key: &str) -> std::io::Result<()>;
+
+    /// Applies every operation in the order it was made.
+    async fn commit(self: Box<Self>) -> std::io::Result<()>;
+
+    async fn rollback(self: Box<Self>) -> std::io::Result<()>;
+}
+
+enum Operation {
+    Put { staged: String, key: String },
+    Delete(String),
+}
+
+/// The transaction of backends without a native one. Values are written under a temporary key
+/// right away, then renamed into place on commit.
+///
+/// Each key still changes atomically, but a failure while committing leaves the operations before
+/// it applied.
+struct StagedTransaction<'a, B: ?Sized> {
+    backend: &'a B,
+    operations: Vec<Operation>,
+}
+
+#[async_trait::async_trait]
+impl<B: StorageBackend + ?Sized> BackendTransaction for StagedTransaction<'_, B> {
+    async fn put(&mut self, key: &str, bytes: Vec<u8>) -> std::io::Result<()> {
+        let staged = format!("{INCOMING_PREFIX}/{}", Uuid::new_v4());
+        self.backend.put(&staged, bytes).await?;
+        self.operations.push(Operation::Put {
+            staged,
+            key: key.to_string(),
+        });
+        Ok(())
+    }
+
+    async fn delete(&mut self, key: &str) -> std::io::Result<()> {
+        self.operations.push(Operation::Delete(key.to_string()));
+        Ok(())
+    }
+
+    async fn commit(self: Box<Self>) -> std::io::Result<()> {
+        for operation in &self.operations {
+            match operation {
+                Operation::Put { staged, key } => self.backend.rename(staged, key).await?,
+                Operation::Delete(key) => self.backend.delete(key).await?,
+            }
+        }
+        Ok(())
+    }
+
+    async fn rollback(self: Box<Self>) -> std::io::Result<()> {
+        for operation in &self.operations {
+            if let Operation::Put { staged, .. } = operation {
+                self.backend.delete(staged).await?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,693 @@
+use base64::Engine;
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use secrecy::{ExposeSecret, SecretBox};
+use sha2::{Digest, Sha256};
+
+use std::sync::Arc;
+
+use super::backend::{BlobWriter, StorageBackend};
+use crate::configuration::EncryptionSettings;
+
+/// Starts every encrypted value, values without it were stored before encryption was enabled.
+const MAGIC: &[u8; 8] = b"PTENC\0v1";
+const KEY_ID_LEN: usize = 8;
+const KEY_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+/// The magic, the id of the key wrapping the data key, then the wrapped data key and its nonce.
+const HEADER_LEN: usize = MAGIC.len() + KEY_ID_LEN + NONCE_LEN + KEY_LEN + TAG_LEN;
+/// Plaintext bytes sealed together, so values can be encrypted while they are written.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+type KeyId = [u8; KEY_ID_LEN];
+
+fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+fn key_id(key: &[u8]) -> KeyId {
+    let mut id = KeyId::default();
+    id.copy_from_slice(&Sha256::digest(key)[..KEY_ID_LEN]);
+    id
+}
+
+fn parse_key(encoded: &SecretBox<String>) -> std::io::Result<(KeyId, LessSafeKey)> {
+    let invalid = |message| std::io::Error::new(std::io::ErrorKind::InvalidInput, message);
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(encoded.expose_secret().trim())
+        .map_err(|_| invalid("Encryption keys must be encoded in base64"))?;
+    if bytes.len() != KEY_LEN {
+        return Err(invalid("Encryption keys must be 32 bytes long"));
+    }
+
+    let key = UnboundKey::new(&AES_256_GCM, &bytes)
+        .map_err(|_| invalid("Encryption keys must be 32 bytes long"))?;
+    Ok((key_id(&bytes), LessSafeKey::new(key)))
+}
+
+/// The key encrypting new values, and the previous ones still decrypting older values.
+pub struct Keyring {
+    current: (KeyId, LessSafeKey),
+    previous: Vec<(KeyId, LessSafeKey)>,
+    rng: SystemRandom,
+}
+
+impl std::fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Keyring")
+            .field("keys", &(1 + self.previous.len()))
+            .finish_non_exhaustive()
+    }
+}
+
+impl TryFrom<&EncryptionSettings> for Keyring {
+    type Error = std::io::Error;
+
+    fn try_from(settings: &EncryptionSettings) -> Result<Self, Self::Error> {
+        Ok(Self {
+            current: parse_key(&settings.key)?,
+            previous: settings
+                .previous_keys
+                .iter()
+                .map(parse_key)
+                .collect::<Result<_, _>>()?,
+            rng: SystemRandom::new(),
+        })
+    }
+}
+
+impl Keyring {
+    fn find(&self, id: &KeyId) -> Option<&LessSafeKey> {
+        std::iter::once(&self.current)
+            .chain(&self.previous)
+            .find(|(key_id, _)| key_id == id)
+            .map(|(_, key)| key)
+    }
+
+    fn random<const N: usize>(&self) -> std::io::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        self.rng
+            .fill(&mut bytes)
+            .map_err(|_| std::io::Error::other("Failed to generate random bytes"))?;
+        Ok(bytes)
+    }
+
+    /// The header of a value whose data key is `data_key`, wrapped with the current key.
+    fn wrap(&self, data_key: &[u8; KEY_LEN]) -> std::io::Result<Vec<u8>> {
+        let (id, key) = &self.current;
+        let nonce = self.random::<NONCE_LEN>()?;
+
+        let mut header = [MAGIC.as_slice(), id, &nonce].concat();
+        let mut wrapped = data_key.to_vec();
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(&header[..MAGIC.len() + KEY_ID_LEN]),
+            &mut wrapped,
+        )
+        .map_err(|_| std::io::Error::other("Failed to wrap the data key"))?;
+        header.extend_from_slice(&wrapped);
+
+        Ok(header)
+    }
+
+    fn unwrap(&self, header: &[u8]) -> std::io::Result<[u8; KEY_LEN]> {
+        let (authenticated, rest) = header.split_at(MAGIC.len() + KEY_ID_LEN);
+        let (nonce, wrapped) = rest.split_at(NONCE_LEN);
+        let id = header_key_id(header);
+        let key = self
+            .find(&id)
+            .ok_or_else(|| invalid_data("Value is encrypted with an unknown key"))?;
+
+        let nonce = Nonce::try_assume_unique_for_key(nonce)
+            .map_err(|_| invalid_data("Value has a malformed encryption header"))?;
+        let mut wrapped = wrapped.to_vec();
+        let data_key = key
+            .open_in_place(nonce, Aad::from(authenticated), &mut wrapped)
+            .map_err(|_| invalid_data("Failed to unwrap the data key of a value"))?;
+
+        data_key
+            .try_into()
+            .map_err(|_| invalid_data("Value has a malformed encryption header"))
+    }
+
+    /// Encrypts `plaintext` the way stored values are, for secrets kept outside the storage.
+    pub fn seal(&self, plaintext: &[u8]) -> std::io::Result<Vec<u8>> {
+        seal(self, plaintext)
+    }
+
+    /// Decrypts a value of [`Keyring::seal`], returning a value in clear as it is.
+    pub fn open(&self, sealed: Vec<u8>) -> std::io::Result<Vec<u8>> {
+        open(self, sealed)
+    }
+
+    /// A fresh data key, and the header holding it.
+    fn envelope(&self) -> std::io::Result<(Vec<u8>, LessSafeKey)> {
+        let data_key = self.random::<KEY_LEN>()?;
+        let header = self.wrap(&data_key)?;
+        let key = UnboundKey::new(&AES_256_GCM, &data_key)
+            .map_err(|_| std::io::Error::other("Failed to create a data key"))?;
+        Ok((header, LessSafeKey::new(key)))
+    }
+}
+
+fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.len() >= HEADER_LEN && bytes.starts_with(MAGIC)
+}
+
+fn header_key_id(header: &[u8]) -> KeyId {
+    let mut id = KeyId::default();
+    id.copy_from_slice(&header[MAGIC.len()..MAGIC.len() + KEY_ID_LEN]);
+    id
+}
+
+/// Chunks are numbered and the last one flagged, so they can neither be reordered nor dropped.
+fn chunk_nonce(index: u64, last: bool) -> Nonce {
+    let mut nonce = [0; NONCE_LEN];
+    nonce[..8].copy_from_slice(&index.to_be_bytes());
+    nonce[8] = last as u8;
+    Nonce::assume_unique_for_key(nonce)
+}
+
+fn seal_chunk(
+    data_key: &LessSafeKey,
+    index: u64,
+    last: bool,
+    plaintext: &[u8],
+) -> std::io::Result<Vec<u8>> {
+    let mut sealed = plaintext.to_vec();
+    data_key
+        .seal_in_place_append_tag(chunk_nonce(index, last), Aad::empty(), &mut sealed)
+        .map_err(|_| std::io::Error::other("Failed to encrypt a value"))?;
+    Ok(sealed)
+}
+
+fn seal(keyring: &Keyring, plaintext: &[u8]) -> std::io::Result<Vec<u8>> {
+    let (mut sealed, data_key) = keyring.envelope()?;
+    let mut chunks: Vec<&[u8]> = plaintext.chunks(CHUNK_SIZE).collect();
+    if chunks.is_empty() {
+        chunks.push(&[]);
+    }
+
+    let count = chunks.len();
+    for (index, chunk) in chunks.into_iter().enumerate() {
+        sealed.extend(seal_chunk(
+            &data_key,
+            index as u64,
+            index + 1 == count,
+            chunk,
+        )?);
+    }
+    Ok(sealed)
+}
+
+/// The data key of a value, unwrapped from its header.
+fn data_key(keyring: &Keyring, header: &[u8]) -> std::io::Result<LessSafeKey> {
+    let data_key = keyring.unwrap(header)?;
+    Ok(LessSafeKey::new(
+        UnboundKey::new(&AES_256_GCM, &data_key)
+            .map_err(|_| invalid_data("Value has a malformed data key"))?,
+    ))
+}
+
+fn open_chunk<'a>(
+    data_key: &LessSafeKey,
+    index: u64,
+    last: bool,
+    chunk: &'a mut [u8],
+) -> std::io::Result<&'a mut [u8]> {
+    data_key
+        .open_in_place(chunk_nonce(index, last), Aad::empty(), chunk)
+        .map_err(|_| invalid_data("Failed to decrypt a value, it was altered or truncated"))
+}
+
+/// Decrypts a value, returning values stored before encryption was enabled as they are.
+fn open(keyring: &Keyring, mut bytes: Vec<u8>) -> std::io::Result<Vec<u8>> {
+    if !is_encrypted(&bytes) {
+        return Ok(bytes);
+    }
+
+    let data_key = data_key(keyring, &bytes[..HEADER_LEN])?;
+
+    let body = &mut bytes[HEADER_LEN..];
+    if body.is_empty() {
+        return Err(invalid_data("Encrypted value is truncated"));
+    }
+    let chunks = body.len().div_ceil(CHUNK_SIZE + TAG_LEN);
+    let mut plaintext = Vec::with_capacity(body.len());
+    for (index, chunk) in body.chunks_mut(CHUNK_SIZE + TAG_LEN).enumerate() {
+        let opened = open_chunk(&data_key, index as u64, index + 1 == chunks, chunk)?;
+        plaintext.extend_from_slice(opened);
+    }
+
+    Ok(plaintext)
+}
+
+/// Where [`open_stream`] is in the value it decrypts.
+struct OpenStream {
+    inner: BoxStream<'static, std::io::Result<Bytes>>,
+    keyring: Arc<Keyring>,
+    /// Bytes read but not decrypted yet.
+    buffer: BytesMut,
+    /// Known once the header is read, `None` forever for a value stored in clear.
+    data_key: Option<LessSafeKey>,
+    in_clear: bool,
+    index: u64,
+    ended: bool,
+}
+
+/// Decrypts a value as it is read, holding one chunk at a time. Like [`open`], values stored
+/// before encryption was enabled are passed as they are.
+fn open_stream(
+    keyring: Arc<Keyring>,
+    inner: BoxStream<'static, std::io::Result<Bytes>>,
+) -> BoxStream<'static, std::io::Result<Bytes>> {
+    const SEALED_LEN: usize = CHUNK_SIZE + TAG_LEN;
+    let state = OpenStream {
+        inner,
+        keyring,
+        buffer: BytesMut::new(),
+        data_key: None,
+        in_clear: false,
+        index: 0,
+        ended: false,
+    };
+
+    stream::try_unfold(state, |mut state| async move {
+        loop {
+            if state.in_clear && !state.buffer.is_empty() {
+                let bytes = state.buffer.split().freeze();
+                return Ok(Some((bytes, state)));
+            }
+
+            if let Some(data_key) = &state.data_key {
+                // INFO: a chunk is only known not to be the last once bytes follow it
+                let len = state.buffer.len();
+                if len > SEALED_LEN || (state.ended && len > 0) {
+                    let last = len <= SEALED_LEN;
+                    let mut chunk = state.buffer.split_to(len.min(SEALED_LEN));
+                    let opened = open_chunk(data_key, state.index, last, &mut chunk)?.len();
+                    chunk.truncate(opened);
+                    state.index += 1;
+                    return Ok(Some((chunk.freeze(), state)));
+                }
+                if state.ended && state.index == 0 {
+                    return Err(invalid_data("Encrypted value is truncated"));
+                }
+            } else if !state.in_clear && (state.buffer.len() >= HEADER_LEN || state.ended) {
+                if !is_encrypted(&state.buffer) {
+                    state.in_clear = true;
+                    continue;
+                }
+                let header = state.buffer.split_to(HEADER_LEN);
+                state.data_key = Some(data_key(&state.keyring, &header)?);
+                continue;
+            }
+
+            if state.ended {
+                return Ok(None);
+            }
+            match state.inner.try_next().await? {
+                Some(bytes) => state.buffer.extend_from_slice(&bytes),
+                None => state.ended = true,
+            }
+        }
+    })
+    .boxed()
+}
+
+/// What the key rotation did, see [`EncryptedBackend::rotate`].
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct KeyRotationReport {
+    pub checked: usize,
+    /// Values encrypted with a previous key, whose data key is now wrapped with the current one.
+    pub rewrapped: usize,
+    /// Values stored before encryption was enabled.
+    pub encrypted: usize,
+}
+
+/// Encrypts every value of another backend with envelope encryption: each value has its own random
+/// data key, stored next to it wrapped with the configured key. Rotating the configured key then
+/// only rewrites the headers.
+///
+/// Values are sealed with AES-256-GCM in chunks of [`CHUNK_SIZE`] bytes, so they can be encrypted
+/// while they are written. Keys and their layout are left in clear.
+#[derive(Debug, Clone)]
+pub struct EncryptedBackend {
+    inner: Arc<dyn StorageBackend>,
+    keyring: Arc<Keyring>,
+}
+
+impl EncryptedBackend {
+    pub fn new(inner: Arc<dyn StorageBackend>, keyring: Keyring) -> Self {
+        Self {
+            inner,
+            keyring: Arc::new(keyring),
+        }
+    }
+
+    /// Brings every value under `prefixes` to the current key, encrypting values stored before
+    /// encryption was enabled.
+    ///
+    /// A value removed while it is re-encrypted may be written back, the garbage collector takes
+    /// care of it later on.
+    pub async fn rotate(&self, prefixes: &[&str]) -> std::io::Result<KeyRotationReport> {
+        let (current, _) = &self.keyring.current;
+        let mut report = KeyRotationReport::default();
+
+        for prefix in prefixes {
+            for key in self.inner.list(prefix).await? {
+                let mut bytes = match self.inner.get(&key).await {
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                    other => other?,
+                };
+                report.checked += 1;
+
+                let rotated = if !is_encrypted(&bytes) {
+                    report.encrypted += 1;
+                    seal(&self.keyring, &bytes)?
+                } else if header_key_id(&bytes) != *current {
+                    report.rewrapped += 1;
+                    let data_key = self.keyring.unwrap(&bytes[..HEADER_LEN])?;
+                    bytes[..HEADER_LEN].copy_from_slice(&self.keyring.wrap(&data_key)?);
+                    bytes
+                } else {
+                    continue;
+                };
+                self.inner.put(&key, rotated).await?;
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// The sealed value of `key` when it opens to `expected`.
+    async fn sealed_if_equals(
+        &self,
+        key: &str,
+        expected: &[u8],
+    ) -> std::io::Result<Option<Vec<u8>>> {
+        let sealed = match self.inner.get(key).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            other => other?,
+        };
+        let current = open(&self.keyring, sealed.clone())?;
+        Ok((current == expected).then_some(sealed))
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for EncryptedBackend {
+    fn init(&self) -> std::io::Result<()> {
+        self.inner.init()
+    }
+
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> std::io::Result<()> {
+        let sealed = seal(&self.keyring, &bytes)?;
+        self.inner.put(key, sealed).await
+    }
+
+    async fn put_if_absent(&self, key: &str, bytes: Vec<u8>) -> std::io::Result<bool> {
+        let sealed = seal(&self.keyring, &bytes)?;
+        self.inner.put_if_absent(key, sealed).await
+    }
+
+    // INFO: sealing the same bytes twice differs, so the sealed value read is the one compared
+    async fn put_if_equals(
+        &self,
+        key: &str,
+        expected: &[u8],
+        bytes: Vec<u8>,
+    ) -> std::io::Result<bool> {
+        let Some(current) = self.sealed_if_equals(key, expected).await? else {
+            return Ok(false);
+        };
+        let sealed = seal(&self.keyring, &bytes)?;
+        self.inner.put_if_equals(key, &current, sealed).await
+    }
+
+    async fn delete_if_equals(&self, key: &str, expected: &[u8]) -> std::io::Result<bool> {
+        let Some(current) = self.sealed_if_equals(key, expected).await? else {
+            return Ok(false);
+        };
+        self.inner.delete_if_equals(key, &current).await
+    }
+
+    async fn writer(&self, key: &str) -> std::io::Result<Box<dyn BlobWriter>> {
+        let (header, data_key) = self.keyring.envelope()?;
+        let mut inner = self.inner.writer(key).await?;
+        inner.write(Bytes::from(header)).await?;
+
+        Ok(Box::new(EncryptedWriter {
+            inner,
+            data_key,
+            index: 0,
+            buffer: BytesMut::new(),
+        }))
+    }
+
+    async fn get(&self, key: &str) -> std::io::Result<Vec<u8>> {
+        let bytes = self.inner.get(key).await?;
+        open(&self.keyring, bytes)
+    }
+
+    async fn stream(
+        &self,
+        key: &str,
+    ) -> std::io::Result<BoxStream<'static, std::io::Result<Bytes>>> {
+        let inner = self.inner.stream(key).await?;
+        Ok(open_stream(self.keyring.clone(), inner))
+    }
+
+    async fn exists(&self, key: &str) -> std::io::Result<bool> {
+        self.inner.exists(key).await
+    }
+
+    async fn last_modified(&self, key: &str) -> std::io::Result<DateTime<Utc>> {
+        self.inner.last_modified(key).await
+    }
+
+    async fn list(&self, prefix: &str) -> std::io::Result<Vec<String>> {
+        self.inner.list(prefix).await
+    }
+
+    async fn delete(&self, key: &str) -> std::io::Result<()> {
+        self.inner.delete(key).await
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> std::io::Result<()> {
+        self.inner.rename(from, to).await
+    }
+
+    // INFO: files on disk are encrypted, so they are streamed rather than served directly
+}
+
+struct EncryptedWriter {
+    inner: Box<dyn BlobWriter>,
+    data_key: LessSafeKey,
+    index: u64,
+    /// Plaintext not sealed yet. The last chunk is only sealed on finish, as it is flagged.
+    buffer: BytesMut,
+}
+
+#[async_trait::async_trait]
+impl BlobWriter for EncryptedWriter {
+    async fn write(&mut self, bytes: Bytes) -> std::io::Result<()> {
+        self.buffer.extend_from_slice(&bytes);
+        while self.buffer.len() > CHUNK_SIZE {
+            let chunk = self.buffer.split_to(CHUNK_SIZE);
+            let sealed = seal_chunk(&self.data_key, self.index, false, &chunk)?;
+            self.index += 1;
+            self.inner.write(Bytes::from(sealed)).await?;
+        }
+        Ok(())
+    }
+
+    async fn finish(mut self: Box<Self>) -> std::io::Result<()> {
+        let sealed = seal_chunk(&self.data_key, self.index, true, &self.buffer)?;
+        self.inner.write(Bytes::from(sealed)).await?;
+        self.inner.finish().await
+    }
+
+    async fn abort(self: Box<Self>) -> std::io::Result<()> {
+        self.inner.abort().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::blob_storage::MemoryBackend;
+
+    fn settings(key: u8, previous: &[u8]) -> EncryptionSettings {
+        let encode = |byte| {
+            let encoded = base64::engine::general_purpose::STANDARD.encode([byte; KEY_LEN]);
+            SecretBox::new(Box::new(encoded))
+        };
+        EncryptionSettings {
+            key: encode(key),
+            previous_keys: previous.iter().copied().map(encode).collect(),
+        }
+    }
+
+    fn encrypted(inner: &Arc<MemoryBackend>, key: u8, previous: &[u8]) -> EncryptedBackend {
+        let keyring = Keyring::try_from(&settings(key, previous)).unwrap();
+        EncryptedBackend::new(inner.clone(), keyring)
+    }
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn values_are_stored_encrypted_and_read_back_in_clear() {
+        let inner = Arc::new(MemoryBackend::default());
+        let backend = encrypted(&inner, 1, &[]);
+
+        for len in [0, 5, CHUNK_SIZE, 2 * CHUNK_SIZE + 3] {
+            let key = format!("objects/{len}");
+            backend.put(&key, sample(len)).await.unwrap();
+            assert_eq!(backend.get(&key).await.unwrap(), sample(len));
+            assert!(is_encrypted(&inner.get(&key).await.unwrap()));
+        }
+
+        let stored = inner.get("objects/5").await.unwrap();
+        assert!(!stored.windows(5).any(|window| window == sample(5)));
+    }
+
+    #[tokio::test]
+    async fn written_values_match_values_put_at_once() {
+        let inner = Arc::new(MemoryBackend::default());
+        let backend = encrypted(&inner, 1, &[]);
+        let value = sample(3 * CHUNK_SIZE);
+
+        let mut writer = backend.writer("incoming/a").await.unwrap();
+        for piece in value.chunks(CHUNK_SIZE / 3 + 7) {
+            writer.write(Bytes::copy_from_slice(piece)).await.unwrap();
+        }
+        writer.finish().await.unwrap();
+
+        let stored = inner.get("incoming/a").await.unwrap();
+        assert_eq!(stored.len(), seal(&backend.keyring, &value).unwrap().len());
+        assert_eq!(backend.get("incoming/a").await.unwrap(), value);
+    }
+
+    /// Reads `stored` through [`open_stream`] in pieces of `piece` bytes.
+    async fn open_in_pieces(
+        backend: &EncryptedBackend,
+        stored: Vec<u8>,
+        piece: usize,
+    ) -> std::io::Result<Vec<u8>> {
+        let pieces: Vec<_> = stored
+            .chunks(piece)
+            .map(|piece| Ok(Bytes::copy_from_slice(piece)))
+            .collect();
+        let opened: Vec<Bytes> = open_stream(backend.keyring.clone(), stream::iter(pieces).boxed())
+            .try_collect()
+            .await?;
+        Ok(opened.concat())
+    }
+
+    #[tokio::test]
+    async fn streamed_values_are_decrypted_chunk_by_chunk() {
+        let inner = Arc::new(MemoryBackend::default());
+        let backend = encrypted(&inner, 1, &[]);
+
+        for len in [0, 5, CHUNK_SIZE, 2 * CHUNK_SIZE + 3] {
+            let key = format!("objects/{len}");
+            backend.put(&key, sample(len)).await.unwrap();
+            let stored = inner.get(&key).await.unwrap();
+            for piece in [7, HEADER_LEN, CHUNK_SIZE + TAG_LEN, stored.len().max(1)] {
+                let opened = open_in_pieces(&backend, stored.clone(), piece).await;
+                assert_eq!(opened.unwrap(), sample(len), "{len} bytes read by {piece}");
+            }
+
+            let streamed: Vec<Bytes> = backend
+                .stream(&key)
+                .await
+                .unwrap()
+                .try_collect()
+                .await
+                .unwrap();
+            assert_eq!(streamed.concat(), sample(len));
+        }
+
+        inner.put("posts/clear", sample(20)).await.unwrap();
+        let streamed: Vec<Bytes> = backend
+            .stream("posts/clear")
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(streamed.concat(), sample(20));
+
+        let mut truncated = inner.get(&format!("objects/{}", CHUNK_SIZE)).await.unwrap();
+        truncated.truncate(HEADER_LEN + CHUNK_SIZE);
+        let ret = open_in_pieces(&backend, truncated, 1000).await;
+        assert_eq!(ret.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn altered_or_truncated_values_fail_to_decrypt() {
+        let inner = Arc::new(MemoryBackend::default());
+        let backend = encrypted(&inner, 1, &[]);
+        backend
+            .put("objects/a", sample(CHUNK_SIZE + 1))
+            .await
+            .unwrap();
+        let stored = inner.get("objects/a").await.unwrap();
+
+        let mut altered = stored.clone();
+        altered[HEADER_LEN + 1] ^= 1;
+        let mut truncated = stored.clone();
+        truncated.truncate(HEADER_LEN + CHUNK_SIZE + TAG_LEN);
+
+        for bytes in [altered, truncated] {
+            inner.put("objects/a", bytes).await.unwrap();
+            assert_eq!(
+                backend.get("objects/a").await.unwrap_err().kind(),
+                std::io::ErrorKind::InvalidData
+            );
+        }
+
+        inner.put("objects/a", stored).await.unwrap();
+        let unknown = encrypted(&inner, 2, &[]);
+        assert_eq!(
+            unknown.get("objects/a").await.unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+    }
+
+    #[tokio::test]
+    async fn rotation_rewraps_old_values_and_encrypts_clear_ones() {
+        let inner = Arc::new(MemoryBackend::default());
+        encrypted(&inner, 1, &[])
+            .put("objects/old", sample(10))
+            .await
+            .unwrap();
+        inner.put("posts/clear", sample(20)).await.unwrap();
+
+        let rotated = encrypted(&inner, 2, &[1]);
+        rotated.put("objects/new", sample(30)).await.unwrap();
+        assert_eq!(rotated.get("posts/clear").await.unwrap(), sample(20));
+
+        let report = rotated.rotate(&["objects", "posts"]).await.unwrap();
+        assert_eq!(
+            report,
+            KeyRotationReport {
+                checked: 3,
+                rewrapped: 1,
+                encrypted: 1,
+            }
+        );
+
+        let current = encrypted(&inner, 2, &[]);
+        assert_eq!(current.get("objects/old").await.unwrap(), sample(10));
+        assert_eq!(current.get("objects/new").await.unwrap(), sample(30));
+        assert!(is_encrypted(&inner.get("posts/clear").await.unwrap()));
+        assert_eq!(current.get("posts/clear").await.unwrap(), sample(20));
+    }
+}
@@ -0,0 +1,474 @@
+use chrono::{DateTime, Utc};
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use super::objects::{INCOMING_PREFIX, OBJECTS_PREFIX};
+use super::{
+    manifest_key, release_objects, staged_manifest_key, BlobStorage, ObjectStore, PostManifest,
+    POSTS_PREFIX, STAGED_PREFIX,
+};
+
+/// Orphans are moved under this prefix, keeping their original key.
+pub(super) const QUARANTINE_PREFIX: &str = "quarantine";
+
+pub const DEFAULT_GRACE_PERIOD_SECS: u64 = 24 * 60 * 60;
+
+/// What happens to orphans once they are past the grace period.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrphanAction {
+    /// Move them under `quarantine/`, a later [`OrphanAction::Delete`] run purges them.
+    #[default]
+    Quarantine,
+    Delete,
+}
+
+impl std::str::FromStr for OrphanAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "quarantine" => Ok(Self::Quarantine),
+            "delete" => Ok(Self::Delete),
+            other => Err(format!(
+                "unknown orphan action `{other}`, expected `quarantine` or `delete`"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct GcOptions {
+    /// Only report what would be collected.
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default)]
+    pub action: OrphanAction,
+    /// Orphans written more recently are left alone, they may belong to an upload in progress.
+    #[serde(default = "default_grace_period_secs")]
+    pub grace_period_secs: u64,
+}
+
+fn default_grace_period_secs() -> u64 {
+    DEFAULT_GRACE_PERIOD_SECS
+}
+
+impl Default for GcOptions {
+    fn default() -> Self {
+        Self {
+            dry_run: false,
+            action: OrphanAction::default(),
+            grace_period_secs: DEFAULT_GRACE_PERIOD_SECS,
+        }
+    }
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct GcReport {
+    pub dry_run: bool,
+    pub action: OrphanAction,
+    /// Post blobs no row refers to.
+    pub orphan_blobs: Vec<String>,
+    /// Blobs rows refer to which have no manifest in the storage.
+    pub missing_blobs: Vec<String>,
+    /// References whose owner has no manifest listing the object, such as after a crash.
+    pub dangling_refs: Vec<String>,
+    /// Stored objects nothing references.
+    pub unreferenced_objects: Vec<String>,
+    /// Objects whose streaming was interrupted before they were complete.
+    pub abandoned_writes: Vec<String>,
+    /// Quarantined keys removed for good.
+    pub purged: Vec<String>,
+    /// Orphans left alone because they are still within the grace period.
+    pub within_grace_period: Vec<String>,
+}
+
+impl BlobStorage {
+    /// Cross-references the post blobs in the storage with `live_blobs`, the blobs post rows refer
+    /// to, and collects whatever is left behind by deleted posts or failed saves.
+    ///
+    /// Orphan blobs are quarantined or deleted along with their references, then references not
+    /// backed by a manifest and objects nothing references anymore.
+    pub async fn collect_garbage(
+        &self,
+        live_blobs: &HashSet<String>,
+        options: &GcOptions,
+    ) -> std::io::Result<GcReport> {
+        let objects = ObjectStore::new(self.backend.clone());
+        let deadline = Utc::now() - chrono::Duration::seconds(options.grace_period_secs as i64);
+        let mut report = GcReport {
+            dry_run: options.dry_run,
+            action: options.action,
+            ..Default::default()
+        };
+        // INFO: objects each manifest references, by blob, quarantined manifests included
+        let mut held = HashMap::new();
+
+        let posts = group_by_blob(self.backend.list(POSTS_PREFIX).await?, POSTS_PREFIX);
+        for (blob, keys) in posts {
+            let manifest = self.manifest_at(&manifest_key(&blob)).await?;
+            if live_blobs.contains(&blob) {
+                hold(&mut held, &blob, manifest.as_ref());
+                continue;
+            }
+            if !self.is_older_than(&keys, deadline).await? {
+                report
+                    .within_grace_period
+                    .push(format!("{POSTS_PREFIX}/{blob}"));
+                hold(&mut held, &blob, manifest.as_ref());
+                continue;
+            }
+
+            report.orphan_blobs.push(blob.clone());
+            match options.action {
+                _ if options.dry_run => hold(&mut held, &blob, manifest.as_ref()),
+                OrphanAction::Quarantine => {
+                    for key in &keys {
+                        self.move_key(key, &quarantine_key(key)).await?;
+                    }
+                    hold(&mut held, &blob, manifest.as_ref());
+                }
+                OrphanAction::Delete => {
+                    if let Some(manifest) = &manifest {
+                        release_objects(&objects, &blob, manifest).await?;
+                    }
+                    for key in &keys {
+                        self.backend.delete(key).await?;
+                    }
+                }
+            }
+        }
+
+        // INFO: staged manifests are left to the recovery of staged writes
+        for blob in group_by_blob(self.backend.list(STAGED_PREFIX).await?, STAGED_PREFIX).keys() {
+            let manifest = self.manifest_at(&staged_manifest_key(blob)).await?;
+            hold(&mut held, blob, manifest.as_ref());
+        }
+
+        report.missing_blobs = live_blobs
+            .iter()
+            .filter(|blob| !held.contains_key(*blob))
+            .cloned()
+            .collect();
+        report.missing_blobs.sort();
+
+        let quarantined_posts = quarantine_key(POSTS_PREFIX);
+        let quarantined = group_by_blob(
+            self.backend.list(&quarantined_posts).await?,
+            &quarantined_posts,
+        );
+        for (blob, keys) in quarantined {
+            let manifest = self
+                .manifest_at(&quarantine_key(&manifest_key(&blob)))
+                .await?;
+            let purge = options.action == OrphanAction::Delete
+                && self.is_older_than(&keys, deadline).await?;
+            if !purge || options.dry_run {
+                if purge {
+                    report.purged.extend(keys);
+                }
+                hold(&mut held, &blob, manifest.as_ref());
+                continue;
+            }
+
+            // INFO: a blob restored from the quarantine keeps its references
+            if let Some(manifest) = manifest.filter(|_| !held.contains_key(&blob)) {
+                release_objects(&objects, &blob, &manifest).await?;
+            }
+            for key in &keys {
+                self.backend.delete(key).await?;
+            }
+            report.purged.extend(keys);
+        }
+
+        if options.action == OrphanAction::Delete {
+            for key in self.backend.list(&quarantine_key(OBJECTS_PREFIX)).await? {
+                if !self
+                    .is_older_than(std::slice::from_ref(&key), deadline)
+                    .await?
+                {
+                    continue;
+                }
+                if !options.dry_run {
+                    self.backend.delete(&key).await?;
+                }
+                report.purged.push(key);
+            }
+        }
+
+        for key in self.backend.list(INCOMING_PREFIX).await? {
+            if !self
+                .is_older_than(std::slice::from_ref(&key), deadline)
+                .await?
+            {
+                continue;
+            }
+            if !options.dry_run {
+                self.backend.delete(&key).await?;
+            }
+            report.abandoned_writes.push(key);
+        }
+
+        let mut referenced = HashSet::new();
+        for (sha256, owner) in objects.references().await? {
+            let key = ObjectStore::ref_key(&sha256, &owner);
+            let is_held = held
+                .get(&owner)
+                .is_some_and(|held: &HashSet<String>| held.contains(&sha256));
+            if is_held {
+                referenced.insert(sha256);
+                continue;
+            }
+            if !self
+                .is_older_than(std::slice::from_ref(&key), deadline)
+                .await?
+            {
+                report.within_grace_period.push(key);
+                referenced.insert(sha256);
+                continue;
+            }
+
+            if !options.dry_run {
+                objects.forget(&sha256, &owner).await?;
+            }
+            report.dangling_refs.push(key);
+        }
+
+        for sha256 in objects.digests().await? {
+            if referenced.contains(&sha256) {
+                continue;
+            }
+            let key = ObjectStore::object_key(&sha256);
+            if !self
+                .is_older_than(std::slice::from_ref(&key), deadline)
+                .await?
+            {
+                report.within_grace_period.push(key);
+                continue;
+            }
+
+            let quarantined =
+                (options.action == OrphanAction::Quarantine).then(|| quarantine_key(&key));
+            if options.dry_run
+                || objects
+                    .evict_unreferenced(&sha256, quarantined.as_deref())
+                    .await?
+            {
+                report.unreferenced_objects.push(key);
+            }
+        }
+
+        Ok(report)
+    }
+
+    async fn manifest_at(&self, key: &str) -> std::io::Result<Option<PostManifest>> {
+        match self.backend.get(key).await {
+            Ok(bytes) => PostManifest::from_slice(&bytes).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether every key was last written before `deadline`, keys removed meanwhile are ignored.
+    async fn is_older_than(
+        &self,
+        keys: &[String],
+        deadline: DateTime<Utc>,
+    ) -> std::io::Result<bool> {
+        for key in keys {
+            match self.backend.last_modified(key).await {
+                Ok(modified) if modified > deadline => return Ok(false),
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(true)
+    }
+
+    async fn move_key(&self, from: &str, to: &str) -> std::io::Result<()> {
+        let bytes = self.backend.get(from).await?;
+        self.backend.put(to, bytes).await?;
+        self.backend.delete(from).await
+    }
+}
+
+fn quarantine_key(key: &str) -> String {
+    format!("{QUARANTINE_PREFIX}/{key}")
+}
+
+/// Groups keys such as `<prefix>/<blob>/manifest.json` by blob.
+fn group_by_blob(keys: Vec<String>, prefix: &str) -> BTreeMap<String, Vec<String>> {
+    let mut blobs = BTreeMap::<String, Vec<String>>::new();
+    for key in keys {
+        let blob = key
+            .strip_prefix(prefix)
+            .and_then(|rest| rest.strip_prefix('/'))
+            .and_then(|rest| rest.split_once('/'))
+            .map(|(blob, _)| blob.to_string());
+        if let Some(blob) = blob {
+            blobs.entry(blob).or_default().push(key);
+        }
+    }
+    blobs
+}
+
+fn hold(held: &mut HashMap<String, HashSet<String>>, blob: &str, manifest: Option<&PostManifest>) {
+    if let Some(manifest) = manifest {
+        held.entry(blob.to_string())
+            .or_default()
+            .extend(manifest.objects().map(|object| object.sha256.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::blob_storage::MemoryBackend;
+
+    use std::sync::Arc;
+
+    async fn storage_with_posts(blobs: &[&str]) -> BlobStorage {
+        let storage = BlobStorage::new(Arc::new(MemoryBackend::default()));
+        for blob in blobs {
+            let mut driver = storage.post_storage_driver(blob);
+            driver
+                .post_save_content("post.md", &format!("words of {blob}"))
+                .await
+                .unwrap();
+            driver
+                .post_save_variant("post.md", "shared", b"shared bytes".to_vec())
+                .await
+                .unwrap();
+            driver.confirm_saved().await.unwrap();
+        }
+        storage
+    }
+
+    fn live(blobs: &[&str]) -> HashSet<String> {
+        blobs.iter().map(|blob| blob.to_string()).collect()
+    }
+
+    fn options(action: OrphanAction, dry_run: bool) -> GcOptions {
+        GcOptions {
+            dry_run,
+            action,
+            grace_period_secs: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn dry_run_reports_orphans_and_missing_blobs_without_touching_them() {
+        let storage = storage_with_posts(&["kept", "orphan"]).await;
+
+        let report = storage
+            .collect_garbage(
+                &live(&["kept", "missing"]),
+                &options(OrphanAction::Delete, true),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(report.orphan_blobs, vec!["orphan"]);
+        assert_eq!(report.missing_blobs, vec!["missing"]);
+        assert!(storage.post_manifest("orphan").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn orphans_within_the_grace_period_are_left_alone() {
+        let storage = storage_with_posts(&["orphan"]).await;
+
+        let report = storage
+            .collect_garbage(&live(&[]), &GcOptions::default())
+            .await
+            .unwrap();
+
+        assert!(report.orphan_blobs.is_empty());
+        assert_eq!(report.within_grace_period, vec!["posts/orphan"]);
+        assert!(storage.post_manifest("orphan").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn quarantined_orphans_keep_their_objects_until_purged() {
+        let storage = storage_with_posts(&["kept", "orphan"]).await;
+        let manifest = storage.post_manifest("orphan").await.unwrap();
+        let content = manifest.content_entry().unwrap().object.clone();
+
+        let report = storage
+            .collect_garbage(&live(&["kept"]), &options(OrphanAction::Quarantine, false))
+            .await
+            .unwrap();
+        assert_eq!(report.orphan_blobs, vec!["orphan"]);
+        assert!(storage.post_manifest("orphan").await.is_err());
+        assert!(storage.read_object(&content).await.is_ok());
+
+        let report = storage
+            .collect_garbage(&live(&["kept"]), &options(OrphanAction::Delete, false))
+            .await
+            .unwrap();
+        assert_eq!(report.purged, vec!["quarantine/posts/orphan/manifest.json"]);
+        assert!(storage.read_object(&content).await.is_err());
+
+        let kept = storage.post_manifest("kept").await.unwrap();
+        for object in kept.objects() {
+            assert!(storage.read_object(object).await.is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn references_left_by_a_failed_save_are_collected() {
+        let storage = storage_with_posts(&["kept"]).await;
+        let objects = ObjectStore::new(storage.backend.clone());
+        let leftover = objects
+            .put_bytes(b"never confirmed".to_vec(), "crashed")
+            .await
+            .unwrap();
+        objects
+            .put_bytes(b"shared bytes".to_vec(), "crashed")
+            .await
+            .unwrap();
+
+        let report = storage
+            .collect_garbage(&live(&["kept"]), &options(OrphanAction::Delete, false))
+            .await
+            .unwrap();
+
+        assert_eq!(report.dangling_refs.len(), 2);
+        assert_eq!(
+            report.unreferenced_objects,
+            vec![ObjectStore::object_key(&leftover.sha256)]
+        );
+        assert!(storage.read_object(&leftover).await.is_err());
+
+        let kept = storage.post_manifest("kept").await.unwrap();
+        for object in kept.objects() {
+            assert_eq!(objects.ref_count(&object.sha256).await.unwrap(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn interrupted_streams_are_collected() {
+        let storage = storage_with_posts(&[]).await;
+        // INFO: what a writer leaves behind when the process dies before it is finished
+        storage
+            .backend
+            .put(
+                &format!("{INCOMING_PREFIX}/stream"),
+                b"half an upload".to_vec(),
+            )
+            .await
+            .unwrap();
+
+        let report = storage
+            .collect_garbage(&live(&[]), &options(OrphanAction::Delete, false))
+            .await
+            .unwrap();
+
+        assert_eq!(report.abandoned_writes.len(), 1);
+        assert!(storage
+            .backend
+            .list(INCOMING_PREFIX)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+
+use super::{manifest_key, sanitize_relative_path, BlobStorage, POSTS_PREFIX};
+
+/// Post blobs written before files were content-addressed kept every file as
+/// `posts/<blob>/<file name>`, the markdown content being the `.md` one, with no manifest.
+impl BlobStorage {
+    /// Moves every post blob still in the legacy layout into the object store behind a manifest,
+    /// returning the imported blobs. Files are kept byte for byte, photos are not stripped again.
+    ///
+    /// The manifest is written before the legacy files are removed, so an interrupted import is
+    /// finished by the next one.
+    pub async fn import_legacy_blobs(&self) -> std::io::Result<Vec<String>> {
+        let mut blobs = BTreeMap::<String, Vec<String>>::new();
+        for key in self.backend.list(POSTS_PREFIX).await? {
+            let file = key
+                .strip_prefix(POSTS_PREFIX)
+                .and_then(|rest| rest.strip_prefix('/'))
+                .and_then(|rest| rest.split_once('/'));
+            if let Some((blob, _)) = file.filter(|(blob, _)| key != manifest_key(blob)) {
+                blobs.entry(blob.to_string()).or_default().push(key);
+            }
+        }
+
+        let mut imported = Vec::new();
+        for (blob, keys) in blobs {
+            if !self.backend.exists(&manifest_key(&blob)).await? {
+                self.import_legacy_blob(&blob, &keys).await?;
+                imported.push(blob);
+            }
+            for key in &keys {
+                self.backend.delete(key).await?;
+            }
+        }
+
+        if !imported.is_empty() {
+            tracing::info!(count = imported.len(), "Imported legacy post blobs");
+        }
+        Ok(imported)
+    }
+
+    async fn import_legacy_blob(&self, blob: &str, keys: &[String]) -> std::io::Result<()> {
+        let prefix = format!("{POSTS_PREFIX}/{blob}/");
+        let mut driver = self.post_storage_driver(blob);
+        driver.set_metadata_stripping(false);
+
+        let mut has_content = false;
+        for key in keys {
+            let Some(path) = key.strip_prefix(&prefix).and_then(sanitize_relative_path) else {
+                tracing::warn!(key, "Skipping a legacy file with an unsafe name");
+                continue;
+            };
+            let bytes = self.backend.get(key).await?;
+
+            if !has_content && path.extension().is_some_and(|ext| ext == "md") {
+                let content = String::from_utf8(bytes)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                driver.post_save_content(&path, &content).await?;
+                has_content = true;
+            } else {
+                driver.post_save_attachment(&path, bytes).await?;
+            }
+        }
+
+        if !has_content {
+            tracing::warn!(blob, "Legacy post blob has no markdown content");
+        }
+        driver.confirm_saved().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::blob_storage::{MemoryBackend, StorageBackend};
+
+    use std::path::Path;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn legacy_blobs_are_imported_once_behind_a_manifest() {
+        let backend = Arc::new(MemoryBackend::default());
+        let storage = BlobStorage::new(backend.clone());
+        storage
+            .post_storage_driver("current")
+            .confirm_saved()
+            .await
+            .unwrap();
+        for (key, bytes) in [
+            ("posts/legacy/trip.md", b"# Trip".to_vec()),
+            ("posts/legacy/cover.png", b"not even a png".to_vec()),
+        ] {
+            backend.put(key, bytes).await.unwrap();
+        }
+
+        let imported = storage.import_legacy_blobs().await.unwrap();
+
+        assert_eq!(imported, vec!["legacy".to_string()]);
+        let manifest = storage.post_manifest("legacy").await.unwrap();
+        assert_eq!(manifest.content, Path::new("trip.md"));
+        let content = storage
+            .read_object(&manifest.content_entry().unwrap().object)
+            .await
+            .unwrap();
+        assert_eq!(content, b"# Trip");
+        let cover = &manifest.files[Path::new("cover.png")].object;
+        assert_eq!(storage.read_object(cover).await.unwrap(), b"not even a png");
+        assert_eq!(
+            backend.list(POSTS_PREFIX).await.unwrap(),
+            vec![manifest_key("current"), manifest_key("legacy")]
+        );
+
+        assert!(storage.import_legacy_blobs().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn an_interrupted_import_only_removes_the_leftover_files() {
+        let backend = Arc::new(MemoryBackend::default());
+        let storage = BlobStorage::new(backend.clone());
+        backend
+            .put("posts/legacy/trip.md", b"# Trip".to_vec())
+            .await
+            .unwrap();
+        storage.import_legacy_blobs().await.unwrap();
+        let manifest = storage.post_manifest("legacy").await.unwrap();
+        backend
+            .put("posts/legacy/trip.md", b"# Trip".to_vec())
+            .await
+            .unwrap();
+
+        assert!(storage.import_legacy_blobs().await.unwrap().is_empty());
+        assert!(!backend.exists("posts/legacy/trip.md").await.unwrap());
+        let again = storage.post_manifest("legacy").await.unwrap();
+        assert_eq!(again.files.len(), manifest.files.len());
+    }
+}
@@ -0,0 +1,332 @@
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use futures::stream::{self, BoxStream, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use uuid::Uuid;
+
+use std::path::{Path, PathBuf};
+
+use super::backend::{BlobWriter, StorageBackend};
+
+/// Directory inside the root where values are written before being renamed into place.
+const STAGING_DIR: &str = ".staging";
+/// Bytes read at once when streaming a value.
+const READ_SIZE: usize = 64 * 1024;
+
+/// Stores every key as a file under a root directory on the local disk.
+#[derive(Debug, Clone)]
+pub struct LocalBackend {
+    root: PathBuf,
+}
+
+impl LocalBackend {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+
+    fn staging_path(&self) -> PathBuf {
+        self.root.join(STAGING_DIR).join(Uuid::new_v4().to_string())
+    }
+
+    /// Moves a fully written staging file to `key`.
+    async fn commit_staged(&self, staged: &Path, key: &str) -> std::io::Result<()> {
+        let ret = self.rename_into(staged, key).await;
+        if ret.is_err() {
+            let _ = tokio::fs::remove_file(staged).await;
+        }
+        ret
+    }
+
+    /// Removes the directories left empty above `path`, removing a non-empty one simply fails.
+    async fn prune_empty_parents(&self, path: &Path) {
+        let mut dir = path.parent();
+        while let Some(parent) = dir.filter(|dir| *dir != self.root) {
+            if tokio::fs::remove_dir(parent).await.is_err() {
+                break;
+            }
+            dir = parent.parent();
+        }
+    }
+
+    /// Renames the local file `from` to the path of `key`.
+    async fn rename_into(&self, from: &Path, key: &str) -> std::io::Result<()> {
+        let path = self.path(key);
+        let mut ret = Err(std::io::ErrorKind::NotFound.into());
+
+        // INFO: a concurrent delete may prune the parent directory right after it is created
+        for _ in 0..3 {
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            ret = tokio::fs::rename(from, &path).await;
+            if !matches!(&ret, Err(e) if e.kind() == std::io::ErrorKind::NotFound)
+                || !tokio::fs::try_exists(from).await?
+            {
+                break;
+            }
+        }
+
+        ret
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for LocalBackend {
+    fn init(&self) -> std::io::Result<()> {
+        std::fs::create_dir_all(self.root.join(STAGING_DIR))
+    }
+
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> std::io::Result<()> {
+        let staged = self.staging_path();
+        tokio::fs::write(&staged, bytes).await?;
+        self.commit_staged(&staged, key).await
+    }
+
+    async fn put_if_absent(&self, key: &str, bytes: Vec<u8>) -> std::io::Result<bool> {
+        let staged = self.staging_path();
+        tokio::fs::write(&staged, bytes).await?;
+        let path = self.path(key);
+        let mut ret = Err(std::io::ErrorKind::NotFound.into());
+
+        // INFO: unlike a rename, a hard link fails rather than replace an existing file
+        for _ in 0..3 {
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            ret = tokio::fs::hard_link(&staged, &path).await;
+            if !matches!(&ret, Err(e) if e.kind() == std::io::ErrorKind::NotFound) {
+                break;
+            }
+        }
+        let _ = tokio::fs::remove_file(&staged).await;
+
+        match ret {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn writer(&self, key: &str) -> std::io::Result<Box<dyn BlobWriter>> {
+        let staged = self.staging_path();
+        let file = tokio::fs::File::create(&staged).await?;
+        Ok(Box::new(LocalWriter {
+            backend: self.clone(),
+            key: key.to_string(),
+            staged,
+            file: Some(file),
+        }))
+    }
+
+    async fn get(&self, key: &str) -> std::io::Result<Vec<u8>> {
+        tokio::fs::read(self.path(key)).await
+    }
+
+    async fn stream(
+        &self,
+        key: &str,
+    ) -> std::io::Result<BoxStream<'static, std::io::Result<Bytes>>> {
+        let file = tokio::fs::File::open(self.path(key)).await?;
+        Ok(stream::try_unfold(file, |mut file| async move {
+            let mut bytes = BytesMut::with_capacity(READ_SIZE);
+            match file.read_buf(&mut bytes).await? {
+                0 => Ok(None),
+                _ => Ok(Some((bytes.freeze(), file))),
+            }
+        })
+        .boxed())
+    }
+
+    async fn exists(&self, key: &str) -> std::io::Result<bool> {
+        tokio::fs::try_exists(self.path(key)).await
+    }
+
+    async fn last_modified(&self, key: &str) -> std::io::Result<DateTime<Utc>> {
+        let modified = tokio::fs::metadata(self.path(key)).await?.modified()?;
+        Ok(modified.into())
+    }
+
+    async fn list(&self, prefix: &str) -> std::io::Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut pending = vec![self.path(prefix)];
+
+        while let Some(dir) = pending.pop() {
+            let mut entries = match tokio::fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if entry.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
+
+                if entry.file_type().await?.is_dir() {
+                    pending.push(path);
+                } else if let Ok(relative) = path.strip_prefix(&self.root) {
+                    keys.push(relative.to_string_lossy().replace('\\', "/"));
+                }
+            }
+        }
+
+        keys.sort();
+        Ok(keys)
+    }
+
+    async fn delete(&self, key: &str) -> std::io::Result<()> {
+        let path = self.path(key);
+        match tokio::fs::remove_file(&path).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            other => other?,
+        }
+
+        self.prune_empty_parents(&path).await;
+        Ok(())
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> std::io::Result<()> {
+        let from = self.path(from);
+        self.rename_into(&from, to).await?;
+        self.prune_empty_parents(&from).await;
+        Ok(())
+    }
+
+    fn local_path(&self, key: &str) -> Option<PathBuf> {
+        Some(self.path(key))
+    }
+}
+
+/// Writes into a staging file, renamed into place once finished.
+struct LocalWriter {
+    backend: LocalBackend,
+    key: String,
+    staged: PathBuf,
+    /// Taken once the staging file is either committed or removed.
+    file: Option<tokio::fs::File>,
+}
+
+#[async_trait::async_trait]
+impl BlobWriter for LocalWriter {
+    async fn write(&mut self, bytes: Bytes) -> std::io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.write_all(&bytes).await,
+            None => Err(std::io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    async fn finish(mut self: Box<Self>) -> std::io::Result<()> {
+        if let Some(mut file) = self.file.take() {
+            file.flush().await?;
+        }
+        self.backend.commit_staged(&self.staged, &self.key).await
+    }
+
+    async fn abort(mut self: Box<Self>) -> std::io::Result<()> {
+        self.file.take();
+        tokio::fs::remove_file(&self.staged).await
+    }
+}
+
+impl Drop for LocalWriter {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = std::fs::remove_file(&self.staged);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::TryStreamExt;
+
+    #[tokio::test]
+    async fn keys_round_trip_and_are_listed_recursively() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = LocalBackend::new(dir.path().to_path_buf());
+        backend.init().unwrap();
+
+        backend
+            .put("posts/a/manifest.json", b"{}".to_vec())
+            .await
+            .unwrap();
+        backend
+            .put("posts/b/manifest.json", b"[]".to_vec())
+            .await
+            .unwrap();
+
+        assert_eq!(backend.get("posts/a/manifest.json").await.unwrap(), b"{}");
+        assert_eq!(
+            backend.list("posts").await.unwrap(),
+            vec!["posts/a/manifest.json", "posts/b/manifest.json"]
+        );
+
+        let mut writer = backend.writer("objects/ab/abc").await.unwrap();
+        writer.write(Bytes::from_static(b"in ")).await.unwrap();
+        writer.write(Bytes::from_static(b"pieces")).await.unwrap();
+        assert!(!backend.exists("objects/ab/abc").await.unwrap());
+        writer.finish().await.unwrap();
+        assert_eq!(backend.get("objects/ab/abc").await.unwrap(), b"in pieces");
+        let streamed: Vec<Bytes> = backend
+            .stream("objects/ab/abc")
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(streamed.concat(), b"in pieces");
+
+        let mut writer = backend.writer("objects/cd/cde").await.unwrap();
+        writer.write(Bytes::from_static(b"lost")).await.unwrap();
+        drop(writer);
+        assert!(!backend.exists("objects/cd/cde").await.unwrap());
+        assert_eq!(
+            std::fs::read_dir(dir.path().join(STAGING_DIR))
+                .unwrap()
+                .count(),
+            0
+        );
+
+        backend.delete("posts/a/manifest.json").await.unwrap();
+        backend.delete("posts/a/manifest.json").await.unwrap();
+        assert!(!dir.path().join("posts/a").exists());
+        assert_eq!(
+            backend
+                .get("posts/a/manifest.json")
+                .await
+                .unwrap_err()
+                .kind(),
+            std::io::ErrorKind::NotFound
+        );
+    }
+
+    #[tokio::test]
+    async fn conditional_puts_never_replace_a_value() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = LocalBackend::new(dir.path().to_path_buf());
+        backend.init().unwrap();
+
+        assert!(backend
+            .put_if_absent("locks/a", b"1".to_vec())
+            .await
+            .unwrap());
+        assert!(!backend
+            .put_if_absent("locks/a", b"2".to_vec())
+            .await
+            .unwrap());
+        assert_eq!(backend.get("locks/a").await.unwrap(), b"1");
+        assert_eq!(
+            std::fs::read_dir(dir.path().join(STAGING_DIR))
+                .unwrap()
+                .count(),
+            0
+        );
+    }
+}
@@ -0,0 +1,55 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use super::objects::StoredObject;
+
+/// The files making up one post blob, by logical path, and the objects holding their bytes.
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PostManifest {
+    /// Logical path of the markdown content among `files`.
+    pub content: PathBuf,
+    pub files: BTreeMap<PathBuf, ManifestEntry>,
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ManifestEntry {
+    #[serde(flatten)]
+    pub object: StoredObject,
+    /// Generated copies of the file, such as resized images, by variant name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variants: BTreeMap<String, StoredObject>,
+    /// Type sniffed from the bytes of the file when it was saved, served as its content type.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime: Option<String>,
+}
+
+impl PostManifest {
+    pub fn from_slice(bytes: &[u8]) -> std::io::Result<Self> {
+        serde_json::from_slice(bytes)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn to_vec(&self) -> std::io::Result<Vec<u8>> {
+        serde_json::to_vec_pretty(self)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn content_entry(&self) -> Option<&ManifestEntry> {
+        self.files.get(&self.content)
+    }
+
+    /// Every file except the markdown content.
+    pub fn attachments(&self) -> impl Iterator<Item = (&Path, &ManifestEntry)> {
+        self.files
+            .iter()
+            .filter(|(path, _)| **path != self.content)
+            .map(|(path, entry)| (path.as_path(), entry))
+    }
+
+    /// Every object referenced by the manifest, variants included.
+    pub fn objects(&self) -> impl Iterator<Item = &StoredObject> {
+        self.files
+            .values()
+            .flat_map(|entry| std::iter::once(&entry.object).chain(entry.variants.values()))
+    }
+}
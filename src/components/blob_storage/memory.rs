@@ -0,0 +1,225 @@
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use super::backend::{BlobWriter, StorageBackend};
+
+/// Keeps every key in memory, everything is gone once the storage is dropped.
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    values: Arc<RwLock<Values>>,
+}
+
+type Values = BTreeMap<String, (Vec<u8>, DateTime<Utc>)>;
+
+impl MemoryBackend {
+    fn handle(&self) -> Self {
+        Self {
+            values: self.values.clone(),
+        }
+    }
+
+    fn values(&self) -> std::sync::RwLockReadGuard<'_, Values> {
+        self.values.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn values_mut(&self) -> std::sync::RwLockWriteGuard<'_, Values> {
+        self.values.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for MemoryBackend {
+    fn init(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> std::io::Result<()> {
+        self.values_mut()
+            .insert(key.to_string(), (bytes, Utc::now()));
+        Ok(())
+    }
+
+    async fn put_if_absent(&self, key: &str, bytes: Vec<u8>) -> std::io::Result<bool> {
+        match self.values_mut().entry(key.to_string()) {
+            Entry::Vacant(entry) => {
+                entry.insert((bytes, Utc::now()));
+                Ok(true)
+            }
+            Entry::Occupied(_) => Ok(false),
+        }
+    }
+
+    async fn put_if_equals(
+        &self,
+        key: &str,
+        expected: &[u8],
+        bytes: Vec<u8>,
+    ) -> std::io::Result<bool> {
+        match self.values_mut().get_mut(key) {
+            Some(value) if value.0 == expected => {
+                *value = (bytes, Utc::now());
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn delete_if_equals(&self, key: &str, expected: &[u8]) -> std::io::Result<bool> {
+        match self.values_mut().entry(key.to_string()) {
+            Entry::Occupied(entry) if entry.get().0 == expected => {
+                entry.remove();
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn writer(&self, key: &str) -> std::io::Result<Box<dyn BlobWriter>> {
+        Ok(Box::new(MemoryWriter {
+            backend: self.handle(),
+            key: key.to_string(),
+            bytes: Vec::new(),
+        }))
+    }
+
+    async fn get(&self, key: &str) -> std::io::Result<Vec<u8>> {
+        self.values()
+            .get(key)
+            .map(|(bytes, _)| bytes.clone())
+            .ok_or_else(|| std::io::ErrorKind::NotFound.into())
+    }
+
+    async fn exists(&self, key: &str) -> std::io::Result<bool> {
+        Ok(self.values().contains_key(key))
+    }
+
+    async fn last_modified(&self, key: &str) -> std::io::Result<DateTime<Utc>> {
+        self.values()
+            .get(key)
+            .map(|(_, modified)| *modified)
+            .ok_or_else(|| std::io::ErrorKind::NotFound.into())
+    }
+
+    async fn list(&self, prefix: &str) -> std::io::Result<Vec<String>> {
+        let prefix = format!("{}/", prefix.trim_end_matches('/'));
+        Ok(self
+            .values()
+            .range(prefix.clone()..)
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(&prefix))
+            .cloned()
+            .collect())
+    }
+
+    async fn delete(&self, key: &str) -> std::io::Result<()> {
+        self.values_mut().remove(key);
+        Ok(())
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> std::io::Result<()> {
+        let mut values = self.values_mut();
+        let value = values
+            .remove(from)
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))?;
+        values.insert(to.to_string(), value);
+        Ok(())
+    }
+}
+
+struct MemoryWriter {
+    backend: MemoryBackend,
+    key: String,
+    bytes: Vec<u8>,
+}
+
+#[async_trait::async_trait]
+impl BlobWriter for MemoryWriter {
+    async fn write(&mut self, bytes: Bytes) -> std::io::Result<()> {
+        self.bytes.extend_from_slice(&bytes);
+        Ok(())
+    }
+
+    async fn finish(self: Box<Self>) -> std::io::Result<()> {
+        self.backend.put(&self.key, self.bytes).await
+    }
+
+    async fn abort(self: Box<Self>) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn listing_only_returns_keys_under_the_prefix_directory() {
+        let backend = MemoryBackend::default();
+        for key in ["refs/ab/post-a", "refs/ab/post-b", "refs/abc/post-a"] {
+            backend.put(key, Vec::new()).await.unwrap();
+        }
+
+        assert_eq!(
+            backend.list("refs/ab").await.unwrap(),
+            vec!["refs/ab/post-a", "refs/ab/post-b"]
+        );
+
+        backend.delete("refs/ab/post-a").await.unwrap();
+        assert!(!backend.exists("refs/ab/post-a").await.unwrap());
+        assert_eq!(
+            backend.get("refs/ab/post-a").await.unwrap_err().kind(),
+            std::io::ErrorKind::NotFound
+        );
+    }
+
+    #[tokio::test]
+    async fn conditional_puts_and_transactions() {
+        let backend = MemoryBackend::default();
+        assert!(backend
+            .put_if_absent("locks/a", b"1".to_vec())
+            .await
+            .unwrap());
+        assert!(!backend
+            .put_if_absent("locks/a", b"2".to_vec())
+            .await
+            .unwrap());
+        assert_eq!(backend.get("locks/a").await.unwrap(), b"1");
+        assert!(!backend
+            .put_if_equals("locks/a", b"2", b"3".to_vec())
+            .await
+            .unwrap());
+        assert!(backend
+            .put_if_equals("locks/a", b"1", b"3".to_vec())
+            .await
+            .unwrap());
+        assert!(!backend.delete_if_equals("locks/a", b"1").await.unwrap());
+        assert!(backend.delete_if_equals("locks/a", b"3").await.unwrap());
+        assert!(!backend.exists("locks/a").await.unwrap());
+
+        backend.put("objects/old", b"old".to_vec()).await.unwrap();
+        let mut transaction = backend.begin().await.unwrap();
+        transaction
+            .put("objects/new", b"new".to_vec())
+            .await
+            .unwrap();
+        transaction.delete("objects/old").await.unwrap();
+        assert!(!backend.exists("objects/new").await.unwrap());
+        transaction.rollback().await.unwrap();
+        assert!(backend.exists("objects/old").await.unwrap());
+        assert!(backend.list("incoming").await.unwrap().is_empty());
+
+        let mut transaction = backend.begin().await.unwrap();
+        transaction
+            .put("objects/new", b"new".to_vec())
+            .await
+            .unwrap();
+        transaction.delete("objects/old").await.unwrap();
+        transaction.commit().await.unwrap();
+        assert_eq!(backend.get("objects/new").await.unwrap(), b"new");
+        assert!(!backend.exists("objects/old").await.unwrap());
+    }
+}
@@ -0,0 +1,361 @@
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+use futures::TryStreamExt;
+use sha2::{Digest, Sha256};
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use super::backend::{BlobWriter, StorageBackend};
+use super::KEY_PREFIXES;
+
+/// Moves the blob storage from a `source` backend to a `target` one while it is in use.
+///
+/// Every write and removal goes to both backends, so nothing written during the migration is
+/// missed. Reads go to the source until [`Self::switch`], once every older key was copied with
+/// [`Self::copy_key`], then to the target.
+#[derive(Debug, Clone)]
+pub struct MirroredBackend {
+    /// Identifies the target across runs, such as `s3://<bucket>`.
+    pub target_name: String,
+    source: Arc<dyn StorageBackend>,
+    target: Arc<dyn StorageBackend>,
+    switched: Arc<AtomicBool>,
+}
+
+impl MirroredBackend {
+    pub fn new(
+        target_name: String,
+        source: Arc<dyn StorageBackend>,
+        target: Arc<dyn StorageBackend>,
+    ) -> Self {
+        Self {
+            target_name,
+            source,
+            target,
+            switched: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Reads from the target from now on.
+    pub fn switch(&self) {
+        self.switched.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_switched(&self) -> bool {
+        self.switched.load(Ordering::SeqCst)
+    }
+
+    /// The backend reads go to, and the other one.
+    fn active(&self) -> (&dyn StorageBackend, &dyn StorageBackend) {
+        match self.is_switched() {
+            true => (self.target.as_ref(), self.source.as_ref()),
+            false => (self.source.as_ref(), self.target.as_ref()),
+        }
+    }
+
+    /// Every key of the source.
+    pub async fn source_keys(&self) -> std::io::Result<Vec<String>> {
+        let mut keys = Vec::new();
+        for prefix in KEY_PREFIXES {
+            keys.extend(self.source.list(prefix).await?);
+        }
+        Ok(keys)
+    }
+
+    /// Copies `key` from the source to the target and reads it back, returning the SHA-256 of
+    /// its bytes. Values are streamed as they are stored, encrypted ones stay encrypted.
+    ///
+    /// Returns `None` when the key was removed in the meantime, and fails with
+    /// [`std::io::ErrorKind::InvalidData`] when the copy does not match.
+    pub async fn copy_key(&self, key: &str) -> std::io::Result<Option<String>> {
+        let mut source = match self.source.stream(key).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            other => other?,
+        };
+
+        let mut writer = self.target.writer(key).await?;
+        let mut hasher = Sha256::new();
+        let written = async {
+            while let Some(bytes) = source.try_next().await? {
+                hasher.update(&bytes);
+                writer.write(bytes).await?;
+            }
+            Ok::<_, std::io::Error>(())
+        }
+        .await;
+        if let Err(e) = written {
+            let _ = writer.abort().await;
+            return Err(e);
+        }
+        writer.finish().await?;
+        let sha256 = format!("{:x}", hasher.finalize());
+
+        // INFO: a removal mirrored while the key was copied found nothing to remove on the target
+        if !self.source.exists(key).await? {
+            self.target.delete(key).await?;
+            return Ok(None);
+        }
+
+        if sha256_of(self.target.stream(key).await?).await? != sha256 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("The copy of {key} does not match its source"),
+            ));
+        }
+
+        Ok(Some(sha256))
+    }
+}
+
+async fn sha256_of(
+    mut stream: BoxStream<'static, std::io::Result<Bytes>>,
+) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    while let Some(bytes) = stream.try_next().await? {
+        hasher.update(&bytes);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Not finding the key on the backend that is not read from is fine, it was written before the
+/// migration started and is copied by it.
+fn ignore_not_found(ret: std::io::Result<()>) -> std::io::Result<()> {
+    match ret {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        other => other,
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for MirroredBackend {
+    fn init(&self) -> std::io::Result<()> {
+        self.source.init()?;
+        self.target.init()
+    }
+
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> std::io::Result<()> {
+        self.source.put(key, bytes.clone()).await?;
+        self.target.put(key, bytes).await
+    }
+
+    // INFO: decided by the source, which every instance writes to until the migration is over
+    async fn put_if_absent(&self, key: &str, bytes: Vec<u8>) -> std::io::Result<bool> {
+        if !self.source.put_if_absent(key, bytes.clone()).await? {
+            return Ok(false);
+        }
+        self.target.put(key, bytes).await?;
+        Ok(true)
+    }
+
+    async fn put_if_equals(
+        &self,
+        key: &str,
+        expected: &[u8],
+        bytes: Vec<u8>,
+    ) -> std::io::Result<bool> {
+        if !self
+            .source
+            .put_if_equals(key, expected, bytes.clone())
+            .await?
+        {
+            return Ok(false);
+        }
+        self.target.put(key, bytes).await?;
+        Ok(true)
+    }
+
+    async fn delete_if_equals(&self, key: &str, expected: &[u8]) -> std::io::Result<bool> {
+        if !self.source.delete_if_equals(key, expected).await? {
+            return Ok(false);
+        }
+        self.target.delete(key).await?;
+        Ok(true)
+    }
+
+    async fn writer(&self, key: &str) -> std::io::Result<Box<dyn BlobWriter>> {
+        Ok(Box::new(MirroredWriter {
+            source: self.source.writer(key).await?,
+            target: self.target.writer(key).await?,
+        }))
+    }
+
+    async fn get(&self, key: &str) -> std::io::Result<Vec<u8>> {
+        let (active, other) = self.active();
+        match active.get(key).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => other.get(key).await,
+            other => other,
+        }
+    }
+
+    async fn stream(
+        &self,
+        key: &str,
+    ) -> std::io::Result<BoxStream<'static, std::io::Result<Bytes>>> {
+        let (active, other) = self.active();
+        match active.stream(key).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => other.stream(key).await,
+            other => other,
+        }
+    }
+
+    async fn exists(&self, key: &str) -> std::io::Result<bool> {
+        let (active, other) = self.active();
+        Ok(active.exists(key).await? || other.exists(key).await?)
+    }
+
+    async fn last_modified(&self, key: &str) -> std::io::Result<DateTime<Utc>> {
+        let (active, other) = self.active();
+        match active.last_modified(key).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => other.last_modified(key).await,
+            other => other,
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> std::io::Result<Vec<String>> {
+        self.active().0.list(prefix).await
+    }
+
+    async fn delete(&self, key: &str) -> std::io::Result<()> {
+        self.source.delete(key).await?;
+        self.target.delete(key).await
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> std::io::Result<()> {
+        let (active, other) = self.active();
+        active.rename(from, to).await?;
+        ignore_not_found(other.rename(from, to).await)
+    }
+
+    fn local_path(&self, key: &str) -> Option<PathBuf> {
+        self.active().0.local_path(key)
+    }
+}
+
+struct MirroredWriter {
+    source: Box<dyn BlobWriter>,
+    target: Box<dyn BlobWriter>,
+}
+
+#[async_trait::async_trait]
+impl BlobWriter for MirroredWriter {
+    async fn write(&mut self, bytes: Bytes) -> std::io::Result<()> {
+        self.source.write(bytes.clone()).await?;
+        self.target.write(bytes).await
+    }
+
+    async fn finish(self: Box<Self>) -> std::io::Result<()> {
+        self.source.finish().await?;
+        self.target.finish().await
+    }
+
+    async fn abort(self: Box<Self>) -> std::io::Result<()> {
+        let source = self.source.abort().await;
+        self.target.abort().await.and(source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::blob_storage::MemoryBackend;
+
+    #[tokio::test]
+    async fn writes_reach_both_backends_and_reads_follow_the_switch() {
+        let source = Arc::new(MemoryBackend::default());
+        let target = Arc::new(MemoryBackend::default());
+        let mirrored = MirroredBackend::new("target".to_string(), source.clone(), target.clone());
+
+        source.put("objects/old", b"old".to_vec()).await.unwrap();
+        source.put("staged/a", b"manifest".to_vec()).await.unwrap();
+        mirrored.put("objects/new", b"new".to_vec()).await.unwrap();
+        let mut writer = mirrored.writer("incoming/a").await.unwrap();
+        writer.write(Bytes::from_static(b"streamed")).await.unwrap();
+        writer.finish().await.unwrap();
+        assert_eq!(target.get("incoming/a").await.unwrap(), b"streamed");
+
+        // INFO: the staged manifest was never copied, the target simply misses it
+        mirrored.rename("staged/a", "posts/a").await.unwrap();
+        assert!(source.exists("posts/a").await.unwrap());
+
+        assert_eq!(mirrored.list("objects").await.unwrap().len(), 2);
+        for key in mirrored.source_keys().await.unwrap() {
+            assert!(mirrored.copy_key(&key).await.unwrap().is_some());
+        }
+        assert_eq!(mirrored.copy_key("objects/gone").await.unwrap(), None);
+
+        mirrored.switch();
+        source.delete("objects/old").await.unwrap();
+        assert_eq!(mirrored.get("objects/old").await.unwrap(), b"old");
+        assert_eq!(mirrored.get("posts/a").await.unwrap(), b"manifest");
+
+        mirrored.delete("objects/new").await.unwrap();
+        assert!(!source.exists("objects/new").await.unwrap());
+        assert!(!target.exists("objects/new").await.unwrap());
+    }
+
+    /// Loses every key right after it is read, as if a removal raced the copy.
+    #[derive(Debug, Default)]
+    struct VanishingBackend(MemoryBackend);
+
+    #[async_trait::async_trait]
+    impl StorageBackend for VanishingBackend {
+        fn init(&self) -> std::io::Result<()> {
+            self.0.init()
+        }
+
+        async fn put(&self, key: &str, bytes: Vec<u8>) -> std::io::Result<()> {
+            self.0.put(key, bytes).await
+        }
+
+        async fn put_if_absent(&self, key: &str, bytes: Vec<u8>) -> std::io::Result<bool> {
+            self.0.put_if_absent(key, bytes).await
+        }
+
+        async fn writer(&self, key: &str) -> std::io::Result<Box<dyn BlobWriter>> {
+            self.0.writer(key).await
+        }
+
+        async fn get(&self, key: &str) -> std::io::Result<Vec<u8>> {
+            self.0.get(key).await
+        }
+
+        async fn stream(
+            &self,
+            key: &str,
+        ) -> std::io::Result<BoxStream<'static, std::io::Result<Bytes>>> {
+            let stream = self.0.stream(key).await?;
+            self.0.delete(key).await?;
+            Ok(stream)
+        }
+
+        async fn exists(&self, key: &str) -> std::io::Result<bool> {
+            self.0.exists(key).await
+        }
+
+        async fn last_modified(&self, key: &str) -> std::io::Result<DateTime<Utc>> {
+            self.0.last_modified(key).await
+        }
+
+        async fn list(&self, prefix: &str) -> std::io::Result<Vec<String>> {
+            self.0.list(prefix).await
+        }
+
+        async fn delete(&self, key: &str) -> std::io::Result<()> {
+            self.0.delete(key).await
+        }
+    }
+
+    #[tokio::test]
+    async fn keys_removed_while_copied_are_not_brought_back_on_the_target() {
+        let source = Arc::new(VanishingBackend::default());
+        let target = Arc::new(MemoryBackend::default());
+        let mirrored = MirroredBackend::new("target".to_string(), source.clone(), target.clone());
+        source.put("refs/ab/post-a", Vec::new()).await.unwrap();
+
+        assert_eq!(mirrored.copy_key("refs/ab/post-a").await.unwrap(), None);
+        assert!(!target.exists("refs/ab/post-a").await.unwrap());
+    }
+}
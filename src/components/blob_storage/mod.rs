@@ -0,0 +1,606 @@
+mod backend;
+mod encrypted;
+mod gc;
+mod legacy;
+mod local;
+mod manifest;
+mod memory;
+mod mirrored;
+mod objects;
+mod s3;
+mod scrub;
+mod uploads;
+
+pub use backend::{BackendTransaction, BlobWriter, StorageBackend};
+pub use encrypted::{EncryptedBackend, KeyRotationReport, Keyring};
+pub use gc::{GcOptions, GcReport, OrphanAction, DEFAULT_GRACE_PERIOD_SECS};
+pub use local::LocalBackend;
+pub use manifest::{ManifestEntry, PostManifest};
+pub use memory::MemoryBackend;
+pub use mirrored::MirroredBackend;
+pub use objects::{ObjectStore, ObjectWriter, StoredObject};
+pub use s3::S3Backend;
+pub use scrub::{Damage, DamagedFile, DamagedPost, PostBlob, ScrubReport};
+
+use bytes::Bytes;
+use futures::stream::BoxStream;
+
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use gc::QUARANTINE_PREFIX;
+use objects::{INCOMING_PREFIX, OBJECTS_PREFIX, REFS_PREFIX};
+use uploads::UPLOADS_PREFIX;
+
+use crate::configuration::{BlobStorageSettings, StorageBackendSettings};
+use crate::domain::attachments::sniff_bytes;
+use crate::domain::images::{strip_metadata, PhotoMetadata};
+use crate::telemetry::spawn_blocking_with_tracing;
+
+const POSTS_PREFIX: &str = "posts";
+/// Manifests written ahead of the database commit making them part of a post.
+const STAGED_PREFIX: &str = "staged";
+const MANIFEST_FILE: &str = "manifest.json";
+/// Every key of the storage lives under one of these, but for the short-lived locks of the
+/// [`ObjectStore`].
+const KEY_PREFIXES: [&str; 7] = [
+    POSTS_PREFIX,
+    STAGED_PREFIX,
+    OBJECTS_PREFIX,
+    REFS_PREFIX,
+    INCOMING_PREFIX,
+    UPLOADS_PREFIX,
+    QUARANTINE_PREFIX,
+];
+
+/// A photo whose metadata could not be parsed to strip it, carried inside the
+/// [`std::io::ErrorKind::InvalidData`] error of [`PostStorageDriver::post_save_attachment`].
+#[derive(thiserror::Error, Debug)]
+#[error("Failed to strip the metadata of the photo: {0:?}")]
+pub struct UnstrippablePhoto(anyhow::Error);
+
+impl UnstrippablePhoto {
+    /// Whether `error` rejects a photo rather than failing to store it.
+    pub fn is(error: &std::io::Error) -> bool {
+        error.get_ref().is_some_and(|inner| inner.is::<Self>())
+    }
+}
+
+fn manifest_key(blob: &str) -> String {
+    format!("{POSTS_PREFIX}/{blob}/{MANIFEST_FILE}")
+}
+
+fn staged_manifest_key(blob: &str) -> String {
+    format!("{STAGED_PREFIX}/{blob}/{MANIFEST_FILE}")
+}
+
+/// Writes the files of one post blob into the [`ObjectStore`] and records them in the blob
+/// manifest. Unless [`Self::confirm_saved`] is called, everything saved is rolled back on drop.
+pub struct PostStorageDriver {
+    blob: String,
+    backend: Arc<dyn StorageBackend>,
+    objects: ObjectStore,
+    manifest: PostManifest,
+    try_saving: bool,
+    confirm: bool,
+    strip_metadata: bool,
+}
+
+impl PostStorageDriver {
+    pub fn new(blob: &str, backend: Arc<dyn StorageBackend>) -> Self {
+        Self {
+            blob: blob.to_string(),
+            objects: ObjectStore::new(backend.clone()),
+            backend,
+            manifest: PostManifest::default(),
+            try_saving: false,
+            confirm: false,
+            strip_metadata: true,
+        }
+    }
+
+    /// Whether photos lose their EXIF, XMP and similar metadata when saved, on by default.
+    pub fn set_metadata_stripping(&mut self, strip_metadata: bool) {
+        self.strip_metadata = strip_metadata;
+    }
+
+    /// The files saved so far.
+    pub fn manifest(&self) -> &PostManifest {
+        &self.manifest
+    }
+
+    /// Commits the saved files by writing the manifest, which makes them part of the post blob.
+    pub async fn confirm_saved(&mut self) -> std::io::Result<()> {
+        self.backend
+            .put(&manifest_key(&self.blob), self.manifest.to_vec()?)
+            .await?;
+
+        self.confirm = true;
+        Ok(())
+    }
+
+    /// Like [`Self::confirm_saved`], but the manifest is only staged until
+    /// [`BlobStorage::publish_staged`]. Rolling back is then up to the caller, with
+    /// [`BlobStorage::discard_blob`].
+    pub async fn stage_saved(&mut self) -> std::io::Result<()> {
+        self.backend
+            .put(&staged_manifest_key(&self.blob), self.manifest.to_vec()?)
+            .await?;
+
+        self.confirm = true;
+        Ok(())
+    }
+
+    pub async fn post_save_content(
+        &mut self,
+        file_name: impl AsRef<Path>,
+        content: &str,
+    ) -> std::io::Result<()> {
+        self.try_saving = true;
+        let object = self
+            .objects
+            .put_bytes(content.as_bytes().to_vec(), &self.blob)
+            .await?;
+        self.manifest.content = file_name.as_ref().to_path_buf();
+        self.manifest_entry(file_name).object = object;
+        Ok(())
+    }
+
+    /// Saves an attachment held in memory under `relative_path`, which is expected to come from
+    /// [`sanitize_relative_path`]. Identical files are only stored once across all posts.
+    ///
+    /// JPEG, PNG and WebP photos are stripped of their metadata unless disabled with
+    /// [`Self::set_metadata_stripping`], what they revealed is returned to be kept privately. A
+    /// photo that cannot be stripped is not stored, failing with an [`UnstrippablePhoto`].
+    pub async fn post_save_attachment(
+        &mut self,
+        relative_path: impl AsRef<Path>,
+        bytes: Vec<u8>,
+    ) -> std::io::Result<Option<PhotoMetadata>> {
+        self.try_saving = true;
+        let mime = sniff_bytes(&bytes);
+        let is_photo = matches!(mime, "image/jpeg" | "image/png" | "image/webp");
+
+        if !(self.strip_metadata && is_photo) {
+            let object = self.objects.put_bytes(bytes, &self.blob).await?;
+            let entry = self.manifest_entry(relative_path);
+            entry.object = object;
+            entry.mime = Some(mime.to_string());
+            return Ok(None);
+        }
+
+        let (stripped, metadata) = spawn_blocking_with_tracing(move || strip_metadata(bytes))
+            .await
+            .map_err(std::io::Error::other)?
+            .map_err(|e| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, UnstrippablePhoto(e))
+            })?;
+        let object = self.objects.put_bytes(stripped, &self.blob).await?;
+        let entry = self.manifest_entry(relative_path);
+        entry.object = object;
+        entry.mime = Some(mime.to_string());
+
+        Ok(Some(metadata).filter(|m| !m.is_empty()))
+    }
+
+    /// Starts streaming an attachment too large to be held in memory, saved with
+    /// [`Self::post_save_written`]. It is stored as it is, photos are not stripped.
+    pub async fn post_attachment_writer(&self) -> std::io::Result<ObjectWriter> {
+        self.objects.writer().await
+    }
+
+    /// Saves a streamed attachment, of the type `mime` sniffed from its first bytes.
+    pub async fn post_save_written(
+        &mut self,
+        relative_path: impl AsRef<Path>,
+        writer: ObjectWriter,
+        mime: &str,
+    ) -> std::io::Result<()> {
+        self.try_saving = true;
+        let object = writer.finish(&self.blob).await?;
+        let entry = self.manifest_entry(relative_path);
+        entry.object = object;
+        entry.mime = Some(mime.to_string());
+        Ok(())
+    }
+
+    /// Moves the saved files under `dir` to the root of the blob, such as once the directory of
+    /// the markdown content is known. Files elsewhere keep their path.
+    pub fn post_relocate(&mut self, dir: &Path) {
+        let files = std::mem::take(&mut self.manifest.files);
+        self.manifest.files = files
+            .into_iter()
+            .map(|(path, entry)| (relative_to(path, dir), entry))
+            .collect();
+    }
+
+    /// Saves a generated copy of the attachment `relative_path`, such as a resized image.
+    pub async fn post_save_variant(
+        &mut self,
+        relative_path: impl AsRef<Path>,
+        variant_name: &str,
+        bytes: Vec<u8>,
+    ) -> std::io::Result<()> {
+        self.try_saving = true;
+        let object = self.objects.put_bytes(bytes, &self.blob).await?;
+        self.manifest_entry(relative_path)
+            .variants
+            .insert(variant_name.to_string(), object);
+        Ok(())
+    }
+
+    fn manifest_entry(&mut self, relative_path: impl AsRef<Path>) -> &mut ManifestEntry {
+        self.manifest
+            .files
+            .entry(relative_path.as_ref().to_path_buf())
+            .or_default()
+    }
+
+    /// Removes the post blob, dropping its references to stored files. Files still referenced by
+    /// other posts are kept.
+    pub async fn post_clear_all(&self) -> std::io::Result<()> {
+        let key = manifest_key(&self.blob);
+        let manifest = match self.backend.get(&key).await {
+            Ok(bytes) => PostManifest::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => self.manifest.clone(),
+            Err(e) => return Err(e),
+        };
+
+        release_objects(&self.objects, &self.blob, &manifest).await?;
+        self.backend.delete(&key).await
+    }
+}
+
+async fn release_objects(
+    objects: &ObjectStore,
+    blob: &str,
+    manifest: &PostManifest,
+) -> std::io::Result<()> {
+    for object in manifest.objects() {
+        objects.release(&object.sha256, blob).await?;
+    }
+    Ok(())
+}
+
+impl Drop for PostStorageDriver {
+    fn drop(&mut self) {
+        if !self.try_saving || self.confirm {
+            return;
+        }
+
+        let objects = self.objects.clone();
+        let blob = std::mem::take(&mut self.blob);
+        let manifest = std::mem::take(&mut self.manifest);
+        let rollback = async move {
+            if let Err(e) = release_objects(&objects, &blob, &manifest).await {
+                tracing::error!("Failed to roll back blob {}: {:?}", blob, e);
+            }
+        };
+
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(rollback);
+            }
+            Err(e) => tracing::error!("Failed to roll back blob without a runtime: {e:?}"),
+        }
+    }
+}
+
+/// `path` relative to `dir` when it lies below it, `path` unchanged otherwise.
+pub fn relative_to(path: PathBuf, dir: &Path) -> PathBuf {
+    path.strip_prefix(dir)
+        .map(Path::to_path_buf)
+        .unwrap_or(path)
+}
+
+/// Turns a client supplied file name such as `images/cover.png` into a relative path that is safe
+/// to join onto a post blob directory.
+///
+/// Both `/` and `\` are treated as separators, `.` segments are dropped, and `None` is returned
+/// for anything that could escape the blob: `..`, absolute paths, drive prefixes or empty names.
+pub fn sanitize_relative_path(file_name: &str) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+
+    for segment in file_name.split(['/', '\\']) {
+        if segment.is_empty() || segment == "." {
+            continue;
+        }
+
+        if segment.contains('\0') || segment.contains(':') {
+            return None;
+        }
+
+        match Path::new(segment).components().next() {
+            Some(Component::Normal(_)) => path.push(segment),
+            _ => return None,
+        }
+    }
+
+    if file_name.starts_with(['/', '\\']) || path.as_os_str().is_empty() {
+        return None;
+    }
+
+    Some(path)
+}
+
+/// Cloning is cheap, clones share the same backend.
+#[derive(Clone)]
+pub struct BlobStorage {
+    backend: Arc<dyn StorageBackend>,
+    /// The same backend when it encrypts, to rotate its key.
+    encrypted: Option<EncryptedBackend>,
+    /// The backend below encryption when it mirrors writes into another one.
+    migration: Option<MirroredBackend>,
+}
+
+impl TryFrom<&BlobStorageSettings> for BlobStorage {
+    type Error = std::io::Error;
+
+    fn try_from(settings: &BlobStorageSettings) -> Result<Self, Self::Error> {
+        if settings.ephemeral {
+            return Ok(Self::new(Arc::new(MemoryBackend::default())));
+        }
+
+        let (name, mut backend) = open_backend(&settings.backend, Some(&settings.base_dir))?;
+        let mut migration = None;
+        if let Some(target) = &settings.migrate_to {
+            let (target_name, target_backend) =
+                open_backend(&target.backend, target.base_dir.as_deref())?;
+            if target_name == name {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("The storage is already on {name}"),
+                ));
+            }
+
+            let mirrored = MirroredBackend::new(target_name, backend, target_backend);
+            backend = Arc::new(mirrored.clone());
+            migration = Some(mirrored);
+        }
+
+        let mut storage = match &settings.encryption {
+            Some(encryption) => Self::encrypted(backend, Keyring::try_from(encryption)?),
+            None => Self::new(backend),
+        };
+        storage.migration = migration;
+        Ok(storage)
+    }
+}
+
+/// Opens a configured backend, along with a name telling it apart from other backends.
+fn open_backend(
+    settings: &StorageBackendSettings,
+    base_dir: Option<&Path>,
+) -> std::io::Result<(String, Arc<dyn StorageBackend>)> {
+    match settings {
+        StorageBackendSettings::Local => {
+            let base_dir = base_dir.ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "A local backend needs a `base_dir`",
+                )
+            })?;
+            Ok((
+                format!("local:{}", base_dir.display()),
+                Arc::new(LocalBackend::new(base_dir.to_path_buf())),
+            ))
+        }
+        StorageBackendSettings::S3(s3) => Ok((
+            format!("s3://{}", s3.bucket),
+            Arc::new(S3Backend::try_from(s3)?),
+        )),
+    }
+}
+
+impl BlobStorage {
+    pub fn new(backend: Arc<dyn StorageBackend>) -> Self {
+        Self {
+            backend,
+            encrypted: None,
+            migration: None,
+        }
+    }
+
+    /// Encrypts everything stored in `backend` with keys from `keyring`.
+    pub fn encrypted(backend: Arc<dyn StorageBackend>, keyring: Keyring) -> Self {
+        let encrypted = EncryptedBackend::new(backend, keyring);
+        Self {
+            backend: Arc::new(encrypted.clone()),
+            encrypted: Some(encrypted),
+            migration: None,
+        }
+    }
+
+    /// Mirrors every write into `target`, see [`MirroredBackend`].
+    pub fn migrating(
+        source: Arc<dyn StorageBackend>,
+        target_name: &str,
+        target: Arc<dyn StorageBackend>,
+    ) -> Self {
+        let mirrored = MirroredBackend::new(target_name.to_string(), source, target);
+        Self {
+            backend: Arc::new(mirrored.clone()),
+            encrypted: None,
+            migration: Some(mirrored),
+        }
+    }
+
+    /// The move to another backend in progress, when one is configured.
+    pub fn migration(&self) -> Option<&MirroredBackend> {
+        self.migration.as_ref()
+    }
+
+    /// Re-encrypts every stored value that is not encrypted with the current key.
+    pub async fn rotate_encryption_key(&self) -> std::io::Result<KeyRotationReport> {
+        let encrypted = self.encrypted.as_ref().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Encryption at rest is not configured",
+            )
+        })?;
+        encrypted.rotate(&KEY_PREFIXES).await
+    }
+
+    pub fn try_init_blob_storage(&self) -> std::io::Result<()> {
+        self.backend.init()
+    }
+
+    pub fn post_storage_driver(&self, blob: &str) -> PostStorageDriver {
+        PostStorageDriver::new(blob, self.backend.clone())
+    }
+
+    /// Reads the manifest listing the files of a post blob.
+    ///
+    /// A blob is only referred to by a post once its database transaction committed, so a manifest
+    /// that is still staged at that point is read as well.
+    pub async fn post_manifest(&self, blob: &str) -> std::io::Result<PostManifest> {
+        let bytes = match self.backend.get(&manifest_key(blob)).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                self.backend.get(&staged_manifest_key(blob)).await?
+            }
+            other => other?,
+        };
+        PostManifest::from_slice(&bytes)
+    }
+
+    /// Moves the staged manifest of a blob into place, doing nothing if it is already there.
+    pub async fn publish_staged(&self, blob: &str) -> std::io::Result<()> {
+        match self
+            .backend
+            .rename(&staged_manifest_key(blob), &manifest_key(blob))
+            .await
+        {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                self.backend.get(&manifest_key(blob)).await.map(|_| ())
+            }
+            other => other,
+        }
+    }
+
+    /// Removes a blob whether its manifest is staged, published or lost, dropping every
+    /// reference it holds.
+    pub async fn discard_blob(&self, blob: &str) -> std::io::Result<()> {
+        let objects = ObjectStore::new(self.backend.clone());
+        match self.post_manifest(blob).await {
+            Ok(manifest) => release_objects(&objects, blob, &manifest).await?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                objects.release_owner(blob).await?
+            }
+            Err(e) => return Err(e),
+        }
+
+        self.backend.delete(&staged_manifest_key(blob)).await?;
+        self.backend.delete(&manifest_key(blob)).await
+    }
+
+    pub async fn read_object(&self, object: &StoredObject) -> std::io::Result<Vec<u8>> {
+        ObjectStore::new(self.backend.clone()).read(object).await
+    }
+
+    /// Reads a stored file piece by piece, for files too large to be held in memory.
+    pub async fn object_stream(
+        &self,
+        object: &StoredObject,
+    ) -> std::io::Result<BoxStream<'static, std::io::Result<Bytes>>> {
+        ObjectStore::new(self.backend.clone()).stream(object).await
+    }
+
+    /// Where the bytes of a stored file live when the backend keeps them on the local disk.
+    pub fn local_object_path(&self, object: &StoredObject) -> Option<PathBuf> {
+        self.backend
+            .local_path(&ObjectStore::object_key(&object.sha256))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_keeps_nested_relative_paths() {
+        assert_eq!(
+            sanitize_relative_path("images/trip/cover.png"),
+            Some(PathBuf::from("images/trip/cover.png"))
+        );
+        assert_eq!(
+            sanitize_relative_path("./files\\notes.pdf"),
+            Some(PathBuf::from("files/notes.pdf"))
+        );
+    }
+
+    #[test]
+    fn sanitize_rejects_paths_escaping_the_blob() {
+        assert_eq!(sanitize_relative_path("../secret.md"), None);
+        assert_eq!(sanitize_relative_path("images/../../secret.md"), None);
+        assert_eq!(sanitize_relative_path("/etc/passwd"), None);
+        assert_eq!(sanitize_relative_path("\\windows\\system.ini"), None);
+        assert_eq!(sanitize_relative_path("C:\\boot.ini"), None);
+        assert_eq!(sanitize_relative_path("./"), None);
+        assert_eq!(sanitize_relative_path(""), None);
+    }
+
+    fn local_storage(dir: &Path) -> BlobStorage {
+        let storage = BlobStorage::new(Arc::new(LocalBackend::new(dir.to_path_buf())));
+        storage.try_init_blob_storage().unwrap();
+        storage
+    }
+
+    #[tokio::test]
+    async fn posts_share_identical_files_until_the_last_one_is_cleared() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = local_storage(dir.path());
+
+        let mut objects = Vec::new();
+        for blob in ["first", "second"] {
+            let mut driver = storage.post_storage_driver(blob);
+            driver
+                .post_save_content("post.md", "same words")
+                .await
+                .unwrap();
+            driver.confirm_saved().await.unwrap();
+            let manifest = storage.post_manifest(blob).await.unwrap();
+            objects.push(manifest.content_entry().unwrap().object.clone());
+        }
+
+        assert_eq!(objects[0], objects[1]);
+        let object_path = storage.local_object_path(&objects[0]).unwrap();
+
+        storage
+            .post_storage_driver("first")
+            .post_clear_all()
+            .await
+            .unwrap();
+        assert!(object_path.is_file());
+        assert!(storage.post_manifest("first").await.is_err());
+
+        storage
+            .post_storage_driver("second")
+            .post_clear_all()
+            .await
+            .unwrap();
+        assert!(!object_path.exists());
+    }
+
+    #[tokio::test]
+    async fn unconfirmed_saves_are_rolled_back_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = local_storage(dir.path());
+
+        let mut driver = storage.post_storage_driver("abandoned");
+        driver.post_save_content("post.md", "draft").await.unwrap();
+        let object = driver.manifest.content_entry().unwrap().object.clone();
+        let object_path = storage.local_object_path(&object).unwrap();
+        assert!(object_path.is_file());
+
+        drop(driver);
+        for _ in 0..100 {
+            if !object_path.exists() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        assert!(!object_path.exists());
+        assert!(storage.post_manifest("abandoned").await.is_err());
+    }
+}
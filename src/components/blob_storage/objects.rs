@@ -0,0 +1,497 @@
+use bytes::Bytes;
+use chrono::{DateTime, TimeDelta, Utc};
+use futures::stream::BoxStream;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::backend::{BlobWriter, StorageBackend};
+
+pub(super) const OBJECTS_PREFIX: &str = "objects";
+pub(super) const REFS_PREFIX: &str = "refs";
+/// Objects being streamed in, whose digest is only known once they are complete.
+pub(super) const INCOMING_PREFIX: &str = "incoming";
+
+/// Locks serializing taking and dropping the references of an object, see
+/// [`ObjectStore::with_refs_locked`].
+pub(super) const LOCKS_PREFIX: &str = "locks";
+/// How long a lock is held at most, past it the instance holding it is assumed to be gone.
+const LOCK_LEASE: TimeDelta = TimeDelta::seconds(30);
+const MAX_LOCK_BACKOFF: Duration = Duration::from_millis(200);
+
+/// A file stored in the [`ObjectStore`].
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct StoredObject {
+    pub sha256: String,
+    pub size: u64,
+}
+
+/// Files stored once under the SHA-256 of their bytes, shared by every post blob referencing them.
+///
+/// Each reference is an empty marker `refs/<sha256>/<owner>`, the object is removed along with its
+/// last marker.
+#[derive(Debug, Clone)]
+pub struct ObjectStore {
+    backend: Arc<dyn StorageBackend>,
+}
+
+impl ObjectStore {
+    pub fn new(backend: Arc<dyn StorageBackend>) -> Self {
+        Self { backend }
+    }
+
+    pub fn object_key(sha256: &str) -> String {
+        let fan_out = sha256.get(..2).unwrap_or(sha256);
+        format!("{OBJECTS_PREFIX}/{fan_out}/{sha256}")
+    }
+
+    pub fn ref_key(sha256: &str, owner: &str) -> String {
+        format!("{REFS_PREFIX}/{sha256}/{owner}")
+    }
+
+    /// Stores `bytes` and records that `owner` references them.
+    pub async fn put_bytes(&self, bytes: Vec<u8>, owner: &str) -> std::io::Result<StoredObject> {
+        let object = StoredObject {
+            sha256: format!("{:x}", Sha256::digest(&bytes)),
+            size: bytes.len() as u64,
+        };
+
+        if !self.retain(&object, owner).await? {
+            let stored = self
+                .backend
+                .put(&Self::object_key(&object.sha256), bytes)
+                .await;
+            self.release_on_error(stored, &object, owner).await?;
+        }
+
+        Ok(object)
+    }
+
+    /// Starts streaming an object in, hashing it on the way. See [`ObjectWriter`].
+    pub async fn writer(&self) -> std::io::Result<ObjectWriter> {
+        let key = format!("{INCOMING_PREFIX}/{}", Uuid::new_v4());
+        Ok(ObjectWriter {
+            objects: self.clone(),
+            inner: self.backend.writer(&key).await?,
+            key,
+            hasher: Sha256::new(),
+            size: 0,
+        })
+    }
+
+    /// Adds the reference of `owner` and tells whether the object is already stored. Once the
+    /// reference exists the object can no longer be removed, so it is safe to store it afterwards.
+    async fn retain(&self, object: &StoredObject, owner: &str) -> std::io::Result<bool> {
+        self.with_refs_locked(&object.sha256, async {
+            self.backend
+                .put(&Self::ref_key(&object.sha256, owner), Vec::new())
+                .await?;
+            self.backend.exists(&Self::object_key(&object.sha256)).await
+        })
+        .await
+    }
+
+    /// Runs `body` while no other task, on any instance sharing the backend, takes or drops a
+    /// reference to the object `sha256`, so it is never removed while a post is about to
+    /// reference it.
+    ///
+    /// The lock is a key created with [`StorageBackend::put_if_absent`] holding the end of its
+    /// lease and a token unique to its holder. A lease held past its end is taken over with
+    /// [`StorageBackend::put_if_equals`], and the lock is only released while it still holds the
+    /// token, so neither removes the lease of another holder.
+    async fn with_refs_locked<T>(
+        &self,
+        sha256: &str,
+        body: impl Future<Output = std::io::Result<T>>,
+    ) -> std::io::Result<T> {
+        let key = format!("{LOCKS_PREFIX}/{sha256}");
+        let token = Uuid::new_v4();
+        let mut backoff = Duration::from_millis(5);
+        let lease = loop {
+            let lease = format!("{} {token}", (Utc::now() + LOCK_LEASE).to_rfc3339()).into_bytes();
+            if self.backend.put_if_absent(&key, lease.clone()).await? {
+                break lease;
+            }
+            if let Some(expired) = self.expired_lease(&key).await? {
+                if self
+                    .backend
+                    .put_if_equals(&key, &expired, lease.clone())
+                    .await?
+                {
+                    tracing::warn!(key, "Broke a lock held past its lease");
+                    break lease;
+                }
+                continue;
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_LOCK_BACKOFF);
+        };
+
+        let ret = body.await;
+        let unlocked = self.backend.delete_if_equals(&key, &lease).await;
+        if let Ok(false) = unlocked {
+            tracing::warn!(key, "The lock was broken while held past its lease");
+        }
+        ret.and_then(|value| unlocked.map(|_| value))
+    }
+
+    /// The lease held by the lock `key` when it is over, to be taken over as it is.
+    async fn expired_lease(&self, key: &str) -> std::io::Result<Option<Vec<u8>>> {
+        let lease = match self.backend.get(key).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            other => other?,
+        };
+        let lease_end = std::str::from_utf8(&lease)
+            .ok()
+            .and_then(|lease| lease.split(' ').next())
+            .and_then(|lease_end| DateTime::parse_from_rfc3339(lease_end).ok());
+        Ok(lease_end
+            .is_none_or(|lease_end| lease_end < Utc::now())
+            .then_some(lease))
+    }
+
+    async fn release_on_error(
+        &self,
+        stored: std::io::Result<()>,
+        object: &StoredObject,
+        owner: &str,
+    ) -> std::io::Result<()> {
+        if stored.is_err() {
+            let _ = self.release(&object.sha256, owner).await;
+        }
+        stored
+    }
+
+    /// Drops the reference `owner` holds on an object, removing the object once nothing
+    /// references it anymore. Releasing a reference that does not exist is not an error.
+    pub async fn release(&self, sha256: &str, owner: &str) -> std::io::Result<()> {
+        self.with_refs_locked(sha256, async {
+            self.backend.delete(&Self::ref_key(sha256, owner)).await?;
+            if self.ref_count(sha256).await? == 0 {
+                self.backend.delete(&Self::object_key(sha256)).await?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Drops every reference `owner` holds, for when its manifest is lost, such as after a crash.
+    pub async fn release_owner(&self, owner: &str) -> std::io::Result<()> {
+        for (sha256, held_by) in self.references().await? {
+            if held_by == owner {
+                self.release(&sha256, owner).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops the reference `owner` holds on an object without removing the object.
+    pub async fn forget(&self, sha256: &str, owner: &str) -> std::io::Result<()> {
+        self.with_refs_locked(sha256, self.backend.delete(&Self::ref_key(sha256, owner)))
+            .await
+    }
+
+    /// Removes an object once nothing references it, copying it to `quarantine_key` first when
+    /// given. Returns `false` and keeps the object if it is referenced again.
+    pub async fn evict_unreferenced(
+        &self,
+        sha256: &str,
+        quarantine_key: Option<&str>,
+    ) -> std::io::Result<bool> {
+        self.with_refs_locked(sha256, async {
+            if self.ref_count(sha256).await? > 0 {
+                return Ok(false);
+            }
+
+            let key = Self::object_key(sha256);
+            let mut transaction = self.backend.begin().await?;
+            if let Some(quarantine_key) = quarantine_key {
+                let bytes = self.backend.get(&key).await?;
+                transaction.put(quarantine_key, bytes).await?;
+            }
+            transaction.delete(&key).await?;
+            transaction.commit().await?;
+
+            Ok(true)
+        })
+        .await
+    }
+
+    /// Every reference as `(sha256, owner)`.
+    pub async fn references(&self) -> std::io::Result<Vec<(String, String)>> {
+        let keys = self.backend.list(REFS_PREFIX).await?;
+        Ok(keys
+            .iter()
+            .filter_map(|key| {
+                key.strip_prefix(REFS_PREFIX)?
+                    .trim_start_matches('/')
+                    .split_once('/')
+            })
+            .map(|(sha256, owner)| (sha256.to_string(), owner.to_string()))
+            .collect())
+    }
+
+    /// Digest of every stored object.
+    pub async fn digests(&self) -> std::io::Result<Vec<String>> {
+        let keys = self.backend.list(OBJECTS_PREFIX).await?;
+        Ok(keys
+            .iter()
+            .filter_map(|key| key.rsplit('/').next())
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Number of post blobs referencing an object.
+    pub async fn ref_count(&self, sha256: &str) -> std::io::Result<usize> {
+        let refs = self
+            .backend
+            .list(&format!("{REFS_PREFIX}/{sha256}"))
+            .await?;
+        Ok(refs.len())
+    }
+
+    pub async fn read(&self, object: &StoredObject) -> std::io::Result<Vec<u8>> {
+        self.backend.get(&Self::object_key(&object.sha256)).await
+    }
+
+    pub async fn stream(
+        &self,
+        object: &StoredObject,
+    ) -> std::io::Result<BoxStream<'static, std::io::Result<Bytes>>> {
+        self.backend.stream(&Self::object_key(&object.sha256)).await
+    }
+}
+
+/// An object written piece by piece under a temporary key, moved to its content address once
+/// complete. Dropping it without finishing leaves the temporary key to the garbage collector.
+pub struct ObjectWriter {
+    objects: ObjectStore,
+    inner: Box<dyn BlobWriter>,
+    key: String,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl ObjectWriter {
+    pub async fn write(&mut self, bytes: Bytes) -> std::io::Result<()> {
+        self.hasher.update(&bytes);
+        self.size += bytes.len() as u64;
+        self.inner.write(bytes).await
+    }
+
+    /// Bytes written so far.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Stores the written bytes and records that `owner` references them. Bytes already stored
+    /// for another owner are simply dropped.
+    pub async fn finish(self, owner: &str) -> std::io::Result<StoredObject> {
+        let Self {
+            objects,
+            inner,
+            key,
+            hasher,
+            size,
+        } = self;
+        let object = StoredObject {
+            sha256: format!("{:x}", hasher.finalize()),
+            size,
+        };
+        inner.finish().await?;
+
+        if objects.retain(&object, owner).await? {
+            objects.backend.delete(&key).await?;
+            return Ok(object);
+        }
+
+        let stored = objects
+            .backend
+            .rename(&key, &ObjectStore::object_key(&object.sha256))
+            .await;
+        if stored.is_err() {
+            let _ = objects.backend.delete(&key).await;
+        }
+        objects.release_on_error(stored, &object, owner).await?;
+
+        Ok(object)
+    }
+
+    pub async fn abort(self) -> std::io::Result<()> {
+        self.inner.abort().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::blob_storage::{LocalBackend, MemoryBackend};
+
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn store(dir: &Path) -> ObjectStore {
+        let backend = LocalBackend::new(dir.to_path_buf());
+        backend.init().unwrap();
+        ObjectStore::new(Arc::new(backend))
+    }
+
+    #[tokio::test]
+    async fn identical_files_are_stored_once_and_removed_with_their_last_reference() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path());
+        let key = |object: &StoredObject| dir.path().join(ObjectStore::object_key(&object.sha256));
+
+        let first = store
+            .put_bytes(b"header image".to_vec(), "post-a")
+            .await
+            .unwrap();
+        let second = store
+            .put_bytes(b"header image".to_vec(), "post-b")
+            .await
+            .unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(first.size, 12);
+        assert_eq!(store.ref_count(&first.sha256).await.unwrap(), 2);
+
+        store.release(&first.sha256, "post-a").await.unwrap();
+        assert!(key(&first).is_file());
+
+        store.release(&first.sha256, "post-b").await.unwrap();
+        assert!(!key(&first).exists());
+        assert_eq!(store.ref_count(&first.sha256).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn releasing_an_unknown_reference_is_a_no_op() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path());
+
+        let object = store.put_bytes(b"notes".to_vec(), "post-a").await.unwrap();
+        store.release(&object.sha256, "post-b").await.unwrap();
+
+        assert_eq!(store.read(&object).await.unwrap(), b"notes");
+    }
+
+    #[tokio::test]
+    async fn streamed_objects_are_stored_under_their_digest() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path());
+        let stored = store
+            .put_bytes(b"streamed bytes".to_vec(), "post-a")
+            .await
+            .unwrap();
+
+        let mut writer = store.writer().await.unwrap();
+        writer
+            .write(Bytes::from_static(b"streamed "))
+            .await
+            .unwrap();
+        writer.write(Bytes::from_static(b"bytes")).await.unwrap();
+        assert_eq!(writer.size(), 14);
+        let streamed = writer.finish("post-b").await.unwrap();
+
+        assert_eq!(streamed, stored);
+        assert_eq!(store.ref_count(&streamed.sha256).await.unwrap(), 2);
+        assert!(store
+            .backend
+            .list(INCOMING_PREFIX)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn references_wait_for_a_lock_held_elsewhere_and_break_expired_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path());
+        let other_instance = store.clone();
+        let sha256 = format!("{:x}", Sha256::digest(b"shared"));
+        let lock = format!("{LOCKS_PREFIX}/{sha256}");
+
+        let lease_end = (Utc::now() + LOCK_LEASE).to_rfc3339();
+        store
+            .backend
+            .put(&lock, lease_end.into_bytes())
+            .await
+            .unwrap();
+        let put = tokio::spawn(async move {
+            other_instance
+                .put_bytes(b"shared".to_vec(), "post-a")
+                .await
+                .unwrap()
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!put.is_finished());
+        assert_eq!(store.ref_count(&sha256).await.unwrap(), 0);
+
+        store.backend.delete(&lock).await.unwrap();
+        put.await.unwrap();
+        assert_eq!(store.ref_count(&sha256).await.unwrap(), 1);
+
+        let lease_end = (Utc::now() - LOCK_LEASE).to_rfc3339();
+        store
+            .backend
+            .put(&lock, lease_end.into_bytes())
+            .await
+            .unwrap();
+        store.release(&sha256, "post-a").await.unwrap();
+        assert_eq!(store.ref_count(&sha256).await.unwrap(), 0);
+        assert!(!store.backend.exists(&lock).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn an_expired_lock_is_taken_over_by_a_single_waiter() {
+        let store = ObjectStore::new(Arc::new(MemoryBackend::default()));
+        let lock = format!("{LOCKS_PREFIX}/shared");
+        let lease_end = (Utc::now() - LOCK_LEASE).to_rfc3339();
+        store
+            .backend
+            .put(&lock, lease_end.into_bytes())
+            .await
+            .unwrap();
+
+        let holders = AtomicUsize::new(0);
+        let hold = || async {
+            assert_eq!(holders.fetch_add(1, Ordering::SeqCst), 0);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            holders.fetch_sub(1, Ordering::SeqCst);
+            Ok(())
+        };
+        let (a, b) = tokio::join!(
+            store.with_refs_locked("shared", hold()),
+            store.with_refs_locked("shared", hold())
+        );
+        a.unwrap();
+        b.unwrap();
+        assert!(!store.backend.exists(&lock).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_lock_broken_while_held_is_left_to_its_new_holder() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path());
+        let lock = format!("{LOCKS_PREFIX}/shared");
+        let other_lease = format!("{} other", (Utc::now() + LOCK_LEASE).to_rfc3339());
+
+        // INFO: another instance takes the lock over, as if this one held it past its lease
+        store
+            .with_refs_locked("shared", async {
+                store
+                    .backend
+                    .put(&lock, other_lease.clone().into_bytes())
+                    .await
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            store.backend.get(&lock).await.unwrap(),
+            other_lease.into_bytes()
+        );
+    }
+}
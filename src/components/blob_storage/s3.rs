@@ -0,0 +1,267 @@
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use futures::stream::{BoxStream, StreamExt};
+use futures::TryStreamExt;
+use object_store::aws::{AmazonS3, AmazonS3Builder};
+use object_store::path::Path as ObjectPath;
+use object_store::{MultipartUpload, ObjectStore as _, PutMode, PutPayload, UpdateVersion};
+use secrecy::ExposeSecret;
+
+use super::backend::{BlobWriter, StorageBackend};
+use crate::configuration::S3Settings;
+
+/// Size of the parts of a multipart upload, S3 requires at least 5 MiB but for the last one.
+const PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// Stores every key as an object of an S3 compatible bucket.
+#[derive(Debug, Clone)]
+pub struct S3Backend {
+    bucket: AmazonS3,
+}
+
+impl TryFrom<&S3Settings> for S3Backend {
+    type Error = std::io::Error;
+
+    fn try_from(settings: &S3Settings) -> Result<Self, Self::Error> {
+        let mut builder = AmazonS3Builder::new()
+            .with_bucket_name(&settings.bucket)
+            .with_region(&settings.region)
+            .with_access_key_id(&settings.access_key_id)
+            .with_secret_access_key(settings.secret_access_key.expose_secret())
+            .with_allow_http(settings.allow_http);
+
+        if let Some(endpoint) = &settings.endpoint {
+            // INFO: stand-ins such as MinIO are reached by path rather than by subdomain
+            builder = builder
+                .with_endpoint(endpoint)
+                .with_virtual_hosted_style_request(false);
+        }
+
+        Ok(Self {
+            bucket: builder.build().map_err(into_io_error)?,
+        })
+    }
+}
+
+fn into_io_error(e: object_store::Error) -> std::io::Error {
+    match e {
+        object_store::Error::NotFound { .. } => {
+            std::io::Error::new(std::io::ErrorKind::NotFound, e)
+        }
+        e => std::io::Error::other(e),
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for S3Backend {
+    fn init(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> std::io::Result<()> {
+        self.bucket
+            .put(&ObjectPath::from(key), PutPayload::from(bytes))
+            .await
+            .map_err(into_io_error)?;
+        Ok(())
+    }
+
+    async fn put_if_absent(&self, key: &str, bytes: Vec<u8>) -> std::io::Result<bool> {
+        // INFO: sent with `If-None-Match: *`, which S3 and most stand-ins support
+        let put = self
+            .bucket
+            .put_opts(
+                &ObjectPath::from(key),
+                PutPayload::from(bytes),
+                PutMode::Create.into(),
+            )
+            .await;
+        match put {
+            Ok(_) => Ok(true),
+            Err(object_store::Error::AlreadyExists { .. }) => Ok(false),
+            Err(e) => Err(into_io_error(e)),
+        }
+    }
+
+    async fn put_if_equals(
+        &self,
+        key: &str,
+        expected: &[u8],
+        bytes: Vec<u8>,
+    ) -> std::io::Result<bool> {
+        let path = ObjectPath::from(key);
+        let current = match self.bucket.get(&path).await {
+            Ok(current) => current,
+            Err(object_store::Error::NotFound { .. }) => return Ok(false),
+            Err(e) => return Err(into_io_error(e)),
+        };
+        let version = UpdateVersion {
+            e_tag: current.meta.e_tag.clone(),
+            version: current.meta.version.clone(),
+        };
+        if current.bytes().await.map_err(into_io_error)? != expected {
+            return Ok(false);
+        }
+
+        // INFO: sent with `If-Match`, so the value read is the one replaced
+        let put = self
+            .bucket
+            .put_opts(
+                &path,
+                PutPayload::from(bytes),
+                PutMode::Update(version).into(),
+            )
+            .await;
+        match put {
+            Ok(_) => Ok(true),
+            Err(object_store::Error::Precondition { .. }) => Ok(false),
+            Err(e) => Err(into_io_error(e)),
+        }
+    }
+
+    async fn writer(&self, key: &str) -> std::io::Result<Box<dyn BlobWriter>> {
+        Ok(Box::new(S3Writer {
+            backend: self.clone(),
+            key: key.to_string(),
+            part: BytesMut::new(),
+            upload: None,
+        }))
+    }
+
+    async fn get(&self, key: &str) -> std::io::Result<Vec<u8>> {
+        let bytes = self
+            .bucket
+            .get(&ObjectPath::from(key))
+            .await
+            .map_err(into_io_error)?
+            .bytes()
+            .await
+            .map_err(into_io_error)?;
+        Ok(bytes.to_vec())
+    }
+
+    async fn stream(
+        &self,
+        key: &str,
+    ) -> std::io::Result<BoxStream<'static, std::io::Result<Bytes>>> {
+        let stream = self
+            .bucket
+            .get(&ObjectPath::from(key))
+            .await
+            .map_err(into_io_error)?
+            .into_stream();
+        Ok(stream.map_err(into_io_error).boxed())
+    }
+
+    async fn exists(&self, key: &str) -> std::io::Result<bool> {
+        match self.bucket.head(&ObjectPath::from(key)).await {
+            Ok(_) => Ok(true),
+            Err(object_store::Error::NotFound { .. }) => Ok(false),
+            Err(e) => Err(into_io_error(e)),
+        }
+    }
+
+    async fn last_modified(&self, key: &str) -> std::io::Result<DateTime<Utc>> {
+        let meta = self
+            .bucket
+            .head(&ObjectPath::from(key))
+            .await
+            .map_err(into_io_error)?;
+        Ok(meta.last_modified)
+    }
+
+    async fn list(&self, prefix: &str) -> std::io::Result<Vec<String>> {
+        let mut keys = self
+            .bucket
+            .list(Some(&ObjectPath::from(prefix)))
+            .map_ok(|meta| meta.location.to_string())
+            .try_collect::<Vec<_>>()
+            .await
+            .map_err(into_io_error)?;
+        keys.sort();
+        Ok(keys)
+    }
+
+    async fn delete(&self, key: &str) -> std::io::Result<()> {
+        match self.bucket.delete(&ObjectPath::from(key)).await {
+            Ok(()) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+            Err(e) => Err(into_io_error(e)),
+        }
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> std::io::Result<()> {
+        self.bucket
+            .rename(&ObjectPath::from(from), &ObjectPath::from(to))
+            .await
+            .map_err(into_io_error)
+    }
+}
+
+/// Holds one part in memory at a time. A value smaller than a part is written with a single put,
+/// anything larger with a multipart upload.
+struct S3Writer {
+    backend: S3Backend,
+    key: String,
+    part: BytesMut,
+    upload: Option<Box<dyn MultipartUpload>>,
+}
+
+impl S3Writer {
+    async fn upload_part(&mut self) -> std::io::Result<()> {
+        let upload = match &mut self.upload {
+            Some(upload) => upload,
+            None => self.upload.insert(
+                self.backend
+                    .bucket
+                    .put_multipart(&ObjectPath::from(self.key.as_str()))
+                    .await
+                    .map_err(into_io_error)?,
+            ),
+        };
+
+        let part = self.part.split().freeze();
+        upload
+            .put_part(PutPayload::from(part))
+            .await
+            .map_err(into_io_error)
+    }
+}
+
+#[async_trait::async_trait]
+impl BlobWriter for S3Writer {
+    async fn write(&mut self, bytes: Bytes) -> std::io::Result<()> {
+        self.part.extend_from_slice(&bytes);
+        if self.part.len() >= PART_SIZE {
+            self.upload_part().await?;
+        }
+        Ok(())
+    }
+
+    async fn finish(mut self: Box<Self>) -> std::io::Result<()> {
+        if self.upload.is_none() {
+            let part = self.part.split().freeze();
+            return self
+                .backend
+                .bucket
+                .put(&ObjectPath::from(self.key.as_str()), PutPayload::from(part))
+                .await
+                .map(|_| ())
+                .map_err(into_io_error);
+        }
+
+        if !self.part.is_empty() {
+            self.upload_part().await?;
+        }
+        if let Some(upload) = &mut self.upload {
+            upload.complete().await.map_err(into_io_error)?;
+        }
+        Ok(())
+    }
+
+    async fn abort(mut self: Box<Self>) -> std::io::Result<()> {
+        match &mut self.upload {
+            Some(upload) => upload.abort().await.map_err(into_io_error),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::{BlobStorage, ObjectStore, PostManifest, StoredObject};
+
+/// A post and the blob holding its files.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PostBlob {
+    pub id: Uuid,
+    pub slug: String,
+    pub blob: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Damage {
+    Missing,
+    /// The bytes no longer match the checksum recorded when the file was written.
+    Corrupted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DamagedFile {
+    pub path: String,
+    /// Set when the damage is in a generated copy, such as a resized image.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    pub sha256: String,
+    pub damage: Damage,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DamagedPost {
+    #[serde(flatten)]
+    pub post: PostBlob,
+    /// The manifest listing the files of the post is itself missing or unreadable.
+    pub manifest: Option<Damage>,
+    pub files: Vec<DamagedFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ScrubReport {
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub posts_checked: usize,
+    pub objects_checked: usize,
+    pub bytes_checked: u64,
+    /// Only the posts with something wrong.
+    pub damaged_posts: Vec<DamagedPost>,
+}
+
+impl BlobStorage {
+    /// Re-hashes every file of `posts` against the checksum recorded in their manifest. Files
+    /// shared by several posts are only read once.
+    pub async fn scrub(&self, posts: Vec<PostBlob>) -> std::io::Result<ScrubReport> {
+        let objects = ObjectStore::new(self.backend.clone());
+        let started_at = Utc::now();
+        let mut checked = HashMap::new();
+        let mut bytes_checked = 0;
+        let mut damaged_posts = Vec::new();
+        let posts_checked = posts.len();
+
+        for post in posts {
+            let manifest = match self.post_manifest(&post.blob).await {
+                Ok(manifest) => manifest,
+                Err(e) => {
+                    let damage = match e.kind() {
+                        std::io::ErrorKind::NotFound => Damage::Missing,
+                        std::io::ErrorKind::InvalidData => Damage::Corrupted,
+                        _ => return Err(e),
+                    };
+                    damaged_posts.push(DamagedPost {
+                        post,
+                        manifest: Some(damage),
+                        files: Vec::new(),
+                    });
+                    continue;
+                }
+            };
+
+            let mut files = Vec::new();
+            for (path, variant, object) in manifest_files(&manifest) {
+                if !checked.contains_key(&object.sha256) {
+                    let damage = check_object(&objects, object).await?;
+                    if damage.is_none() {
+                        bytes_checked += object.size;
+                    }
+                    checked.insert(object.sha256.clone(), damage);
+                }
+
+                if let Some(damage) = checked[&object.sha256] {
+                    files.push(DamagedFile {
+                        path: path.to_string_lossy().into_owned(),
+                        variant: variant.map(str::to_string),
+                        sha256: object.sha256.clone(),
+                        damage,
+                    });
+                }
+            }
+
+            if !files.is_empty() {
+                damaged_posts.push(DamagedPost {
+                    post,
+                    manifest: None,
+                    files,
+                });
+            }
+        }
+
+        Ok(ScrubReport {
+            started_at,
+            finished_at: Utc::now(),
+            posts_checked,
+            objects_checked: checked.len(),
+            bytes_checked,
+            damaged_posts,
+        })
+    }
+}
+
+/// Every file of a manifest as `(path, variant, object)`.
+fn manifest_files(
+    manifest: &PostManifest,
+) -> impl Iterator<Item = (&Path, Option<&str>, &StoredObject)> {
+    manifest.files.iter().flat_map(|(path, entry)| {
+        std::iter::once((path.as_path(), None, &entry.object)).chain(
+            entry
+                .variants
+                .iter()
+                .map(move |(name, object)| (path.as_path(), Some(name.as_str()), object)),
+        )
+    })
+}
+
+async fn check_object(
+    objects: &ObjectStore,
+    object: &StoredObject,
+) -> std::io::Result<Option<Damage>> {
+    let bytes = match objects.read(object).await {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Some(Damage::Missing)),
+        // INFO: encrypted objects fail to decrypt once altered
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+            return Ok(Some(Damage::Corrupted))
+        }
+        Err(e) => return Err(e),
+    };
+
+    let intact = bytes.len() as u64 == object.size
+        && format!("{:x}", Sha256::digest(&bytes)) == object.sha256;
+    Ok((!intact).then_some(Damage::Corrupted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::blob_storage::MemoryBackend;
+
+    use std::sync::Arc;
+
+    fn post(blob: &str) -> PostBlob {
+        PostBlob {
+            id: Uuid::new_v4(),
+            slug: blob.to_string(),
+            blob: blob.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn scrub_reports_missing_and_corrupted_files_per_post() {
+        let storage = BlobStorage::new(Arc::new(MemoryBackend::default()));
+        for blob in ["intact", "damaged"] {
+            let mut driver = storage.post_storage_driver(blob);
+            driver
+                .post_save_content("post.md", &format!("about {blob}"))
+                .await
+                .unwrap();
+            driver
+                .post_save_variant("post.md", "w480", format!("{blob} variant").into_bytes())
+                .await
+                .unwrap();
+            driver.confirm_saved().await.unwrap();
+        }
+
+        let manifest = storage.post_manifest("damaged").await.unwrap();
+        let entry = manifest.content_entry().unwrap();
+        storage
+            .backend
+            .put(
+                &ObjectStore::object_key(&entry.object.sha256),
+                b"flipped bits".to_vec(),
+            )
+            .await
+            .unwrap();
+        storage
+            .backend
+            .delete(&ObjectStore::object_key(&entry.variants["w480"].sha256))
+            .await
+            .unwrap();
+
+        let report = storage
+            .scrub(vec![post("intact"), post("damaged"), post("gone")])
+            .await
+            .unwrap();
+
+        assert_eq!(report.posts_checked, 3);
+        assert_eq!(report.objects_checked, 4);
+        assert_eq!(report.damaged_posts.len(), 2);
+
+        let damaged = &report.damaged_posts[0];
+        assert_eq!(damaged.post.blob, "damaged");
+        let damages: Vec<_> = damaged
+            .files
+            .iter()
+            .map(|file| (file.variant.as_deref(), file.damage))
+            .collect();
+        assert_eq!(
+            damages,
+            vec![(None, Damage::Corrupted), (Some("w480"), Damage::Missing)]
+        );
+
+        let gone = &report.damaged_posts[1];
+        assert_eq!(gone.manifest, Some(Damage::Missing));
+    }
+}
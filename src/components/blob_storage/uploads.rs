@@ -0,0 +1,140 @@
+use bytes::Bytes;
+use futures::stream::{self, BoxStream, StreamExt};
+use uuid::Uuid;
+
+use super::BlobStorage;
+
+pub(super) const UPLOADS_PREFIX: &str = "uploads";
+
+fn upload_prefix(id: &Uuid) -> String {
+    format!("{UPLOADS_PREFIX}/{id}")
+}
+
+/// Zero padded so that chunks list in the order of their offset.
+fn chunk_key(id: &Uuid, offset: u64) -> String {
+    format!("{}/{offset:020}", upload_prefix(id))
+}
+
+fn chunk_offset(key: &str) -> Option<u64> {
+    key.rsplit('/').next()?.parse().ok()
+}
+
+/// Resumable uploads are kept as chunks named after their offset, so an interrupted upload
+/// resumes by appending chunks whatever the backend.
+impl BlobStorage {
+    pub async fn put_upload_chunk(
+        &self,
+        id: &Uuid,
+        offset: u64,
+        bytes: Vec<u8>,
+    ) -> std::io::Result<()> {
+        self.backend.put(&chunk_key(id, offset), bytes).await
+    }
+
+    /// Drops the chunks from `offset` on, left behind by a write that was never acknowledged.
+    pub async fn truncate_upload(&self, id: &Uuid, offset: u64) -> std::io::Result<()> {
+        for key in self.backend.list(&upload_prefix(id)).await? {
+            if chunk_offset(&key).is_some_and(|start| start >= offset) {
+                self.backend.delete(&key).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the first `length` bytes of an upload, one chunk at a time.
+    pub fn upload_stream(
+        &self,
+        id: &Uuid,
+        length: u64,
+    ) -> BoxStream<'static, std::io::Result<Bytes>> {
+        let backend = self.backend.clone();
+        let (id, prefix) = (*id, upload_prefix(id));
+
+        stream::try_unfold((None, 0), move |(keys, written)| {
+            let (backend, prefix) = (backend.clone(), prefix.clone());
+            async move {
+                if written == length {
+                    return Ok(None);
+                }
+                let mut keys: std::vec::IntoIter<String> = match keys {
+                    Some(keys) => keys,
+                    None => backend.list(&prefix).await?.into_iter(),
+                };
+
+                // INFO: a chunk starting elsewhere is a leftover of an unacknowledged write
+                let Some(key) = keys.find(|key| chunk_offset(key) == Some(written)) else {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        format!("Upload {id} holds {written} of its {length} bytes"),
+                    ));
+                };
+
+                let mut bytes = backend.get(&key).await?;
+                bytes.truncate((length - written) as usize);
+                let read = bytes.len() as u64;
+                Ok(Some((Bytes::from(bytes), (Some(keys), written + read))))
+            }
+        })
+        .boxed()
+    }
+
+    pub async fn discard_upload(&self, id: &Uuid) -> std::io::Result<()> {
+        for key in self.backend.list(&upload_prefix(id)).await? {
+            self.backend.delete(&key).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::blob_storage::MemoryBackend;
+
+    use futures::TryStreamExt;
+
+    use std::sync::Arc;
+
+    async fn read_upload(
+        storage: &BlobStorage,
+        id: &Uuid,
+        length: u64,
+    ) -> std::io::Result<Vec<u8>> {
+        let chunks: Vec<Bytes> = storage.upload_stream(id, length).try_collect().await?;
+        Ok(chunks.concat())
+    }
+
+    #[tokio::test]
+    async fn chunks_are_joined_in_order_skipping_unacknowledged_leftovers() {
+        let storage = BlobStorage::new(Arc::new(MemoryBackend::default()));
+        let id = Uuid::new_v4();
+
+        storage
+            .put_upload_chunk(&id, 0, b"resum".to_vec())
+            .await
+            .unwrap();
+        storage
+            .put_upload_chunk(&id, 5, b"XXXXXXXXXX".to_vec())
+            .await
+            .unwrap();
+        storage
+            .put_upload_chunk(&id, 12, b"ZZ".to_vec())
+            .await
+            .unwrap();
+
+        storage.truncate_upload(&id, 5).await.unwrap();
+        storage
+            .put_upload_chunk(&id, 5, b"able".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(read_upload(&storage, &id, 9).await.unwrap(), b"resumable");
+
+        assert_eq!(
+            read_upload(&storage, &id, 12).await.unwrap_err().kind(),
+            std::io::ErrorKind::UnexpectedEof
+        );
+
+        storage.discard_upload(&id).await.unwrap();
+        assert!(read_upload(&storage, &id, 1).await.is_err());
+    }
+}
@@ -0,0 +1,331 @@
+//! Single-use links emailed to confirm an address, for accounts and newsletter subscriptions
+//! alike. Only the SHA-256 of a token is stored, see [`new_token`].
+
+use actix_web::{http, ResponseError};
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use secrecy::ExposeSecret;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::components::email_delivery::EmailClient;
+use crate::configuration::ConfirmationSettings;
+use crate::domain::credentials::{hash_token, new_token};
+use crate::domain::users::UserEmail;
+use crate::routes::users::{CONFIRMED, PENDING_CONFIRMATION};
+use crate::startup::engine::WebBaseUrl;
+
+/// What an emailed link confirms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfirmationKind {
+    /// A user account, see [`crate::routes::users`].
+    User,
+    /// A newsletter subscription, see [`crate::routes::subscriptions`].
+    Subscription,
+}
+
+impl ConfirmationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::User => "user",
+            Self::Subscription => "subscription",
+        }
+    }
+
+    /// The subject, HTML and plain text of the email carrying `link`.
+    fn email(&self, link: &str) -> (&'static str, String, String) {
+        match self {
+            Self::User => (
+                "Confirm your account",
+                format!(r#"Welcome! Click <a href="{link}">here</a> to confirm your account."#),
+                format!("Welcome! Visit {link} to confirm your account."),
+            ),
+            Self::Subscription => (
+                "Confirm your subscription",
+                format!(
+                    r#"Thanks for subscribing! Click <a href="{link}">here</a> to confirm your subscription."#
+                ),
+                format!("Thanks for subscribing! Visit {link} to confirm your subscription."),
+            ),
+        }
+    }
+
+    fn link_path(&self) -> &'static str {
+        match self {
+            Self::User => "/users/confirm",
+            Self::Subscription => "/subscriptions/confirm",
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfirmationError {
+    #[error("The confirmation link is invalid")]
+    InvalidError,
+    #[error("The confirmation link expired")]
+    ExpiredError,
+    #[error("A confirmation email can be requested every {0} seconds")]
+    TooManyRequestsError(u64),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for ConfirmationError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            Self::UnexpectedError(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+            Self::InvalidError | Self::ExpiredError => http::StatusCode::UNAUTHORIZED,
+            Self::TooManyRequestsError(_) => http::StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+}
+
+/// Replaces the confirmation tokens of `subject_id` with a new one and emails its link to
+/// `email`. Called within the transaction that created the account, subscription or token, so a
+/// failed email leaves nothing behind.
+pub async fn send_confirmation(
+    connection: &mut PgConnection,
+    email_client: &EmailClient,
+    base_url: &WebBaseUrl,
+    kind: ConfirmationKind,
+    subject_id: Uuid,
+    email: &UserEmail,
+) -> anyhow::Result<()> {
+    let (token, token_hash) = new_token()?;
+
+    sqlx::query!(
+        "DELETE FROM confirmation_tokens WHERE kind = $1 AND subject_id = $2",
+        kind.as_str(),
+        subject_id
+    )
+    .execute(&mut *connection)
+    .await
+    .context("Failed to remove the previous confirmation tokens")?;
+
+    sqlx::query!(
+        "INSERT INTO confirmation_tokens (token_hash, kind, subject_id) VALUES ($1, $2, $3)",
+        token_hash,
+        kind.as_str(),
+        subject_id
+    )
+    .execute(&mut *connection)
+    .await
+    .context("Failed to store the confirmation token")?;
+
+    let link = base_url.api_url(&format!(
+        "{}?token={}",
+        kind.link_path(),
+        token.expose_secret()
+    ));
+    let (subject, html, text) = kind.email(&link);
+    email_client
+        .send_email(email, subject, &html, &text)
+        .await
+        .context("Failed to send the confirmation email")?;
+
+    Ok(())
+}
+
+/// Sends a new confirmation link to the pending account or subscription of `email`, at most once
+/// per `settings.resend_cooldown_secs`. Unknown and confirmed addresses get no email, and are not
+/// told apart from pending ones.
+pub async fn resend_confirmation(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &WebBaseUrl,
+    settings: &ConfirmationSettings,
+    kind: ConfirmationKind,
+    email: &UserEmail,
+) -> Result<(), ConfirmationError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    // INFO: locking the account or subscription keeps concurrent requests from both passing the
+    // cooldown
+    let pending = match kind {
+        ConfirmationKind::User => {
+            sqlx::query_scalar!(
+                "SELECT id FROM users WHERE email = $1 AND status = $2 FOR UPDATE",
+                email.as_ref(),
+                PENDING_CONFIRMATION,
+            )
+            .fetch_optional(&mut *transaction)
+            .await
+        }
+        ConfirmationKind::Subscription => {
+            sqlx::query_scalar!(
+                "SELECT id FROM subscriptions WHERE email = $1 AND status = $2 FOR UPDATE",
+                email.as_ref(),
+                PENDING_CONFIRMATION,
+            )
+            .fetch_optional(&mut *transaction)
+            .await
+        }
+    }
+    .context("Failed to fetch the pending confirmation")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let Some(subject_id) = pending else {
+        return Ok(());
+    };
+
+    let last_sent = sqlx::query_scalar!(
+        "SELECT max(created_at) FROM confirmation_tokens WHERE kind = $1 AND subject_id = $2",
+        kind.as_str(),
+        subject_id
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to fetch the last confirmation email")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let cooldown = Duration::seconds(settings.resend_cooldown_secs as i64);
+    if last_sent.is_some_and(|last_sent| last_sent + cooldown > Utc::now()) {
+        return Err(ConfirmationError::TooManyRequestsError(
+            settings.resend_cooldown_secs,
+        ));
+    }
+
+    send_confirmation(
+        &mut transaction,
+        email_client,
+        base_url,
+        kind,
+        subject_id,
+        email,
+    )
+    .await
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+    Ok(())
+}
+
+/// Confirms the account or subscription `token` was emailed for, returning its id. A token is
+/// used up whether it is still valid or not.
+pub async fn confirm(
+    pool: &PgPool,
+    settings: &ConfirmationSettings,
+    kind: ConfirmationKind,
+    token: &str,
+) -> Result<Uuid, ConfirmationError> {
+    let token_hash = hash_token(token);
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let token = sqlx::query!(
+        r#"
+        DELETE FROM confirmation_tokens WHERE token_hash = $1 AND kind = $2
+        RETURNING subject_id, created_at
+        "#,
+        token_hash,
+        kind.as_str()
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to fetch the confirmation token")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let expiry = Duration::seconds(settings.confirmation_expiry_secs as i64);
+    let confirmed = match &token {
+        Some(token) if token.created_at + expiry > Utc::now() => {
+            match kind {
+                ConfirmationKind::User => {
+                    sqlx::query!(
+                        "UPDATE users SET status = $1 WHERE id = $2",
+                        CONFIRMED,
+                        token.subject_id
+                    )
+                    .execute(&mut *transaction)
+                    .await
+                }
+                ConfirmationKind::Subscription => {
+                    sqlx::query!(
+                        "UPDATE subscriptions SET status = $1 WHERE id = $2",
+                        CONFIRMED,
+                        token.subject_id
+                    )
+                    .execute(&mut *transaction)
+                    .await
+                }
+            }
+            .context("Failed to confirm")
+            .inspect_err(|e| tracing::error!("{e:?}"))?
+            .rows_affected()
+                > 0
+        }
+        _ => false,
+    };
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    match token {
+        Some(token) if confirmed => Ok(token.subject_id),
+        // INFO: the account or subscription may have been removed since
+        Some(token) if token.created_at + expiry <= Utc::now() => {
+            Err(ConfirmationError::ExpiredError)
+        }
+        _ => Err(ConfirmationError::InvalidError),
+    }
+}
+
+/// Removes the accounts or subscriptions left pending for longer than
+/// `settings.pending_expiry_secs`, along with their confirmation tokens.
+pub async fn purge_pending(
+    pool: &PgPool,
+    settings: &ConfirmationSettings,
+    kind: ConfirmationKind,
+) -> anyhow::Result<()> {
+    let cutoff = Utc::now() - Duration::seconds(settings.pending_expiry_secs as i64);
+    let purged = match kind {
+        ConfirmationKind::User => {
+            sqlx::query!(
+                "DELETE FROM users WHERE status = $1 AND created_at < $2",
+                PENDING_CONFIRMATION,
+                cutoff
+            )
+            .execute(pool)
+            .await
+        }
+        ConfirmationKind::Subscription => {
+            sqlx::query!(
+                "DELETE FROM subscriptions WHERE status = $1 AND subscribed_at < $2",
+                PENDING_CONFIRMATION,
+                cutoff
+            )
+            .execute(pool)
+            .await
+        }
+    }?
+    .rows_affected();
+
+    // INFO: tokens no longer cascade with what they confirm, the ones as old as a purged
+    // account or subscription are of no use either
+    sqlx::query!(
+        "DELETE FROM confirmation_tokens WHERE kind = $1 AND created_at < $2",
+        kind.as_str(),
+        cutoff
+    )
+    .execute(pool)
+    .await?;
+
+    if purged > 0 {
+        tracing::info!("Purged {purged} unconfirmed {}s", kind.as_str());
+    }
+    Ok(())
+}
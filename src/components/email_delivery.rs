@@ -1,5 +1,6 @@
 use base64::prelude::*;
-use lettre::message::{header, Message, MultiPart, SinglePart};
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::{header, Message, MessageBuilder, MultiPart, SinglePart};
 use reqwest::Client;
 use secrecy::{ExposeSecret, SecretBox};
 use serde_json::json;
@@ -133,23 +134,59 @@ impl EmailClient {
         html_content: &str,
         text_content: &str,
     ) -> Result<(), EmailClientError> {
-        let message = Message::builder()
+        let message = self.message_to(recipient, subject);
+        self.send(message, html_content, text_content).await
+    }
+
+    /// Sends an email of a mailing list, which mail clients can unsubscribe from in one click
+    /// by posting to `unsubscribe_link`, following [RFC 8058](https://www.rfc-editor.org/rfc/rfc8058).
+    pub async fn send_list_email(
+        &self,
+        recipient: &UserEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        unsubscribe_link: &str,
+    ) -> Result<(), EmailClientError> {
+        let message = self
+            .message_to(recipient, subject)
+            .raw_header(HeaderValue::new(
+                HeaderName::new_from_ascii_str("List-Unsubscribe"),
+                format!("<{unsubscribe_link}>"),
+            ))
+            .raw_header(HeaderValue::new(
+                HeaderName::new_from_ascii_str("List-Unsubscribe-Post"),
+                "List-Unsubscribe=One-Click".to_string(),
+            ));
+        self.send(message, html_content, text_content).await
+    }
+
+    fn message_to(&self, recipient: &UserEmail, subject: &str) -> MessageBuilder {
+        Message::builder()
             .from(self.sender.as_ref().parse().unwrap())
             .to(recipient.as_ref().parse().unwrap())
             .subject(subject)
-            .multipart(
-                MultiPart::alternative() // This is composed of two parts.
-                    .singlepart(
-                        SinglePart::builder()
-                            .header(header::ContentType::TEXT_PLAIN)
-                            .body(String::from(text_content)), // Every message should have a plain text fallback.
-                    )
-                    .singlepart(
-                        SinglePart::builder()
-                            .header(header::ContentType::TEXT_HTML)
-                            .body(String::from(html_content)),
-                    ),
-            )?;
+    }
+
+    async fn send(
+        &self,
+        message: MessageBuilder,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), EmailClientError> {
+        let message = message.multipart(
+            MultiPart::alternative() // This is composed of two parts.
+                .singlepart(
+                    SinglePart::builder()
+                        .header(header::ContentType::TEXT_PLAIN)
+                        .body(String::from(text_content)), // Every message should have a plain text fallback.
+                )
+                .singlepart(
+                    SinglePart::builder()
+                        .header(header::ContentType::TEXT_HTML)
+                        .body(String::from(html_content)),
+                ),
+        )?;
 
         let raw_message = BASE64_STANDARD.encode(message.formatted());
         let email_body = json!({
@@ -0,0 +1,150 @@
+use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, PgExecutor};
+
+use crate::components::audit::RequestOrigin;
+use crate::configuration::UserSettings;
+use crate::domain::login_throttle::{Penalty, ThrottleKind};
+
+/// An account or address that has to wait before logging in again.
+#[derive(Debug, serde::Serialize)]
+pub struct LoginLock {
+    pub kind: String,
+    pub key: String,
+    pub failures: i32,
+    pub last_failure_at: DateTime<Utc>,
+    pub blocked_until: DateTime<Utc>,
+    /// Locked out, rather than backing off.
+    pub locked: bool,
+}
+
+/// What failed logins of `username` from `origin` are counted against.
+fn throttle_keys<'a>(username: &'a str, origin: &'a RequestOrigin) -> Vec<(ThrottleKind, &'a str)> {
+    let mut keys = vec![(ThrottleKind::Account, username)];
+    // INFO: without a peer address, as behind some test servers, only the account is counted
+    if let Some(ip) = &origin.ip {
+        keys.push((ThrottleKind::Ip, ip.as_str()));
+    }
+    keys
+}
+
+/// Until when a login of `username` from `origin` has to wait, if it has to.
+///
+/// The throttles are locked until the end of the transaction of `connection`, so concurrent
+/// logins of the same account or address are checked, tried and counted one after the other.
+pub async fn login_blocked_until(
+    connection: &mut PgConnection,
+    username: &str,
+    origin: &RequestOrigin,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    let mut blocked_until: Option<DateTime<Utc>> = None;
+    // INFO: always locked in the same order, the account first, so logins cannot deadlock
+    for (kind, key) in throttle_keys(username, origin) {
+        let until = sqlx::query_scalar!(
+            r#"
+            INSERT INTO login_throttles (kind, key, failures, last_failure_at)
+            VALUES ($1, $2, 0, now())
+            ON CONFLICT (kind, key) DO UPDATE SET kind = EXCLUDED.kind
+            RETURNING blocked_until
+            "#,
+            kind.as_str(),
+            key,
+        )
+        .fetch_one(&mut *connection)
+        .await?;
+
+        blocked_until = blocked_until.max(until.filter(|until| *until > Utc::now()));
+    }
+    Ok(blocked_until)
+}
+
+/// Counts a failed login of `username` from `origin`, answering with what it costs the account.
+/// Failures are forgotten once none came for as long as a lockout lasts.
+pub async fn record_failed_login(
+    connection: &mut PgConnection,
+    settings: &UserSettings,
+    username: &str,
+    origin: &RequestOrigin,
+) -> Result<Penalty, sqlx::Error> {
+    let mut account_penalty = Penalty::None;
+    for (kind, key) in throttle_keys(username, origin) {
+        let policy = settings.throttle_policy(kind);
+        let now = Utc::now();
+
+        let failures = sqlx::query_scalar!(
+            r#"
+            INSERT INTO login_throttles (kind, key, failures, last_failure_at)
+            VALUES ($1, $2, 1, $3)
+            ON CONFLICT (kind, key) DO UPDATE SET
+                failures = CASE
+                    WHEN login_throttles.last_failure_at < $4 THEN 1
+                    ELSE login_throttles.failures + 1
+                END,
+                last_failure_at = $3
+            RETURNING failures
+            "#,
+            kind.as_str(),
+            key,
+            now,
+            now - policy.lockout,
+        )
+        .fetch_one(&mut *connection)
+        .await?;
+
+        let penalty = policy.penalty(failures as u32);
+        let (blocked_until, locked) = match penalty {
+            Penalty::None => (None, false),
+            Penalty::Backoff(wait) => (Some(now + wait), false),
+            Penalty::Lockout(wait) => (Some(now + wait), true),
+        };
+        sqlx::query!(
+            r#"
+            UPDATE login_throttles SET blocked_until = $1, locked = $2
+            WHERE kind = $3 AND key = $4
+            "#,
+            blocked_until,
+            locked,
+            kind.as_str(),
+            key,
+        )
+        .execute(&mut *connection)
+        .await?;
+
+        if kind == ThrottleKind::Account {
+            account_penalty = penalty;
+        }
+    }
+    Ok(account_penalty)
+}
+
+/// Forgets the failed logins of `key`, answering whether there were any.
+pub async fn clear_login_failures(
+    executor: impl PgExecutor<'_>,
+    kind: ThrottleKind,
+    key: &str,
+) -> Result<bool, sqlx::Error> {
+    let cleared = sqlx::query!(
+        "DELETE FROM login_throttles WHERE kind = $1 AND key = $2",
+        kind.as_str(),
+        key
+    )
+    .execute(executor)
+    .await?;
+    Ok(cleared.rows_affected() > 0)
+}
+
+/// The accounts and addresses currently waiting, the longest wait first.
+pub async fn list_login_locks(
+    executor: impl PgExecutor<'_>,
+) -> Result<Vec<LoginLock>, sqlx::Error> {
+    sqlx::query_as!(
+        LoginLock,
+        r#"
+        SELECT kind, key, failures, last_failure_at, blocked_until AS "blocked_until!", locked
+        FROM login_throttles
+        WHERE blocked_until > now()
+        ORDER BY blocked_until DESC
+        "#
+    )
+    .fetch_all(executor)
+    .await
+}
@@ -1,2 +1,12 @@
+pub mod audit;
+pub mod blob_migration;
 pub mod blob_storage;
+pub mod confirmations;
 pub mod email_delivery;
+pub mod login_throttle;
+pub mod newsletter;
+pub mod reviews;
+pub mod secrets;
+pub mod sessions;
+pub mod staged_writes;
+pub mod storage_usage;
@@ -0,0 +1,74 @@
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::components::email_delivery::EmailClient;
+use crate::domain::html::escape_html;
+use crate::domain::users::UserEmail;
+use crate::routes::users::CONFIRMED;
+use crate::startup::engine::WebBaseUrl;
+
+/// What one newsletter delivery did.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct NewsletterReport {
+    pub sent: usize,
+    pub failed: usize,
+}
+
+/// Emails every confirmed subscriber about the post published at `slug`. A subscriber failing
+/// to receive it does not stop the others.
+#[tracing::instrument(name = "Notify subscribers", skip(pool, email_client, base_url))]
+pub async fn notify_subscribers(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &WebBaseUrl,
+    title: &str,
+    slug: &str,
+) -> anyhow::Result<NewsletterReport> {
+    let subscribers = sqlx::query!(
+        "SELECT email, name, unsubscribe_token FROM subscriptions WHERE status = $1",
+        CONFIRMED
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the confirmed subscribers")?;
+
+    let post_link = base_url.api_url(&format!("/posts/slug/{slug}"));
+    let mut report = NewsletterReport::default();
+    for subscriber in subscribers {
+        let unsubscribe_link = base_url.api_url(&format!(
+            "/subscriptions/unsubscribe?token={}",
+            subscriber.unsubscribe_token
+        ));
+        let html = format!(
+            r#"<p>Hi {name}, <a href="{post_link}">{title}</a> was just published.</p>
+<p><a href="{unsubscribe_link}">Unsubscribe</a></p>"#,
+            name = escape_html(&subscriber.name),
+            post_link = escape_html(&post_link),
+            title = escape_html(title),
+            unsubscribe_link = escape_html(&unsubscribe_link),
+        );
+        let text = format!(
+            "Hi {name}, {title} was just published: {post_link}\n\nUnsubscribe: {unsubscribe_link}",
+            name = subscriber.name,
+        );
+
+        // INFO: addresses were validated when subscribing
+        let sent = match UserEmail::try_from(subscriber.email) {
+            Ok(email) => email_client
+                .send_list_email(&email, title, &html, &text, &unsubscribe_link)
+                .await
+                .context("Failed to send the newsletter"),
+            Err(e) => Err(anyhow::anyhow!(e)),
+        };
+        match sent {
+            Ok(()) => report.sent += 1,
+            Err(e) => {
+                tracing::warn!("{e:?}");
+                report.failed += 1;
+            }
+        }
+    }
+
+    tracing::info!(?report, "Newsletter delivered");
+    Ok(report)
+}
@@ -0,0 +1,127 @@
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::components::email_delivery::EmailClient;
+use crate::domain::html::escape_html;
+use crate::domain::roles::{Permission, Role};
+use crate::domain::users::UserEmail;
+use crate::routes::users::CONFIRMED;
+use crate::startup::engine::WebBaseUrl;
+
+/// A step of the editorial review, emailed to whoever has to act on it next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReviewNotice {
+    /// A revision waits for review, told to its reviewer or to everyone who may publish.
+    Submitted,
+    /// Told to the reviewer just assigned.
+    Assigned,
+    /// Told to the owner, who has to save a new revision.
+    ChangesRequested,
+    /// Told to the owner, the post is out.
+    Approved,
+}
+
+/// Emails the people concerned by `notice` about the post `post_id`. A recipient failing to
+/// receive it does not stop the others.
+#[tracing::instrument(name = "Notify review", skip(pool, email_client, base_url))]
+pub async fn notify_review(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &WebBaseUrl,
+    post_id: Uuid,
+    notice: ReviewNotice,
+) -> anyhow::Result<()> {
+    let post = sqlx::query!(
+        r#"
+        SELECT COALESCE(posts.pending_title, posts.title) AS "title!", posts.slug,
+            posts.revision, posts.owner_id,
+            owner.email AS "owner_email?", reviewer.email AS "reviewer_email?"
+        FROM posts
+            LEFT JOIN users owner ON owner.id = posts.owner_id
+            LEFT JOIN users reviewer ON reviewer.id = posts.reviewer_id
+        WHERE posts.id = $1
+        "#,
+        post_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to fetch the reviewed post")?;
+
+    let recipients = match (notice, post.reviewer_email) {
+        (ReviewNotice::Submitted, Some(reviewer)) => vec![reviewer],
+        (ReviewNotice::Submitted, None) => publisher_emails(pool, post.owner_id).await?,
+        (ReviewNotice::Assigned, reviewer) => reviewer.into_iter().collect(),
+        (ReviewNotice::ChangesRequested | ReviewNotice::Approved, _) => {
+            post.owner_email.into_iter().collect()
+        }
+    };
+
+    let title = post.title;
+    let revision = post.revision;
+    let review_link = base_url.api_url(&format!("/posts/{post_id}/review"));
+    let post_link = base_url.api_url(&format!("/posts/slug/{}", post.slug));
+    // INFO: the title is the text of the author, the HTML part only carries it escaped
+    let (html_title, html_review_link, html_post_link) = (
+        escape_html(&title),
+        escape_html(&review_link),
+        escape_html(&post_link),
+    );
+    let (subject, html, text) = match notice {
+        ReviewNotice::Submitted => (
+            format!("{title} waits for review"),
+            format!(r#"<p>Revision {revision} of <a href="{html_review_link}">{html_title}</a> waits for review.</p>"#),
+            format!("Revision {revision} of {title} waits for review: {review_link}"),
+        ),
+        ReviewNotice::Assigned => (
+            format!("You were asked to review {title}"),
+            format!(r#"<p>You were asked to review <a href="{html_review_link}">{html_title}</a>.</p>"#),
+            format!("You were asked to review {title}: {review_link}"),
+        ),
+        ReviewNotice::ChangesRequested => (
+            format!("Changes were requested on {title}"),
+            format!(r#"<p>Changes were requested on revision {revision} of <a href="{html_review_link}">{html_title}</a>. Saving a new revision sends it back to review.</p>"#),
+            format!("Changes were requested on revision {revision} of {title}: {review_link}\n\nSaving a new revision sends it back to review."),
+        ),
+        ReviewNotice::Approved => (
+            format!("{title} was published"),
+            format!(r#"<p>Revision {revision} of <a href="{html_post_link}">{html_title}</a> was approved and published.</p>"#),
+            format!("Revision {revision} of {title} was approved and published: {post_link}"),
+        ),
+    };
+
+    for recipient in recipients {
+        // INFO: addresses were validated when registering
+        let sent = match UserEmail::try_from(recipient) {
+            Ok(email) => email_client
+                .send_email(&email, &subject, &html, &text)
+                .await
+                .context("Failed to send the review email"),
+            Err(e) => Err(anyhow::anyhow!(e)),
+        };
+        let _ret = sent.inspect_err(|e| tracing::warn!("{e:?}"));
+    }
+    Ok(())
+}
+
+/// The emails of the confirmed users who may publish, and so review, other than `owner_id`.
+async fn publisher_emails(pool: &PgPool, owner_id: Option<Uuid>) -> anyhow::Result<Vec<String>> {
+    let users = sqlx::query!(
+        "SELECT id, email, role FROM users WHERE status = $1",
+        CONFIRMED
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the reviewers")?;
+
+    Ok(users
+        .into_iter()
+        .filter(|user| Some(user.id) != owner_id)
+        .filter(|user| {
+            user.role
+                .parse::<Role>()
+                .is_ok_and(|role| role.can(Permission::PublishPosts))
+        })
+        .map(|user| user.email)
+        .collect())
+}
@@ -0,0 +1,110 @@
+//! Secrets the database keeps and the server needs back in clear, such as TOTP secrets. When
+//! encryption at rest is configured they are sealed with its keys, see [`Keyring`], so a leaked
+//! database does not give them away on its own.
+
+use anyhow::Context;
+use base64::prelude::*;
+
+use crate::components::blob_storage::Keyring;
+use crate::configuration::BlobStorageSettings;
+
+/// Marks a sealed secret, secrets without it were stored in clear.
+const SEALED_PREFIX: &str = "sealed:";
+
+#[derive(Debug, Default)]
+pub struct SecretSealer {
+    keyring: Option<Keyring>,
+}
+
+impl TryFrom<&BlobStorageSettings> for SecretSealer {
+    type Error = std::io::Error;
+
+    fn try_from(settings: &BlobStorageSettings) -> Result<Self, Self::Error> {
+        Ok(Self {
+            keyring: settings
+                .encryption
+                .as_ref()
+                .map(Keyring::try_from)
+                .transpose()?,
+        })
+    }
+}
+
+impl SecretSealer {
+    pub fn new(keyring: Keyring) -> Self {
+        Self {
+            keyring: Some(keyring),
+        }
+    }
+
+    /// The form of `secret` to store, in clear without encryption at rest.
+    pub fn seal(&self, secret: &str) -> anyhow::Result<String> {
+        let Some(keyring) = &self.keyring else {
+            return Ok(secret.to_string());
+        };
+        let sealed = keyring
+            .seal(secret.as_bytes())
+            .context("Failed to seal the secret")?;
+        Ok(format!("{SEALED_PREFIX}{}", BASE64_STANDARD.encode(sealed)))
+    }
+
+    /// The secret `stored` in clear, whether it was sealed or not.
+    pub fn open(&self, stored: &str) -> anyhow::Result<String> {
+        let Some(sealed) = stored.strip_prefix(SEALED_PREFIX) else {
+            return Ok(stored.to_string());
+        };
+        let keyring = self
+            .keyring
+            .as_ref()
+            .context("The secret is sealed but encryption at rest is not configured")?;
+
+        let sealed = BASE64_STANDARD
+            .decode(sealed)
+            .context("The sealed secret is not in base64")?;
+        let secret = keyring
+            .open(sealed)
+            .context("Failed to open the sealed secret")?;
+        String::from_utf8(secret).context("The sealed secret is not UTF-8")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::EncryptionSettings;
+
+    use secrecy::SecretBox;
+
+    fn keyring(key: u8, previous: &[u8]) -> Keyring {
+        let encode = |byte| SecretBox::new(Box::new(BASE64_STANDARD.encode([byte; 32])));
+        Keyring::try_from(&EncryptionSettings {
+            key: encode(key),
+            previous_keys: previous.iter().copied().map(encode).collect(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn sealed_secrets_open_with_the_current_or_a_previous_key() {
+        let sealed = SecretSealer::new(keyring(1, &[]))
+            .seal("JBSWY3DPEHPK3PXP")
+            .unwrap();
+        assert!(sealed.starts_with(SEALED_PREFIX));
+        assert!(!sealed.contains("JBSWY3DPEHPK3PXP"));
+
+        let rotated = SecretSealer::new(keyring(2, &[1]));
+        assert_eq!(rotated.open(&sealed).unwrap(), "JBSWY3DPEHPK3PXP");
+        assert!(SecretSealer::new(keyring(2, &[])).open(&sealed).is_err());
+        assert!(SecretSealer::default().open(&sealed).is_err());
+    }
+
+    #[test]
+    fn secrets_stay_in_clear_without_encryption_at_rest() {
+        let sealer = SecretSealer::default();
+        assert_eq!(sealer.seal("JBSWY3DPEHPK3PXP").unwrap(), "JBSWY3DPEHPK3PXP");
+
+        // INFO: secrets stored before encryption was enabled are still read
+        let sealer = SecretSealer::new(keyring(1, &[]));
+        assert_eq!(sealer.open("JBSWY3DPEHPK3PXP").unwrap(), "JBSWY3DPEHPK3PXP");
+    }
+}
@@ -0,0 +1,189 @@
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::{http, web, FromRequest, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use secrecy::SecretBox;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use std::future::Future;
+use std::pin::Pin;
+
+use crate::components::audit::RequestOrigin;
+use crate::domain::credentials::{hash_token, new_token};
+use crate::domain::roles::{Permission, Role};
+use crate::routes::users::CONFIRMED;
+
+/// A signed-in user, extracted from the `Authorization: Bearer <token>` header of a request.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub user_id: Uuid,
+    pub username: String,
+    pub role: Role,
+    /// Where the request came from, for the audit log.
+    pub origin: RequestOrigin,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SessionError {
+    #[error("Signing in is required")]
+    MissingError,
+    #[error("The session is invalid or expired")]
+    InvalidError,
+    #[error("The {0} role is not allowed to do this")]
+    ForbiddenError(Role),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for SessionError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            Self::UnexpectedError(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+            Self::MissingError | Self::InvalidError => http::StatusCode::UNAUTHORIZED,
+            Self::ForbiddenError(_) => http::StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if self.status_code() == http::StatusCode::UNAUTHORIZED {
+            response.insert_header((header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer")));
+        }
+        response.body(self.to_string())
+    }
+}
+
+/// The bearer token of `req`, if it carries one.
+pub fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+impl Session {
+    async fn from_token(
+        pool: &PgPool,
+        token: &str,
+        origin: RequestOrigin,
+    ) -> Result<Self, SessionError> {
+        let session = sqlx::query!(
+            r#"
+            SELECT users.id, users.username, users.role
+            FROM user_sessions JOIN users ON users.id = user_sessions.user_id
+            WHERE token_hash = $1 AND expires_at > now() AND users.status = $2
+            "#,
+            hash_token(token),
+            CONFIRMED,
+        )
+        .fetch_optional(pool)
+        .await
+        .context("Failed to fetch the session")
+        .inspect_err(|e| tracing::error!("{e:?}"))?
+        .ok_or(SessionError::InvalidError)?;
+
+        let role = session
+            .role
+            .parse()
+            .map_err(|e: String| anyhow::anyhow!(e))
+            .context(format!("User {} has an invalid role", session.id))
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+        Ok(Self {
+            user_id: session.id,
+            username: session.username,
+            role,
+            origin,
+        })
+    }
+
+    pub fn can(&self, permission: Permission) -> bool {
+        self.role.can(permission)
+    }
+
+    /// Fails with [`SessionError::ForbiddenError`] unless the role of the user grants
+    /// `permission`.
+    pub fn require(&self, permission: Permission) -> Result<(), SessionError> {
+        match self.can(permission) {
+            true => Ok(()),
+            false => Err(SessionError::ForbiddenError(self.role)),
+        }
+    }
+}
+
+impl FromRequest for Session {
+    type Error = SessionError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
+        let token = bearer_token(req).map(str::to_string);
+        let pool = req.app_data::<web::Data<PgPool>>().cloned();
+        let origin = RequestOrigin::of(req);
+
+        Box::pin(async move {
+            let token = token.ok_or(SessionError::MissingError)?;
+            let pool = pool
+                .context("The database pool is not configured")
+                .map_err(SessionError::UnexpectedError)?;
+            Session::from_token(&pool, &token, origin).await
+        })
+    }
+}
+
+/// Opens a session for `user_id`, returning its bearer token and when it expires.
+pub async fn create_session(
+    connection: &mut PgConnection,
+    user_id: Uuid,
+    expiry: Duration,
+) -> anyhow::Result<(SecretBox<String>, DateTime<Utc>)> {
+    let (token, token_hash) = new_token()?;
+    let expires_at = Utc::now() + expiry;
+
+    sqlx::query!(
+        "INSERT INTO user_sessions (token_hash, user_id, expires_at) VALUES ($1, $2, $3)",
+        token_hash,
+        user_id,
+        expires_at,
+    )
+    .execute(&mut *connection)
+    .await
+    .context("Failed to store the session")?;
+
+    // INFO: expired sessions are only cleaned up here, they are refused anyway
+    sqlx::query!(
+        "DELETE FROM user_sessions WHERE user_id = $1 AND expires_at <= now()",
+        user_id
+    )
+    .execute(&mut *connection)
+    .await
+    .context("Failed to remove the expired sessions")?;
+
+    Ok((token, expires_at))
+}
+
+/// Ends the session of `token`.
+pub async fn revoke_session(pool: &PgPool, token: &str) -> anyhow::Result<()> {
+    sqlx::query!(
+        "DELETE FROM user_sessions WHERE token_hash = $1",
+        hash_token(token)
+    )
+    .execute(pool)
+    .await
+    .context("Failed to remove the session")?;
+    Ok(())
+}
+
+/// Ends every session of `user_id`, returning how many there were.
+pub async fn revoke_user_sessions(
+    connection: &mut PgConnection,
+    user_id: Uuid,
+) -> anyhow::Result<u64> {
+    let revoked = sqlx::query!("DELETE FROM user_sessions WHERE user_id = $1", user_id)
+        .execute(&mut *connection)
+        .await
+        .context("Failed to remove the sessions")?
+        .rows_affected();
+    Ok(revoked)
+}
@@ -0,0 +1,156 @@
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::components::blob_storage::BlobStorage;
+
+/// A post blob written ahead of the database transaction that makes a post refer to it.
+///
+/// 1. [`StagedWrite::begin`] journals the new blob before anything is written to the blob storage.
+/// 2. The files are saved and the manifest staged with `PostStorageDriver::stage_saved`.
+/// 3. [`StagedWrite::commit`] marks the journal entry, with the blob it replaces, inside the
+///    transaction of the post, once that blob is known under the lock of the post row.
+/// 4. [`StagedWrite::settle`] then publishes the staged manifest and removes the replaced blob
+///    when the transaction committed, or removes the new blob when it did not.
+///
+/// Entries left behind by a crash are settled by [`recover_staged_writes`] on startup.
+#[derive(Debug)]
+pub struct StagedWrite {
+    id: Uuid,
+}
+
+impl StagedWrite {
+    pub async fn begin(pool: &PgPool, post_id: Uuid, new_blob: &str) -> Result<Self, sqlx::Error> {
+        let id = Uuid::new_v4();
+        sqlx::query!(
+            "INSERT INTO blob_journal (id, post_id, new_blob) VALUES ($1, $2, $3)",
+            id,
+            post_id,
+            new_blob,
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(Self { id })
+    }
+
+    pub async fn commit(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        old_blob: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE blob_journal SET committed = TRUE, old_blob = $2 WHERE id = $1",
+            self.id,
+            old_blob,
+        )
+        .execute(&mut **transaction)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Rolls the write forward or back depending on whether its transaction committed. On
+    /// failure the journal entry is kept for [`recover_staged_writes`].
+    pub async fn settle(self, pool: &PgPool, blob_storage: &BlobStorage) -> anyhow::Result<()> {
+        let entry = sqlx::query!(
+            "SELECT new_blob, old_blob, committed FROM blob_journal WHERE id = $1",
+            self.id
+        )
+        .fetch_one(pool)
+        .await
+        .context("Failed to read the journal entry")?;
+
+        settle_entry(
+            pool,
+            blob_storage,
+            self.id,
+            &entry.new_blob,
+            entry.old_blob.as_deref(),
+            entry.committed,
+        )
+        .await
+    }
+}
+
+async fn settle_entry(
+    pool: &PgPool,
+    blob_storage: &BlobStorage,
+    id: Uuid,
+    new_blob: &str,
+    old_blob: Option<&str>,
+    committed: bool,
+) -> anyhow::Result<()> {
+    if committed {
+        match blob_storage.publish_staged(new_blob).await {
+            // INFO: a later write to the post replaced the blob, and removed it, before this one
+            // got to publish it
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                tracing::info!("Blob {new_blob} was replaced before being published")
+            }
+            ret => ret.context(format!("Failed to publish the manifest of blob {new_blob}"))?,
+        }
+        if let Some(old_blob) = old_blob {
+            blob_storage
+                .discard_blob(old_blob)
+                .await
+                .context(format!("Failed to remove the replaced blob {old_blob}"))?;
+        }
+    } else {
+        blob_storage
+            .discard_blob(new_blob)
+            .await
+            .context(format!("Failed to remove the uncommitted blob {new_blob}"))?;
+    }
+
+    sqlx::query!("DELETE FROM blob_journal WHERE id = $1", id)
+        .execute(pool)
+        .await
+        .context("Failed to close the journal entry")?;
+
+    Ok(())
+}
+
+/// Settles the staged writes a crashed run left unfinished, meant to run on startup. An entry that
+/// is not committed yet is rolled back, so only entries older than `grace_period` are taken for
+/// abandoned, younger ones may belong to another instance still writing them.
+pub async fn recover_staged_writes(
+    pool: &PgPool,
+    blob_storage: &BlobStorage,
+    grace_period: Duration,
+) -> anyhow::Result<()> {
+    let entries = sqlx::query!(
+        r#"
+        SELECT id, new_blob, old_blob, committed FROM blob_journal
+        WHERE created_at < $1
+        ORDER BY created_at
+        "#,
+        Utc::now() - grace_period
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to read the blob journal")?;
+
+    for entry in entries {
+        tracing::info!(
+            id = %entry.id,
+            committed = entry.committed,
+            "Recovering staged write of blob {}",
+            entry.new_blob
+        );
+        // INFO: one failing entry should not keep the others from recovering
+        let _ret = settle_entry(
+            pool,
+            blob_storage,
+            entry.id,
+            &entry.new_blob,
+            entry.old_blob.as_deref(),
+            entry.committed,
+        )
+        .await
+        .inspect_err(|e| tracing::error!("{e:?}"));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use actix_files::file_extension_to_mime;
+use actix_web::mime;
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::components::blob_storage::BlobStorage;
+use crate::domain::storage_usage::{measure, TypeUsage};
+
+/// Replaces the storage usage recorded for a post, for its revision waiting for review when
+/// `pending`.
+pub async fn save_storage_usage(
+    transaction: &mut Transaction<'_, Postgres>,
+    post_id: Uuid,
+    pending: bool,
+    usage: &[TypeUsage],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM post_storage_usage WHERE post_id = $1 AND pending = $2",
+        post_id,
+        pending
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    for usage in usage {
+        sqlx::query!(
+            r#"
+            INSERT INTO post_storage_usage (post_id, pending, mime, files, bytes)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            post_id,
+            pending,
+            usage.mime,
+            usage.files as i32,
+            usage.bytes as i64,
+        )
+        .execute(&mut **transaction)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Bytes recorded for every post but the `excluded` revision, such as the revision being
+/// replaced: the published one of a post, or the one waiting for review when `pending`.
+///
+/// Uploads running at the same time do not see each other, so together they may go over the
+/// global quota by the size of the posts in flight.
+pub async fn storage_used_elsewhere(
+    pool: &PgPool,
+    excluded: Option<Uuid>,
+    pending: bool,
+) -> Result<u64, sqlx::Error> {
+    let used = sqlx::query_scalar!(
+        r#"
+        SELECT COALESCE(SUM(bytes), 0)::BIGINT AS "used!"
+        FROM post_storage_usage
+        WHERE (post_id, pending) IS DISTINCT FROM ($1, $2)
+        "#,
+        excluded,
+        pending
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(used as u64)
+}
+
+/// Records the usage of posts saved before it was tracked, measured from their manifest. Their
+/// attachments are typed by extension, as they were sniffed when uploaded but not kept.
+pub async fn backfill_storage_usage(
+    pool: &PgPool,
+    blob_storage: &BlobStorage,
+) -> anyhow::Result<()> {
+    let posts = sqlx::query!(
+        r#"
+        SELECT id, blob FROM posts p
+        WHERE NOT EXISTS (SELECT 1 FROM post_storage_usage u WHERE u.post_id = p.id)
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch posts without storage usage")?;
+
+    for post in posts {
+        // INFO: a post without a manifest is reported by the scrub, not accounted here
+        let manifest = match blob_storage.post_manifest(&post.blob).await {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                tracing::warn!(id = %post.id, "Failed to measure the blob of a post: {e}");
+                continue;
+            }
+        };
+        let usage = measure(&manifest, |path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .map(file_extension_to_mime)
+                .unwrap_or(mime::APPLICATION_OCTET_STREAM)
+                .essence_str()
+                .to_string()
+        });
+
+        let mut transaction = pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?;
+        save_storage_usage(&mut transaction, post.id, false, &usage)
+            .await
+            .context("Failed to save storage usage")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit transaction")?;
+    }
+
+    Ok(())
+}
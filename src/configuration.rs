@@ -1,7 +1,11 @@
+use std::num::NonZeroU32;
 use std::path::PathBuf;
 
+use chrono::Duration;
 use secrecy::{ExposeSecret, SecretBox};
 
+use crate::components::blob_storage::DEFAULT_GRACE_PERIOD_SECS;
+use crate::domain::login_throttle::{ThrottleKind, ThrottlePolicy};
 use crate::domain::users::UserEmail;
 use crate::telemetry::LoggerFormat;
 
@@ -11,6 +15,10 @@ pub struct Settings {
     pub application: AppSettings,
     pub gmail_service: GmailApiSettings,
     pub blob_storage: BlobStorageSettings,
+    #[serde(default)]
+    pub users: UserSettings,
+    #[serde(default)]
+    pub newsletter: NewsletterSettings,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -34,8 +42,234 @@ pub struct AppSettings {
 #[derive(serde::Deserialize, Debug)]
 pub struct BlobStorageSettings {
     pub base_dir: PathBuf,
+    /// Keep everything in memory instead of the configured backend, lost once the server stops.
     #[serde(default)]
     pub ephemeral: bool,
+    #[serde(default)]
+    pub backend: StorageBackendSettings,
+    #[serde(default)]
+    pub attachments: AttachmentSettings,
+    #[serde(default)]
+    pub resumable_uploads: ResumableUploadSettings,
+    #[serde(default)]
+    pub quotas: StorageQuotaSettings,
+    /// Encrypt every stored file when set.
+    pub encryption: Option<EncryptionSettings>,
+    /// Mirror every write into another backend, see the `migrate-storage` command.
+    pub migrate_to: Option<StorageMigrationSettings>,
+    /// Seconds a staged write stays unsettled before an instance starting up takes it for
+    /// abandoned, as other instances may still be writing younger ones.
+    #[serde(default = "default_recovery_grace_period_secs")]
+    pub recovery_grace_period_secs: u64,
+}
+
+fn default_recovery_grace_period_secs() -> u64 {
+    DEFAULT_GRACE_PERIOD_SECS
+}
+
+/// Where stored files live, the local backend keeps them under `base_dir`.
+#[derive(serde::Deserialize, Debug, Default)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum StorageBackendSettings {
+    #[default]
+    Local,
+    S3(S3Settings),
+}
+
+/// The backend stored files are moved to.
+#[derive(serde::Deserialize, Debug)]
+pub struct StorageMigrationSettings {
+    /// Directory of a `local` target.
+    pub base_dir: Option<PathBuf>,
+    pub backend: StorageBackendSettings,
+}
+
+/// An S3 compatible object storage such as AWS S3, MinIO or Garage.
+#[derive(serde::Deserialize, Debug)]
+pub struct S3Settings {
+    pub bucket: String,
+    pub region: String,
+    /// Custom endpoint of an S3 compatible service, AWS is used when left out.
+    pub endpoint: Option<String>,
+    pub access_key_id: String,
+    pub secret_access_key: SecretBox<String>,
+    /// Allow a plain `http://` endpoint, only meant for local stand-ins.
+    #[serde(default)]
+    pub allow_http: bool,
+}
+
+/// Which attachments an upload may carry, matched against the sniffed content type.
+#[derive(serde::Deserialize, Debug)]
+pub struct AttachmentSettings {
+    /// Limit of a whole multipart upload in bytes. Resumable uploads attached by id are not part
+    /// of it, so files larger than it may still be attached that way.
+    pub total_limit: usize,
+    pub allowed: Vec<AttachmentRule>,
+    /// Keep the capture date and coarse location stripped from photos in the database.
+    #[serde(default)]
+    pub record_photo_metadata: bool,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct AttachmentRule {
+    /// Either an exact mime type such as `application/pdf` or a wildcard such as `image/*`.
+    pub mime: String,
+    /// Limit of a single file of this type in bytes.
+    pub max_size: u64,
+}
+
+impl Default for AttachmentSettings {
+    fn default() -> Self {
+        const MB: u64 = 1024 * 1024;
+        let rule = |mime: &str, max_size: u64| AttachmentRule {
+            mime: mime.to_string(),
+            max_size,
+        };
+
+        Self {
+            total_limit: 100 * 1024 * 1024,
+            allowed: vec![
+                rule("image/*", 20 * MB),
+                rule("application/pdf", 50 * MB),
+                rule("application/zip", 100 * MB),
+                rule("application/gzip", 100 * MB),
+                rule("application/x-tar", 100 * MB),
+                rule("application/x-7z-compressed", 100 * MB),
+                rule("video/mp4", 1024 * MB),
+                rule("text/plain", 5 * MB),
+            ],
+            record_photo_metadata: false,
+        }
+    }
+}
+
+/// Attachments sent in chunks with the tus protocol, for files too large for a single request.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct ResumableUploadSettings {
+    /// Largest upload accepted in bytes, on top of the limit of its type of attachment.
+    pub max_size: u64,
+    /// Seconds an upload may go without progress before it is abandoned.
+    pub expiry_secs: u64,
+}
+
+impl Default for ResumableUploadSettings {
+    fn default() -> Self {
+        Self {
+            max_size: 1024 * 1024 * 1024,
+            expiry_secs: 24 * 60 * 60,
+        }
+    }
+}
+
+/// Bytes the stored files may take, variants included. Left out means unlimited.
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct StorageQuotaSettings {
+    /// Limit of a single post.
+    pub per_post: Option<u64>,
+    /// Limit of every post together.
+    pub global: Option<u64>,
+}
+
+/// Keys of the encryption at rest, each one 32 bytes encoded in base64.
+#[derive(serde::Deserialize, Debug)]
+pub struct EncryptionSettings {
+    /// Encrypts new files.
+    pub key: SecretBox<String>,
+    /// Keys older files may still be encrypted with, until the `rotate-key` command re-encrypted
+    /// them with `key`.
+    #[serde(default)]
+    pub previous_keys: Vec<SecretBox<String>>,
+}
+
+/// User accounts: sign-up confirmed by email, logins and password resets.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct UserSettings {
+    /// Its `resend_cooldown_secs` also spaces password reset emails.
+    #[serde(flatten)]
+    pub confirmation: ConfirmationSettings,
+    /// PBKDF2 iterations passwords are hashed with.
+    pub password_hash_iterations: NonZeroU32,
+    /// Seconds a login stays valid.
+    pub session_expiry_secs: u64,
+    /// Seconds a password reset link stays valid.
+    pub password_reset_expiry_secs: u64,
+    /// Names the site in authenticator apps.
+    pub totp_issuer: String,
+    /// Seconds a login waits for its second factor.
+    pub two_factor_expiry_secs: u64,
+    /// Wrong second factors a login takes before it has to start over.
+    pub two_factor_attempts: u32,
+    /// Failed logins in a row an account or address takes before it has to wait.
+    pub login_free_attempts: u32,
+    /// Seconds of the first wait, doubled with every further failure.
+    pub login_backoff_secs: u64,
+    /// Failed logins in a row that lock an account out.
+    pub login_lockout_attempts: u32,
+    /// Failed logins in a row that lock an address out, higher as many users may share one.
+    pub login_ip_lockout_attempts: u32,
+    /// Seconds a lockout lasts, after which the failures are forgotten.
+    pub login_lockout_secs: u64,
+}
+
+impl UserSettings {
+    pub fn throttle_policy(&self, kind: ThrottleKind) -> ThrottlePolicy {
+        ThrottlePolicy {
+            free_attempts: self.login_free_attempts,
+            backoff: Duration::seconds(self.login_backoff_secs as i64),
+            lockout_attempts: match kind {
+                ThrottleKind::Account => self.login_lockout_attempts,
+                ThrottleKind::Ip => self.login_ip_lockout_attempts,
+            },
+            lockout: Duration::seconds(self.login_lockout_secs as i64),
+        }
+    }
+}
+
+impl Default for UserSettings {
+    fn default() -> Self {
+        Self {
+            confirmation: ConfirmationSettings::default(),
+            password_hash_iterations: NonZeroU32::new(600_000).unwrap(),
+            session_expiry_secs: 7 * 24 * 60 * 60,
+            password_reset_expiry_secs: 60 * 60,
+            totp_issuer: "pine-tails".to_string(),
+            two_factor_expiry_secs: 5 * 60,
+            two_factor_attempts: 5,
+            login_free_attempts: 3,
+            login_backoff_secs: 1,
+            login_lockout_attempts: 10,
+            login_ip_lockout_attempts: 50,
+            login_lockout_secs: 15 * 60,
+        }
+    }
+}
+
+/// Newsletter subscriptions, confirmed by email.
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct NewsletterSettings {
+    #[serde(flatten)]
+    pub confirmation: ConfirmationSettings,
+}
+
+/// Addresses confirmed by an emailed link, see [`crate::components::confirmations`].
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct ConfirmationSettings {
+    /// Seconds a confirmation link stays valid.
+    pub confirmation_expiry_secs: u64,
+    /// Seconds to wait before another email is sent to the same address.
+    pub resend_cooldown_secs: u64,
+    /// Seconds an account or subscription stays around without being confirmed.
+    pub pending_expiry_secs: u64,
+}
+
+impl Default for ConfirmationSettings {
+    fn default() -> Self {
+        Self {
+            confirmation_expiry_secs: 24 * 60 * 60,
+            resend_cooldown_secs: 60,
+            pending_expiry_secs: 7 * 24 * 60 * 60,
+        }
+    }
 }
 
 #[derive(serde::Deserialize, Debug)]
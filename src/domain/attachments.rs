@@ -0,0 +1,212 @@
+use std::path::Path;
+
+use crate::configuration::{AttachmentRule, AttachmentSettings};
+
+/// Number of leading bytes inspected when sniffing the type of an attachment.
+pub const SNIFF_LEN: usize = 8192;
+
+const TEXT_PLAIN: &str = "text/plain";
+const OCTET_STREAM: &str = "application/octet-stream";
+
+#[derive(thiserror::Error, Debug)]
+pub enum AttachmentRejection {
+    #[error("`{name}` is a `{mime}` file, which is not allowed as an attachment")]
+    UnsupportedType { name: String, mime: String },
+    #[error("`{name}` is a `{mime}` file, which its extension does not match")]
+    MismatchedExtension { name: String, mime: String },
+    #[error(
+        "`{name}` is at least {size} bytes, but `{mime}` attachments are limited to {limit} bytes"
+    )]
+    TooLarge {
+        name: String,
+        mime: String,
+        size: u64,
+        limit: u64,
+    },
+}
+
+/// Detects the mime type of a file from its first [`SNIFF_LEN`] bytes, ignoring the file
+/// extension.
+///
+/// Files without a known signature are reported as `text/plain` when they decode as UTF-8 and as
+/// `application/octet-stream` otherwise.
+pub fn sniff_bytes(head: &[u8]) -> &'static str {
+    if let Some(kind) = infer::get(head) {
+        return kind.mime_type();
+    }
+
+    // INFO: the sniffed prefix may cut a multi-byte character in half, which is still text
+    match std::str::from_utf8(head) {
+        Ok(_) => TEXT_PLAIN,
+        Err(e) if e.error_len().is_none() => TEXT_PLAIN,
+        Err(_) => OCTET_STREAM,
+    }
+}
+
+#[derive(Debug)]
+pub struct AttachmentPolicy {
+    rules: Vec<AttachmentRule>,
+    pub record_photo_metadata: bool,
+    /// Limit of a whole post upload, in bytes.
+    pub total_limit: u64,
+}
+
+impl From<&AttachmentSettings> for AttachmentPolicy {
+    fn from(settings: &AttachmentSettings) -> Self {
+        Self {
+            rules: settings.allowed.clone(),
+            record_photo_metadata: settings.record_photo_metadata,
+            total_limit: settings.total_limit as u64,
+        }
+    }
+}
+
+impl AttachmentPolicy {
+    /// Sniffs an attachment from its first bytes, `head`, and checks it against the allowlist
+    /// and its extension. Returns the detected mime type when the attachment is accepted.
+    pub fn check(&self, name: &str, head: &[u8]) -> Result<&'static str, AttachmentRejection> {
+        let mime = sniff_bytes(head);
+        self.check_mime(name, mime, head.len() as u64)?;
+
+        if !extension_matches(name, mime) {
+            return Err(AttachmentRejection::MismatchedExtension {
+                name: name.to_string(),
+                mime: mime.to_string(),
+            });
+        }
+        Ok(mime)
+    }
+
+    /// Checks that `size` bytes are allowed for an attachment of type `mime`, such as while the
+    /// rest of it is received.
+    pub fn check_mime(&self, name: &str, mime: &str, size: u64) -> Result<(), AttachmentRejection> {
+        let rule = self
+            .rules
+            .iter()
+            .find(|rule| mime_matches(&rule.mime, mime))
+            .ok_or_else(|| AttachmentRejection::UnsupportedType {
+                name: name.to_string(),
+                mime: mime.to_string(),
+            })?;
+
+        if size > rule.max_size {
+            return Err(AttachmentRejection::TooLarge {
+                name: name.to_string(),
+                mime: mime.to_string(),
+                size,
+                limit: rule.max_size,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// The limit of the largest type of attachment allowed.
+    pub fn largest_limit(&self) -> u64 {
+        self.rules
+            .iter()
+            .map(|rule| rule.max_size)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Whether the extension of `name` stands for the sniffed `mime`. Text has no signature to tell
+/// its format by, so `text/plain` passes for any text extension short of the ones browsers render
+/// or run, such as `.html` or `.svg`.
+fn extension_matches(name: &str, mime: &str) -> bool {
+    let Some(extension) = Path::new(name).extension().and_then(|ext| ext.to_str()) else {
+        return false;
+    };
+
+    mime_guess::from_ext(extension).iter().any(|guess| {
+        guess.essence_str().eq_ignore_ascii_case(mime)
+            || (mime == TEXT_PLAIN
+                && guess.type_() == mime_guess::mime::TEXT
+                && !matches!(
+                    guess.subtype().as_str(),
+                    "html" | "xml" | "javascript" | "css"
+                ))
+    })
+}
+
+fn mime_matches(pattern: &str, mime: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(top_level) => mime
+            .split_once('/')
+            .is_some_and(|(ty, _)| ty.eq_ignore_ascii_case(top_level)),
+        None => pattern.eq_ignore_ascii_case(mime),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG_HEADER: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    fn policy() -> AttachmentPolicy {
+        AttachmentPolicy::from(&AttachmentSettings::default())
+    }
+
+    #[test]
+    fn sniffing_ignores_the_extension_and_reads_magic_bytes() {
+        assert_eq!(sniff_bytes(PNG_HEADER), "image/png");
+        assert_eq!(sniff_bytes(b"%PDF-1.7\n"), "application/pdf");
+        assert_eq!(sniff_bytes("plain old notes ✈".as_bytes()), TEXT_PLAIN);
+        assert_eq!(sniff_bytes(&[0xff, 0x00, 0xfe, 0x80, 0x81]), OCTET_STREAM);
+    }
+
+    #[test]
+    fn attachments_must_be_named_after_their_sniffed_type() {
+        assert!(policy().check("logo.png", PNG_HEADER).is_ok());
+        assert!(policy().check("notes.txt", b"plain old notes").is_ok());
+        assert!(policy().check("notes.md", b"# plain old notes").is_ok());
+
+        for (name, head) in [
+            ("logo.jpg", PNG_HEADER),
+            ("page.html", b"hello <b>there</b>".as_slice()),
+            ("logo.svg", b"<svg onload=\"alert(1)\"/>".as_slice()),
+            ("notes", b"plain old notes".as_slice()),
+        ] {
+            assert!(
+                matches!(
+                    policy().check(name, head),
+                    Err(AttachmentRejection::MismatchedExtension { .. })
+                ),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn wildcard_rules_match_the_top_level_type() {
+        assert!(mime_matches("image/*", "image/webp"));
+        assert!(!mime_matches("image/*", "video/mp4"));
+        assert!(mime_matches("application/pdf", "application/pdf"));
+        assert!(!mime_matches("application/pdf", "application/zip"));
+    }
+
+    #[test]
+    fn executables_are_rejected_as_unsupported() {
+        let ret = policy().check_mime("tool.png", "application/x-msdownload", 10);
+        assert!(matches!(
+            ret,
+            Err(AttachmentRejection::UnsupportedType { .. })
+        ));
+    }
+
+    #[test]
+    fn attachments_over_the_type_limit_are_rejected() {
+        let ret = policy().check_mime("huge.png", "image/png", 21 * 1024 * 1024);
+        assert!(matches!(ret, Err(AttachmentRejection::TooLarge { .. })));
+
+        let ret = policy().check_mime("huge.zip", "application/zip", 21 * 1024 * 1024);
+        assert!(ret.is_ok());
+    }
+
+    #[test]
+    fn the_largest_limit_is_the_one_of_videos_by_default() {
+        assert_eq!(policy().largest_limit(), 1024 * 1024 * 1024);
+    }
+}
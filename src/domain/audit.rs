@@ -0,0 +1,95 @@
+use serde_json::{Map, Value};
+
+/// What an audit log entry records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    PostUpload,
+    PostUpdate,
+    PostDelete,
+    ReviewAssign,
+    ReviewApprove,
+    ReviewRequestChanges,
+    Login,
+    LoginFailed,
+    RoleChange,
+    PasswordReset,
+    TotpEnable,
+    TotpDisable,
+    ProfileUpdate,
+    SubscriptionRemove,
+    LoginUnlock,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::PostUpload => "post.upload",
+            Self::PostUpdate => "post.update",
+            Self::PostDelete => "post.delete",
+            Self::ReviewAssign => "post.review_assign",
+            Self::ReviewApprove => "post.review_approve",
+            Self::ReviewRequestChanges => "post.review_request_changes",
+            Self::Login => "user.login",
+            Self::LoginFailed => "user.login_failed",
+            Self::RoleChange => "user.role_change",
+            Self::PasswordReset => "user.password_reset",
+            Self::TotpEnable => "user.totp_enable",
+            Self::TotpDisable => "user.totp_disable",
+            Self::ProfileUpdate => "user.profile_update",
+            Self::SubscriptionRemove => "subscription.remove",
+            Self::LoginUnlock => "admin.login_unlock",
+        }
+    }
+}
+
+/// The fields that differ between two JSON objects, as `{"field": {"from": .., "to": ..}}`.
+/// A field missing on one side counts as `null`, so an empty `before` records a creation and an
+/// empty `after` a removal.
+pub fn json_diff(before: &Value, after: &Value) -> Value {
+    let empty = Map::new();
+    let before = before.as_object().unwrap_or(&empty);
+    let after = after.as_object().unwrap_or(&empty);
+
+    let mut diff = Map::new();
+    for key in before
+        .keys()
+        .chain(after.keys().filter(|key| !before.contains_key(*key)))
+    {
+        let from = before.get(key).unwrap_or(&Value::Null);
+        let to = after.get(key).unwrap_or(&Value::Null);
+        if from != to {
+            diff.insert(key.clone(), serde_json::json!({ "from": from, "to": to }));
+        }
+    }
+    Value::Object(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn diffs_keep_only_changed_fields() {
+        let before = json!({ "title": "Old", "slug": "old", "date": "2024-10-26" });
+        let after = json!({ "title": "New", "slug": "old", "owner": "neil" });
+
+        assert_eq!(
+            json_diff(&before, &after),
+            json!({
+                "title": { "from": "Old", "to": "New" },
+                "date": { "from": "2024-10-26", "to": null },
+                "owner": { "from": null, "to": "neil" },
+            })
+        );
+        assert_eq!(json_diff(&before, &before), json!({}));
+    }
+
+    #[test]
+    fn diffs_from_nothing_record_every_field() {
+        assert_eq!(
+            json_diff(&Value::Null, &json!({ "title": "New" })),
+            json!({ "title": { "from": null, "to": "New" } })
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use base64::prelude::*;
+use ring::pbkdf2::{self, PBKDF2_HMAC_SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use secrecy::{ExposeSecret, SecretBox};
+use sha2::{Digest, Sha256};
+
+use std::num::NonZeroU32;
+
+const HASH_SCHEME: &str = "pbkdf2-sha256";
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+const TOKEN_LEN: usize = 32;
+
+#[derive(thiserror::Error, Debug)]
+#[error("Failed to generate random bytes")]
+pub struct RandomnessError;
+
+pub(crate) fn random<const N: usize>() -> Result<[u8; N], RandomnessError> {
+    let mut bytes = [0; N];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| RandomnessError)?;
+    Ok(bytes)
+}
+
+/// Hashes a password with PBKDF2-HMAC-SHA256 and a random salt, as
+/// `pbkdf2-sha256$<iterations>$<salt>$<hash>`. The iterations are kept with the hash, so raising
+/// them later does not break the passwords hashed before.
+pub fn hash_password(
+    password: &SecretBox<String>,
+    iterations: NonZeroU32,
+) -> Result<String, RandomnessError> {
+    let salt = random::<SALT_LEN>()?;
+    let mut hash = [0; HASH_LEN];
+    pbkdf2::derive(
+        PBKDF2_HMAC_SHA256,
+        iterations,
+        &salt,
+        password.expose_secret().as_bytes(),
+        &mut hash,
+    );
+
+    Ok(format!(
+        "{HASH_SCHEME}${iterations}${}${}",
+        BASE64_STANDARD_NO_PAD.encode(salt),
+        BASE64_STANDARD_NO_PAD.encode(hash)
+    ))
+}
+
+/// Checks a password against a hash from [`hash_password`], in constant time.
+pub fn verify_password(password: &SecretBox<String>, hashed: &str) -> bool {
+    let mut parts = hashed.split('$');
+    let (Some(HASH_SCHEME), Some(iterations), Some(salt), Some(hash), None) = (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    ) else {
+        return false;
+    };
+    let (Ok(iterations), Ok(salt), Ok(hash)) = (
+        iterations.parse::<NonZeroU32>(),
+        BASE64_STANDARD_NO_PAD.decode(salt),
+        BASE64_STANDARD_NO_PAD.decode(hash),
+    ) else {
+        return false;
+    };
+
+    pbkdf2::verify(
+        PBKDF2_HMAC_SHA256,
+        iterations,
+        &salt,
+        password.expose_secret().as_bytes(),
+        &hash,
+    )
+    .is_ok()
+}
+
+/// A random token to send to a user, and the hash of it to store. A leaked table of hashes does
+/// not give the tokens away.
+pub fn new_token() -> Result<(SecretBox<String>, String), RandomnessError> {
+    let token = BASE64_URL_SAFE_NO_PAD.encode(random::<TOKEN_LEN>()?);
+    let hash = hash_token(&token);
+    Ok((SecretBox::new(Box::new(token)), hash))
+}
+
+/// Tokens are random enough for a plain SHA-256 to stand in for them.
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret(value: &str) -> SecretBox<String> {
+        SecretBox::new(Box::new(value.to_string()))
+    }
+
+    #[test]
+    fn passwords_verify_against_their_own_hash_only() {
+        let iterations = NonZeroU32::new(1000).unwrap();
+        let hashed = hash_password(&secret("correct horse battery"), iterations).unwrap();
+
+        assert!(hashed.starts_with("pbkdf2-sha256$1000$"));
+        assert!(verify_password(&secret("correct horse battery"), &hashed));
+        assert!(!verify_password(&secret("correct horse battery!"), &hashed));
+        assert!(!verify_password(
+            &secret("correct horse battery"),
+            "plain text"
+        ));
+        assert_ne!(
+            hashed,
+            hash_password(&secret("correct horse battery"), iterations).unwrap()
+        );
+    }
+
+    #[test]
+    fn tokens_are_stored_as_their_hash() {
+        let (token, hash) = new_token().unwrap();
+
+        assert_eq!(hash_token(token.expose_secret()), hash);
+        assert_ne!(token.expose_secret(), &hash);
+        assert_ne!(new_token().unwrap().1, hash);
+    }
+}
@@ -0,0 +1,22 @@
+/// Escapes `value` to be written in HTML, as text or as a double quoted attribute value.
+pub fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markup_is_escaped_and_the_rest_kept() {
+        assert_eq!(
+            escape_html(r#"Tom & Jerry <b>"live"</b>"#),
+            "Tom &amp; Jerry &lt;b&gt;&quot;live&quot;&lt;/b&gt;"
+        );
+        assert_eq!(escape_html("Plain title, été"), "Plain title, été");
+    }
+}
@@ -0,0 +1,557 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset, NaiveDateTime, Utc};
+use exif::{In, Tag, Value};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, RgbaImage};
+use img_parts::{jpeg::Jpeg, png::Png, webp::WebP, ImageEXIF};
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::Path;
+
+use crate::domain::html::escape_html;
+
+/// Widths, in pixels, of the downscaled copies generated for every image attachment.
+pub const VARIANT_WIDTHS: [u32; 3] = [480, 960, 1920];
+
+const JPEG_QUALITY: u8 = 82;
+/// Quality used when a photo has to be re-encoded to bake its EXIF orientation into the pixels.
+const REENCODE_JPEG_QUALITY: u8 = 92;
+
+/// Longest side, in pixels, of the thumbnail colors are sampled from when summarizing an image.
+const SUMMARY_THUMBNAIL_SIZE: u32 = 64;
+/// Horizontal and vertical components of the blurhash, 4x3 is the size recommended upstream.
+const BLURHASH_COMPONENTS: (u32, u32) = (4, 3);
+
+/// Decimal places kept from GPS coordinates, one place is roughly 11 km.
+const COARSE_LOCATION_PRECISION: i32 = 1;
+
+/// Image types we know how to decode and re-encode without losing anything important,
+/// animated GIFs for instance would be reduced to their first frame.
+pub fn is_resizable(mime: &str) -> bool {
+    matches!(mime, "image/jpeg" | "image/png" | "image/webp")
+}
+
+/// A downscaled copy of an image attachment.
+pub struct ImageVariant {
+    pub width: u32,
+    pub extension: &'static str,
+    pub bytes: Vec<u8>,
+}
+
+impl ImageVariant {
+    pub fn file_name(&self) -> String {
+        format!("{}.{}", self.width, self.extension)
+    }
+}
+
+/// Parses a variant file name produced by [`ImageVariant::file_name`] back into its width and
+/// extension.
+pub fn parse_variant_file_name(file_name: &str) -> Option<(u32, &str)> {
+    let (width, extension) = file_name.split_once('.')?;
+    Some((width.parse().ok()?, extension))
+}
+
+/// The variant format to prefer for a client sending `accept`: WebP when it lists `image/webp`
+/// without refusing it through `q=0`.
+pub fn preferred_format(accept: &str) -> Option<&'static str> {
+    accept
+        .split(',')
+        .filter_map(|range| {
+            let mut params = range.split(';').map(str::trim);
+            let media = params.next()?;
+            let refused = params.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q <= 0.0)
+            });
+            (!refused).then_some(media)
+        })
+        .any(|media| media.eq_ignore_ascii_case("image/webp"))
+        .then_some("webp")
+}
+
+/// Picks the variant to serve for a requested display `width` among `file_names` of the
+/// available variants: the narrowest one at least as wide as requested, in `format` when such a
+/// copy exists, otherwise in the format of the original. Returns `None` when the original is the
+/// best fit.
+pub fn pick_variant<'a>(
+    file_names: impl IntoIterator<Item = &'a str>,
+    width: u32,
+    format: Option<&str>,
+) -> Option<&'a str> {
+    let candidates = file_names
+        .into_iter()
+        .filter_map(|name| parse_variant_file_name(name).map(|(w, ext)| (w, ext, name)))
+        .filter(|(w, _, _)| *w >= width)
+        .collect::<Vec<_>>();
+
+    let narrowest = candidates.iter().map(|(w, _, _)| *w).min()?;
+    let at_width = candidates.iter().filter(|(w, _, _)| *w == narrowest);
+
+    at_width
+        .clone()
+        .find(|(_, ext, _)| format.is_some_and(|f| f.eq_ignore_ascii_case(ext)))
+        .or_else(|| at_width.clone().find(|(_, ext, _)| *ext != "webp"))
+        .or_else(|| at_width.clone().next())
+        .map(|(_, _, name)| *name)
+}
+
+/// Opens the image at `path` upright, along with the format it is stored in.
+pub fn open_upright(path: &Path) -> Result<(DynamicImage, ImageFormat)> {
+    let reader = ImageReader::open(path)
+        .context("Failed to open image")?
+        .with_guessed_format()
+        .context("Failed to guess image format")?;
+    let format = reader.format().context("Unknown image format")?;
+    Ok((decode_upright(reader)?, format))
+}
+
+/// Like [`open_upright`], for an image held in memory.
+pub fn read_upright(bytes: &[u8]) -> Result<(DynamicImage, ImageFormat)> {
+    let reader = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .context("Failed to guess image format")?;
+    let format = reader.format().context("Unknown image format")?;
+    Ok((decode_upright(reader)?, format))
+}
+
+/// Renders a copy of `img` for every width of [`VARIANT_WIDTHS`] narrower than the original, once
+/// in its original `format` and once as WebP. Which of the two a client gets is left to
+/// [`pick_variant`], from the formats it accepts.
+pub fn render_variants(img: &DynamicImage, format: ImageFormat) -> Result<Vec<ImageVariant>> {
+    let mut variants = Vec::new();
+    for width in VARIANT_WIDTHS.into_iter().filter(|w| *w < img.width()) {
+        let resized = img.resize(width, u32::MAX, FilterType::CatmullRom);
+
+        if format != ImageFormat::WebP {
+            variants.push(encode(&resized, ImageFormat::WebP)?);
+        }
+        variants.push(encode(&resized, format)?);
+    }
+
+    Ok(variants)
+}
+
+/// What a renderer needs to reserve room for an image and paint a placeholder while it loads.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ImageSummary {
+    pub width: u32,
+    pub height: u32,
+    /// Most common color of the image as `#rrggbb`.
+    pub dominant_color: String,
+    pub blurhash: String,
+}
+
+/// Computes the displayed dimensions, dominant color and blurhash of an upright image.
+///
+/// Colors are taken from a thumbnail of at most [`SUMMARY_THUMBNAIL_SIZE`] pixels per side, which
+/// is plenty for a placeholder and keeps large photos cheap to summarize.
+pub fn summarize(img: &DynamicImage) -> Result<ImageSummary> {
+    let thumbnail = img
+        .thumbnail(SUMMARY_THUMBNAIL_SIZE, SUMMARY_THUMBNAIL_SIZE)
+        .to_rgba8();
+
+    let (x, y) = BLURHASH_COMPONENTS;
+    let blurhash = blurhash::encode(
+        x,
+        y,
+        thumbnail.width(),
+        thumbnail.height(),
+        thumbnail.as_raw(),
+    )
+    .map_err(|e| anyhow::anyhow!("Failed to encode blurhash: {e:?}"))?;
+
+    Ok(ImageSummary {
+        width: img.width(),
+        height: img.height(),
+        dominant_color: dominant_color(&thumbnail),
+        blurhash,
+    })
+}
+
+/// Buckets the opaque pixels by their 4 most significant bits per channel and returns the average
+/// color of the most populated bucket.
+fn dominant_color(img: &RgbaImage) -> String {
+    let mut buckets: HashMap<[u8; 3], (u32, [u32; 3])> = HashMap::new();
+    for pixel in img.pixels().filter(|p| p[3] >= 128) {
+        let (count, sum) = buckets
+            .entry([pixel[0] >> 4, pixel[1] >> 4, pixel[2] >> 4])
+            .or_default();
+        *count += 1;
+        for (total, channel) in sum.iter_mut().zip(pixel.0) {
+            *total += u32::from(channel);
+        }
+    }
+
+    let [r, g, b] = buckets
+        .into_values()
+        .max_by_key(|(count, _)| *count)
+        .map(|(count, sum)| sum.map(|total| total / count))
+        .unwrap_or_default();
+
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+/// Rewrites markdown images whose source resolves to a summary through `lookup` into `<img>` tags
+/// carrying the dimensions, dominant color and blurhash, so renderers can lay the page out before
+/// the images arrive. Other images, remote ones for instance, are left untouched.
+pub fn annotate_images<'a>(
+    content: &str,
+    lookup: impl Fn(&str) -> Option<&'a ImageSummary>,
+) -> String {
+    static MARKDOWN_IMAGE: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r#"!\[([^\]]*)\]\(\s*([^)\s]+)(?:\s+"([^"]*)")?\s*\)"#).unwrap());
+
+    MARKDOWN_IMAGE
+        .replace_all(content, |caps: &regex::Captures| {
+            let src = &caps[2];
+            let Some(summary) = lookup(src) else {
+                return caps[0].to_string();
+            };
+
+            let title = caps
+                .get(3)
+                .map(|title| format!(r#" title="{}""#, escape_html(title.as_str())))
+                .unwrap_or_default();
+
+            format!(
+                r#"<img src="{}" alt="{}"{title} width="{}" height="{}" data-blurhash="{}" data-dominant-color="{}" style="background-color: {}" />"#,
+                escape_html(src),
+                escape_html(&caps[1]),
+                summary.width,
+                summary.height,
+                escape_html(&summary.blurhash),
+                summary.dominant_color,
+                summary.dominant_color,
+            )
+        })
+        .into_owned()
+}
+
+/// Decodes an image and rotates it the way its EXIF orientation tells viewers to display it.
+fn decode_upright<R: std::io::BufRead + std::io::Seek>(
+    reader: ImageReader<R>,
+) -> Result<DynamicImage> {
+    let mut decoder = reader
+        .into_decoder()
+        .context("Failed to create image decoder")?;
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let mut img = DynamicImage::from_decoder(decoder).context("Failed to decode image")?;
+    img.apply_orientation(orientation);
+    Ok(img)
+}
+
+fn encode(img: &DynamicImage, format: ImageFormat) -> Result<ImageVariant> {
+    encode_with_quality(img, format, JPEG_QUALITY)
+}
+
+fn encode_with_quality(
+    img: &DynamicImage,
+    format: ImageFormat,
+    quality: u8,
+) -> Result<ImageVariant> {
+    let mut bytes = Vec::new();
+    match format {
+        ImageFormat::Jpeg => {
+            let encoder = JpegEncoder::new_with_quality(&mut bytes, quality);
+            DynamicImage::ImageRgb8(img.to_rgb8()).write_with_encoder(encoder)
+        }
+        ImageFormat::WebP => {
+            let encoder = WebPEncoder::new_lossless(&mut bytes);
+            DynamicImage::ImageRgba8(img.to_rgba8()).write_with_encoder(encoder)
+        }
+        other => img.write_to(&mut Cursor::new(&mut bytes), other),
+    }
+    .context(format!("Failed to encode image as {format:?}"))?;
+
+    Ok(ImageVariant {
+        width: img.width(),
+        extension: format.extensions_str()[0],
+        bytes,
+    })
+}
+
+/// What a photo tells about where and when it was taken, kept privately once stripped.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PhotoMetadata {
+    pub captured_at: Option<DateTime<Utc>>,
+    /// Latitude rounded to [`COARSE_LOCATION_PRECISION`] decimal places.
+    pub latitude: Option<f64>,
+    /// Longitude rounded to [`COARSE_LOCATION_PRECISION`] decimal places.
+    pub longitude: Option<f64>,
+}
+
+impl PhotoMetadata {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Removes EXIF, XMP and textual metadata from a JPEG, PNG or WebP image, returning the cleaned
+/// image and the capture date and coarse location it carried.
+///
+/// Metadata segments are dropped without touching the pixels, unless the EXIF orientation asks
+/// viewers to rotate the photo. Such photos are re-encoded upright, as they would be displayed
+/// sideways once the orientation is gone.
+pub fn strip_metadata(bytes: Vec<u8>) -> Result<(Vec<u8>, PhotoMetadata)> {
+    let exif = exif::Reader::new()
+        .read_from_container(&mut Cursor::new(&bytes))
+        .ok();
+    let metadata = exif.as_ref().map(photo_metadata).unwrap_or_default();
+    let orientation = exif
+        .as_ref()
+        .and_then(|exif| exif.get_field(Tag::Orientation, In::PRIMARY))
+        .and_then(|field| field.value.get_uint(0))
+        .unwrap_or(1);
+
+    if orientation > 1 {
+        let reader = ImageReader::new(Cursor::new(&bytes))
+            .with_guessed_format()
+            .context("Failed to guess image format")?;
+        let format = reader.format().context("Unknown image format")?;
+        let img = decode_upright(reader)?;
+        let upright = encode_with_quality(&img, format, REENCODE_JPEG_QUALITY)?;
+        return Ok((upright.bytes, metadata));
+    }
+
+    let stripped = match image::guess_format(&bytes).context("Unknown image format")? {
+        ImageFormat::Jpeg => {
+            let mut jpeg = Jpeg::from_bytes(bytes.into()).context("Failed to parse JPEG")?;
+            // APP1 holds EXIF and XMP, APP13 holds IPTC, the color profile in APP2 is kept
+            jpeg.segments_mut()
+                .retain(|segment| !matches!(segment.marker(), 0xE1 | 0xED));
+            jpeg.encoder().bytes()
+        }
+        ImageFormat::Png => {
+            let mut png = Png::from_bytes(bytes.into()).context("Failed to parse PNG")?;
+            for kind in [*b"eXIf", *b"tEXt", *b"zTXt", *b"iTXt", *b"tIME"] {
+                png.remove_chunks_by_type(kind);
+            }
+            png.encoder().bytes()
+        }
+        ImageFormat::WebP => {
+            let mut webp = WebP::from_bytes(bytes.into()).context("Failed to parse WebP")?;
+            webp.remove_chunks_by_id(img_parts::webp::CHUNK_XMP);
+            webp.set_exif(None);
+            webp.encoder().bytes()
+        }
+        other => anyhow::bail!("Stripping metadata from {other:?} is not supported"),
+    };
+
+    Ok((stripped.to_vec(), metadata))
+}
+
+fn photo_metadata(exif: &exif::Exif) -> PhotoMetadata {
+    let captured_at = [
+        (Tag::DateTimeOriginal, Tag::OffsetTimeOriginal),
+        (Tag::DateTime, Tag::OffsetTime),
+    ]
+    .into_iter()
+    .find_map(|(tag, offset_tag)| {
+        let local =
+            NaiveDateTime::parse_from_str(ascii_field(exif, tag)?, "%Y:%m:%d %H:%M:%S").ok()?;
+        // INFO: a camera that did not record its offset from UTC is taken to be on UTC
+        let offset = ascii_field(exif, offset_tag)
+            .and_then(|raw| raw.parse::<FixedOffset>().ok())
+            .unwrap_or(FixedOffset::east_opt(0)?);
+        local
+            .and_local_timezone(offset)
+            .single()
+            .map(|at| at.with_timezone(&Utc))
+    });
+
+    PhotoMetadata {
+        captured_at,
+        latitude: gps_coordinate(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, b'S'),
+        longitude: gps_coordinate(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, b'W'),
+    }
+}
+
+/// The trimmed text of an ASCII EXIF field.
+fn ascii_field(exif: &exif::Exif, tag: Tag) -> Option<&str> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => Some(std::str::from_utf8(values.first()?).ok()?.trim()),
+        _ => None,
+    }
+}
+
+fn gps_coordinate(exif: &exif::Exif, tag: Tag, ref_tag: Tag, negative_ref: u8) -> Option<f64> {
+    let Value::Rational(dms) = &exif.get_field(tag, In::PRIMARY)?.value else {
+        return None;
+    };
+
+    let degrees = dms
+        .iter()
+        .zip([1.0, 60.0, 3600.0])
+        .map(|(part, divisor)| part.to_f64() / divisor)
+        .sum::<f64>();
+
+    let negative = exif
+        .get_field(ref_tag, In::PRIMARY)
+        .is_some_and(|field| match &field.value {
+            Value::Ascii(values) => values.first().and_then(|v| v.first()) == Some(&negative_ref),
+            _ => false,
+        });
+
+    let scale = 10f64.powi(COARSE_LOCATION_PRECISION);
+    let coarse = (degrees * scale).round() / scale;
+    Some(if negative { -coarse } else { coarse })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variants_are_only_rendered_below_the_original_width() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wide.png");
+        DynamicImage::new_rgb8(1000, 500).save(&path).unwrap();
+
+        let (img, format) = open_upright(&path).unwrap();
+        let variants = render_variants(&img, format).unwrap();
+        let names = variants
+            .iter()
+            .map(ImageVariant::file_name)
+            .collect::<Vec<_>>();
+
+        assert_eq!(names, vec!["480.webp", "480.png", "960.webp", "960.png"]);
+    }
+
+    #[test]
+    fn jpeg_sources_always_get_a_webp_variant() {
+        let img = DynamicImage::new_rgb8(600, 400);
+
+        let variants = render_variants(&img, ImageFormat::Jpeg).unwrap();
+        let names = variants
+            .iter()
+            .map(ImageVariant::file_name)
+            .collect::<Vec<_>>();
+
+        assert_eq!(names, vec!["480.webp", "480.jpg"]);
+    }
+
+    #[test]
+    fn webp_is_preferred_when_the_client_accepts_it() {
+        assert_eq!(
+            preferred_format("image/avif,image/webp,image/*,*/*;q=0.8"),
+            Some("webp")
+        );
+        assert_eq!(preferred_format("image/webp;q=0, image/*"), None);
+        assert_eq!(preferred_format("image/*"), None);
+        assert_eq!(preferred_format(""), None);
+    }
+
+    #[test]
+    fn pick_variant_prefers_the_narrowest_sufficient_width() {
+        let names = ["480.jpg", "480.webp", "960.jpg", "960.webp", "1920.jpg"];
+
+        assert_eq!(pick_variant(names, 500, None), Some("960.jpg"));
+        assert_eq!(pick_variant(names, 500, Some("webp")), Some("960.webp"));
+        assert_eq!(pick_variant(names, 1000, Some("webp")), Some("1920.jpg"));
+        assert_eq!(pick_variant(names, 3000, None), None);
+    }
+
+    #[test]
+    fn variant_file_names_round_trip() {
+        let variant = ImageVariant {
+            width: 960,
+            extension: "webp",
+            bytes: vec![],
+        };
+
+        assert_eq!(
+            parse_variant_file_name(&variant.file_name()),
+            Some((960, "webp"))
+        );
+        assert_eq!(parse_variant_file_name("cover.jpeg"), None);
+    }
+
+    #[test]
+    fn stripping_removes_exif_from_jpeg() {
+        let bytes = std::fs::read("tests/data/travel/image.jpeg").unwrap();
+        let mut jpeg = Jpeg::from_bytes(bytes.into()).unwrap();
+        // TIFF header followed by an IFD with a single DateTime entry
+        let mut exif = b"MM\x00\x2a\x00\x00\x00\x08\x00\x01\x01\x32\x00\x02\x00\x00\x00\x14\x00\x00\x00\x1a\x00\x00\x00\x00".to_vec();
+        exif.extend_from_slice(b"2024:10:06 09:30:00\0");
+        jpeg.set_exif(Some(exif.into()));
+        let tagged = jpeg.encoder().bytes().to_vec();
+
+        let (stripped, metadata) = strip_metadata(tagged).unwrap();
+
+        assert_eq!(
+            metadata.captured_at,
+            "2024-10-06T09:30:00Z".parse::<DateTime<Utc>>().ok()
+        );
+        assert!(Jpeg::from_bytes(stripped.clone().into())
+            .unwrap()
+            .exif()
+            .is_none());
+        assert!(image::load_from_memory(&stripped).is_ok());
+    }
+
+    #[test]
+    fn capture_dates_are_moved_to_utc_by_their_recorded_offset() {
+        let bytes = std::fs::read("tests/data/travel/image.jpeg").unwrap();
+        let mut jpeg = Jpeg::from_bytes(bytes.into()).unwrap();
+        // TIFF header, an IFD with DateTime and a pointer to an Exif IFD with OffsetTime
+        let mut exif = b"MM\x00\x2a\x00\x00\x00\x08\x00\x02\x01\x32\x00\x02\x00\x00\x00\x14\x00\x00\x00\x38\x87\x69\x00\x04\x00\x00\x00\x01\x00\x00\x00\x26\x00\x00\x00\x00".to_vec();
+        exif.extend_from_slice(
+            b"\x00\x01\x90\x10\x00\x02\x00\x00\x00\x07\x00\x00\x00\x4c\x00\x00\x00\x00",
+        );
+        exif.extend_from_slice(b"2024:10:06 09:30:00\0+09:00\0");
+        jpeg.set_exif(Some(exif.into()));
+        let tagged = jpeg.encoder().bytes().to_vec();
+
+        let (_, metadata) = strip_metadata(tagged).unwrap();
+
+        assert_eq!(
+            metadata.captured_at,
+            "2024-10-06T00:30:00Z".parse::<DateTime<Utc>>().ok()
+        );
+    }
+
+    #[test]
+    fn summary_reports_upright_dimensions_and_the_dominant_color() {
+        let mut img = RgbaImage::from_pixel(300, 200, image::Rgba([200, 30, 30, 255]));
+        for x in 0..100 {
+            for y in 0..200 {
+                img.put_pixel(x, y, image::Rgba([10, 10, 240, 255]));
+            }
+        }
+
+        let summary = summarize(&DynamicImage::ImageRgba8(img)).unwrap();
+
+        assert_eq!((summary.width, summary.height), (300, 200));
+        assert_eq!(summary.dominant_color, "#c81e1e");
+        assert_eq!(summary.blurhash.len(), 28);
+    }
+
+    #[test]
+    fn only_local_images_with_a_summary_are_annotated() {
+        let summary = ImageSummary {
+            width: 1024,
+            height: 768,
+            dominant_color: "#336699".to_string(),
+            blurhash: "LEHV6nWB2yk8pyo0adR*.7kCMdnj".to_string(),
+        };
+        let content = "![a \"cat\"](./cat.jpg \"Tom\")\n![remote](https://example.com/a.png)";
+
+        let annotated = annotate_images(content, |src| (src == "./cat.jpg").then_some(&summary));
+
+        assert_eq!(
+            annotated,
+            "<img src=\"./cat.jpg\" alt=\"a &quot;cat&quot;\" title=\"Tom\" width=\"1024\" \
+             height=\"768\" data-blurhash=\"LEHV6nWB2yk8pyo0adR*.7kCMdnj\" \
+             data-dominant-color=\"#336699\" style=\"background-color: #336699\" />\n\
+             ![remote](https://example.com/a.png)"
+        );
+    }
+}
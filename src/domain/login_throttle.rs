@@ -0,0 +1,115 @@
+use chrono::Duration;
+
+/// Who failed logins are counted against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThrottleKind {
+    /// The username tried, whether such an account exists or not.
+    Account,
+    /// The address the attempt came from.
+    Ip,
+}
+
+impl ThrottleKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Account => "account",
+            Self::Ip => "ip",
+        }
+    }
+}
+
+impl std::str::FromStr for ThrottleKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "account" => Ok(Self::Account),
+            "ip" => Ok(Self::Ip),
+            other => Err(format!("{other} is not a kind of login lock")),
+        }
+    }
+}
+
+/// What a number of failed logins in a row costs.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Penalty {
+    None,
+    /// Wait this long before the next attempt.
+    Backoff(Duration),
+    /// Locked out for this long.
+    Lockout(Duration),
+}
+
+#[derive(Debug, Clone)]
+pub struct ThrottlePolicy {
+    /// Failures taken without waiting, for typos.
+    pub free_attempts: u32,
+    /// The first wait, doubled with every further failure.
+    pub backoff: Duration,
+    /// Failures that lock the account or address out.
+    pub lockout_attempts: u32,
+    pub lockout: Duration,
+}
+
+impl ThrottlePolicy {
+    pub fn penalty(&self, failures: u32) -> Penalty {
+        if failures >= self.lockout_attempts {
+            return Penalty::Lockout(self.lockout);
+        }
+        if failures <= self.free_attempts {
+            return Penalty::None;
+        }
+        // INFO: the doubling is capped, a backoff never lasts longer than a lockout
+        let doublings = (failures - self.free_attempts - 1).min(30);
+        let backoff = self
+            .backoff
+            .checked_mul(1 << doublings)
+            .unwrap_or(self.lockout);
+        Penalty::Backoff(backoff.min(self.lockout))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> ThrottlePolicy {
+        ThrottlePolicy {
+            free_attempts: 2,
+            backoff: Duration::seconds(1),
+            lockout_attempts: 8,
+            lockout: Duration::seconds(10),
+        }
+    }
+
+    #[test]
+    fn waits_double_after_the_free_attempts_until_the_lockout() {
+        let penalties: Vec<Penalty> = (1..=9).map(|failures| policy().penalty(failures)).collect();
+        assert_eq!(
+            penalties,
+            [
+                Penalty::None,
+                Penalty::None,
+                Penalty::Backoff(Duration::seconds(1)),
+                Penalty::Backoff(Duration::seconds(2)),
+                Penalty::Backoff(Duration::seconds(4)),
+                Penalty::Backoff(Duration::seconds(8)),
+                Penalty::Backoff(Duration::seconds(10)),
+                Penalty::Lockout(Duration::seconds(10)),
+                Penalty::Lockout(Duration::seconds(10)),
+            ]
+        );
+    }
+
+    #[test]
+    fn long_runs_of_failures_do_not_overflow_the_backoff() {
+        let policy = ThrottlePolicy {
+            lockout_attempts: u32::MAX,
+            ..policy()
+        };
+        assert_eq!(
+            policy.penalty(1000),
+            Penalty::Backoff(Duration::seconds(10))
+        );
+    }
+}
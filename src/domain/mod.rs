@@ -1,2 +1,12 @@
+pub mod attachments;
+pub mod audit;
+pub mod credentials;
+pub mod html;
+pub mod images;
+pub mod login_throttle;
 pub mod posts;
+pub mod reviews;
+pub mod roles;
+pub mod storage_usage;
+pub mod totp;
 pub mod users;
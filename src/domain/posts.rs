@@ -14,6 +14,23 @@ pub struct PostMetadata {
     pub title: String,
     pub slug: String,
     pub date: DateTime<Utc>,
+    /// Whether EXIF and similar metadata are stripped from the photos of the post.
+    #[serde(
+        default = "strip_metadata_default",
+        skip_serializing_if = "is_strip_metadata_default"
+    )]
+    pub strip_metadata: bool,
+    /// Username of the user the post is credited to, the uploader when left out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+}
+
+fn strip_metadata_default() -> bool {
+    true
+}
+
+fn is_strip_metadata_default(strip_metadata: &bool) -> bool {
+    *strip_metadata == strip_metadata_default()
 }
 
 impl TryFrom<&str> for PostMetadata {
@@ -28,6 +45,8 @@ pub struct PostBuilder {
     title: Option<String>,
     slug: Option<String>,
     date: Option<DateTime<Utc>>,
+    strip_metadata: Option<bool>,
+    author: Option<String>,
     #[serde(skip)]
     content: Option<String>,
 }
@@ -81,6 +100,11 @@ impl PostBuilder {
         self
     }
 
+    pub fn with_author(mut self, username: &str) -> Self {
+        self.author = Some(username.to_string());
+        self
+    }
+
     // Build method to construct the Post object, setting default values if fields are None
     pub fn build(self) -> Post {
         let id = Uuid::new_v4();
@@ -90,13 +114,20 @@ impl PostBuilder {
             .slug
             .map_or_else(|| slug::slugify(&title), |x| slug::slugify(&x));
         let date = self.date.unwrap_or_else(Utc::now);
+        let strip_metadata = self.strip_metadata.unwrap_or_else(strip_metadata_default);
         let content = self
             .content
             .filter(|x| !x.is_empty())
             .unwrap_or_else(|| "hello".to_string());
 
         Post {
-            metadata: PostMetadata { title, slug, date },
+            metadata: PostMetadata {
+                title,
+                slug,
+                date,
+                strip_metadata,
+                author: self.author,
+            },
             content,
         }
     }
@@ -191,6 +222,22 @@ slug: "My first post"
         assert_eq!(post.metadata.slug, "my-first-post".to_string());
     }
 
+    #[test]
+    fn metadata_is_stripped_unless_the_post_opts_out() {
+        let raw = r#"
+------
+title: "Raw photos"
+strip_metadata: false
+------
+        "#;
+
+        let post = PostBuilder::from_raw_post(raw).build();
+        assert!(!post.metadata.strip_metadata);
+
+        let post = PostBuilder::default().with_title("Default").build();
+        assert!(post.metadata.strip_metadata);
+    }
+
     #[test]
     fn try_get_post_return_err_with_invalid_metadata() {
         let raw = r#"
@@ -213,6 +260,19 @@ definitely_not_metadata
         assert_eq!(post.content, "content directly");
     }
 
+    #[test]
+    fn the_author_is_read_from_the_metadata_and_written_back() {
+        let raw = "---\ntitle: Guest post\nauthor: neil\n---\n\nHello\n";
+
+        let post = PostBuilder::from_raw_post(raw).build();
+        assert_eq!(post.metadata.author.as_deref(), Some("neil"));
+        assert!(post.to_string().contains("author: neil"));
+
+        let post = PostBuilder::new().with_title("Unsigned").build();
+        assert_eq!(post.metadata.author, None);
+        assert!(!post.to_string().contains("author"));
+    }
+
     #[test]
     fn post_display_gives_right_format() {
         let post = Post {
@@ -220,6 +280,8 @@ definitely_not_metadata
                 title: "My first post".to_string(),
                 slug: "my-first-post".to_string(),
                 date: Utc::now(),
+                strip_metadata: true,
+                author: None,
             },
             content: "Hello world".to_string(),
         };
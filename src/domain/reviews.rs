@@ -0,0 +1,148 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Where the latest revision of a post stands in the editorial review. Readers are shown the
+/// last revision that was published, a post that never was stays hidden from them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostStatus {
+    /// Waiting for a reviewer.
+    InReview,
+    /// Sent back to its author, until a new revision is saved.
+    ChangesRequested,
+    Published,
+}
+
+impl PostStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InReview => "in_review",
+            Self::ChangesRequested => "changes_requested",
+            Self::Published => "published",
+        }
+    }
+
+    /// The status of a revision saved over `current`, or of a new post. Users who may publish
+    /// keep the status as it is, so copy editing a post under review does not approve it, every
+    /// revision of anyone else goes to review.
+    pub fn on_save(can_publish: bool, current: Option<Self>) -> Self {
+        match (can_publish, current) {
+            (true, Some(current)) => current,
+            (true, None) => Self::Published,
+            (false, _) => Self::InReview,
+        }
+    }
+
+    /// The status a review decision leads to, only posts under review are decided on.
+    pub fn decide(self, decision: ReviewDecision) -> Result<Self, String> {
+        if self != Self::InReview {
+            return Err(format!("The post is {}, not in review", self.as_str()));
+        }
+        Ok(match decision {
+            ReviewDecision::Approve => Self::Published,
+            ReviewDecision::RequestChanges => Self::ChangesRequested,
+        })
+    }
+}
+
+impl std::str::FromStr for PostStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "in_review" => Ok(Self::InReview),
+            "changes_requested" => Ok(Self::ChangesRequested),
+            "published" => Ok(Self::Published),
+            other => Err(format!("unknown post status `{other}`")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReviewDecision {
+    Approve,
+    RequestChanges,
+}
+
+/// A reviewer's remark on a revision, on one of its lines or on the whole of it.
+#[derive(Debug, PartialEq)]
+pub struct ReviewComment {
+    pub body: String,
+    pub line: Option<i32>,
+}
+
+impl ReviewComment {
+    /// Checks a comment on a revision of `lines` lines, counted from 1.
+    pub fn parse(body: &str, line: Option<i32>, lines: usize) -> Result<Self, String> {
+        let body = body.trim();
+        if body.is_empty() {
+            return Err("comment is empty".into());
+        }
+        if body.graphemes(true).count() > 10_000 {
+            return Err("comment is too long".into());
+        }
+        if line.is_some_and(|line| line < 1 || line as usize > lines) {
+            return Err(format!("the revision has no such line, it has {lines}"));
+        }
+
+        Ok(Self {
+            body: body.to_string(),
+            line,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_publishers_skip_the_review() {
+        use PostStatus::*;
+
+        assert_eq!(PostStatus::on_save(true, None), Published);
+        assert_eq!(PostStatus::on_save(true, Some(InReview)), InReview);
+        assert_eq!(PostStatus::on_save(true, Some(Published)), Published);
+        assert_eq!(PostStatus::on_save(false, None), InReview);
+        assert_eq!(PostStatus::on_save(false, Some(ChangesRequested)), InReview);
+        assert_eq!(PostStatus::on_save(false, Some(Published)), InReview);
+    }
+
+    #[test]
+    fn decisions_are_only_taken_on_posts_in_review() {
+        use PostStatus::*;
+
+        assert_eq!(InReview.decide(ReviewDecision::Approve), Ok(Published));
+        assert_eq!(
+            InReview.decide(ReviewDecision::RequestChanges),
+            Ok(ChangesRequested)
+        );
+        assert!(ChangesRequested.decide(ReviewDecision::Approve).is_err());
+        assert!(Published.decide(ReviewDecision::RequestChanges).is_err());
+    }
+
+    #[test]
+    fn statuses_round_trip_through_their_name() {
+        for status in [
+            PostStatus::InReview,
+            PostStatus::ChangesRequested,
+            PostStatus::Published,
+        ] {
+            assert_eq!(status.as_str().parse::<PostStatus>(), Ok(status));
+        }
+    }
+
+    #[test]
+    fn comments_are_on_an_existing_line_or_on_none() {
+        assert_eq!(
+            ReviewComment::parse("  Typo here ", Some(3), 3),
+            Ok(ReviewComment {
+                body: "Typo here".to_string(),
+                line: Some(3)
+            })
+        );
+        assert!(ReviewComment::parse("Overall fine", None, 0).is_ok());
+        assert!(ReviewComment::parse("Typo here", Some(4), 3).is_err());
+        assert!(ReviewComment::parse("Typo here", Some(0), 3).is_err());
+        assert!(ReviewComment::parse("   ", None, 3).is_err());
+        assert!(ReviewComment::parse(&"a".repeat(10_001), None, 3).is_err());
+    }
+}
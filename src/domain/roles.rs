@@ -0,0 +1,120 @@
+/// What a user may do, from the most to the least trusted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Everything, including the admin endpoints and the roles of other users.
+    Admin,
+    /// Writes, edits, reviews and deletes any post, and manages the newsletter subscribers.
+    Editor,
+    /// Writes posts and edits their own ones, which are published once an editor approved them.
+    Author,
+    /// Only reads, as any visitor does.
+    #[default]
+    Viewer,
+}
+
+/// An action guarded by a role, checked with [`Role::can`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    CreatePosts,
+    EditOwnPosts,
+    EditAnyPost,
+    /// Publishes without a review, and reviews the posts of everyone else.
+    PublishPosts,
+    DeletePosts,
+    ManageSubscriptions,
+    ManageUsers,
+    Administer,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Admin => "admin",
+            Self::Editor => "editor",
+            Self::Author => "author",
+            Self::Viewer => "viewer",
+        }
+    }
+
+    pub fn can(&self, permission: Permission) -> bool {
+        use Permission::*;
+
+        match self {
+            Self::Admin => true,
+            Self::Editor => matches!(
+                permission,
+                CreatePosts
+                    | EditOwnPosts
+                    | EditAnyPost
+                    | PublishPosts
+                    | DeletePosts
+                    | ManageSubscriptions
+            ),
+            Self::Author => matches!(permission, CreatePosts | EditOwnPosts),
+            Self::Viewer => false,
+        }
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(Self::Admin),
+            "editor" => Ok(Self::Editor),
+            "author" => Ok(Self::Author),
+            "viewer" => Ok(Self::Viewer),
+            other => Err(format!(
+                "unknown role `{other}`, expected `admin`, `editor`, `author` or `viewer`"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roles_grant_less_the_less_trusted_they_are() {
+        let granted = |role: Role| {
+            [
+                Permission::CreatePosts,
+                Permission::EditOwnPosts,
+                Permission::EditAnyPost,
+                Permission::PublishPosts,
+                Permission::DeletePosts,
+                Permission::ManageSubscriptions,
+                Permission::ManageUsers,
+                Permission::Administer,
+            ]
+            .into_iter()
+            .filter(|permission| role.can(*permission))
+            .count()
+        };
+
+        assert_eq!(granted(Role::Admin), 8);
+        assert_eq!(granted(Role::Editor), 6);
+        assert_eq!(granted(Role::Author), 2);
+        assert_eq!(granted(Role::Viewer), 0);
+        assert!(!Role::Editor.can(Permission::ManageUsers));
+        assert!(!Role::Author.can(Permission::DeletePosts));
+        assert!(!Role::Author.can(Permission::PublishPosts));
+    }
+
+    #[test]
+    fn roles_round_trip_through_their_name() {
+        for role in [Role::Admin, Role::Editor, Role::Author, Role::Viewer] {
+            assert_eq!(role.as_str().parse::<Role>(), Ok(role));
+        }
+        assert!("owner".parse::<Role>().is_err());
+    }
+}
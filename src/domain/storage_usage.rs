@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::components::blob_storage::PostManifest;
+use crate::configuration::StorageQuotaSettings;
+
+/// Mime type the markdown content of a post is accounted under.
+pub const CONTENT_MIME: &str = "text/markdown";
+
+/// Files of one type and the bytes they take.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct TypeUsage {
+    pub mime: String,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// Bytes taken by the files of a post, by type. Generated copies such as resized images are
+/// accounted with the file they were made from, under its type given by `mime_of`.
+///
+/// Files sharing their bytes with other posts are accounted in each of them.
+pub fn measure(manifest: &PostManifest, mime_of: impl Fn(&Path) -> String) -> Vec<TypeUsage> {
+    let mut by_type = BTreeMap::<String, TypeUsage>::new();
+    for (path, entry) in &manifest.files {
+        let mime = match *path == manifest.content {
+            true => CONTENT_MIME.to_string(),
+            false => mime_of(path),
+        };
+        let bytes = entry.object.size + entry.variants.values().map(|v| v.size).sum::<u64>();
+
+        let usage = by_type.entry(mime.clone()).or_insert(TypeUsage {
+            mime,
+            files: 0,
+            bytes: 0,
+        });
+        usage.files += 1;
+        usage.bytes += bytes;
+    }
+
+    by_type.into_values().collect()
+}
+
+pub fn total_bytes(usage: &[TypeUsage]) -> u64 {
+    usage.iter().map(|usage| usage.bytes).sum()
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum QuotaExceeded {
+    #[error("The post would take {bytes} bytes, but posts are limited to {quota} bytes")]
+    PerPost { bytes: u64, quota: u64 },
+    #[error("The post would take {bytes} bytes, but only {available} of the {quota} bytes of the storage quota are left")]
+    Global {
+        bytes: u64,
+        available: u64,
+        quota: u64,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, serde::Serialize)]
+pub struct StorageQuota {
+    pub per_post: Option<u64>,
+    pub global: Option<u64>,
+}
+
+impl From<&StorageQuotaSettings> for StorageQuota {
+    fn from(settings: &StorageQuotaSettings) -> Self {
+        Self {
+            per_post: settings.per_post,
+            global: settings.global,
+        }
+    }
+}
+
+impl StorageQuota {
+    /// Checks that a post of `bytes` fits, while the other posts take `used_elsewhere` bytes.
+    pub fn check(&self, bytes: u64, used_elsewhere: u64) -> Result<(), QuotaExceeded> {
+        if let Some(quota) = self.per_post.filter(|quota| bytes > *quota) {
+            return Err(QuotaExceeded::PerPost { bytes, quota });
+        }
+        if let Some(quota) = self.global {
+            let available = quota.saturating_sub(used_elsewhere);
+            if bytes > available {
+                return Err(QuotaExceeded::Global {
+                    bytes,
+                    available,
+                    quota,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::blob_storage::{ManifestEntry, StoredObject};
+
+    fn object(size: u64) -> StoredObject {
+        StoredObject {
+            size,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn usage_is_grouped_by_type_with_variants_counted_with_their_original() {
+        let mut manifest = PostManifest {
+            content: "post.md".into(),
+            ..Default::default()
+        };
+        manifest.files.insert(
+            "post.md".into(),
+            ManifestEntry {
+                object: object(100),
+                ..Default::default()
+            },
+        );
+        for (path, size) in [("a.png", 1000), ("b.png", 2000)] {
+            manifest.files.insert(
+                path.into(),
+                ManifestEntry {
+                    object: object(size),
+                    variants: [("w480".to_string(), object(10))].into(),
+                    ..Default::default()
+                },
+            );
+        }
+        manifest.files.insert(
+            "notes.txt".into(),
+            ManifestEntry {
+                object: object(5),
+                ..Default::default()
+            },
+        );
+
+        let usage = measure(&manifest, |path| {
+            match path.extension().and_then(|e| e.to_str()) {
+                Some("png") => "image/png".to_string(),
+                _ => "text/plain".to_string(),
+            }
+        });
+
+        let summary: Vec<_> = usage
+            .iter()
+            .map(|usage| (usage.mime.as_str(), usage.files, usage.bytes))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("image/png", 2, 3020),
+                ("text/markdown", 1, 100),
+                ("text/plain", 1, 5)
+            ]
+        );
+        assert_eq!(total_bytes(&usage), 3125);
+    }
+
+    #[test]
+    fn quota_checks_the_post_and_what_the_other_posts_left() {
+        let quota = StorageQuota {
+            per_post: Some(100),
+            global: Some(250),
+        };
+
+        assert_eq!(quota.check(100, 150), Ok(()));
+        assert_eq!(
+            quota.check(101, 0),
+            Err(QuotaExceeded::PerPost {
+                bytes: 101,
+                quota: 100
+            })
+        );
+        assert_eq!(
+            quota.check(60, 200),
+            Err(QuotaExceeded::Global {
+                bytes: 60,
+                available: 50,
+                quota: 250
+            })
+        );
+        assert_eq!(StorageQuota::default().check(u64::MAX, u64::MAX), Ok(()));
+    }
+}
@@ -0,0 +1,190 @@
+//! Time-based one-time passwords, as in [RFC 6238](https://www.rfc-editor.org/rfc/rfc6238) with
+//! the defaults authenticator apps expect: HMAC-SHA1, 6 digits and 30 second steps.
+
+use ring::hmac;
+
+use super::credentials::{random, RandomnessError};
+
+const SECRET_LEN: usize = 20;
+const STEP_SECS: u64 = 30;
+const DIGITS: u32 = 6;
+/// Steps accepted on each side of the current one, for clocks drifting apart.
+const SKEW_STEPS: u64 = 1;
+const RECOVERY_CODES: usize = 10;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// A new random secret, base32 encoded as authenticator apps take it.
+pub fn new_secret() -> Result<String, RandomnessError> {
+    Ok(base32_encode(&random::<SECRET_LEN>()?))
+}
+
+/// The `otpauth://` URI authenticator apps enroll from, usually shown as a QR code.
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &str) -> String {
+    let issuer = percent_encode(issuer);
+    format!(
+        "otpauth://totp/{issuer}:{}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECS}",
+        percent_encode(account)
+    )
+}
+
+/// Checks `code` against the base32 `secret` at `unix_time`. Returns the step it matched, which
+/// must be recorded and passed as `last_step` next time so a code cannot be used twice.
+pub fn verify(secret: &str, code: &str, unix_time: u64, last_step: Option<u64>) -> Option<u64> {
+    let secret = base32_decode(secret)?;
+    let code: u32 = code.trim().parse().ok()?;
+    let current = unix_time / STEP_SECS;
+
+    (current.saturating_sub(SKEW_STEPS)..=current + SKEW_STEPS)
+        .filter(|step| last_step.is_none_or(|last| *step > last))
+        .find(|step| code_at(&secret, *step) == code)
+}
+
+/// The code of the base32 `secret` at `unix_time`, as an authenticator app shows it.
+pub fn code(secret: &str, unix_time: u64) -> Option<String> {
+    let secret = base32_decode(secret)?;
+    Some(format!(
+        "{:0width$}",
+        code_at(&secret, unix_time / STEP_SECS),
+        width = DIGITS as usize
+    ))
+}
+
+fn code_at(secret: &[u8], step: u64) -> u32 {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let digest = hmac::sign(&key, &step.to_be_bytes());
+    let digest = digest.as_ref();
+
+    // INFO: the dynamic truncation of RFC 4226
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes(digest[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+    binary % 10u32.pow(DIGITS)
+}
+
+/// Single-use codes to sign in with when the authenticator is lost, as `xxxxx-xxxxx`.
+pub fn new_recovery_codes() -> Result<Vec<String>, RandomnessError> {
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let code = base32_encode(&random::<7>()?).to_lowercase();
+            Ok(format!("{}-{}", &code[..5], &code[5..10]))
+        })
+        .collect()
+}
+
+/// Recovery codes are compared without their dash or case, as people type them.
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[(buffer >> bits) as usize & 0x1f] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[(buffer << (5 - bits)) as usize & 0x1f] as char);
+    }
+    encoded
+}
+
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in encoded.trim_end_matches('=').bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // INFO: the SHA-1 secret of the RFC 6238 test vectors
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn codes_match_the_rfc_test_vectors() {
+        for (time, code) in [
+            (59, 287082),
+            (1111111109, 81804),
+            (1234567890, 5924),
+            (2000000000, 279037),
+        ] {
+            assert_eq!(code_at(RFC_SECRET, time / STEP_SECS), code);
+        }
+    }
+
+    #[test]
+    fn codes_verify_once_within_the_skew() {
+        let secret = base32_encode(RFC_SECRET);
+
+        assert_eq!(verify(&secret, "287082", 59, None), Some(1));
+        assert_eq!(verify(&secret, "287082", 59 + STEP_SECS, None), Some(1));
+        assert_eq!(verify(&secret, "287082", 59 + 3 * STEP_SECS, None), None);
+        assert_eq!(verify(&secret, "287082", 59, Some(1)), None);
+        assert_eq!(verify(&secret, "000000", 59, None), None);
+        assert_eq!(verify(&secret, "not a code", 59, None), None);
+        assert_eq!(code(&secret, 1234567890).as_deref(), Some("005924"));
+    }
+
+    #[test]
+    fn base32_round_trips() {
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        for len in 0..12 {
+            let bytes: Vec<u8> = (0..len).map(|i: u8| i.wrapping_mul(37)).collect();
+            assert_eq!(base32_decode(&base32_encode(&bytes)).unwrap(), bytes);
+        }
+        assert_eq!(base32_decode("MZXW6YTBOI======").unwrap(), b"foobar");
+        assert!(base32_decode("not base32!").is_none());
+    }
+
+    #[test]
+    fn otpauth_uris_escape_the_account() {
+        let uri = otpauth_uri("pine tails", "neil@example.com", "ABC");
+        assert_eq!(
+            uri,
+            "otpauth://totp/pine%20tails:neil%40example.com?secret=ABC&issuer=pine%20tails&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+
+    #[test]
+    fn recovery_codes_are_distinct_and_normalized() {
+        let codes = new_recovery_codes().unwrap();
+        assert_eq!(codes.len(), RECOVERY_CODES);
+        assert_eq!(codes[0].len(), 11);
+        assert_ne!(codes[0], codes[1]);
+        assert_eq!(
+            normalize_recovery_code(&format!(" {} ", codes[0].to_uppercase())),
+            codes[0].replace('-', "")
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use secrecy::{ExposeSecret, SecretBox};
 use unicode_segmentation::UnicodeSegmentation;
 
 pub struct NewUser {
@@ -11,6 +12,16 @@ pub struct UserName(String);
 #[derive(Debug, Default)]
 pub struct UserEmail(String);
 
+/// A password chosen by a user, only ever kept hashed.
+#[derive(Debug)]
+pub struct UserPassword(SecretBox<String>);
+
+impl UserPassword {
+    pub fn expose(&self) -> &SecretBox<String> {
+        &self.0
+    }
+}
+
 impl AsRef<str> for UserName {
     fn as_ref(&self) -> &str {
         &self.0
@@ -58,6 +69,54 @@ impl TryFrom<String> for UserName {
     }
 }
 
+/// What a user tells about themselves on their public author profile. Blank fields are left out.
+#[derive(Debug, Default, PartialEq)]
+pub struct UserProfile {
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+}
+
+impl UserProfile {
+    pub fn parse(display_name: &str, bio: &str) -> Result<Self, String> {
+        let display_name = Some(display_name.trim()).filter(|name| !name.is_empty());
+        let bio = Some(bio.trim()).filter(|bio| !bio.is_empty());
+
+        if let Some(name) = display_name {
+            if name.graphemes(true).count() > 64 {
+                return Err("display name is too long".into());
+            }
+            if name.chars().any(char::is_control) {
+                return Err("display name contains control characters".into());
+            }
+        }
+        if bio.is_some_and(|bio| bio.graphemes(true).count() > 2000) {
+            return Err("bio is too long".into());
+        }
+
+        Ok(Self {
+            display_name: display_name.map(str::to_string),
+            bio: bio.map(str::to_string),
+        })
+    }
+}
+
+impl TryFrom<SecretBox<String>> for UserPassword {
+    type Error = String;
+
+    fn try_from(password: SecretBox<String>) -> Result<Self, Self::Error> {
+        let length = password.expose_secret().graphemes(true).count();
+        if length < 12 {
+            return Err("password must be at least 12 characters long".into());
+        }
+
+        if length > 128 {
+            return Err("password is too long".into());
+        }
+
+        Ok(Self(password))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -69,4 +128,29 @@ mod tests {
         let email = SafeEmail().fake::<String>();
         assert!(UserEmail::try_from(email).is_ok());
     }
+
+    #[test]
+    fn passwords_must_be_between_12_and_128_characters() {
+        let password = |length: usize| SecretBox::new(Box::new("é".repeat(length)));
+
+        assert!(UserPassword::try_from(password(11)).is_err());
+        assert!(UserPassword::try_from(password(12)).is_ok());
+        assert!(UserPassword::try_from(password(128)).is_ok());
+        assert!(UserPassword::try_from(password(129)).is_err());
+    }
+
+    #[test]
+    fn profiles_are_trimmed_and_bounded() {
+        assert_eq!(
+            UserProfile::parse("  Neil  ", " \n"),
+            Ok(UserProfile {
+                display_name: Some("Neil".to_string()),
+                bio: None,
+            })
+        );
+        assert!(UserProfile::parse(&"é".repeat(64), "").is_ok());
+        assert!(UserProfile::parse(&"é".repeat(65), "").is_err());
+        assert!(UserProfile::parse("Neil\u{7}", "").is_err());
+        assert!(UserProfile::parse("", &"a".repeat(2001)).is_err());
+    }
 }
@@ -1,3 +1,4 @@
+pub mod cli;
 pub mod components;
 pub mod configuration;
 pub mod domain;
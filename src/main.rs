@@ -1,11 +1,21 @@
 use anyhow::Result;
+use chrono::Duration;
+use clap::Parser;
+use pine_tails::cli::{self, Cli, Command};
+use pine_tails::components::blob_migration::{follow_blob_migration, watch_blob_migration};
+use pine_tails::components::confirmations::{purge_pending, ConfirmationKind};
+use pine_tails::components::staged_writes::recover_staged_writes;
+use pine_tails::components::storage_usage::backfill_storage_usage;
 use pine_tails::configuration::get_configurations;
+use pine_tails::routes::purge_expired_uploads;
 use pine_tails::startup::engine::Engine;
 use pine_tails::startup::prepare::Kits;
-use pine_tails::telemetry::{get_subscriber, init_subscriber, LoggerOutbound};
+use pine_tails::telemetry::{get_subscriber, init_subscriber, spawn_with_tracing, LoggerOutbound};
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let cli = Cli::parse();
+
     // Load Configurations
     let config = get_configurations().expect("Failed to read configuration");
 
@@ -18,8 +28,58 @@ async fn main() -> Result<()> {
     );
     init_subscriber(log_subscriber);
 
-    let kits = Kits::prepare(&config)?;
-    Engine::build(config, kits)?.spinup().await?;
+    match cli.command {
+        None | Some(Command::Serve) => {
+            let kits = Kits::prepare(&config)?;
+            // INFO: blobs written before content addressing are unreadable until imported
+            let _ret = kits
+                .blob_storage
+                .import_legacy_blobs()
+                .await
+                .inspect_err(|e| tracing::error!("{e:?}"));
+            // INFO: serving is still possible with unsettled blobs, they are retried next time
+            let grace_period =
+                Duration::seconds(config.blob_storage.recovery_grace_period_secs as i64);
+            let _ret = recover_staged_writes(&kits.db_pool, &kits.blob_storage, grace_period)
+                .await
+                .inspect_err(|e| tracing::error!("{e:?}"));
+            let _ret = purge_expired_uploads(&kits.db_pool, &kits.blob_storage)
+                .await
+                .inspect_err(|e| tracing::error!("{e:?}"));
+            let confirmations = [
+                (ConfirmationKind::User, &config.users.confirmation),
+                (
+                    ConfirmationKind::Subscription,
+                    &config.newsletter.confirmation,
+                ),
+            ];
+            for (kind, settings) in confirmations {
+                let _ret = purge_pending(&kits.db_pool, settings, kind)
+                    .await
+                    .inspect_err(|e| tracing::error!("{e:?}"));
+            }
+            let _ret = backfill_storage_usage(&kits.db_pool, &kits.blob_storage)
+                .await
+                .inspect_err(|e| tracing::error!("{e:?}"));
+            if kits.blob_storage.migration().is_some() {
+                let switched = follow_blob_migration(&kits.db_pool, &kits.blob_storage)
+                    .await
+                    .inspect_err(|e| tracing::error!("{e:?}"));
+                if !matches!(switched, Ok(true)) {
+                    spawn_with_tracing(watch_blob_migration(
+                        kits.db_pool.clone(),
+                        kits.blob_storage.clone(),
+                    ));
+                }
+            }
+            Engine::build(config, kits)?.spinup().await?;
+        }
+        Some(Command::Gc(args)) => cli::collect_garbage(&config, args).await?,
+        Some(Command::Scrub) => cli::scrub(&config).await?,
+        Some(Command::RotateKey) => cli::rotate_key(&config).await?,
+        Some(Command::MigrateStorage(args)) => cli::migrate_storage(&config, args).await?,
+        Some(Command::SetRole(args)) => cli::set_role(&config, args).await?,
+    }
 
     Ok(())
 }
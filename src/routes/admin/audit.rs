@@ -0,0 +1,116 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use super::AdminError;
+use crate::components::sessions::Session;
+use crate::domain::roles::Permission;
+
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    action: Option<String>,
+    actor_id: Option<Uuid>,
+    target_id: Option<Uuid>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    #[serde(default = "first_page")]
+    page: i64,
+    #[serde(default = "default_page_size")]
+    page_size: i64,
+}
+
+fn first_page() -> i64 {
+    1
+}
+
+fn default_page_size() -> i64 {
+    20
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub at: DateTime<Utc>,
+    pub action: String,
+    pub actor_id: Option<Uuid>,
+    /// The username of the actor, if they are still around.
+    pub actor: Option<String>,
+    pub target_id: Option<Uuid>,
+    pub request_id: Option<Uuid>,
+    pub ip: Option<String>,
+    pub diff: serde_json::Value,
+}
+
+/// Narrows `builder` down to the entries `query` asks for, the same for counting and listing.
+fn push_filters(builder: &mut QueryBuilder<'_, Postgres>, query: &AuditQuery) {
+    builder.push(" WHERE TRUE");
+    if let Some(action) = &query.action {
+        builder.push(" AND a.action = ").push_bind(action.clone());
+    }
+    if let Some(actor_id) = query.actor_id {
+        builder.push(" AND a.actor_id = ").push_bind(actor_id);
+    }
+    if let Some(target_id) = query.target_id {
+        builder.push(" AND a.target_id = ").push_bind(target_id);
+    }
+    if let Some(since) = query.since {
+        builder.push(" AND a.at >= ").push_bind(since);
+    }
+    if let Some(until) = query.until {
+        builder.push(" AND a.at < ").push_bind(until);
+    }
+}
+
+/// Lists the audit log, latest first, filtered by action, actor, target and time.
+#[tracing::instrument(name = "Get the audit log", skip(session, pool))]
+pub async fn get_audit_log(
+    session: Session,
+    query: web::Query<AuditQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    session.require(Permission::Administer)?;
+
+    let page = query.page.max(1);
+    let page_size = query.page_size.clamp(1, MAX_PAGE_SIZE);
+
+    let mut count = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM audit_log a");
+    push_filters(&mut count, &query);
+    let total: i64 = count
+        .build_query_scalar()
+        .fetch_one(pool.get_ref())
+        .await
+        .context("Failed to count the audit log entries")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let mut list = QueryBuilder::<Postgres>::new(
+        r#"
+        SELECT a.id, a.at, a.action, a.actor_id, u.username AS actor,
+               a.target_id, a.request_id, a.ip, a.diff
+        FROM audit_log a
+        LEFT JOIN users u ON u.id = a.actor_id
+        "#,
+    );
+    push_filters(&mut list, &query);
+    list.push(" ORDER BY a.id DESC LIMIT ")
+        .push_bind(page_size)
+        .push(" OFFSET ")
+        .push_bind((page - 1) * page_size);
+    let entries: Vec<AuditEntry> = list
+        .build_query_as()
+        .fetch_all(pool.get_ref())
+        .await
+        .context("Failed to fetch the audit log")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "entries": entries,
+        "page": page,
+        "page_size": page_size,
+        "total": total,
+    })))
+}
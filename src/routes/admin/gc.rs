@@ -0,0 +1,52 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+
+use std::collections::HashSet;
+
+use super::AdminError;
+use crate::components::blob_storage::{BlobStorage, GcOptions};
+use crate::components::sessions::Session;
+use crate::domain::roles::Permission;
+
+/// The blobs of every post row, published or waiting for review, and of every avatar, which is
+/// stored like a post of one file.
+pub async fn live_post_blobs(pool: &PgPool) -> Result<HashSet<String>, sqlx::Error> {
+    let blobs = sqlx::query_scalar!(
+        r#"
+        SELECT blob AS "blob!" FROM posts
+        UNION ALL
+        SELECT pending_blob AS "blob!" FROM posts WHERE pending_blob IS NOT NULL
+        UNION ALL
+        SELECT avatar_blob AS "blob!" FROM users WHERE avatar_blob IS NOT NULL
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(blobs.into_iter().collect())
+}
+
+#[tracing::instrument(name = "Collect blob garbage", skip(session, pool, blob_storage))]
+pub async fn collect_blob_garbage(
+    session: Session,
+    options: web::Query<GcOptions>,
+    pool: web::Data<PgPool>,
+    blob_storage: web::Data<BlobStorage>,
+) -> Result<HttpResponse, AdminError> {
+    session.require(Permission::Administer)?;
+
+    let live_blobs = live_post_blobs(pool.get_ref())
+        .await
+        .context("Failed to fetch the blobs of posts")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let report = blob_storage
+        .collect_garbage(&live_blobs, &options)
+        .await
+        .context("Failed to collect blob garbage")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    tracing::info!(?report, "Blob garbage collected");
+
+    Ok(HttpResponse::Ok().json(report))
+}
@@ -0,0 +1,75 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+
+use super::AdminError;
+use crate::components::audit::{record_audit, AuditEvent};
+use crate::components::login_throttle::{self, clear_login_failures};
+use crate::components::sessions::Session;
+use crate::domain::audit::AuditAction;
+use crate::domain::login_throttle::ThrottleKind;
+use crate::domain::roles::Permission;
+
+/// Lists the accounts and addresses that have to wait after failed logins.
+#[tracing::instrument(name = "List login locks", skip(session, pool))]
+pub async fn list_login_locks(
+    session: Session,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    session.require(Permission::Administer)?;
+
+    let locks = login_throttle::list_login_locks(pool.get_ref())
+        .await
+        .context("Failed to fetch the login locks")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    Ok(HttpResponse::Ok().json(locks))
+}
+
+/// Forgets the failed logins of an account (by username) or an address, lifting its lock.
+#[tracing::instrument(name = "Clear a login lock", skip(session, pool))]
+pub async fn clear_login_lock(
+    session: Session,
+    path: web::Path<(String, String)>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    session.require(Permission::Administer)?;
+
+    let (kind, key) = path.into_inner();
+    let kind: ThrottleKind = kind.parse().map_err(AdminError::NotFoundError)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let cleared = clear_login_failures(&mut *transaction, kind, &key)
+        .await
+        .context("Failed to clear the login lock")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+    if !cleared {
+        return Err(AdminError::NotFoundError(format!(
+            "No failed logins of {} {key}",
+            kind.as_str()
+        )));
+    }
+
+    let event = AuditEvent {
+        action: AuditAction::LoginUnlock,
+        actor_id: Some(session.user_id),
+        target_id: None,
+        diff: serde_json::json!({ "kind": kind.as_str(), "key": key }),
+    };
+    record_audit(&mut *transaction, &session.origin, event)
+        .await
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    tracing::info!(kind = kind.as_str(), key, "Login lock cleared");
+    Ok(HttpResponse::NoContent().finish())
+}
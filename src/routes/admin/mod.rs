@@ -0,0 +1,35 @@
+mod audit;
+mod gc;
+mod login_locks;
+mod scrub;
+mod storage;
+
+pub use audit::*;
+pub use gc::*;
+pub use login_locks::*;
+pub use scrub::*;
+pub use storage::*;
+
+use actix_web::{http, ResponseError};
+
+use crate::components::sessions::SessionError;
+
+#[derive(thiserror::Error, Debug)]
+pub enum AdminError {
+    #[error("{0}")]
+    NotFoundError(String),
+    #[error(transparent)]
+    SessionError(#[from] SessionError),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for AdminError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            Self::UnexpectedError(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+            Self::SessionError(e) => e.status_code(),
+            Self::NotFoundError(_) => http::StatusCode::NOT_FOUND,
+        }
+    }
+}
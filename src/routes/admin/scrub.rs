@@ -0,0 +1,95 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::types::Json;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::AdminError;
+use crate::components::blob_storage::{BlobStorage, PostBlob, ScrubReport};
+use crate::components::sessions::Session;
+use crate::domain::roles::Permission;
+use crate::telemetry::spawn_with_tracing;
+
+/// Re-hashes the files of every post, and of its revision waiting for review, and records the
+/// result as the latest scrub report.
+pub async fn scrub_blob_storage(
+    pool: &PgPool,
+    blob_storage: &BlobStorage,
+) -> anyhow::Result<ScrubReport> {
+    let posts = sqlx::query_as!(
+        PostBlob,
+        r#"
+        SELECT id AS "id!", slug AS "slug!", blob AS "blob!" FROM (
+            SELECT id, slug, blob, date FROM posts
+            UNION ALL
+            SELECT id, slug, pending_blob, date FROM posts WHERE pending_blob IS NOT NULL
+        ) blobs
+        ORDER BY date
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the blobs of posts")?;
+
+    let report = blob_storage
+        .scrub(posts)
+        .await
+        .context("Failed to scrub the blob storage")?;
+
+    sqlx::query!(
+        "INSERT INTO scrub_reports (id, finished_at, report) VALUES ($1, $2, $3)",
+        Uuid::new_v4(),
+        report.finished_at,
+        Json(&report) as _,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to save the scrub report")?;
+
+    Ok(report)
+}
+
+#[tracing::instrument(name = "Start blob scrub", skip(session, pool, blob_storage))]
+pub async fn start_blob_scrub(
+    session: Session,
+    pool: web::Data<PgPool>,
+    blob_storage: web::Data<BlobStorage>,
+) -> Result<HttpResponse, AdminError> {
+    session.require(Permission::Administer)?;
+
+    spawn_with_tracing(async move {
+        match scrub_blob_storage(&pool, &blob_storage).await {
+            Ok(report) => tracing::info!(
+                damaged_posts = report.damaged_posts.len(),
+                "Blob storage scrubbed"
+            ),
+            Err(e) => tracing::error!("{e:?}"),
+        }
+    });
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[tracing::instrument(name = "Get last blob scrub", skip(session, pool))]
+pub async fn last_blob_scrub(
+    session: Session,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    session.require(Permission::Administer)?;
+
+    let last = sqlx::query!(
+        r#"
+        SELECT report AS "report: Json<ScrubReport>"
+        FROM scrub_reports
+        ORDER BY finished_at DESC
+        LIMIT 1
+        "#
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to fetch the last scrub report")
+    .inspect_err(|e| tracing::error!("{e:?}"))?
+    .ok_or_else(|| AdminError::NotFoundError("The blob storage was never scrubbed".to_string()))?;
+
+    Ok(HttpResponse::Ok().json(last.report.0))
+}
@@ -0,0 +1,100 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use std::collections::BTreeMap;
+
+use super::AdminError;
+use crate::components::sessions::Session;
+use crate::domain::roles::Permission;
+use crate::domain::storage_usage::{total_bytes, StorageQuota, TypeUsage};
+
+#[derive(Debug, serde::Serialize)]
+pub struct PostUsage {
+    pub id: Uuid,
+    pub slug: String,
+    pub title: String,
+    pub bytes: u64,
+    pub by_type: Vec<TypeUsage>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct StorageReport {
+    pub total_bytes: u64,
+    pub quota: StorageQuota,
+    pub by_type: Vec<TypeUsage>,
+    /// Largest first.
+    pub posts: Vec<PostUsage>,
+}
+
+#[tracing::instrument(name = "Get storage usage", skip(session, pool, quota))]
+pub async fn storage_usage(
+    session: Session,
+    pool: web::Data<PgPool>,
+    quota: web::Data<StorageQuota>,
+) -> Result<HttpResponse, AdminError> {
+    session.require(Permission::Administer)?;
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT p.id, p.slug, p.title, u.mime,
+            SUM(u.files)::INTEGER AS "files!", SUM(u.bytes)::BIGINT AS "bytes!"
+        FROM post_storage_usage u
+        JOIN posts p ON p.id = u.post_id
+        GROUP BY p.id, u.mime
+        ORDER BY p.id, u.mime
+        "#
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch the storage usage")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let mut posts = BTreeMap::<Uuid, PostUsage>::new();
+    let mut by_type = BTreeMap::<String, TypeUsage>::new();
+    for row in rows {
+        let usage = TypeUsage {
+            mime: row.mime,
+            files: row.files as u64,
+            bytes: row.bytes as u64,
+        };
+
+        let total = by_type.entry(usage.mime.clone()).or_insert(TypeUsage {
+            mime: usage.mime.clone(),
+            files: 0,
+            bytes: 0,
+        });
+        total.files += usage.files;
+        total.bytes += usage.bytes;
+
+        posts
+            .entry(row.id)
+            .or_insert(PostUsage {
+                id: row.id,
+                slug: row.slug,
+                title: row.title,
+                bytes: 0,
+                by_type: Vec::new(),
+            })
+            .by_type
+            .push(usage);
+    }
+
+    let mut posts: Vec<_> = posts
+        .into_values()
+        .map(|mut post| {
+            post.bytes = total_bytes(&post.by_type);
+            post
+        })
+        .collect();
+    posts.sort_by_key(|post| std::cmp::Reverse(post.bytes));
+    let by_type: Vec<_> = by_type.into_values().collect();
+
+    Ok(HttpResponse::Ok().json(StorageReport {
+        total_bytes: total_bytes(&by_type),
+        quota: **quota,
+        by_type,
+        posts,
+    }))
+}
@@ -0,0 +1,126 @@
+use actix_files::file_extension_to_mime;
+use actix_web::mime;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{fetch_author, AuthorsError};
+use crate::components::blob_storage::BlobStorage;
+use crate::startup::engine::WebBaseUrl;
+
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[tracing::instrument(name = "Get author", skip(pool, base_url))]
+pub async fn get_author(
+    username: web::Path<String>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<WebBaseUrl>,
+) -> Result<HttpResponse, AuthorsError> {
+    let author = fetch_author(pool.get_ref(), &username).await?;
+    let avatar_url = author
+        .avatar_blob
+        .as_ref()
+        .map(|_| base_url.api_url(&format!("/authors/{}/avatar", author.username)));
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "username": author.username,
+        "display_name": author.display_name,
+        "bio": author.bio,
+        "avatar_url": avatar_url,
+        "posts": author.posts,
+    })))
+}
+
+#[tracing::instrument(name = "Get author avatar", skip(pool, blob_storage))]
+pub async fn get_author_avatar(
+    username: web::Path<String>,
+    pool: web::Data<PgPool>,
+    blob_storage: web::Data<BlobStorage>,
+) -> Result<HttpResponse, AuthorsError> {
+    let author = fetch_author(pool.get_ref(), &username).await?;
+    let blob = author
+        .avatar_blob
+        .ok_or_else(|| AuthorsError::NotFoundError(format!("{username} has no avatar")))?;
+
+    let manifest = blob_storage
+        .post_manifest(&blob)
+        .await
+        .context("Failed to read the avatar manifest")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+    let (path, entry) = manifest
+        .attachments()
+        .next()
+        .context("The avatar blob holds no file")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+    let mime = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(file_extension_to_mime)
+        .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+
+    let bytes = blob_storage
+        .read_object(&entry.object)
+        .await
+        .context("Failed to read the avatar")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    Ok(HttpResponse::Ok().content_type(mime).body(bytes))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthorPostsQuery {
+    #[serde(default = "first_page")]
+    page: i64,
+    #[serde(default = "default_page_size")]
+    page_size: i64,
+}
+
+fn first_page() -> i64 {
+    1
+}
+
+fn default_page_size() -> i64 {
+    10
+}
+
+/// The posts credited to an author, latest first, paged like [`crate::routes::get_all_posts`].
+#[tracing::instrument(name = "Get author posts", skip(pool))]
+pub async fn get_author_posts(
+    username: web::Path<String>,
+    query: web::Query<AuthorPostsQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AuthorsError> {
+    let author = fetch_author(pool.get_ref(), &username).await?;
+    let page = query.page.max(1);
+    let page_size = query.page_size.clamp(1, MAX_PAGE_SIZE);
+
+    let posts = sqlx::query_as!(
+        AuthorPost,
+        r#"
+        SELECT id, slug, title, date FROM posts
+        WHERE author_id = $1 AND published_at IS NOT NULL
+        ORDER BY date DESC
+        LIMIT $2 OFFSET $3
+        "#,
+        author.id,
+        page_size,
+        (page - 1) * page_size,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch the posts of the author")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    Ok(HttpResponse::Ok().json(posts))
+}
+
+#[derive(serde::Serialize)]
+struct AuthorPost {
+    id: Uuid,
+    slug: String,
+    title: String,
+    date: DateTime<Utc>,
+}
@@ -0,0 +1,78 @@
+//! Public profiles of the users posts are credited to, see [`crate::routes::update_profile`].
+
+mod fetch;
+
+pub use fetch::*;
+
+use actix_web::{http, ResponseError};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::roles::{Permission, Role};
+use crate::routes::users::CONFIRMED;
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthorsError {
+    #[error("{0}")]
+    NotFoundError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for AuthorsError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            Self::UnexpectedError(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+            Self::NotFoundError(_) => http::StatusCode::NOT_FOUND,
+        }
+    }
+}
+
+struct Author {
+    id: Uuid,
+    username: String,
+    display_name: Option<String>,
+    bio: Option<String>,
+    avatar_blob: Option<String>,
+    posts: i64,
+}
+
+/// The confirmed user named `username`, as long as they wrote a post or may write one, readers
+/// have no public profile.
+async fn fetch_author(pool: &PgPool, username: &str) -> Result<Author, AuthorsError> {
+    let user = sqlx::query!(
+        r#"
+        SELECT id, username, display_name, bio, avatar_blob, role,
+            (SELECT COUNT(*) FROM posts
+                WHERE author_id = users.id AND posts.published_at IS NOT NULL) AS "posts!"
+        FROM users WHERE username = $1 AND status = $2
+        "#,
+        username,
+        CONFIRMED,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the author")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let is_author = |role: &str, posts: i64| {
+        posts > 0
+            || role
+                .parse::<Role>()
+                .is_ok_and(|role| role.can(Permission::CreatePosts))
+    };
+    match user {
+        Some(user) if is_author(&user.role, user.posts) => Ok(Author {
+            id: user.id,
+            username: user.username,
+            display_name: user.display_name,
+            bio: user.bio,
+            avatar_blob: user.avatar_blob,
+            posts: user.posts,
+        }),
+        _ => Err(AuthorsError::NotFoundError(format!(
+            "Author {username} not found"
+        ))),
+    }
+}
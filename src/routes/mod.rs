@@ -1,7 +1,17 @@
+pub mod admin;
+pub mod authors;
 pub mod health_check;
 pub mod playground;
 pub mod posts;
+pub mod subscriptions;
+pub mod uploads;
+pub mod users;
 
+pub use admin::*;
+pub use authors::*;
 pub use health_check::*;
 pub use playground::*;
 pub use posts::*;
+pub use subscriptions::*;
+pub use uploads::*;
+pub use users::*;
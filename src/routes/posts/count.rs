@@ -7,8 +7,8 @@ use super::PostsError;
 #[tracing::instrument(name = "Get posts count", skip(pool))]
 pub async fn posts_count(pool: web::Data<PgPool>) -> Result<HttpResponse, PostsError> {
     tracing::info!("Getting posts count");
-    // Query to count all posts
-    let record = sqlx::query!("SELECT COUNT(*) as count FROM posts")
+    // Query to count all published posts
+    let record = sqlx::query!("SELECT COUNT(*) as count FROM posts WHERE published_at IS NOT NULL")
         .fetch_one(pool.get_ref())
         .await
         .context("Failed to fetch posts count")
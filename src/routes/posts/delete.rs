@@ -3,28 +3,44 @@ use anyhow::Context;
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use crate::components::audit::{record_audit, AuditEvent};
 use crate::components::blob_storage::BlobStorage;
+use crate::components::sessions::Session;
+use crate::domain::audit::{json_diff, AuditAction};
+use crate::domain::roles::Permission;
 
 use super::PostsError;
 
-#[tracing::instrument(name = "Delete post", skip(pool, blob_storage))]
+#[tracing::instrument(
+    name = "Delete post",
+    skip(session, pool, blob_storage),
+    fields(user = %session.username)
+)]
 pub async fn delete_post(
+    session: Session,
     post_id: web::Path<Uuid>,
     pool: web::Data<PgPool>,
     blob_storage: web::Data<BlobStorage>,
 ) -> Result<HttpResponse, PostsError> {
+    session.require(Permission::DeletePosts)?;
+
     let post_id = post_id.into_inner();
     tracing::info!(target: "Deleting post", ?post_id);
 
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
     let to_delete_post = sqlx::query!(
         r#"
         DELETE FROM posts
         WHERE id = $1
-        RETURNING id, title, slug
+        RETURNING id, title, slug, blob, pending_blob
         "#,
         post_id
     )
-    .fetch_optional(pool.get_ref())
+    .fetch_optional(&mut *transaction)
     .await
     .context(format!(
         "Failed to execute delete query on post with id: {}",
@@ -38,12 +54,39 @@ pub async fn delete_post(
         ))
     })?;
 
-    let post_blob = blob_storage.post_storage_driver(&to_delete_post.slug);
+    let event = AuditEvent {
+        action: AuditAction::PostDelete,
+        actor_id: Some(session.user_id),
+        target_id: Some(post_id),
+        diff: json_diff(
+            &serde_json::json!({
+                "title": to_delete_post.title,
+                "slug": to_delete_post.slug,
+                "blob": to_delete_post.blob,
+                "pending_blob": to_delete_post.pending_blob,
+            }),
+            &serde_json::Value::Null,
+        ),
+    };
+    record_audit(&mut *transaction, &session.origin, event)
+        .await
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
     // INFO: only warn about leftover blob files, still consider it a success
-    let _result = post_blob
-        .post_clear_all()
-        .context("Failed to delete post blob")
-        .inspect_err(|e| tracing::warn!("{e:?}"));
+    for blob in std::iter::once(&to_delete_post.blob).chain(&to_delete_post.pending_blob) {
+        let _result = blob_storage
+            .post_storage_driver(blob)
+            .post_clear_all()
+            .await
+            .context("Failed to delete post blob")
+            .inspect_err(|e| tracing::warn!("{e:?}"));
+    }
 
     tracing::info!("Post deleted: {:?}", to_delete_post);
 
@@ -1,16 +1,25 @@
-use actix_files::NamedFile;
-use actix_web::{web, HttpResponse};
+use actix_files::{file_extension_to_mime, NamedFile};
+use actix_web::http::header::{self, ContentDisposition, DispositionParam, DispositionType};
+use actix_web::mime;
+use actix_web::{web, HttpRequest, HttpResponse};
 use anyhow::Context;
 use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use uuid::Uuid;
 
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
-use crate::components::blob_storage::BlobStorage;
+use crate::components::blob_storage::{
+    sanitize_relative_path, BlobStorage, PostManifest, StoredObject,
+};
+use crate::domain::images::{
+    annotate_images, parse_variant_file_name, pick_variant, preferred_format, ImageSummary,
+};
 
 use super::PostsError;
-use super::{locate_post_content_file, read_file_to_string};
+use super::{read_post_content, read_post_manifest};
 
 // TODO: allow without query, return all
 #[tracing::instrument(name = "Get all posts with paging", skip(pool))]
@@ -33,7 +42,7 @@ pub async fn get_all_posts(
     tracing::info!(target: "Fetching posts", page, per_page);
 
     let mut builder = sqlx::QueryBuilder::<sqlx::Postgres>::new(
-        "SELECT id, slug, title, date FROM posts ORDER BY date DESC",
+        "SELECT id, slug, title, date FROM posts WHERE published_at IS NOT NULL ORDER BY date DESC",
     );
 
     // if page < 0 or per_page <= 0, return all
@@ -79,7 +88,12 @@ pub async fn get_post_by_slug(
 ) -> Result<HttpResponse, PostsError> {
     let slug = slug.into_inner();
     let post = sqlx::query!(
-        "SELECT id, slug, title, date, blob FROM posts WHERE slug = $1",
+        r#"
+        SELECT posts.id, posts.slug, posts.title, posts.date, posts.blob,
+            users.username AS "author?", users.display_name AS author_display_name
+        FROM posts LEFT JOIN users ON users.id = posts.author_id
+        WHERE posts.slug = $1 AND posts.published_at IS NOT NULL
+        "#,
         &slug,
     )
     .fetch_optional(pool.get_ref())
@@ -91,13 +105,19 @@ pub async fn get_post_by_slug(
         PostsError::NotFoundError(format!("Post with slug `{}` not found", &slug))
     })?;
 
-    let post_file_path = locate_post_content_file(&post.blob, blob_storage.get_ref())
-        .await
-        .context("Failed to locate post content file")?;
+    let manifest = read_post_manifest(&post.blob, blob_storage.get_ref()).await?;
+    let content = read_post_content(&manifest, blob_storage.get_ref()).await?;
 
-    let content = read_file_to_string(&post_file_path)
+    let images = fetch_image_summaries(pool.get_ref(), post.id)
         .await
-        .context("Failed to read post content")?;
+        .context("Failed to fetch image summaries")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let content = annotate_images(&content, |src| {
+        sanitize_relative_path(src).and_then(|path| images.get(path.to_string_lossy().as_ref()))
+    });
+
+    let attachments = list_post_attachments(&blob_storage, &manifest, &images).await;
 
     Ok(HttpResponse::Ok().json(serde_json::json!({
                 "id": post.id,
@@ -105,39 +125,248 @@ pub async fn get_post_by_slug(
                 "content": content,
                 "title": post.title,
                 "date": post.date,
+                "author": post.author.map(|username| serde_json::json!({
+                    "username": username,
+                    "display_name": post.author_display_name,
+                })),
+                "attachments": attachments,
     })))
 }
 
-#[tracing::instrument(name = "Get post attachments", skip(pool, blob_storage))]
+/// Dimensions and placeholders of the image attachments of a post, keyed by attachment path.
+async fn fetch_image_summaries(
+    pool: &PgPool,
+    post_id: Uuid,
+) -> Result<HashMap<String, ImageSummary>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT path, width, height, dominant_color, blurhash
+        FROM image_attachments WHERE post_id = $1 AND NOT pending
+        "#,
+        post_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let summary = ImageSummary {
+                width: row.width as u32,
+                height: row.height as u32,
+                dominant_color: row.dominant_color,
+                blurhash: row.blurhash,
+            };
+            (row.path, summary)
+        })
+        .collect())
+}
+
+#[derive(Debug, Serialize)]
+struct AttachmentListing {
+    path: PathBuf,
+    widths: Vec<u32>,
+    #[serde(flatten)]
+    image: Option<ImageSummary>,
+}
+
+/// Lists the attachments of a post with the widths each image is available in, so the renderer
+/// can build a `srcset` out of `?w=` links, along with the dimensions and placeholder of images.
+async fn list_post_attachments(
+    blob_storage: &BlobStorage,
+    manifest: &PostManifest,
+    images: &HashMap<String, ImageSummary>,
+) -> Vec<AttachmentListing> {
+    let mut attachments = Vec::new();
+
+    for (path, entry) in manifest.attachments() {
+        let mut widths = entry
+            .variants
+            .keys()
+            .filter_map(|name| parse_variant_file_name(name).map(|(w, _)| w))
+            .collect::<Vec<_>>();
+
+        let summary = images.get(path.to_string_lossy().as_ref());
+        if !widths.is_empty() {
+            let original_width = match summary {
+                Some(summary) => Some(summary.width),
+                None => read_image_width(blob_storage, &entry.object).await,
+            };
+            widths.extend(original_width);
+        }
+        widths.sort_unstable();
+        widths.dedup();
+
+        attachments.push(AttachmentListing {
+            path: path.to_path_buf(),
+            widths,
+            image: summary.cloned(),
+        });
+    }
+
+    attachments
+}
+
+/// Width of an image stored before its summary was recorded.
+async fn read_image_width(blob_storage: &BlobStorage, object: &StoredObject) -> Option<u32> {
+    let bytes = blob_storage.read_object(object).await.ok()?;
+    let (width, _) = image::ImageReader::new(std::io::Cursor::new(bytes))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()?;
+    Some(width)
+}
+
+/// Optional hints of which copy of an image attachment fits the client best.
+#[derive(Debug, Deserialize)]
+pub struct AttachmentQuery {
+    /// Display width in pixels, the narrowest variant at least this wide is served.
+    w: Option<u32>,
+    /// Preferred image format such as `webp`, used when a variant exists in that format. Without
+    /// it, the format is negotiated from the `Accept` header.
+    format: Option<String>,
+}
+
+#[tracing::instrument(name = "Get post attachments", skip(req, pool, blob_storage))]
 pub async fn get_post_attachment(
+    req: HttpRequest,
     pool: web::Data<PgPool>,
     slug_attachment: web::Path<(String, String)>,
+    query: web::Query<AttachmentQuery>,
     blob_storage: web::Data<BlobStorage>,
-) -> Result<NamedFile, PostsError> {
+) -> Result<HttpResponse, PostsError> {
     let (slug, attachment) = slug_attachment.into_inner();
-    let post = sqlx::query!("SELECT slug, blob FROM posts WHERE slug = $1", &slug,)
-        .fetch_optional(pool.get_ref())
-        .await
-        .context("Failed to fetch post")
-        .inspect_err(|e| tracing::error!("{e:?}"))?;
+    let post = sqlx::query!(
+        "SELECT slug, blob FROM posts WHERE slug = $1 AND published_at IS NOT NULL",
+        &slug,
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to fetch post")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
 
     let post = post.ok_or_else(|| {
         PostsError::NotFoundError(format!("Post attachment with slug `{}` not found", &slug))
     })?;
 
-    let file_path = blob_storage
-        .single_post_dir(&post.blob)
-        .join(attachment.as_str());
+    serve_post_attachment(&req, &blob_storage, &post.blob, &slug, &attachment, &query).await
+}
+
+/// Serves the `attachment` file of the post saved in `blob`, named by `post` in errors.
+pub(super) async fn serve_post_attachment(
+    req: &HttpRequest,
+    blob_storage: &BlobStorage,
+    blob: &str,
+    post: &str,
+    attachment: &str,
+    query: &AttachmentQuery,
+) -> Result<HttpResponse, PostsError> {
+    let manifest = read_post_manifest(blob, blob_storage).await?;
+    let entry = sanitize_relative_path(attachment)
+        .and_then(|relative| manifest.files.get(&relative).map(|entry| (relative, entry)));
 
-    if !file_path.exists() {
-        tracing::warn!("File not found: {}/{}", slug, attachment);
+    let Some((relative, entry)) = entry else {
+        tracing::warn!("File not found: {}/{}", post, attachment);
         return Err(PostsError::NotFoundError(format!(
             "File not found: {}/{}",
-            slug, attachment
+            post, attachment
         )));
+    };
+
+    let mut served = (
+        relative.to_string_lossy().into_owned(),
+        &entry.object,
+        entry.mime.as_deref(),
+    );
+
+    if let Some(width) = query.w {
+        let accepted = req
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .and_then(preferred_format);
+        let picked = pick_variant(
+            entry.variants.keys().map(String::as_str),
+            width,
+            query.format.as_deref().or(accepted),
+        );
+
+        if let Some((name, object)) = picked.and_then(|name| entry.variants.get_key_value(name)) {
+            served = (name.clone(), object, None);
+        }
     }
 
-    Ok(NamedFile::open(file_path)
-        .context("Failed to open file")
-        .inspect_err(|e| tracing::error!("{e:?}"))?)
+    let (name, object, sniffed) = served;
+    let (mime, disposition) = content_headers(&name, sniffed);
+    // INFO: the same URL serves WebP or the original format depending on the `Accept` header
+    let vary = (header::VARY, header::HeaderValue::from_static("Accept"));
+    let nosniff = (
+        header::X_CONTENT_TYPE_OPTIONS,
+        header::HeaderValue::from_static("nosniff"),
+    );
+
+    if let Some(path) = blob_storage.local_object_path(object) {
+        let file = NamedFile::open_async(path)
+            .await
+            .context("Failed to open file")
+            .inspect_err(|e| tracing::error!("{e:?}"))?
+            .set_content_type(mime)
+            .set_content_disposition(disposition);
+        let mut response = file.into_response(req);
+        response.headers_mut().insert(vary.0, vary.1);
+        response.headers_mut().insert(nosniff.0, nosniff.1);
+        return Ok(response);
+    }
+
+    // INFO: large videos are never held in memory, encrypted ones are decrypted as they are sent
+    let stream = blob_storage
+        .object_stream(object)
+        .await
+        .context("Failed to read file")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(mime)
+        .insert_header(disposition)
+        .insert_header(vary)
+        .insert_header(nosniff)
+        .no_chunking(object.size)
+        .streaming(stream))
+}
+
+/// Content type and disposition of a served file. The type is the one `sniffed` when the file was
+/// saved, or else taken from its logical `name`, such as for generated variants, since stored
+/// objects are named by their hash.
+///
+/// Only raster images and videos are shown inline, anything else a browser could render as a page
+/// of this origin, such as HTML or SVG, is downloaded.
+fn content_headers(name: &str, sniffed: Option<&str>) -> (mime::Mime, ContentDisposition) {
+    let name = Path::new(name);
+    let mime = match sniffed {
+        Some(sniffed) => sniffed.parse().unwrap_or(mime::APPLICATION_OCTET_STREAM),
+        None => name
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(file_extension_to_mime)
+            .unwrap_or(mime::APPLICATION_OCTET_STREAM),
+    };
+
+    let disposition = match (mime.type_(), mime.subtype()) {
+        (mime::IMAGE, mime::SVG) => DispositionType::Attachment,
+        (mime::IMAGE | mime::VIDEO, _) => DispositionType::Inline,
+        _ => DispositionType::Attachment,
+    };
+    let file_name = name
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    (
+        mime,
+        ContentDisposition {
+            disposition,
+            parameters: vec![DispositionParam::Filename(file_name)],
+        },
+    )
 }
@@ -0,0 +1,413 @@
+use actix_multipart::{Field, Multipart};
+use anyhow::Context;
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt, TryStreamExt};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use super::{process_image, PersistedAttachments, PostsError};
+use crate::components::blob_storage::{
+    relative_to, sanitize_relative_path, BlobStorage, PostStorageDriver, UnstrippablePhoto,
+};
+use crate::domain::attachments::{AttachmentPolicy, SNIFF_LEN};
+use crate::domain::posts::{Post, PostBuilder};
+use crate::domain::storage_usage::{measure, total_bytes, StorageQuota, TypeUsage};
+use crate::telemetry::spawn_blocking_with_tracing;
+
+/// Longest accepted value of a text field, such as an upload id.
+const TEXT_FIELD_LIMIT: usize = 256;
+
+/// A post form once read, its files saved with the storage driver but not staged yet.
+pub(super) struct ReceivedPost {
+    pub post: Post,
+    pub persisted: PersistedAttachments,
+    /// Resumable uploads the post was made of, to discard once it is saved.
+    pub uploads: Vec<Uuid>,
+    /// Bytes the saved files take, by type.
+    pub usage: Vec<TypeUsage>,
+}
+
+/// Reads a post form field by field: the markdown content and attachments sent as `file`, and
+/// the ids of completed resumable uploads sent as `upload`.
+///
+/// Attachments are checked against `policy` from their first bytes, then streamed into the blob
+/// storage while they arrive, so neither memory nor temporary files grow with their size. Images
+/// are the exception as decoding them needs the whole file, they are held in memory within the
+/// limit of their type.
+///
+/// The stored bytes are checked against `quota` as they grow, the other posts taking
+/// `used_elsewhere` bytes. Only the uploads of `uploader` can be attached.
+///
+/// Attachment paths are kept relative to the directory of the markdown file, so links such as
+/// `![img](images/cover.png)` keep resolving when the post is served.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn receive_post_form(
+    mut payload: Multipart,
+    driver: &mut PostStorageDriver,
+    policy: &AttachmentPolicy,
+    pool: &PgPool,
+    blob_storage: &BlobStorage,
+    uploader: Uuid,
+    quota: StorageQuota,
+    used_elsewhere: u64,
+) -> Result<ReceivedPost, PostsError> {
+    let mut form = FormReader {
+        driver,
+        policy,
+        quota,
+        used_elsewhere,
+        received: 0,
+        mimes: HashMap::new(),
+        content: None,
+        pending_images: Vec::new(),
+        persisted: PersistedAttachments::default(),
+        uploads: Vec::new(),
+    };
+
+    while let Some(field) = payload.try_next().await.map_err(invalid_form)? {
+        match field.name() {
+            Some("file") => form.receive_field(field).await?,
+            Some("upload") => {
+                form.receive_upload(field, pool, blob_storage, uploader)
+                    .await?
+            }
+            // INFO: the bytes of an unknown field are skipped when reading the next one
+            _ => continue,
+        }
+    }
+
+    form.finish().await
+}
+
+fn invalid_form(e: actix_multipart::MultipartError) -> PostsError {
+    PostsError::BadRequestError(format!("Invalid post form: {e}"))
+}
+
+struct FormReader<'a> {
+    driver: &'a mut PostStorageDriver,
+    policy: &'a AttachmentPolicy,
+    quota: StorageQuota,
+    used_elsewhere: u64,
+    /// Bytes of the request body received across every file of the form.
+    received: u64,
+    /// Sniffed type of each attachment, by path.
+    mimes: HashMap<PathBuf, &'static str>,
+    content: Option<(PathBuf, Post)>,
+    /// Images received before the content, which tells whether to strip their metadata.
+    pending_images: Vec<(PathBuf, &'static str, Vec<u8>)>,
+    persisted: PersistedAttachments,
+    uploads: Vec<Uuid>,
+}
+
+impl FormReader<'_> {
+    async fn receive_field(&mut self, field: Field) -> Result<(), PostsError> {
+        let Some(file_name) = field
+            .content_disposition()
+            .and_then(|disposition| disposition.get_filename())
+            .map(str::to_string)
+        else {
+            return Ok(());
+        };
+
+        self.receive_file(&file_name, field.map_err(invalid_form), true)
+            .await
+    }
+
+    async fn receive_upload(
+        &mut self,
+        field: Field,
+        pool: &PgPool,
+        blob_storage: &BlobStorage,
+        uploader: Uuid,
+    ) -> Result<(), PostsError> {
+        let text = read_text(field).await?;
+        let id = Uuid::parse_str(text.trim())
+            .map_err(|_| PostsError::BadRequestError(format!("Invalid upload id: {text}")))?;
+
+        let upload = sqlx::query!(
+            r#"
+            SELECT file_name, length, upload_offset
+            FROM uploads
+            WHERE id = $1 AND owner_id = $2 AND expires_at > now()
+            "#,
+            id,
+            uploader
+        )
+        .fetch_optional(pool)
+        .await
+        .context("Failed to fetch upload")
+        .inspect_err(|e| tracing::error!("{e:?}"))?
+        .ok_or_else(|| PostsError::NotFoundError(format!("Upload {id} does not exist")))?;
+        if upload.upload_offset != upload.length {
+            return Err(PostsError::BadRequestError(format!(
+                "Upload {id} is incomplete"
+            )));
+        }
+
+        let chunks = blob_storage
+            .upload_stream(&id, upload.length as u64)
+            .map_err(|e| {
+                PostsError::UnexpectedError(anyhow::Error::new(e).context("Failed to read upload"))
+            });
+        // INFO: uploads were checked against the limit of their type when created
+        self.receive_file(&upload.file_name, chunks, false).await?;
+        self.uploads.push(id);
+
+        Ok(())
+    }
+
+    /// Receives a file, `from_body` telling whether it is part of the request body rather than
+    /// an upload attached by id.
+    async fn receive_file(
+        &mut self,
+        file_name: &str,
+        stream: impl Stream<Item = Result<Bytes, PostsError>> + Unpin,
+        from_body: bool,
+    ) -> Result<(), PostsError> {
+        // INFO: a multipart field must not be polled again once it ended
+        let mut stream = stream.fuse();
+        let path = sanitize_relative_path(file_name)
+            .ok_or_else(|| PostsError::BadRequestError(format!("Invalid file path: {file_name}")))
+            .inspect_err(|e| tracing::warn!("{e:?}"))?;
+
+        if self.content.is_none() && file_name.ends_with(".md") {
+            let mut raw = Vec::new();
+            while let Some(chunk) = stream.try_next().await? {
+                self.count(chunk.len(), from_body)?;
+                raw.extend_from_slice(&chunk);
+            }
+            let raw = String::from_utf8(raw).map_err(|_| {
+                PostsError::BadRequestError(format!("{file_name} is not valid UTF-8"))
+            })?;
+
+            let post = PostBuilder::from_raw_post(&raw).build();
+            self.driver
+                .set_metadata_stripping(post.metadata.strip_metadata);
+            self.content = Some((path, post));
+
+            for (path, mime, bytes) in std::mem::take(&mut self.pending_images) {
+                self.save_image(path, mime, bytes).await?;
+            }
+            return Ok(());
+        }
+
+        let mut head = BytesMut::new();
+        while head.len() < SNIFF_LEN {
+            let Some(chunk) = stream.try_next().await? else {
+                break;
+            };
+            self.count(chunk.len(), from_body)?;
+            head.extend_from_slice(&chunk);
+        }
+        let mime = self
+            .policy
+            .check(file_name, &head)
+            .inspect_err(|e| tracing::warn!("Attachment rejected: {e}"))?;
+        self.mimes.insert(path.clone(), mime);
+
+        if mime.starts_with("image/") {
+            let mut bytes = head.to_vec();
+            while let Some(chunk) = stream.try_next().await? {
+                self.count(chunk.len(), from_body)?;
+                self.check_size(file_name, mime, (bytes.len() + chunk.len()) as u64)?;
+                bytes.extend_from_slice(&chunk);
+            }
+
+            match self.content {
+                Some(_) => self.save_image(path, mime, bytes).await?,
+                None => self.pending_images.push((path, mime, bytes)),
+            }
+            return Ok(());
+        }
+
+        let mut writer = self
+            .driver
+            .post_attachment_writer()
+            .await
+            .context("Failed to start storing attachment")
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+        let written = async {
+            writer
+                .write(head.freeze())
+                .await
+                .context("Failed to store attachment")?;
+            while let Some(chunk) = stream.try_next().await? {
+                self.count(chunk.len(), from_body)?;
+                self.check_size(file_name, mime, writer.size() + chunk.len() as u64)?;
+                self.check_quota(writer.size() + chunk.len() as u64)?;
+                writer
+                    .write(chunk)
+                    .await
+                    .context("Failed to store attachment")?;
+            }
+            Ok::<_, PostsError>(())
+        }
+        .await;
+
+        if let Err(e) = written {
+            let _ret = writer
+                .abort()
+                .await
+                .context("Failed to abort storing attachment")
+                .inspect_err(|e| tracing::warn!("{e:?}"));
+            return Err(e);
+        }
+
+        self.driver
+            .post_save_written(&path, writer, mime)
+            .await
+            .context("Failed to store attachment")
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+        Ok(())
+    }
+
+    /// Summarizes an image, saves its responsive variants and then the image itself.
+    async fn save_image(
+        &mut self,
+        path: PathBuf,
+        mime: &'static str,
+        bytes: Vec<u8>,
+    ) -> Result<(), PostsError> {
+        let image_path = path.clone();
+        let ((summary, variants), bytes) = spawn_blocking_with_tracing(move || {
+            let processed = process_image(&image_path, &bytes, mime);
+            (processed, bytes)
+        })
+        .await
+        .context("Failed to process image")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+        self.persisted
+            .images
+            .extend(summary.map(|summary| (path.clone(), summary)));
+
+        for variant in variants {
+            self.driver
+                .post_save_variant(&path, &variant.file_name(), variant.bytes)
+                .await
+                .context("Failed to store image variant")
+                .inspect_err(|e| tracing::error!("{e:?}"))?;
+        }
+
+        let saved = match self.driver.post_save_attachment(&path, bytes).await {
+            // INFO: storing the photo as it is could leak the location it was taken at
+            Err(e) if UnstrippablePhoto::is(&e) => {
+                tracing::warn!("{e:?}");
+                return Err(PostsError::UnprocessableEntityError(format!(
+                    "The metadata of {path:?} cannot be read to be stripped"
+                )));
+            }
+            saved => saved
+                .context("Failed to store image")
+                .inspect_err(|e| tracing::error!("{e:?}"))?,
+        };
+        if let Some(metadata) = saved {
+            self.persisted.stripped_photos.push((path, metadata));
+        }
+
+        self.check_quota(0)
+    }
+
+    /// Counts `len` bytes received, those of the request body being limited as a whole.
+    fn count(&mut self, len: usize, from_body: bool) -> Result<(), PostsError> {
+        if !from_body {
+            return Ok(());
+        }
+        self.received += len as u64;
+        if self.received > self.policy.total_limit {
+            return Err(PostsError::PayloadTooLargeError(format!(
+                "Posts are limited to {} bytes",
+                self.policy.total_limit
+            )));
+        }
+        Ok(())
+    }
+
+    fn check_size(&self, file_name: &str, mime: &str, size: u64) -> Result<(), PostsError> {
+        Ok(self
+            .policy
+            .check_mime(file_name, mime, size)
+            .inspect_err(|e| tracing::warn!("Attachment rejected: {e}"))?)
+    }
+
+    /// Checks the bytes stored so far, plus `pending` bytes not saved yet, against the quota.
+    fn check_quota(&self, pending: u64) -> Result<(), PostsError> {
+        let stored: u64 = self.driver.manifest().objects().map(|o| o.size).sum();
+        Ok(self
+            .quota
+            .check(stored + pending, self.used_elsewhere)
+            .inspect_err(|e| tracing::warn!("Post rejected: {e}"))?)
+    }
+
+    /// Saves the content once every file is received, and re-roots the attachments at its
+    /// directory.
+    async fn finish(self) -> Result<ReceivedPost, PostsError> {
+        let (content_path, post) = self
+            .content
+            .context("Failed to handle posts because the post content is missing")
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+        let content_dir = content_path.parent().unwrap_or(Path::new(""));
+        self.driver.post_relocate(content_dir);
+        let content_name = content_path
+            .file_name()
+            .map(PathBuf::from)
+            .unwrap_or_else(|| content_path.clone());
+        self.driver
+            .post_save_content(&content_name, &post.content)
+            .await
+            .context("Failed to save post content")
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+        let mimes: HashMap<_, _> = self
+            .mimes
+            .into_iter()
+            .map(|(path, mime)| (relative_to(path, content_dir), mime))
+            .collect();
+        let usage = measure(self.driver.manifest(), |path| {
+            mimes
+                .get(path)
+                .copied()
+                .unwrap_or("application/octet-stream")
+                .to_string()
+        });
+        self.quota
+            .check(total_bytes(&usage), self.used_elsewhere)
+            .inspect_err(|e| tracing::warn!("Post rejected: {e}"))?;
+
+        let mut persisted = self.persisted;
+        for (path, _) in &mut persisted.images {
+            *path = relative_to(std::mem::take(path), content_dir);
+        }
+        for (path, _) in &mut persisted.stripped_photos {
+            *path = relative_to(std::mem::take(path), content_dir);
+        }
+
+        Ok(ReceivedPost {
+            post,
+            persisted,
+            uploads: self.uploads,
+            usage,
+        })
+    }
+}
+
+async fn read_text(mut field: Field) -> Result<String, PostsError> {
+    let mut text = Vec::new();
+    while let Some(chunk) = field.try_next().await.map_err(invalid_form)? {
+        if text.len() + chunk.len() > TEXT_FIELD_LIMIT {
+            return Err(PostsError::BadRequestError(
+                "Text field is too long".to_string(),
+            ));
+        }
+        text.extend_from_slice(&chunk);
+    }
+
+    String::from_utf8(text)
+        .map_err(|_| PostsError::BadRequestError("Text field is not valid UTF-8".to_string()))
+}
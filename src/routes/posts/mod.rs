@@ -1,32 +1,55 @@
 mod count;
 mod delete;
 mod fetch;
+mod form;
+mod review;
 mod update;
 mod upload;
 
 pub use count::*;
 pub use delete::*;
 pub use fetch::*;
+pub use review::*;
 pub use update::*;
 pub use upload::*;
 
-use actix_multipart::form::tempfile::TempFile;
 use actix_web::{http, ResponseError};
 use anyhow::Context;
 use regex::Regex;
-use sqlx::PgPool;
-use tokio::fs::File;
-use tokio::io::AsyncReadExt;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
 
 use std::path::{Path, PathBuf};
 
-use crate::components::blob_storage::BlobStorage;
-use crate::domain::posts::{Post, PostBuilder};
+use crate::components::blob_storage::{BlobStorage, PostManifest};
+use crate::components::sessions::{Session, SessionError};
+use crate::domain::attachments::AttachmentRejection;
+use crate::domain::images::{
+    is_resizable, read_upright, render_variants, summarize, ImageSummary, ImageVariant,
+    PhotoMetadata,
+};
+use crate::domain::roles::Permission;
+use crate::domain::storage_usage::QuotaExceeded;
+use crate::routes::users::CONFIRMED;
 
 #[derive(thiserror::Error, Debug)]
 pub enum PostsError {
     #[error("{0}")]
     NotFoundError(String),
+    #[error("{0}")]
+    BadRequestError(String),
+    #[error("{0}")]
+    UnsupportedMediaTypeError(String),
+    #[error("{0}")]
+    UnprocessableEntityError(String),
+    #[error("{0}")]
+    PayloadTooLargeError(String),
+    #[error("{0}")]
+    ForbiddenError(String),
+    #[error("{0}")]
+    ConflictError(String),
+    #[error(transparent)]
+    SessionError(#[from] SessionError),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -35,90 +58,282 @@ impl ResponseError for PostsError {
     fn status_code(&self) -> actix_web::http::StatusCode {
         match self {
             Self::UnexpectedError(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+            Self::SessionError(e) => e.status_code(),
             Self::NotFoundError(_) => http::StatusCode::NOT_FOUND,
+            Self::BadRequestError(_) => http::StatusCode::BAD_REQUEST,
+            Self::UnsupportedMediaTypeError(_) => http::StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::UnprocessableEntityError(_) => http::StatusCode::UNPROCESSABLE_ENTITY,
+            Self::PayloadTooLargeError(_) => http::StatusCode::PAYLOAD_TOO_LARGE,
+            Self::ForbiddenError(_) => http::StatusCode::FORBIDDEN,
+            Self::ConflictError(_) => http::StatusCode::CONFLICT,
         }
     }
 }
 
-async fn read_file_to_string(path: &Path) -> Result<String, PostsError> {
-    let mut file = File::open(path)
-        .await
-        .context("Failed to open post content file")
-        .inspect_err(|e| tracing::error!("{e:?}"))?;
+impl From<QuotaExceeded> for PostsError {
+    fn from(exceeded: QuotaExceeded) -> Self {
+        Self::PayloadTooLargeError(exceeded.to_string())
+    }
+}
 
-    let mut content = String::new();
-    file.read_to_string(&mut content)
-        .await
-        .context("Failed to read post content")
-        .inspect_err(|e| tracing::error!("{e:?}"))?;
+impl From<AttachmentRejection> for PostsError {
+    fn from(rejection: AttachmentRejection) -> Self {
+        match rejection {
+            AttachmentRejection::UnsupportedType { .. }
+            | AttachmentRejection::MismatchedExtension { .. } => {
+                Self::UnsupportedMediaTypeError(rejection.to_string())
+            }
+            AttachmentRejection::TooLarge { .. } => {
+                Self::PayloadTooLargeError(rejection.to_string())
+            }
+        }
+    }
+}
 
-    Ok(content)
+/// Editors edit any post, authors only the ones they uploaded.
+fn check_can_edit(session: &Session, owner_id: Option<Uuid>) -> Result<(), PostsError> {
+    if session.can(Permission::EditAnyPost) {
+        return Ok(());
+    }
+
+    session.require(Permission::EditOwnPosts)?;
+    if owner_id != Some(session.user_id) {
+        return Err(PostsError::ForbiddenError(
+            "Authors may only edit their own posts".to_string(),
+        ));
+    }
+    Ok(())
 }
 
-async fn locate_post_content_file(blob: &str, blob_storage: &BlobStorage) -> Option<PathBuf> {
-    let post_dir = blob_storage.single_post_dir(blob);
+/// The user a post is credited to: the `author` named by its front matter, or whoever uploads
+/// it. Crediting somebody else than the uploader or `current`, the user the post is already
+/// credited to, takes the permission to edit any post.
+async fn resolve_author(
+    pool: &PgPool,
+    session: &Session,
+    author: Option<&str>,
+    current: Option<Uuid>,
+) -> Result<Uuid, PostsError> {
+    let Some(username) = author.filter(|username| *username != session.username) else {
+        return Ok(session.user_id);
+    };
 
-    let mut entries = tokio::fs::read_dir(post_dir.clone())
-        .await
-        .context(format!("Failed to read post directory: {post_dir:?}"))
-        .inspect_err(|e| tracing::error!("{e:?}"))
-        .ok()?;
-
-    while let Some(entry) = entries.next_entry().await.ok()? {
-        let path = entry.path();
-        if path.extension().map(|ext| ext == "md").unwrap_or(false) {
-            return Some(path);
-        }
+    let author_id = sqlx::query_scalar!(
+        "SELECT id FROM users WHERE username = $1 AND status = $2",
+        username,
+        CONFIRMED
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the author")?;
+
+    // INFO: keeping the credit a post already has credits nobody new
+    let keeps_credit = author_id.is_some() && author_id == current;
+    if !keeps_credit && !session.can(Permission::EditAnyPost) {
+        return Err(PostsError::ForbiddenError(
+            "Only editors may credit a post to somebody else".to_string(),
+        ));
     }
 
-    None
+    author_id.ok_or_else(|| PostsError::BadRequestError(format!("No user is named {username}")))
 }
 
-async fn split_post_content_from_files(files: &[TempFile]) -> Result<Post, PostsError> {
-    let post = files
-        .iter()
-        .find(|f| {
-            f.file_name
-                .as_ref()
-                .map(|x| x.ends_with(".md"))
-                .unwrap_or(false)
-        })
-        .context("Failed to handle posts because the post content is missing")
+async fn read_post_manifest(
+    blob: &str,
+    blob_storage: &BlobStorage,
+) -> Result<PostManifest, PostsError> {
+    Ok(blob_storage
+        .post_manifest(blob)
+        .await
+        .context(format!("Failed to read manifest of post blob: {blob}"))
+        .inspect_err(|e| tracing::error!("{e:?}"))?)
+}
+
+/// The markdown of the post saved in `manifest`.
+async fn read_post_content(
+    manifest: &PostManifest,
+    blob_storage: &BlobStorage,
+) -> Result<String, PostsError> {
+    let content_entry = manifest
+        .content_entry()
+        .context("Failed to locate post content file")
         .inspect_err(|e| tracing::error!("{e:?}"))?;
 
-    let raw = read_file_to_string(post.file.path())
+    let content = blob_storage
+        .read_object(&content_entry.object)
         .await
         .context("Failed to read post content")
         .inspect_err(|e| tracing::error!("{e:?}"))?;
+    Ok(String::from_utf8(content)
+        .context("Post content is not valid UTF-8")
+        .inspect_err(|e| tracing::error!("{e:?}"))?)
+}
 
-    let pb = PostBuilder::from_raw_post(&raw);
+/// Photo metadata stripped while saving, keyed by the attachment path.
+type StrippedPhotos = Vec<(PathBuf, PhotoMetadata)>;
 
-    Ok(pb.build())
+/// What was learnt about the attachments while saving them, to be saved in the database.
+#[derive(Debug, Default)]
+struct PersistedAttachments {
+    stripped_photos: StrippedPhotos,
+    images: Vec<(PathBuf, ImageSummary)>,
 }
 
-fn persist_post_and_attachments(
-    files: Vec<TempFile>,
-    post: Post,
-    blob: String,
-    blob_storage: &BlobStorage,
-) -> std::io::Result<()> {
-    let mut local_driver = blob_storage.post_storage_driver(&blob);
-    local_driver.try_init()?;
+/// Summarizes an image attachment and renders its responsive variants.
+///
+/// A broken image is still kept as it is, it just won't get a summary nor variants.
+fn process_image(
+    path: &Path,
+    bytes: &[u8],
+    mime: &str,
+) -> (Option<ImageSummary>, Vec<ImageVariant>) {
+    let Some((img, format)) = read_upright(bytes)
+        .context(format!("Failed to decode image {path:?}"))
+        .inspect_err(|e| tracing::warn!("{e:?}"))
+        .ok()
+    else {
+        return (None, Vec::new());
+    };
 
-    for f in files {
-        if f.file_name.is_none() {
-            continue;
-        }
-        let file_name = f.file_name.as_ref().unwrap().to_string();
+    let summary = summarize(&img)
+        .context(format!("Failed to summarize {path:?}"))
+        .inspect_err(|e| tracing::warn!("{e:?}"))
+        .ok();
 
-        if file_name.ends_with(".md") {
-            local_driver.post_save_content(&file_name, &post.content)?;
-        } else {
-            local_driver.post_save_attachment(&file_name, f)?;
-        }
+    let variants = if is_resizable(mime) {
+        render_variants(&img, format)
+            .context(format!("Failed to render variants of {path:?}"))
+            .inspect_err(|e| tracing::warn!("{e:?}"))
+            .unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+
+    (summary, variants)
+}
+
+/// Replaces the dimensions and placeholders of the image attachments of a post, of its revision
+/// waiting for review when `pending`.
+async fn save_image_summaries(
+    transaction: &mut Transaction<'_, Postgres>,
+    post_id: Uuid,
+    pending: bool,
+    images: Vec<(PathBuf, ImageSummary)>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM image_attachments WHERE post_id = $1 AND pending = $2",
+        post_id,
+        pending
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    for (path, summary) in images {
+        let path = path.to_string_lossy();
+        sqlx::query!(
+            r#"
+            INSERT INTO image_attachments
+                (post_id, pending, path, width, height, dominant_color, blurhash)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+            post_id,
+            pending,
+            path.as_ref(),
+            summary.width as i32,
+            summary.height as i32,
+            summary.dominant_color,
+            summary.blurhash,
+        )
+        .execute(&mut **transaction)
+        .await?;
     }
 
-    local_driver.confirm_saved();
+    Ok(())
+}
+
+/// Replaces the privately kept photo metadata of a post, of its revision waiting for review when
+/// `pending`. It is never served, only meant for authoring features such as sorting a trip by
+/// capture date.
+async fn save_photo_metadata(
+    transaction: &mut Transaction<'_, Postgres>,
+    post_id: Uuid,
+    pending: bool,
+    photos: StrippedPhotos,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM photo_metadata WHERE post_id = $1 AND pending = $2",
+        post_id,
+        pending
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    for (path, metadata) in photos {
+        let path = path.to_string_lossy();
+        sqlx::query!(
+            r#"
+            INSERT INTO photo_metadata
+                (post_id, pending, path, captured_at, latitude, longitude)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            post_id,
+            pending,
+            path.as_ref(),
+            metadata.captured_at,
+            metadata.latitude,
+            metadata.longitude,
+        )
+        .execute(&mut **transaction)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Makes the image summaries, storage usage and photo metadata of the revision of a post waiting
+/// for review the published ones, in place of the published revision's.
+async fn publish_pending_records(
+    transaction: &mut Transaction<'_, Postgres>,
+    post_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM image_attachments WHERE post_id = $1 AND NOT pending",
+        post_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        "UPDATE image_attachments SET pending = FALSE WHERE post_id = $1",
+        post_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM post_storage_usage WHERE post_id = $1 AND NOT pending",
+        post_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        "UPDATE post_storage_usage SET pending = FALSE WHERE post_id = $1",
+        post_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM photo_metadata WHERE post_id = $1 AND NOT pending",
+        post_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        "UPDATE photo_metadata SET pending = FALSE WHERE post_id = $1",
+        post_id
+    )
+    .execute(&mut **transaction)
+    .await?;
 
     Ok(())
 }
@@ -0,0 +1,626 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{
+    generate_uniq_slug, publish_pending_records, read_post_content, read_post_manifest,
+    serve_post_attachment, AttachmentQuery, PostsError,
+};
+use crate::components::audit::{record_audit, AuditEvent};
+use crate::components::blob_storage::BlobStorage;
+use crate::components::email_delivery::EmailClient;
+use crate::components::newsletter::notify_subscribers;
+use crate::components::reviews::{notify_review, ReviewNotice};
+use crate::components::sessions::Session;
+use crate::domain::audit::{json_diff, AuditAction};
+use crate::domain::reviews::{PostStatus, ReviewComment, ReviewDecision};
+use crate::domain::roles::{Permission, Role};
+use crate::routes::users::CONFIRMED;
+use crate::startup::engine::WebBaseUrl;
+use crate::telemetry::spawn_with_tracing;
+
+/// A post as its review sees it, the revision waiting for review rather than the published one.
+struct ReviewedPost {
+    title: String,
+    blob: String,
+    owner_id: Option<Uuid>,
+    status: PostStatus,
+    revision: i32,
+}
+
+async fn fetch_reviewed_post(pool: &PgPool, post_id: Uuid) -> Result<ReviewedPost, PostsError> {
+    let post = sqlx::query!(
+        r#"
+        SELECT COALESCE(pending_title, title) AS "title!", COALESCE(pending_blob, blob) AS "blob!",
+            owner_id, status, revision
+        FROM posts WHERE id = $1
+        "#,
+        post_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch post")
+    .inspect_err(|e| tracing::error!("{e:?}"))?
+    .ok_or_else(|| PostsError::NotFoundError(format!("Post {post_id} not found")))?;
+
+    let status = post
+        .status
+        .parse::<PostStatus>()
+        .map_err(anyhow::Error::msg)
+        .context("Failed to parse the status of the post")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    Ok(ReviewedPost {
+        title: post.title,
+        blob: post.blob,
+        owner_id: post.owner_id,
+        status,
+        revision: post.revision,
+    })
+}
+
+/// The review of a post is shared between its owner and the users who may publish.
+fn check_can_follow_review(session: &Session, post: &ReviewedPost) -> Result<(), PostsError> {
+    if post.owner_id == Some(session.user_id) || session.can(Permission::PublishPosts) {
+        return Ok(());
+    }
+    Err(PostsError::ForbiddenError(
+        "Only the owner of the post and its reviewers follow its review".to_string(),
+    ))
+}
+
+#[derive(serde::Serialize)]
+struct ReviewCommentEntry {
+    id: Uuid,
+    revision: i32,
+    /// Written about an earlier revision, whose content is gone, so its `line` may no longer
+    /// match the current content.
+    outdated: bool,
+    line: Option<i32>,
+    body: String,
+    author: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+/// The current revision of a post with every comment of its review, the ones written about an
+/// earlier revision marked outdated.
+#[tracing::instrument(
+    name = "Get post review",
+    skip(session, pool, blob_storage),
+    fields(user = %session.username)
+)]
+pub async fn get_post_review(
+    session: Session,
+    post_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    blob_storage: web::Data<BlobStorage>,
+) -> Result<HttpResponse, PostsError> {
+    let post_id = post_id.into_inner();
+    let post = fetch_reviewed_post(pool.get_ref(), post_id).await?;
+    check_can_follow_review(&session, &post)?;
+
+    let people = sqlx::query!(
+        r#"
+        SELECT owner.username AS "owner?", reviewer.username AS "reviewer?"
+        FROM posts
+            LEFT JOIN users owner ON owner.id = posts.owner_id
+            LEFT JOIN users reviewer ON reviewer.id = posts.reviewer_id
+        WHERE posts.id = $1
+        "#,
+        post_id
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to fetch the owner and reviewer of the post")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let comments = sqlx::query_as!(
+        ReviewCommentEntry,
+        r#"
+        SELECT c.id, c.revision, c.revision <> $2 AS "outdated!", c.line, c.body,
+            users.username AS "author?", c.created_at
+        FROM review_comments c LEFT JOIN users ON users.id = c.author_id
+        WHERE c.post_id = $1
+        ORDER BY c.created_at, c.id
+        "#,
+        post_id,
+        post.revision
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch the review comments")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let manifest = read_post_manifest(&post.blob, blob_storage.get_ref()).await?;
+    let content = read_post_content(&manifest, blob_storage.get_ref()).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "id": post_id,
+        "title": post.title,
+        "status": post.status.as_str(),
+        "revision": post.revision,
+        "owner": people.owner,
+        "reviewer": people.reviewer,
+        "content": content,
+        "comments": comments,
+    })))
+}
+
+/// An attachment of the current revision of a post, for its review, whether the post is
+/// published or not.
+#[tracing::instrument(
+    name = "Get post review attachment",
+    skip(req, session, pool, query, blob_storage),
+    fields(user = %session.username)
+)]
+pub async fn get_review_attachment(
+    req: HttpRequest,
+    session: Session,
+    id_attachment: web::Path<(Uuid, String)>,
+    query: web::Query<AttachmentQuery>,
+    pool: web::Data<PgPool>,
+    blob_storage: web::Data<BlobStorage>,
+) -> Result<HttpResponse, PostsError> {
+    let (post_id, attachment) = id_attachment.into_inner();
+    let post = fetch_reviewed_post(pool.get_ref(), post_id).await?;
+    check_can_follow_review(&session, &post)?;
+
+    serve_post_attachment(
+        &req,
+        &blob_storage,
+        &post.blob,
+        &post_id.to_string(),
+        &attachment,
+        &query,
+    )
+    .await
+}
+
+#[derive(serde::Deserialize)]
+pub struct ReviewCommentForm {
+    body: String,
+    line: Option<i32>,
+}
+
+/// Comments on the current revision of a post, on one of its lines when `line` is given.
+#[tracing::instrument(
+    name = "Comment post review",
+    skip(session, form, pool, blob_storage),
+    fields(user = %session.username)
+)]
+pub async fn add_review_comment(
+    session: Session,
+    post_id: web::Path<Uuid>,
+    form: web::Form<ReviewCommentForm>,
+    pool: web::Data<PgPool>,
+    blob_storage: web::Data<BlobStorage>,
+) -> Result<HttpResponse, PostsError> {
+    let post_id = post_id.into_inner();
+    let post = fetch_reviewed_post(pool.get_ref(), post_id).await?;
+    check_can_follow_review(&session, &post)?;
+    if post.status == PostStatus::Published {
+        return Err(PostsError::ConflictError(
+            "The post is published, it is not under review".to_string(),
+        ));
+    }
+
+    let manifest = read_post_manifest(&post.blob, blob_storage.get_ref()).await?;
+    let content = read_post_content(&manifest, blob_storage.get_ref()).await?;
+    let comment = ReviewComment::parse(&form.body, form.line, content.lines().count())
+        .map_err(PostsError::BadRequestError)?;
+
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO review_comments (id, post_id, revision, author_id, line, body)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        id,
+        post_id,
+        post.revision,
+        session.user_id,
+        comment.line,
+        comment.body,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to save the review comment")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "id": id,
+        "revision": post.revision,
+    })))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ReviewerForm {
+    username: String,
+}
+
+/// Asks a user who may publish to review a post, they are the one told of its next revisions.
+#[tracing::instrument(
+    name = "Assign reviewer",
+    skip(session, form, pool, email_client, base_url),
+    fields(user = %session.username, reviewer = %form.username)
+)]
+pub async fn assign_reviewer(
+    session: Session,
+    post_id: web::Path<Uuid>,
+    form: web::Form<ReviewerForm>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<WebBaseUrl>,
+) -> Result<HttpResponse, PostsError> {
+    session.require(Permission::PublishPosts)?;
+    let post_id = post_id.into_inner();
+
+    let reviewer = sqlx::query!(
+        "SELECT id, role FROM users WHERE username = $1 AND status = $2",
+        form.username,
+        CONFIRMED
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to fetch the reviewer")
+    .inspect_err(|e| tracing::error!("{e:?}"))?
+    .filter(|user| {
+        user.role
+            .parse::<Role>()
+            .is_ok_and(|role| role.can(Permission::PublishPosts))
+    })
+    .ok_or_else(|| {
+        PostsError::BadRequestError(format!("{} may not review posts", form.username))
+    })?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let previous = sqlx::query!(
+        "SELECT status, reviewer_id FROM posts WHERE id = $1 FOR UPDATE",
+        post_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to fetch post")
+    .inspect_err(|e| tracing::error!("{e:?}"))?
+    .ok_or_else(|| PostsError::NotFoundError(format!("Post {post_id} not found")))?;
+
+    if previous.status == PostStatus::Published.as_str() {
+        return Err(PostsError::ConflictError(
+            "The post is published, it is not under review".to_string(),
+        ));
+    }
+
+    sqlx::query!(
+        "UPDATE posts SET reviewer_id = $1 WHERE id = $2",
+        reviewer.id,
+        post_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to assign the reviewer")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let event = AuditEvent {
+        action: AuditAction::ReviewAssign,
+        actor_id: Some(session.user_id),
+        target_id: Some(post_id),
+        diff: json_diff(
+            &serde_json::json!({ "reviewer_id": previous.reviewer_id }),
+            &serde_json::json!({ "reviewer_id": reviewer.id }),
+        ),
+    };
+    record_audit(&mut *transaction, &session.origin, event)
+        .await
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    spawn_with_tracing(async move {
+        let _ret = notify_review(
+            &pool,
+            &email_client,
+            &base_url,
+            post_id,
+            ReviewNotice::Assigned,
+        )
+        .await
+        .inspect_err(|e| tracing::error!("{e:?}"));
+    });
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(serde::Deserialize)]
+pub struct ReviewDecisionForm {
+    /// The revision the reviewer decided on, a later one is left for them to read first.
+    revision: i32,
+}
+
+/// Publishes a post under review, its revision replacing the one published before if any.
+/// Subscribers only hear of its first publication.
+#[tracing::instrument(
+    name = "Approve post",
+    skip(session, form, pool, blob_storage, email_client, base_url),
+    fields(user = %session.username, revision = form.revision)
+)]
+pub async fn approve_post(
+    session: Session,
+    post_id: web::Path<Uuid>,
+    form: web::Form<ReviewDecisionForm>,
+    pool: web::Data<PgPool>,
+    blob_storage: web::Data<BlobStorage>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<WebBaseUrl>,
+) -> Result<HttpResponse, PostsError> {
+    let post_id = post_id.into_inner();
+    let decided = decide_review(
+        &session,
+        &pool,
+        post_id,
+        form.revision,
+        ReviewDecision::Approve,
+    )
+    .await?;
+
+    // INFO: a replaced blob left behind is collected as garbage
+    if let Some(replaced_blob) = &decided.replaced_blob {
+        let _ret = blob_storage
+            .discard_blob(replaced_blob)
+            .await
+            .context(format!(
+                "Failed to remove the replaced blob {replaced_blob}"
+            ))
+            .inspect_err(|e| tracing::warn!("{e:?}"));
+    }
+
+    spawn_with_tracing(async move {
+        let _ret = notify_review(
+            &pool,
+            &email_client,
+            &base_url,
+            post_id,
+            ReviewNotice::Approved,
+        )
+        .await
+        .inspect_err(|e| tracing::error!("{e:?}"));
+
+        if decided.first_publication {
+            let _ret = notify_subscribers(
+                &pool,
+                &email_client,
+                &base_url,
+                &decided.title,
+                &decided.slug,
+            )
+            .await
+            .inspect_err(|e| tracing::error!("{e:?}"));
+        }
+    });
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Sends a post under review back to its owner, the comments tell what to change.
+#[tracing::instrument(
+    name = "Request post changes",
+    skip(session, form, pool, email_client, base_url),
+    fields(user = %session.username, revision = form.revision)
+)]
+pub async fn request_post_changes(
+    session: Session,
+    post_id: web::Path<Uuid>,
+    form: web::Form<ReviewDecisionForm>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<WebBaseUrl>,
+) -> Result<HttpResponse, PostsError> {
+    let post_id = post_id.into_inner();
+    decide_review(
+        &session,
+        &pool,
+        post_id,
+        form.revision,
+        ReviewDecision::RequestChanges,
+    )
+    .await?;
+
+    spawn_with_tracing(async move {
+        let _ret = notify_review(
+            &pool,
+            &email_client,
+            &base_url,
+            post_id,
+            ReviewNotice::ChangesRequested,
+        )
+        .await
+        .inspect_err(|e| tracing::error!("{e:?}"));
+    });
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// What a review decision did to the post.
+struct DecidedReview {
+    title: String,
+    slug: String,
+    first_publication: bool,
+    /// The blob of the published revision an approved one took the place of.
+    replaced_blob: Option<String>,
+}
+
+/// Moves a post under review on according to `decision`, taken on `revision` which must still be
+/// the current one. Whoever decides becomes its reviewer when none was assigned. An approved
+/// revision waiting next to a published one replaces it.
+async fn decide_review(
+    session: &Session,
+    pool: &PgPool,
+    post_id: Uuid,
+    revision: i32,
+    decision: ReviewDecision,
+) -> Result<DecidedReview, PostsError> {
+    session.require(Permission::PublishPosts)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let post = sqlx::query!(
+        r#"
+        SELECT title, slug, blob, author_id, status, revision, reviewer_id, published_at,
+            pending_blob, pending_title, pending_slug, pending_author_id
+        FROM posts WHERE id = $1 FOR UPDATE
+        "#,
+        post_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to fetch post")
+    .inspect_err(|e| tracing::error!("{e:?}"))?
+    .ok_or_else(|| PostsError::NotFoundError(format!("Post {post_id} not found")))?;
+
+    if post.revision != revision {
+        return Err(PostsError::ConflictError(format!(
+            "Revision {revision} is not the current one, the post is at revision {}",
+            post.revision
+        )));
+    }
+
+    let status = post
+        .status
+        .parse::<PostStatus>()
+        .map_err(anyhow::Error::msg)
+        .context("Failed to parse the status of the post")
+        .inspect_err(|e| tracing::error!("{e:?}"))?
+        .decide(decision)
+        .map_err(PostsError::ConflictError)?;
+    let reviewer_id = post.reviewer_id.unwrap_or(session.user_id);
+    let published_at = match status {
+        PostStatus::Published => post.published_at.or_else(|| Some(Utc::now())),
+        _ => post.published_at,
+    };
+
+    let approved_pending = post
+        .pending_blob
+        .clone()
+        .filter(|_| status == PostStatus::Published);
+    let (title, slug, blob, author_id) = match &approved_pending {
+        Some(pending_blob) => {
+            let title = post.pending_title.clone().unwrap_or(post.title.clone());
+            let slug = if title == post.title {
+                post.slug.clone()
+            } else {
+                let base_slug = post.pending_slug.as_deref().unwrap_or(&post.slug);
+                generate_uniq_slug(pool, base_slug)
+                    .await
+                    .context("Failed to generate unique slug")
+                    .inspect_err(|e| tracing::error!("{e:?}"))?
+            };
+            (title, slug, pending_blob.clone(), post.pending_author_id)
+        }
+        None => (
+            post.title.clone(),
+            post.slug.clone(),
+            post.blob.clone(),
+            post.author_id,
+        ),
+    };
+
+    sqlx::query!(
+        r#"
+        UPDATE posts
+        SET status = $1, reviewer_id = $2, published_at = $3,
+            title = $4, slug = $5, blob = $6, author_id = $7
+        WHERE id = $8
+        "#,
+        status.as_str(),
+        reviewer_id,
+        published_at,
+        title,
+        slug,
+        blob,
+        author_id,
+        post_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to save the review decision")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    if approved_pending.is_some() {
+        sqlx::query!(
+            r#"
+            UPDATE posts
+            SET pending_blob = NULL, pending_title = NULL, pending_slug = NULL,
+                pending_author_id = NULL
+            WHERE id = $1
+            "#,
+            post_id
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to clear the pending revision")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+        publish_pending_records(&mut transaction, post_id)
+            .await
+            .context("Failed to publish the records of the pending revision")
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+    }
+
+    let action = match decision {
+        ReviewDecision::Approve => AuditAction::ReviewApprove,
+        ReviewDecision::RequestChanges => AuditAction::ReviewRequestChanges,
+    };
+    let event = AuditEvent {
+        action,
+        actor_id: Some(session.user_id),
+        target_id: Some(post_id),
+        diff: json_diff(
+            &serde_json::json!({
+                "title": post.title,
+                "slug": post.slug,
+                "blob": post.blob,
+                "author_id": post.author_id,
+                "pending_blob": post.pending_blob,
+                "status": post.status,
+                "reviewer_id": post.reviewer_id,
+            }),
+            &serde_json::json!({
+                "title": title,
+                "slug": slug,
+                "blob": blob,
+                "author_id": author_id,
+                "pending_blob": approved_pending.is_none().then_some(&post.pending_blob),
+                "status": status.as_str(),
+                "reviewer_id": reviewer_id,
+            }),
+        ),
+    };
+    record_audit(&mut *transaction, &session.origin, event)
+        .await
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    Ok(DecidedReview {
+        title,
+        slug,
+        first_publication: post.published_at.is_none() && status == PostStatus::Published,
+        replaced_blob: approved_pending.map(|_| post.blob),
+    })
+}
@@ -1,36 +1,61 @@
-use actix_multipart::form::{tempfile::TempFile, MultipartForm};
+use actix_multipart::Multipart;
 use actix_web::{web, HttpResponse};
 use anyhow::Context;
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use super::form::receive_post_form;
 use super::{
-    generate_uniq_slug, persist_post_and_attachments, split_post_content_from_files, PostsError,
+    check_can_edit, generate_uniq_slug, resolve_author, save_image_summaries, save_photo_metadata,
+    PostsError,
 };
-use crate::{components::blob_storage::BlobStorage, telemetry::spawn_blocking_with_tracing};
+use crate::components::audit::{record_audit, AuditEvent};
+use crate::components::email_delivery::EmailClient;
+use crate::components::reviews::{notify_review, ReviewNotice};
+use crate::components::sessions::Session;
+use crate::components::storage_usage::{save_storage_usage, storage_used_elsewhere};
+use crate::components::{blob_storage::BlobStorage, staged_writes::StagedWrite};
+use crate::domain::attachments::AttachmentPolicy;
+use crate::domain::audit::{json_diff, AuditAction};
+use crate::domain::reviews::PostStatus;
+use crate::domain::roles::Permission;
+use crate::domain::storage_usage::StorageQuota;
+use crate::routes::uploads::discard_uploads;
+use crate::startup::engine::WebBaseUrl;
+use crate::telemetry::spawn_with_tracing;
 
-#[derive(Debug, MultipartForm)]
-pub struct UpdateForm {
-    #[multipart(rename = "file")]
-    files: Vec<TempFile>,
-}
-
-#[tracing::instrument(name = "Update post", skip(pool, blob_storage, payload))]
+// INFO: every argument is an extractor, as actix handlers take them
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Update post",
+    skip(
+        session,
+        pool,
+        blob_storage,
+        attachment_policy,
+        storage_quota,
+        payload,
+        email_client,
+        base_url
+    ),
+    fields(user = %session.username)
+)]
 pub async fn update_post(
+    session: Session,
     post_id: web::Path<Uuid>,
-    MultipartForm(payload): MultipartForm<UpdateForm>,
+    payload: Multipart,
     pool: web::Data<PgPool>,
     blob_storage: web::Data<BlobStorage>,
+    attachment_policy: web::Data<AttachmentPolicy>,
+    storage_quota: web::Data<StorageQuota>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<WebBaseUrl>,
 ) -> Result<HttpResponse, PostsError> {
     let post_id = post_id.into_inner();
-    let files = payload.files;
-    tracing::info!(target: "Updating post", ?post_id, ?files);
-
-    // Fetch the existing post from the database
-    let existing_post = sqlx::query!(
-        r#"
-        SELECT title, slug, blob FROM posts WHERE id = $1
-        "#,
+
+    // INFO: only tells who may edit the post, it is read again locked before being written
+    let editable = sqlx::query!(
+        "SELECT owner_id, status, published_at FROM posts WHERE id = $1",
         post_id
     )
     .fetch_optional(pool.get_ref())
@@ -38,61 +63,258 @@ pub async fn update_post(
     .context("Failed to fetch post")?
     .ok_or_else(|| PostsError::NotFoundError("Post to upload not found".to_string()))?;
 
-    let mut post = split_post_content_from_files(&files).await?;
+    check_can_edit(&session, editable.owner_id)?;
+    let can_publish = session.can(Permission::PublishPosts);
+
+    let new_blob = Uuid::new_v4().to_string();
+    let staged = StagedWrite::begin(pool.get_ref(), post_id, &new_blob)
+        .await
+        .context("Failed to journal the post blob")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
 
-    if existing_post.title != post.metadata.title {
-        post.metadata.slug = generate_uniq_slug(pool.get_ref(), &post.metadata.slug)
+    let saved = async {
+        // INFO: the revision replaces its own files, so only the rest counts against it, the
+        // published revision included while a pending one is saved next to it
+        let pending = saves_pending(
+            can_publish,
+            &editable.status,
+            editable.published_at.is_some(),
+        )?;
+        let used_elsewhere = storage_used_elsewhere(pool.get_ref(), Some(post_id), pending)
             .await
-            .context("Failed to generate unique slug")
+            .context("Failed to fetch the storage usage")
             .inspect_err(|e| tracing::error!("{e:?}"))?;
-    }
 
-    let mut transaction = pool
-        .begin()
-        .await
-        .context("Failed to acquire a Postgres connection from the pool")?;
+        let mut driver = blob_storage.post_storage_driver(&new_blob);
+        let received = receive_post_form(
+            payload,
+            &mut driver,
+            &attachment_policy,
+            pool.get_ref(),
+            &blob_storage,
+            session.user_id,
+            **storage_quota,
+            used_elsewhere,
+        )
+        .await?;
+        let mut post = received.post;
+        tracing::info!(target: "Updating post", ?post_id, title = post.metadata.title);
 
-    let old_blob = existing_post.blob;
-    let new_blob = Uuid::new_v4().to_string();
+        let mut transaction = pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?;
 
-    sqlx::query!(
-        r#"
-            UPDATE posts 
-            SET title = $1, slug = $2, blob = $3
-            WHERE id = $4
+        // INFO: concurrent updates are saved one after the other, each replacing the blob and
+        // counting the revision the previous one left
+        let existing_post = sqlx::query!(
+            r#"
+            SELECT title, slug, blob, author_id, status, revision, published_at,
+                pending_blob, pending_title
+            FROM posts WHERE id = $1 FOR UPDATE
             "#,
-        post.metadata.title,
-        post.metadata.slug,
-        new_blob,
-        post_id,
-    )
-    .execute(&mut *transaction)
-    .await
-    .context("Failed to update post")
-    .inspect_err(|e| tracing::error!("{e:?}"))?;
-
-    let handle = spawn_blocking_with_tracing(move || {
-        persist_post_and_attachments(files, post, new_blob, &blob_storage)?;
-        let old_blob = blob_storage.post_storage_driver(&old_blob);
-        // INFO: we ignore the error here because we don't want to fail the request
-        let _ret = old_blob
-            .post_clear_all()
-            .inspect_err(|e| tracing::error!("{e:?}"));
-        std::io::Result::Ok(())
-    });
+            post_id
+        )
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("Failed to fetch post")?
+        .ok_or_else(|| PostsError::NotFoundError("Post to upload not found".to_string()))?;
+
+        let old_status = parse_status(&existing_post.status)?;
+        let status = PostStatus::on_save(can_publish, Some(old_status));
+        let revision = existing_post.revision + 1;
+        // INFO: readers keep being served a published post while its next revision waits for review
+        let pending = existing_post.published_at.is_some() && status != PostStatus::Published;
+        let old_blob = match pending {
+            true => existing_post.pending_blob.clone(),
+            false => Some(existing_post.blob.clone()),
+        };
+
+        // INFO: a pending revision gets its unique slug once approved
+        if !pending && existing_post.title != post.metadata.title {
+            post.metadata.slug = generate_uniq_slug(pool.get_ref(), &post.metadata.slug)
+                .await
+                .context("Failed to generate unique slug")
+                .inspect_err(|e| tracing::error!("{e:?}"))?;
+        }
+
+        // INFO: a revision without an `author` keeps crediting whoever it was credited to
+        let author_id = match post.metadata.author.as_deref() {
+            Some(author) => Some(
+                resolve_author(
+                    pool.get_ref(),
+                    &session,
+                    Some(author),
+                    existing_post.author_id,
+                )
+                .await?,
+            ),
+            None => existing_post.author_id,
+        };
+
+        let update = if pending {
+            sqlx::query!(
+                r#"
+                UPDATE posts
+                SET pending_blob = $1, pending_title = $2, pending_slug = $3,
+                    pending_author_id = $4, status = $5, revision = $6
+                WHERE id = $7
+                "#,
+                new_blob,
+                post.metadata.title,
+                post.metadata.slug,
+                author_id,
+                status.as_str(),
+                revision,
+                post_id,
+            )
+        } else {
+            sqlx::query!(
+                r#"
+                UPDATE posts 
+                SET title = $1, slug = $2, blob = $3, author_id = $4, status = $5, revision = $6
+                WHERE id = $7
+                "#,
+                post.metadata.title,
+                post.metadata.slug,
+                new_blob,
+                author_id,
+                status.as_str(),
+                revision,
+                post_id,
+            )
+        };
+        update
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to update post")
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
 
-    handle
+        driver
+            .stage_saved()
+            .await
+            .context("Failed to update post blob")
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+        save_image_summaries(
+            &mut transaction,
+            post_id,
+            pending,
+            received.persisted.images,
+        )
         .await
-        .context("Failed await join handle")
-        .inspect_err(|e| tracing::error!("{e:?}"))?
-        .context("Failed to update post blob")
+        .context("Failed to save image summaries")
         .inspect_err(|e| tracing::error!("{e:?}"))?;
 
-    transaction
-        .commit()
+        save_storage_usage(&mut transaction, post_id, pending, &received.usage)
+            .await
+            .context("Failed to save storage usage")
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+        // INFO: the replaced photos are gone with the replaced blob, so their metadata goes as well
+        let stripped_photos = if attachment_policy.record_photo_metadata {
+            received.persisted.stripped_photos
+        } else {
+            Vec::new()
+        };
+        save_photo_metadata(&mut transaction, post_id, pending, stripped_photos)
+            .await
+            .context("Failed to save photo metadata")
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+        let before = serde_json::json!({
+            "title": existing_post.title,
+            "slug": existing_post.slug,
+            "blob": existing_post.blob,
+            "author_id": existing_post.author_id,
+            "pending_blob": existing_post.pending_blob,
+            "pending_title": existing_post.pending_title,
+            "status": existing_post.status,
+            "revision": existing_post.revision,
+        });
+        let mut after = before.clone();
+        if pending {
+            after["pending_blob"] = serde_json::json!(new_blob);
+            after["pending_title"] = serde_json::json!(post.metadata.title);
+        } else {
+            after["title"] = serde_json::json!(post.metadata.title);
+            after["slug"] = serde_json::json!(post.metadata.slug);
+            after["blob"] = serde_json::json!(new_blob);
+            after["author_id"] = serde_json::json!(author_id);
+        }
+        after["status"] = serde_json::json!(status.as_str());
+        after["revision"] = serde_json::json!(revision);
+
+        let event = AuditEvent {
+            action: AuditAction::PostUpdate,
+            actor_id: Some(session.user_id),
+            target_id: Some(post_id),
+            diff: json_diff(&before, &after),
+        };
+        record_audit(&mut *transaction, &session.origin, event)
+            .await
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+        staged
+            .commit(&mut transaction, old_blob.as_deref())
+            .await
+            .context("Failed to mark the post blob committed")
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit transaction")
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+        Ok::<_, PostsError>((received.uploads, old_status, status))
+    }
+    .await;
+
+    // INFO: the old blob is only removed once the post refers to the new one, whatever is left
+    // unsettled here is settled again on the next startup
+    let _ret = staged
+        .settle(pool.get_ref(), &blob_storage)
         .await
-        .context("Failed to commit transaction")
-        .inspect_err(|e| tracing::error!("{e:?}"))?;
+        .context("Failed to settle the post blob")
+        .inspect_err(|e| tracing::error!("{e:?}"));
+    let (uploads, old_status, status) = saved?;
+
+    // INFO: leftover uploads expire on their own
+    let _ret = discard_uploads(pool.get_ref(), &blob_storage, &uploads)
+        .await
+        .context("Failed to discard attached uploads")
+        .inspect_err(|e| tracing::warn!("{e:?}"));
+
+    // INFO: a revision already waiting for review does not notify the reviewers again
+    if status == PostStatus::InReview && old_status != PostStatus::InReview {
+        spawn_with_tracing(async move {
+            let _ret = notify_review(
+                &pool,
+                &email_client,
+                &base_url,
+                post_id,
+                ReviewNotice::Submitted,
+            )
+            .await
+            .inspect_err(|e| tracing::error!("{e:?}"));
+        });
+    }
 
     Ok(HttpResponse::Ok().finish())
 }
+
+fn parse_status(status: &str) -> Result<PostStatus, PostsError> {
+    let status = status
+        .parse::<PostStatus>()
+        .map_err(anyhow::Error::msg)
+        .context("Failed to parse the status of the post")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+    Ok(status)
+}
+
+/// Whether a save by someone who may, or may not, publish makes a pending revision of the post.
+fn saves_pending(can_publish: bool, status: &str, published: bool) -> Result<bool, PostsError> {
+    let status = PostStatus::on_save(can_publish, Some(parse_status(status)?));
+    Ok(published && status != PostStatus::Published)
+}
@@ -1,77 +1,217 @@
-use actix_multipart::form::{tempfile::TempFile, MultipartForm};
+use actix_multipart::Multipart;
 use actix_web::{web, HttpResponse};
 use anyhow::Context;
+use chrono::Utc;
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use super::form::receive_post_form;
 use super::{
-    generate_uniq_slug, persist_post_and_attachments, split_post_content_from_files, PostsError,
+    generate_uniq_slug, resolve_author, save_image_summaries, save_photo_metadata, PostsError,
 };
+use crate::components::audit::{record_audit, AuditEvent};
 use crate::components::blob_storage::BlobStorage;
-use crate::telemetry::spawn_blocking_with_tracing;
+use crate::components::email_delivery::EmailClient;
+use crate::components::newsletter::notify_subscribers;
+use crate::components::reviews::{notify_review, ReviewNotice};
+use crate::components::sessions::Session;
+use crate::components::staged_writes::StagedWrite;
+use crate::components::storage_usage::{save_storage_usage, storage_used_elsewhere};
+use crate::domain::attachments::AttachmentPolicy;
+use crate::domain::audit::{json_diff, AuditAction};
+use crate::domain::reviews::PostStatus;
+use crate::domain::roles::Permission;
+use crate::domain::storage_usage::StorageQuota;
+use crate::routes::uploads::discard_uploads;
+use crate::startup::engine::WebBaseUrl;
+use crate::telemetry::spawn_with_tracing;
 
-#[derive(Debug, MultipartForm)]
-pub struct UploadForm {
-    #[multipart(rename = "file")]
-    files: Vec<TempFile>,
-}
-
-#[tracing::instrument(name = "Upload post", skip(payload, pool, blob_storage))]
+// INFO: every argument is an extractor, as actix handlers take them
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Upload post",
+    skip(
+        session,
+        payload,
+        pool,
+        blob_storage,
+        attachment_policy,
+        storage_quota,
+        email_client,
+        base_url
+    ),
+    fields(user = %session.username)
+)]
 pub async fn upload_post(
-    MultipartForm(payload): MultipartForm<UploadForm>,
+    session: Session,
+    payload: Multipart,
     pool: web::Data<PgPool>,
     blob_storage: web::Data<BlobStorage>,
+    attachment_policy: web::Data<AttachmentPolicy>,
+    storage_quota: web::Data<StorageQuota>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<WebBaseUrl>,
 ) -> Result<HttpResponse, PostsError> {
-    let files = payload.files;
+    session.require(Permission::CreatePosts)?;
+    let status = PostStatus::on_save(session.can(Permission::PublishPosts), None);
 
-    tracing::info!(target: "Uploading a post", ?files);
-
-    let post = split_post_content_from_files(&files).await?;
     let id = Uuid::new_v4();
     let blob = id.to_string();
-    let uniq_slug = generate_uniq_slug(pool.get_ref(), &post.metadata.slug)
+
+    // INFO: attachments are stored while the form is read, so the blob is journaled first
+    let staged = StagedWrite::begin(pool.get_ref(), id, &blob)
         .await
-        .context("Failed to generate unique slug")
+        .context("Failed to journal the post blob")
         .inspect_err(|e| tracing::error!("{e:?}"))?;
 
-    let mut transaction = pool
-        .begin()
-        .await
-        .context("Failed to acquire a Postgres connection from the pool")?;
-
-    sqlx::query!(
-        "INSERT INTO posts (id, slug, title, blob, date) VALUES ($1, $2, $3, $4, $5)",
-        id,
-        uniq_slug,
-        post.metadata.title,
-        blob,
-        post.metadata.date
-    )
-    .execute(&mut *transaction)
-    .await
-    .context("Failed to insert post")
-    .inspect_err(|e| tracing::error!("{e:?}"))?;
-
-    let handle = spawn_blocking_with_tracing(move || {
-        persist_post_and_attachments(files, post, blob, &blob_storage)
-    });
-    handle
+    let saved = async {
+        let used_elsewhere = storage_used_elsewhere(pool.get_ref(), None, false)
+            .await
+            .context("Failed to fetch the storage usage")
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+        let mut driver = blob_storage.post_storage_driver(&blob);
+        let received = receive_post_form(
+            payload,
+            &mut driver,
+            &attachment_policy,
+            pool.get_ref(),
+            &blob_storage,
+            session.user_id,
+            **storage_quota,
+            used_elsewhere,
+        )
+        .await?;
+        let post = received.post;
+
+        tracing::info!(target: "Uploading a post", ?id, title = post.metadata.title);
+
+        let author_id =
+            resolve_author(pool.get_ref(), &session, post.metadata.author.as_deref(), None).await?;
+
+        let uniq_slug = generate_uniq_slug(pool.get_ref(), &post.metadata.slug)
+            .await
+            .context("Failed to generate unique slug")
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+        let mut transaction = pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO posts (id, slug, title, blob, date, owner_id, author_id, status, published_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#,
+            id,
+            uniq_slug,
+            post.metadata.title,
+            blob,
+            post.metadata.date,
+            session.user_id,
+            author_id,
+            status.as_str(),
+            (status == PostStatus::Published).then(Utc::now),
+        )
+        .execute(&mut *transaction)
         .await
-        .context("Failed await join handle")
-        .inspect_err(|e| tracing::error!("{e:?}"))?
-        .context("Failed to save post")
+        .context("Failed to insert post")
         .inspect_err(|e| tracing::error!("{e:?}"))?;
 
-    transaction
-        .commit()
+        driver
+            .stage_saved()
+            .await
+            .context("Failed to save post")
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+        save_image_summaries(&mut transaction, id, false, received.persisted.images)
+            .await
+            .context("Failed to save image summaries")
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+        save_storage_usage(&mut transaction, id, false, &received.usage)
+            .await
+            .context("Failed to save storage usage")
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+        if attachment_policy.record_photo_metadata {
+            save_photo_metadata(&mut transaction, id, false, received.persisted.stripped_photos)
+                .await
+                .context("Failed to save photo metadata")
+                .inspect_err(|e| tracing::error!("{e:?}"))?;
+        }
+
+        let event = AuditEvent {
+            action: AuditAction::PostUpload,
+            actor_id: Some(session.user_id),
+            target_id: Some(id),
+            diff: json_diff(
+                &serde_json::Value::Null,
+                &serde_json::json!({
+                    "title": post.metadata.title,
+                    "slug": uniq_slug,
+                    "date": post.metadata.date,
+                    "blob": blob,
+                    "author_id": author_id,
+                    "status": status.as_str(),
+                }),
+            ),
+        };
+        record_audit(&mut *transaction, &session.origin, event)
+            .await
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+        staged
+            .commit(&mut transaction, None)
+            .await
+            .context("Failed to mark the post blob committed")
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit transaction")
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+        Ok::<_, PostsError>((uniq_slug, post.metadata.title, received.uploads))
+    }
+    .await;
+
+    // INFO: whatever is left unsettled here is settled again on the next startup
+    let _ret = staged
+        .settle(pool.get_ref(), &blob_storage)
         .await
-        .context("Failed to commit transaction")
-        .inspect_err(|e| tracing::error!("{e:?}"))?;
+        .context("Failed to settle the post blob")
+        .inspect_err(|e| tracing::error!("{e:?}"));
+    let (uniq_slug, title, uploads) = saved?;
+
+    // INFO: leftover uploads expire on their own
+    let _ret = discard_uploads(pool.get_ref(), &blob_storage, &uploads)
+        .await
+        .context("Failed to discard attached uploads")
+        .inspect_err(|e| tracing::warn!("{e:?}"));
+
+    // INFO: subscribers hear of the post once it is out, reviewers while it waits for them,
+    // neither holding the response back
+    let slug = uniq_slug.clone();
+    spawn_with_tracing(async move {
+        let _ret = match status {
+            PostStatus::Published => {
+                notify_subscribers(&pool, &email_client, &base_url, &title, &slug)
+                    .await
+                    .map(|_| ())
+            }
+            _ => notify_review(&pool, &email_client, &base_url, id, ReviewNotice::Submitted).await,
+        }
+        .inspect_err(|e| tracing::error!("{e:?}"));
+    });
 
     Ok(HttpResponse::Created().json(serde_json::json!(
     {
         "slug": uniq_slug,
-        "id": id
+        "id": id,
+        "status": status.as_str()
     }
     )))
 }
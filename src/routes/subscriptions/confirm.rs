@@ -0,0 +1,30 @@
+use actix_web::{web, HttpResponse};
+use secrecy::{ExposeSecret, SecretBox};
+use sqlx::PgPool;
+
+use super::SubscriptionsError;
+use crate::components::confirmations::{confirm, ConfirmationKind};
+use crate::configuration::NewsletterSettings;
+
+#[derive(serde::Deserialize)]
+pub struct SubscriptionConfirmationQuery {
+    token: SecretBox<String>,
+}
+
+#[tracing::instrument(name = "Confirm a subscription", skip(query, pool, settings))]
+pub async fn confirm_subscription(
+    query: web::Query<SubscriptionConfirmationQuery>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<NewsletterSettings>,
+) -> Result<HttpResponse, SubscriptionsError> {
+    let subscription_id = confirm(
+        pool.get_ref(),
+        &settings.confirmation,
+        ConfirmationKind::Subscription,
+        query.token.expose_secret(),
+    )
+    .await?;
+
+    tracing::info!(%subscription_id, "Subscription confirmed");
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,99 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::SubscriptionsError;
+use crate::components::audit::{record_audit, AuditEvent};
+use crate::components::sessions::Session;
+use crate::domain::audit::{json_diff, AuditAction};
+use crate::domain::roles::Permission;
+
+#[derive(Debug, serde::Serialize)]
+pub struct Subscriber {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(
+    name = "List subscribers",
+    skip(session, pool),
+    fields(user = %session.username)
+)]
+pub async fn list_subscriptions(
+    session: Session,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SubscriptionsError> {
+    session.require(Permission::ManageSubscriptions)?;
+
+    let subscribers = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        ORDER BY subscribed_at
+        "#
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch the subscribers")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    Ok(HttpResponse::Ok().json(subscribers))
+}
+
+#[tracing::instrument(
+    name = "Remove a subscriber",
+    skip(session, pool),
+    fields(user = %session.username)
+)]
+pub async fn remove_subscription(
+    session: Session,
+    subscription_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SubscriptionsError> {
+    session.require(Permission::ManageSubscriptions)?;
+
+    let subscription_id = subscription_id.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let removed = sqlx::query!(
+        "DELETE FROM subscriptions WHERE id = $1 RETURNING email, status",
+        subscription_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to remove the subscriber")
+    .inspect_err(|e| tracing::error!("{e:?}"))?
+    .ok_or_else(|| {
+        SubscriptionsError::NotFoundError(format!("Subscription {subscription_id} not found"))
+    })?;
+
+    let event = AuditEvent {
+        action: AuditAction::SubscriptionRemove,
+        actor_id: Some(session.user_id),
+        target_id: Some(subscription_id),
+        diff: json_diff(
+            &serde_json::json!({ "email": removed.email, "status": removed.status }),
+            &serde_json::Value::Null,
+        ),
+    };
+    record_audit(&mut *transaction, &session.origin, event)
+        .await
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,46 @@
+//! Newsletter subscriptions with double opt-in: a subscription only receives posts once the link
+//! emailed to its address is followed. Every newsletter carries an unsubscribe link, which mail
+//! clients can post to in one click.
+
+mod confirm;
+mod manage;
+mod resend;
+mod subscribe;
+mod unsubscribe;
+
+pub use confirm::*;
+pub use manage::*;
+pub use resend::*;
+pub use subscribe::*;
+pub use unsubscribe::*;
+
+use actix_web::{http, ResponseError};
+
+use crate::components::confirmations::ConfirmationError;
+use crate::components::sessions::SessionError;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SubscriptionsError {
+    #[error("{0}")]
+    NotFoundError(String),
+    #[error("{0}")]
+    BadRequestError(String),
+    #[error(transparent)]
+    SessionError(#[from] SessionError),
+    #[error(transparent)]
+    ConfirmationError(#[from] ConfirmationError),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for SubscriptionsError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            Self::UnexpectedError(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+            Self::SessionError(e) => e.status_code(),
+            Self::ConfirmationError(e) => e.status_code(),
+            Self::NotFoundError(_) => http::StatusCode::NOT_FOUND,
+            Self::BadRequestError(_) => http::StatusCode::BAD_REQUEST,
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+use super::SubscriptionsError;
+use crate::components::confirmations::{resend_confirmation, ConfirmationKind};
+use crate::components::email_delivery::EmailClient;
+use crate::configuration::NewsletterSettings;
+use crate::domain::users::UserEmail;
+use crate::startup::engine::WebBaseUrl;
+
+#[derive(serde::Deserialize)]
+pub struct ResendSubscriptionForm {
+    email: String,
+}
+
+/// Sends a new confirmation link to a pending subscription, see
+/// [`resend_confirmation`].
+#[tracing::instrument(
+    name = "Resend a subscription confirmation email",
+    skip(form, pool, email_client, base_url, settings),
+    fields(email = %form.email)
+)]
+pub async fn resend_subscription_confirmation(
+    form: web::Form<ResendSubscriptionForm>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<WebBaseUrl>,
+    settings: web::Data<NewsletterSettings>,
+) -> Result<HttpResponse, SubscriptionsError> {
+    let email = UserEmail::try_from(form.into_inner().email)
+        .map_err(SubscriptionsError::BadRequestError)?;
+
+    resend_confirmation(
+        pool.get_ref(),
+        &email_client,
+        &base_url,
+        &settings.confirmation,
+        ConfirmationKind::Subscription,
+        &email,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,107 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::SubscriptionsError;
+use crate::components::confirmations::{purge_pending, send_confirmation, ConfirmationKind};
+use crate::components::email_delivery::EmailClient;
+use crate::configuration::NewsletterSettings;
+use crate::domain::credentials::new_token;
+use crate::domain::users::{NewUser, UserEmail, UserName};
+use crate::startup::engine::WebBaseUrl;
+
+#[derive(serde::Deserialize)]
+pub struct SubscribeForm {
+    email: String,
+    name: String,
+}
+
+impl TryFrom<SubscribeForm> for NewUser {
+    type Error = String;
+
+    fn try_from(form: SubscribeForm) -> Result<Self, Self::Error> {
+        Ok(Self {
+            email: UserEmail::try_from(form.email)?,
+            name: UserName::try_from(form.name)?,
+        })
+    }
+}
+
+/// Subscribes an address to the newsletter, pending until confirmed. An address already
+/// subscribed gets no email, and is not told apart from a new one.
+#[tracing::instrument(
+    name = "Subscribe to the newsletter",
+    skip(form, pool, email_client, base_url, settings),
+    fields(email = %form.email, name = %form.name)
+)]
+pub async fn subscribe(
+    form: web::Form<SubscribeForm>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<WebBaseUrl>,
+    settings: web::Data<NewsletterSettings>,
+) -> Result<HttpResponse, SubscriptionsError> {
+    let subscriber =
+        NewUser::try_from(form.into_inner()).map_err(SubscriptionsError::BadRequestError)?;
+
+    // INFO: a stale pending subscription can be started over
+    let _ret = purge_pending(
+        pool.get_ref(),
+        &settings.confirmation,
+        ConfirmationKind::Subscription,
+    )
+    .await
+    .context("Failed to purge pending subscriptions")
+    .inspect_err(|e| tracing::warn!("{e:?}"));
+
+    let (unsubscribe_token, _) = new_token().context("Failed to generate the unsubscribe token")?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let id = Uuid::new_v4();
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, unsubscribe_token)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (email) DO NOTHING
+        "#,
+        id,
+        subscriber.email.as_ref(),
+        subscriber.name.as_ref(),
+        unsubscribe_token.expose_secret(),
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to insert subscription")
+    .inspect_err(|e| tracing::error!("{e:?}"))?
+    .rows_affected();
+    if inserted == 0 {
+        tracing::info!("Already subscribed");
+        return Ok(HttpResponse::Ok().finish());
+    }
+
+    send_confirmation(
+        &mut transaction,
+        &email_client,
+        &base_url,
+        ConfirmationKind::Subscription,
+        id,
+        &subscriber.email,
+    )
+    .await
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    tracing::info!(%id, "Subscription created, waiting for confirmation");
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,66 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use secrecy::{ExposeSecret, SecretBox};
+use sqlx::PgPool;
+
+use super::SubscriptionsError;
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeQuery {
+    token: SecretBox<String>,
+}
+
+/// Asks to confirm unsubscribing, as following the link in the newsletter must not remove the
+/// subscription by itself: mail scanners and link previews fetch it too. The page posts back to
+/// its own URL, token included.
+#[tracing::instrument(name = "Show the unsubscribe page", skip(query, pool))]
+pub async fn show_unsubscribe(
+    query: web::Query<UnsubscribeQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SubscriptionsError> {
+    let subscribed = sqlx::query_scalar!(
+        "SELECT id FROM subscriptions WHERE unsubscribe_token = $1",
+        query.token.expose_secret()
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to fetch the subscription")
+    .inspect_err(|e| tracing::error!("{e:?}"))?
+    .is_some();
+
+    let body = if subscribed {
+        r#"<p>Stop receiving an email for every new post?</p>
+<form method="post"><button type="submit">Unsubscribe</button></form>"#
+    } else {
+        "<p>This address is not subscribed to the newsletter.</p>"
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// Removes the subscription `token` belongs to. Answered the same whether it still existed or
+/// not, so posting twice is harmless.
+///
+/// Posted by the page of [`show_unsubscribe`], and by mail clients that unsubscribe in one
+/// click following [RFC 8058](https://www.rfc-editor.org/rfc/rfc8058).
+#[tracing::instrument(name = "Unsubscribe from the newsletter", skip(query, pool))]
+pub async fn unsubscribe(
+    query: web::Query<UnsubscribeQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SubscriptionsError> {
+    let removed = sqlx::query_scalar!(
+        "DELETE FROM subscriptions WHERE unsubscribe_token = $1 RETURNING id",
+        query.token.expose_secret()
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to remove the subscription")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    if let Some(id) = removed {
+        tracing::info!(%id, "Unsubscribed");
+    }
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,151 @@
+use actix_files::file_extension_to_mime;
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use base64::prelude::*;
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::{
+    check_tus_resumable, header_u64, http_date, purge_expired_uploads, tus_response, UploadsError,
+    TUS_EXTENSIONS, TUS_VERSION,
+};
+use crate::components::blob_storage::{sanitize_relative_path, BlobStorage};
+use crate::components::sessions::Session;
+use crate::configuration::ResumableUploadSettings;
+use crate::domain::attachments::AttachmentPolicy;
+use crate::domain::roles::Permission;
+
+#[tracing::instrument(name = "Describe resumable uploads", skip(settings, policy))]
+pub async fn describe_uploads(
+    settings: web::Data<ResumableUploadSettings>,
+    policy: web::Data<AttachmentPolicy>,
+) -> HttpResponse {
+    tus_response(StatusCode::NO_CONTENT)
+        .insert_header(("Tus-Version", TUS_VERSION))
+        .insert_header(("Tus-Extension", TUS_EXTENSIONS))
+        .insert_header(("Tus-Max-Size", max_size(&settings, &policy)))
+        .finish()
+}
+
+/// No upload can go over the limit of the largest type of attachment, whatever it is.
+fn max_size(settings: &ResumableUploadSettings, policy: &AttachmentPolicy) -> u64 {
+    settings.max_size.min(policy.largest_limit())
+}
+
+#[tracing::instrument(
+    name = "Create resumable upload",
+    skip(session, req, pool, blob_storage, settings, policy)
+)]
+pub async fn create_upload(
+    session: Session,
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    blob_storage: web::Data<BlobStorage>,
+    settings: web::Data<ResumableUploadSettings>,
+    policy: web::Data<AttachmentPolicy>,
+) -> Result<HttpResponse, UploadsError> {
+    session.require(Permission::CreatePosts)?;
+
+    check_tus_resumable(&req)?;
+
+    let length = header_u64(&req, "Upload-Length")?
+        .ok_or_else(|| UploadsError::BadRequestError("Upload-Length is required".to_string()))?;
+    let max_size = max_size(&settings, &policy);
+    if length > max_size {
+        return Err(UploadsError::PayloadTooLargeError(format!(
+            "Uploads are limited to {max_size} bytes"
+        )));
+    }
+
+    let metadata = parse_metadata(&req)?;
+    let file_name = metadata
+        .get("filename")
+        .and_then(|name| sanitize_relative_path(name))
+        .ok_or_else(|| {
+            UploadsError::BadRequestError("Upload-Metadata must carry a valid filename".to_string())
+        })?;
+
+    // INFO: the content is sniffed once attached, this spares sending a file bound to be rejected
+    let mime = match metadata.get("filetype") {
+        Some(mime) => mime.clone(),
+        None => declared_mime(&file_name),
+    };
+    policy
+        .check_mime(&file_name.to_string_lossy(), &mime, length)
+        .inspect_err(|e| tracing::warn!("Upload rejected: {e}"))?;
+
+    // INFO: abandoned uploads are only purged lazily, failing to do so is not this upload's issue
+    let _ret = purge_expired_uploads(pool.get_ref(), &blob_storage)
+        .await
+        .context("Failed to purge expired uploads")
+        .inspect_err(|e| tracing::warn!("{e:?}"));
+
+    let id = Uuid::new_v4();
+    let file_name = file_name.to_string_lossy();
+    let expires_at = Utc::now() + Duration::seconds(settings.expiry_secs as i64);
+    sqlx::query!(
+        r#"
+        INSERT INTO uploads (id, file_name, length, expires_at, owner_id)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        id,
+        file_name.as_ref(),
+        length as i64,
+        expires_at,
+        session.user_id,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to create upload")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    tracing::info!(%id, length, ?file_name, "Upload created");
+
+    Ok(tus_response(StatusCode::CREATED)
+        .insert_header((header::LOCATION, format!("{}/{id}", req.path())))
+        .insert_header(("Upload-Offset", 0))
+        .insert_header(("Upload-Expires", http_date(expires_at)))
+        .finish())
+}
+
+/// The type of a file going by its extension, as `filetype` is optional in `Upload-Metadata`.
+fn declared_mime(file_name: &Path) -> String {
+    let extension = file_name
+        .extension()
+        .map(|extension| extension.to_string_lossy())
+        .unwrap_or_default();
+    file_extension_to_mime(&extension).essence_str().to_string()
+}
+
+/// Parses `Upload-Metadata`, comma separated pairs of a key and its base64 encoded value.
+fn parse_metadata(req: &HttpRequest) -> Result<HashMap<String, String>, UploadsError> {
+    let Some(header) = req.headers().get("Upload-Metadata") else {
+        return Ok(HashMap::new());
+    };
+    let invalid = || UploadsError::BadRequestError("Invalid Upload-Metadata".to_string());
+
+    let mut metadata = HashMap::new();
+    for pair in header.to_str().map_err(|_| invalid())?.split(',') {
+        let mut parts = pair.trim().splitn(2, ' ');
+        let key = parts
+            .next()
+            .filter(|key| !key.is_empty())
+            .ok_or_else(invalid)?;
+        let value = match parts.next() {
+            Some(encoded) => BASE64_STANDARD
+                .decode(encoded.trim())
+                .ok()
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .ok_or_else(invalid)?,
+            None => String::new(),
+        };
+        metadata.insert(key.to_string(), value);
+    }
+
+    Ok(metadata)
+}
@@ -0,0 +1,184 @@
+//! Resumable uploads following the [tus protocol](https://tus.io/protocols/resumable-upload),
+//! with its creation, expiration and termination extensions.
+//!
+//! A completed upload is attached to a post by sending its id in the `upload` field of the post
+//! form, see [`crate::routes::upload_post`]. Uploads belong to the user who created them, and
+//! are missing to anyone else.
+
+mod create;
+mod patch;
+mod status;
+mod terminate;
+
+pub use create::*;
+pub use patch::*;
+pub use status::*;
+pub use terminate::*;
+
+use actix_web::http::StatusCode;
+use actix_web::{http, HttpRequest, HttpResponse, HttpResponseBuilder, ResponseError};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::components::blob_storage::BlobStorage;
+use crate::components::sessions::SessionError;
+use crate::domain::attachments::AttachmentRejection;
+
+pub const TUS_VERSION: &str = "1.0.0";
+const TUS_RESUMABLE: &str = "Tus-Resumable";
+const TUS_EXTENSIONS: &str = "creation,expiration,termination";
+const OFFSET_OCTET_STREAM: &str = "application/offset+octet-stream";
+
+#[derive(thiserror::Error, Debug)]
+pub enum UploadsError {
+    #[error("{0}")]
+    NotFoundError(String),
+    #[error("{0}")]
+    BadRequestError(String),
+    #[error("{0}")]
+    ConflictError(String),
+    #[error("{0}")]
+    UnsupportedMediaTypeError(String),
+    #[error("{0}")]
+    PayloadTooLargeError(String),
+    #[error("Only version {TUS_VERSION} of the tus protocol is supported")]
+    UnsupportedVersionError,
+    #[error(transparent)]
+    SessionError(#[from] SessionError),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for UploadsError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            Self::UnexpectedError(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+            Self::SessionError(e) => e.status_code(),
+            Self::NotFoundError(_) => http::StatusCode::NOT_FOUND,
+            Self::BadRequestError(_) => http::StatusCode::BAD_REQUEST,
+            Self::ConflictError(_) => http::StatusCode::CONFLICT,
+            Self::UnsupportedMediaTypeError(_) => http::StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::PayloadTooLargeError(_) => http::StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UnsupportedVersionError => http::StatusCode::PRECONDITION_FAILED,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = tus_response(self.status_code());
+        if let Self::UnsupportedVersionError = self {
+            response.insert_header(("Tus-Version", TUS_VERSION));
+        }
+        response.body(self.to_string())
+    }
+}
+
+impl From<AttachmentRejection> for UploadsError {
+    fn from(rejection: AttachmentRejection) -> Self {
+        match rejection {
+            AttachmentRejection::UnsupportedType { .. }
+            | AttachmentRejection::MismatchedExtension { .. } => {
+                Self::UnsupportedMediaTypeError(rejection.to_string())
+            }
+            AttachmentRejection::TooLarge { .. } => {
+                Self::PayloadTooLargeError(rejection.to_string())
+            }
+        }
+    }
+}
+
+/// Every tus response, errors included, carries the protocol version.
+fn tus_response(status: StatusCode) -> HttpResponseBuilder {
+    let mut response = HttpResponse::build(status);
+    response.insert_header((TUS_RESUMABLE, TUS_VERSION));
+    response
+}
+
+fn check_tus_resumable(req: &HttpRequest) -> Result<(), UploadsError> {
+    match req.headers().get(TUS_RESUMABLE) {
+        Some(version) if version == TUS_VERSION => Ok(()),
+        _ => Err(UploadsError::UnsupportedVersionError),
+    }
+}
+
+fn header_u64(req: &HttpRequest, name: &str) -> Result<Option<u64>, UploadsError> {
+    req.headers()
+        .get(name)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| {
+                    UploadsError::BadRequestError(format!("{name} must be a non-negative integer"))
+                })
+        })
+        .transpose()
+}
+
+fn http_date(date: DateTime<Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+#[derive(Debug)]
+struct UploadRecord {
+    length: i64,
+    upload_offset: i64,
+    expires_at: DateTime<Utc>,
+}
+
+/// The upload `id` of `owner_id`, the uploads of other users being answered as missing.
+async fn fetch_upload(
+    pool: &PgPool,
+    id: Uuid,
+    owner_id: Uuid,
+) -> Result<UploadRecord, UploadsError> {
+    sqlx::query_as!(
+        UploadRecord,
+        r#"
+        SELECT length, upload_offset, expires_at
+        FROM uploads
+        WHERE id = $1 AND owner_id = $2 AND expires_at > now()
+        "#,
+        id,
+        owner_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(anyhow::Error::from)?
+    .ok_or_else(|| UploadsError::NotFoundError(format!("Upload {id} does not exist")))
+}
+
+/// Removes uploads and their chunks, such as once they are attached to a post.
+pub async fn discard_uploads(
+    pool: &PgPool,
+    blob_storage: &BlobStorage,
+    ids: &[Uuid],
+) -> anyhow::Result<()> {
+    sqlx::query!("DELETE FROM uploads WHERE id = ANY($1)", ids)
+        .execute(pool)
+        .await?;
+
+    for id in ids {
+        blob_storage.discard_upload(id).await?;
+    }
+    Ok(())
+}
+
+/// Removes the uploads that went without progress for longer than they are kept.
+pub async fn purge_expired_uploads(
+    pool: &PgPool,
+    blob_storage: &BlobStorage,
+) -> anyhow::Result<()> {
+    let expired = sqlx::query_scalar!("DELETE FROM uploads WHERE expires_at <= now() RETURNING id")
+        .fetch_all(pool)
+        .await?;
+
+    for id in &expired {
+        blob_storage.discard_upload(id).await?;
+    }
+    if !expired.is_empty() {
+        tracing::info!("Purged {} expired uploads", expired.len());
+    }
+    Ok(())
+}
@@ -0,0 +1,247 @@
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use futures::StreamExt;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use std::time::Instant;
+
+use super::{
+    check_tus_resumable, fetch_upload, header_u64, http_date, tus_response, UploadsError,
+    OFFSET_OCTET_STREAM,
+};
+use crate::components::blob_storage::BlobStorage;
+use crate::components::sessions::Session;
+use crate::configuration::ResumableUploadSettings;
+use crate::domain::roles::Permission;
+
+/// Bytes buffered before they are stored as a chunk, bounding the memory a request uses and how
+/// much an interrupted request loses.
+const CHUNK_SIZE: usize = 8 * 1024 * 1024;
+/// How long a request appending to an upload keeps it to itself without receiving anything.
+const CLAIM_LEASE: Duration = Duration::seconds(60);
+
+#[tracing::instrument(
+    name = "Append to upload",
+    skip(session, req, body, pool, blob_storage, settings)
+)]
+pub async fn patch_upload(
+    session: Session,
+    req: HttpRequest,
+    id: web::Path<Uuid>,
+    mut body: web::Payload,
+    pool: web::Data<PgPool>,
+    blob_storage: web::Data<BlobStorage>,
+    settings: web::Data<ResumableUploadSettings>,
+) -> Result<HttpResponse, UploadsError> {
+    session.require(Permission::CreatePosts)?;
+
+    check_tus_resumable(&req)?;
+    let id = id.into_inner();
+
+    if req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_none_or(|content_type| content_type != OFFSET_OCTET_STREAM)
+    {
+        return Err(UploadsError::UnsupportedMediaTypeError(format!(
+            "Chunks must be sent as {OFFSET_OCTET_STREAM}"
+        )));
+    }
+    let offset = header_u64(&req, "Upload-Offset")?
+        .ok_or_else(|| UploadsError::BadRequestError("Upload-Offset is required".to_string()))?;
+
+    let claim = Uuid::new_v4();
+    let length = claim_upload(&pool, id, session.user_id, offset, claim).await?;
+
+    // INFO: chunks past the offset are leftovers of a write that was never acknowledged
+    blob_storage
+        .truncate_upload(&id, offset)
+        .await
+        .context("Failed to drop unacknowledged chunks")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let (stored, interruption) =
+        receive_chunks(&pool, &blob_storage, id, claim, offset, length, &mut body).await;
+
+    // INFO: whatever arrived before an interruption is kept, the client resumes from there
+    let expires_at = Utc::now() + Duration::seconds(settings.expiry_secs as i64);
+    let recorded = sqlx::query!(
+        r#"
+        UPDATE uploads
+        SET upload_offset = $1, expires_at = $2, claimed_by = NULL, claimed_until = NULL
+        WHERE id = $3 AND upload_offset = $4 AND claimed_by = $5
+        "#,
+        stored as i64,
+        expires_at,
+        id,
+        offset as i64,
+        claim,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to record upload progress")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    if recorded.rows_affected() == 0 {
+        return Err(UploadsError::ConflictError(format!(
+            "Upload {id} was written by another request in the meantime"
+        )));
+    }
+    if let Some(interruption) = interruption {
+        return Err(interruption);
+    }
+
+    Ok(tus_response(StatusCode::NO_CONTENT)
+        .insert_header(("Upload-Offset", stored))
+        .insert_header(("Upload-Expires", http_date(expires_at)))
+        .finish())
+}
+
+/// Claims an upload of `owner_id` at `offset` for the request `claim`, returning its length. A
+/// claim rather than a row lock is held while the body is received, so no connection is held
+/// meanwhile.
+async fn claim_upload(
+    pool: &PgPool,
+    id: Uuid,
+    owner_id: Uuid,
+    offset: u64,
+    claim: Uuid,
+) -> Result<u64, UploadsError> {
+    let claimed = sqlx::query_scalar!(
+        r#"
+        UPDATE uploads
+        SET claimed_by = $1, claimed_until = $2
+        WHERE id = $3 AND owner_id = $4 AND upload_offset = $5 AND expires_at > now()
+            AND (claimed_until IS NULL OR claimed_until < now())
+        RETURNING length
+        "#,
+        claim,
+        Utc::now() + CLAIM_LEASE,
+        id,
+        owner_id,
+        offset as i64,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to claim upload")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    if let Some(length) = claimed {
+        return Ok(length as u64);
+    }
+
+    let upload = fetch_upload(pool, id, owner_id).await?;
+    if upload.upload_offset as u64 != offset {
+        return Err(UploadsError::ConflictError(format!(
+            "Upload {id} is at offset {}",
+            upload.upload_offset
+        )));
+    }
+    Err(UploadsError::ConflictError(format!(
+        "Upload {id} is being written by another request"
+    )))
+}
+
+/// Stores the body as chunks from `offset` on, returning where it stopped and why when it did
+/// before the body ended.
+async fn receive_chunks(
+    pool: &PgPool,
+    blob_storage: &BlobStorage,
+    id: Uuid,
+    claim: Uuid,
+    offset: u64,
+    length: u64,
+    body: &mut web::Payload,
+) -> (u64, Option<UploadsError>) {
+    let mut stored = offset;
+    let mut buffer = Vec::new();
+    let mut interruption = None;
+    let mut renewed_at = Instant::now();
+
+    while let Some(chunk) = body.next().await {
+        let bytes = match chunk {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                interruption = Some(UploadsError::BadRequestError(format!(
+                    "Upload interrupted: {e}"
+                )));
+                break;
+            }
+        };
+
+        let remaining = (length - stored) as usize - buffer.len();
+        if bytes.len() > remaining {
+            buffer.extend_from_slice(&bytes[..remaining]);
+            interruption = Some(UploadsError::PayloadTooLargeError(format!(
+                "Upload {id} is limited to {length} bytes"
+            )));
+            break;
+        }
+        buffer.extend_from_slice(&bytes);
+
+        if renewed_at.elapsed() > CLAIM_LEASE.to_std().unwrap_or_default() / 2 {
+            if let Err(e) = renew_claim(pool, id, claim).await {
+                interruption = Some(e);
+                break;
+            }
+            renewed_at = Instant::now();
+        }
+
+        if buffer.len() >= CHUNK_SIZE {
+            let size = buffer.len() as u64;
+            if let Err(e) = store_chunk(blob_storage, id, stored, std::mem::take(&mut buffer)).await
+            {
+                return (stored, Some(e));
+            }
+            stored += size;
+        }
+    }
+
+    if !buffer.is_empty() {
+        let size = buffer.len() as u64;
+        if let Err(e) = store_chunk(blob_storage, id, stored, buffer).await {
+            return (stored, Some(e));
+        }
+        stored += size;
+    }
+
+    (stored, interruption)
+}
+
+async fn store_chunk(
+    blob_storage: &BlobStorage,
+    id: Uuid,
+    offset: u64,
+    bytes: Vec<u8>,
+) -> Result<(), UploadsError> {
+    blob_storage
+        .put_upload_chunk(&id, offset, bytes)
+        .await
+        .context("Failed to store upload chunk")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+    Ok(())
+}
+
+/// Keeps the claim of a request receiving a slow body.
+async fn renew_claim(pool: &PgPool, id: Uuid, claim: Uuid) -> Result<(), UploadsError> {
+    let renewed = sqlx::query!(
+        "UPDATE uploads SET claimed_until = $1 WHERE id = $2 AND claimed_by = $3",
+        Utc::now() + CLAIM_LEASE,
+        id,
+        claim,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to renew upload claim")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    match renewed.rows_affected() {
+        0 => Err(UploadsError::ConflictError(format!(
+            "Upload {id} was written by another request in the meantime"
+        ))),
+        _ => Ok(()),
+    }
+}
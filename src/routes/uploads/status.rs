@@ -0,0 +1,29 @@
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{check_tus_resumable, fetch_upload, http_date, tus_response, UploadsError};
+use crate::components::sessions::Session;
+use crate::domain::roles::Permission;
+
+#[tracing::instrument(name = "Get upload progress", skip(session, req, pool))]
+pub async fn upload_status(
+    session: Session,
+    req: HttpRequest,
+    id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, UploadsError> {
+    session.require(Permission::CreatePosts)?;
+
+    check_tus_resumable(&req)?;
+    let upload = fetch_upload(pool.get_ref(), id.into_inner(), session.user_id).await?;
+
+    Ok(tus_response(StatusCode::OK)
+        .insert_header(("Upload-Offset", upload.upload_offset))
+        .insert_header(("Upload-Length", upload.length))
+        .insert_header(("Upload-Expires", http_date(upload.expires_at)))
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .finish())
+}
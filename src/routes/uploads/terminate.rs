@@ -0,0 +1,32 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{check_tus_resumable, discard_uploads, fetch_upload, tus_response, UploadsError};
+use crate::components::blob_storage::BlobStorage;
+use crate::components::sessions::Session;
+use crate::domain::roles::Permission;
+
+#[tracing::instrument(name = "Terminate upload", skip(session, req, pool, blob_storage))]
+pub async fn terminate_upload(
+    session: Session,
+    req: HttpRequest,
+    id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    blob_storage: web::Data<BlobStorage>,
+) -> Result<HttpResponse, UploadsError> {
+    session.require(Permission::CreatePosts)?;
+
+    check_tus_resumable(&req)?;
+    let id = id.into_inner();
+    fetch_upload(pool.get_ref(), id, session.user_id).await?;
+
+    discard_uploads(pool.get_ref(), &blob_storage, &[id])
+        .await
+        .context("Failed to terminate upload")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    Ok(tus_response(StatusCode::NO_CONTENT).finish())
+}
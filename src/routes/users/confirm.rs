@@ -0,0 +1,30 @@
+use actix_web::{web, HttpResponse};
+use secrecy::{ExposeSecret, SecretBox};
+use sqlx::PgPool;
+
+use super::UsersError;
+use crate::components::confirmations::{confirm, ConfirmationKind};
+use crate::configuration::UserSettings;
+
+#[derive(serde::Deserialize)]
+pub struct ConfirmationQuery {
+    token: SecretBox<String>,
+}
+
+#[tracing::instrument(name = "Confirm a user", skip(query, pool, settings))]
+pub async fn confirm_user(
+    query: web::Query<ConfirmationQuery>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<UserSettings>,
+) -> Result<HttpResponse, UsersError> {
+    let user_id = confirm(
+        pool.get_ref(),
+        &settings.confirmation,
+        ConfirmationKind::User,
+        query.token.expose_secret(),
+    )
+    .await?;
+
+    tracing::info!(%user_id, "User confirmed");
+    Ok(HttpResponse::Ok().finish())
+}
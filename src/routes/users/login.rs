@@ -0,0 +1,278 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use secrecy::{ExposeSecret, SecretBox};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use super::{create_login_challenge, UsersError, CONFIRMED};
+use crate::components::audit::{record_audit, AuditEvent, RequestOrigin};
+use crate::components::email_delivery::EmailClient;
+use crate::components::login_throttle::{
+    clear_login_failures, login_blocked_until, record_failed_login,
+};
+use crate::components::sessions::{bearer_token, create_session, revoke_session, Session};
+use crate::configuration::UserSettings;
+use crate::domain::audit::AuditAction;
+use crate::domain::credentials::verify_password;
+use crate::domain::login_throttle::{Penalty, ThrottleKind};
+use crate::domain::users::UserEmail;
+use crate::telemetry::{spawn_blocking_with_tracing, spawn_with_tracing};
+
+#[derive(serde::Deserialize)]
+pub struct LoginForm {
+    username: String,
+    password: SecretBox<String>,
+}
+
+/// Either a session, or a challenge to complete with the second factor of the user at
+/// [`super::complete_two_factor_login`].
+#[derive(serde::Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum LoginResponse {
+    Session {
+        token: String,
+        expires_at: DateTime<Utc>,
+    },
+    TwoFactorRequired {
+        challenge: String,
+        expires_at: DateTime<Utc>,
+    },
+}
+
+/// Checks `password` against the hash of `user`, or against a made-up hash of the same cost
+/// when there is no such user, so both take as long.
+async fn check_credentials(
+    user: Option<(Uuid, String)>,
+    password: SecretBox<String>,
+    settings: &UserSettings,
+) -> anyhow::Result<Option<Uuid>> {
+    let (user_id, hash) = match user {
+        Some((id, hash)) => (Some(id), hash),
+        None => (
+            None,
+            format!(
+                "pbkdf2-sha256${}$AAAAAAAAAAAAAAAAAAAAAA$AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA",
+                settings.password_hash_iterations
+            ),
+        ),
+    };
+
+    let verified = spawn_blocking_with_tracing(move || verify_password(&password, &hash))
+        .await
+        .context("Failed to spawn the password verification")?;
+    Ok(user_id.filter(|_| verified))
+}
+
+/// Refuses a login of `username` from `origin` while either has to wait after failed ones.
+/// Otherwise the login holds the throttles until its transaction ends, which has to count it
+/// if it fails.
+pub(super) async fn check_login_throttle(
+    connection: &mut PgConnection,
+    username: &str,
+    origin: &RequestOrigin,
+) -> Result<(), UsersError> {
+    let blocked_until = login_blocked_until(connection, username, origin)
+        .await
+        .context("Failed to check the failed logins")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+    match blocked_until {
+        Some(until) => {
+            let wait = (until - Utc::now()).num_seconds().max(1);
+            Err(UsersError::TooManyRequestsError(format!(
+                "Too many failed logins, try again in {wait} seconds"
+            )))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Counts a failed login of `username`, and tells the owner of the account, if there is one,
+/// once it gets locked out.
+pub(super) async fn count_failed_login(
+    connection: &mut PgConnection,
+    settings: &UserSettings,
+    email_client: web::Data<EmailClient>,
+    origin: &RequestOrigin,
+    username: &str,
+    owner_email: Option<String>,
+) -> Result<(), UsersError> {
+    let penalty = record_failed_login(connection, settings, username, origin)
+        .await
+        .context("Failed to count the failed login")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let (Penalty::Lockout(lockout), Some(email)) = (penalty, owner_email) else {
+        return Ok(());
+    };
+    tracing::warn!(username, "Account locked out after failed logins");
+    let email = UserEmail::try_from(email)
+        .map_err(anyhow::Error::msg)
+        .context("Failed to parse the email of the locked account")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let minutes = lockout.num_minutes().max(1);
+    let attempts = settings.login_lockout_attempts;
+    let username = username.to_string();
+    spawn_with_tracing(async move {
+        let _ret = email_client
+            .send_email(
+                &email,
+                "Your account was locked",
+                &format!(
+                    "After {attempts} failed logins in a row, logging in as <b>{username}</b> is locked for {minutes} minutes. If these were not you, consider resetting your password."
+                ),
+                &format!(
+                    "After {attempts} failed logins in a row, logging in as {username} is locked for {minutes} minutes. If these were not you, consider resetting your password."
+                ),
+            )
+            .await
+            .context("Failed to send the lockout email")
+            .inspect_err(|e| tracing::error!("{e:?}"));
+    });
+    Ok(())
+}
+
+/// Logs in with a password, or starts a two-factor login. Failed logins make the account and
+/// the address wait longer and longer, up to a lockout.
+#[tracing::instrument(
+    name = "Log in",
+    skip(form, origin, pool, settings, email_client),
+    fields(username = %form.username)
+)]
+pub async fn login(
+    form: web::Form<LoginForm>,
+    origin: RequestOrigin,
+    pool: web::Data<PgPool>,
+    settings: web::Data<UserSettings>,
+    email_client: web::Data<EmailClient>,
+) -> Result<HttpResponse, UsersError> {
+    let form = form.into_inner();
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    check_login_throttle(&mut transaction, &form.username, &origin).await?;
+
+    let user = sqlx::query!(
+        r#"
+        SELECT id, email, password, totp_secret IS NOT NULL AS "two_factor!"
+        FROM users WHERE username = $1 AND status = $2
+        "#,
+        form.username,
+        CONFIRMED
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to fetch the user")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+    let two_factor = user.as_ref().is_some_and(|user| user.two_factor);
+    let known_user_id = user.as_ref().map(|user| user.id);
+    let owner_email = user.as_ref().map(|user| user.email.clone());
+
+    let verified = check_credentials(
+        user.map(|user| (user.id, user.password)),
+        form.password,
+        &settings,
+    )
+    .await
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let Some(user_id) = verified else {
+        // INFO: what was typed for an unknown account may well be a password, it is not kept
+        let diff = match known_user_id {
+            Some(_) => serde_json::json!({ "username": form.username }),
+            None => serde_json::json!({}),
+        };
+        let event = AuditEvent {
+            action: AuditAction::LoginFailed,
+            actor_id: None,
+            target_id: known_user_id,
+            diff,
+        };
+        record_audit(&mut *transaction, &origin, event)
+            .await
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+        count_failed_login(
+            &mut transaction,
+            &settings,
+            email_client,
+            &origin,
+            &form.username,
+            owner_email,
+        )
+        .await?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit transaction")?;
+        return Err(UsersError::UnauthorizedError(
+            "Invalid username or password".to_string(),
+        ));
+    };
+
+    // INFO: failures are only forgotten once the second factor is right as well
+    if two_factor {
+        let expiry = Duration::seconds(settings.two_factor_expiry_secs as i64);
+        let (challenge, expires_at) = create_login_challenge(&mut transaction, user_id, expiry)
+            .await
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit transaction")?;
+
+        tracing::info!(%user_id, "User logged in, waiting for the second factor");
+        return Ok(HttpResponse::Ok().json(LoginResponse::TwoFactorRequired {
+            challenge: challenge.expose_secret().clone(),
+            expires_at,
+        }));
+    }
+
+    let expiry = Duration::seconds(settings.session_expiry_secs as i64);
+    let (token, expires_at) = create_session(&mut transaction, user_id, expiry)
+        .await
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+    clear_login_failures(&mut *transaction, ThrottleKind::Account, &form.username)
+        .await
+        .context("Failed to forget the failed logins")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let event = AuditEvent {
+        action: AuditAction::Login,
+        actor_id: Some(user_id),
+        target_id: Some(user_id),
+        diff: serde_json::json!({}),
+    };
+    record_audit(&mut *transaction, &origin, event)
+        .await
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    tracing::info!(%user_id, "User logged in");
+    Ok(HttpResponse::Ok().json(LoginResponse::Session {
+        token: token.expose_secret().clone(),
+        expires_at,
+    }))
+}
+
+#[tracing::instrument(name = "Log out", skip(req, session, pool), fields(user_id = %session.user_id))]
+pub async fn logout(
+    req: HttpRequest,
+    session: Session,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, UsersError> {
+    // INFO: the session extractor already made sure there is a token
+    if let Some(token) = bearer_token(&req) {
+        revoke_session(pool.get_ref(), token)
+            .await
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
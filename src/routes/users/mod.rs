@@ -0,0 +1,68 @@
+//! User accounts. An account stays pending until the link emailed to its address is followed,
+//! and pending accounts that are never confirmed are removed after a while. Confirmed accounts
+//! log in for a bearer token, see [`crate::components::sessions::Session`], and can reset a
+//! forgotten password by email. Users who enrolled an authenticator app complete their login
+//! with a TOTP or recovery code. Failed logins slow down the account and address they came
+//! from, until both are locked out for a while. Authors fill in a public profile, see
+//! [`crate::routes::authors`].
+
+mod confirm;
+mod login;
+mod password_reset;
+mod profile;
+mod register;
+mod resend;
+mod roles;
+mod two_factor;
+
+pub use confirm::*;
+pub use login::*;
+pub use password_reset::*;
+pub use profile::*;
+pub use register::*;
+pub use resend::*;
+pub use roles::*;
+pub use two_factor::*;
+
+use actix_web::{http, ResponseError};
+
+use crate::components::confirmations::ConfirmationError;
+use crate::components::sessions::SessionError;
+
+pub const PENDING_CONFIRMATION: &str = "pending_confirmation";
+pub const CONFIRMED: &str = "confirmed";
+
+#[derive(thiserror::Error, Debug)]
+pub enum UsersError {
+    #[error("{0}")]
+    NotFoundError(String),
+    #[error("{0}")]
+    BadRequestError(String),
+    #[error("{0}")]
+    ConflictError(String),
+    #[error("{0}")]
+    UnauthorizedError(String),
+    #[error("{0}")]
+    TooManyRequestsError(String),
+    #[error(transparent)]
+    SessionError(#[from] SessionError),
+    #[error(transparent)]
+    ConfirmationError(#[from] ConfirmationError),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for UsersError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            Self::UnexpectedError(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+            Self::SessionError(e) => e.status_code(),
+            Self::ConfirmationError(e) => e.status_code(),
+            Self::NotFoundError(_) => http::StatusCode::NOT_FOUND,
+            Self::BadRequestError(_) => http::StatusCode::BAD_REQUEST,
+            Self::ConflictError(_) => http::StatusCode::CONFLICT,
+            Self::UnauthorizedError(_) => http::StatusCode::UNAUTHORIZED,
+            Self::TooManyRequestsError(_) => http::StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use secrecy::{ExposeSecret, SecretBox};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{UsersError, CONFIRMED};
+use crate::components::audit::{record_audit, AuditEvent, RequestOrigin};
+use crate::components::email_delivery::EmailClient;
+use crate::components::sessions::revoke_user_sessions;
+use crate::configuration::UserSettings;
+use crate::domain::audit::AuditAction;
+use crate::domain::credentials::{hash_password, hash_token, new_token};
+use crate::domain::users::{UserEmail, UserPassword};
+use crate::startup::engine::WebBaseUrl;
+use crate::telemetry::{spawn_blocking_with_tracing, spawn_with_tracing};
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetRequestForm {
+    email: String,
+}
+
+/// Emails a password reset link to the confirmed account of `email`, at most once per
+/// `settings.confirmation.resend_cooldown_secs`.
+///
+/// The answer is the same whether the address is registered or not, and the email is sent after
+/// responding so the timing does not tell either.
+#[tracing::instrument(
+    name = "Request a password reset",
+    skip(form, pool, email_client, base_url, settings)
+)]
+pub async fn request_password_reset(
+    form: web::Form<PasswordResetRequestForm>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<WebBaseUrl>,
+    settings: web::Data<UserSettings>,
+) -> Result<HttpResponse, UsersError> {
+    let email =
+        UserEmail::try_from(form.into_inner().email).map_err(UsersError::BadRequestError)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let user = sqlx::query!(
+        r#"
+        SELECT id, (SELECT max(created_at) FROM password_reset_tokens WHERE user_id = id)
+            AS last_sent
+        FROM users WHERE email = $1 AND status = $2
+        FOR UPDATE
+        "#,
+        email.as_ref(),
+        CONFIRMED,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to fetch the user")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let cooldown = Duration::seconds(settings.confirmation.resend_cooldown_secs as i64);
+    let Some(user) = user.filter(|user| {
+        user.last_sent
+            .is_none_or(|last_sent| last_sent + cooldown <= Utc::now())
+    }) else {
+        return Ok(HttpResponse::Ok().finish());
+    };
+
+    let (token, token_hash) = new_token().context("Failed to generate the reset token")?;
+    sqlx::query!(
+        "DELETE FROM password_reset_tokens WHERE user_id = $1",
+        user.id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to remove the previous reset tokens")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+    sqlx::query!(
+        "INSERT INTO password_reset_tokens (token_hash, user_id) VALUES ($1, $2)",
+        token_hash,
+        user.id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the reset token")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let link = base_url.api_url(&format!(
+        "/users/password_reset/complete?token={}",
+        token.expose_secret()
+    ));
+    let expiry_minutes = settings.password_reset_expiry_secs / 60;
+    spawn_with_tracing(async move {
+        let _ret = email_client
+            .send_email(
+                &email,
+                "Reset your password",
+                &format!(
+                    r#"Click <a href="{link}">here</a> to choose a new password, within {expiry_minutes} minutes. Ignore this email if you did not ask for it."#
+                ),
+                &format!(
+                    "Visit {link} to choose a new password, within {expiry_minutes} minutes. Ignore this email if you did not ask for it."
+                ),
+            )
+            .await
+            .context("Failed to send the password reset email")
+            .inspect_err(|e| tracing::error!("{e:?}"));
+    });
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetQuery {
+    token: SecretBox<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetForm {
+    password: SecretBox<String>,
+}
+
+/// Asks for the new password when the emailed link is followed. The token is only used up once
+/// the page posts back to its own URL, token included, as mail scanners fetch the link too.
+#[tracing::instrument(name = "Show the password reset page", skip(query, pool, settings))]
+pub async fn show_password_reset(
+    query: web::Query<PasswordResetQuery>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<UserSettings>,
+) -> Result<HttpResponse, UsersError> {
+    let created_at = sqlx::query_scalar!(
+        "SELECT created_at FROM password_reset_tokens WHERE token_hash = $1",
+        hash_token(query.token.expose_secret())
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to fetch the reset token")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let expiry = Duration::seconds(settings.password_reset_expiry_secs as i64);
+    let body = match created_at {
+        Some(created_at) if created_at + expiry > Utc::now() => {
+            r#"<p>Choose a new password.</p>
+<form method="post"><input type="password" name="password" autocomplete="new-password" required><button type="submit">Reset password</button></form>"#
+        }
+        Some(_) => "<p>This password reset link expired.</p>",
+        None => "<p>This password reset link is invalid.</p>",
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// Sets the password of the account a reset link was sent to, and signs it out everywhere.
+/// Posted by the page of [`show_password_reset`], or by a frontend of its own.
+#[tracing::instrument(
+    name = "Complete a password reset",
+    skip(query, form, origin, pool, settings)
+)]
+pub async fn complete_password_reset(
+    query: web::Query<PasswordResetQuery>,
+    form: web::Form<PasswordResetForm>,
+    origin: RequestOrigin,
+    pool: web::Data<PgPool>,
+    settings: web::Data<UserSettings>,
+) -> Result<HttpResponse, UsersError> {
+    let password =
+        UserPassword::try_from(form.into_inner().password).map_err(UsersError::BadRequestError)?;
+    let token_hash = hash_token(query.token.expose_secret());
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    // INFO: a token is used up whether it is still valid or not
+    let token = sqlx::query!(
+        r#"
+        DELETE FROM password_reset_tokens WHERE token_hash = $1
+        RETURNING user_id, created_at
+        "#,
+        token_hash
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to fetch the reset token")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let expiry = Duration::seconds(settings.password_reset_expiry_secs as i64);
+    let user_id: Uuid = match token {
+        Some(token) if token.created_at + expiry > Utc::now() => token.user_id,
+        expired => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit transaction")?;
+            let reason = match expired {
+                Some(_) => "The password reset link expired",
+                None => "The password reset link is invalid",
+            };
+            return Err(UsersError::UnauthorizedError(reason.to_string()));
+        }
+    };
+
+    let iterations = settings.password_hash_iterations;
+    let password_hash =
+        spawn_blocking_with_tracing(move || hash_password(password.expose(), iterations))
+            .await
+            .context("Failed to spawn the password hashing")?
+            .context("Failed to hash the password")
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    sqlx::query!(
+        "UPDATE users SET password = $1 WHERE id = $2",
+        password_hash,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update the password")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let revoked = revoke_user_sessions(&mut transaction, user_id)
+        .await
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    // INFO: not even the hashes go into the log
+    let event = AuditEvent {
+        action: AuditAction::PasswordReset,
+        actor_id: Some(user_id),
+        target_id: Some(user_id),
+        diff: serde_json::json!({ "sessions_revoked": revoked }),
+    };
+    record_audit(&mut *transaction, &origin, event)
+        .await
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    tracing::info!(%user_id, revoked, "Password reset");
+    Ok(HttpResponse::Ok().finish())
+}
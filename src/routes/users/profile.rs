@@ -0,0 +1,228 @@
+use actix_multipart::Multipart;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use futures::TryStreamExt;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::UsersError;
+use crate::components::audit::{record_audit, AuditEvent};
+use crate::components::blob_storage::BlobStorage;
+use crate::components::sessions::Session;
+use crate::domain::attachments::sniff_bytes;
+use crate::domain::audit::{json_diff, AuditAction};
+use crate::domain::users::UserProfile;
+
+/// Largest avatar accepted.
+const MAX_AVATAR_BYTES: usize = 2 * 1024 * 1024;
+
+#[derive(serde::Deserialize)]
+pub struct ProfileForm {
+    #[serde(default)]
+    display_name: String,
+    #[serde(default)]
+    bio: String,
+}
+
+#[tracing::instrument(name = "Update profile", skip(session, form, pool), fields(user = %session.username))]
+pub async fn update_profile(
+    session: Session,
+    form: web::Form<ProfileForm>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, UsersError> {
+    let profile =
+        UserProfile::parse(&form.display_name, &form.bio).map_err(UsersError::BadRequestError)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let previous = sqlx::query!(
+        "SELECT display_name, bio FROM users WHERE id = $1 FOR UPDATE",
+        session.user_id
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to fetch the profile")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    sqlx::query!(
+        "UPDATE users SET display_name = $1, bio = $2 WHERE id = $3",
+        profile.display_name,
+        profile.bio,
+        session.user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update the profile")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let event = AuditEvent {
+        action: AuditAction::ProfileUpdate,
+        actor_id: Some(session.user_id),
+        target_id: Some(session.user_id),
+        diff: json_diff(
+            &serde_json::json!({ "display_name": previous.display_name, "bio": previous.bio }),
+            &serde_json::json!({ "display_name": profile.display_name, "bio": profile.bio }),
+        ),
+    };
+    record_audit(&mut *transaction, &session.origin, event)
+        .await
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Reads the `avatar` field of a form, skipping any other.
+async fn read_avatar(mut payload: Multipart) -> Result<Vec<u8>, UsersError> {
+    let invalid_form = |e| UsersError::BadRequestError(format!("Invalid form: {e}"));
+
+    while let Some(mut field) = payload.try_next().await.map_err(invalid_form)? {
+        if field.name() != Some("avatar") {
+            continue;
+        }
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.try_next().await.map_err(invalid_form)? {
+            if bytes.len() + chunk.len() > MAX_AVATAR_BYTES {
+                return Err(UsersError::BadRequestError(format!(
+                    "An avatar takes at most {} MiB",
+                    MAX_AVATAR_BYTES / 1024 / 1024
+                )));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        return Ok(bytes);
+    }
+
+    Err(UsersError::BadRequestError(
+        "The form has no avatar".to_string(),
+    ))
+}
+
+/// Points the avatar of `session` to `blob`, or to none, and removes the one it replaces.
+async fn replace_avatar(
+    session: &Session,
+    pool: &PgPool,
+    blob_storage: &BlobStorage,
+    blob: Option<&str>,
+) -> Result<(), UsersError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let previous = sqlx::query_scalar!(
+        "SELECT avatar_blob FROM users WHERE id = $1 FOR UPDATE",
+        session.user_id
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to fetch the avatar")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    sqlx::query!(
+        "UPDATE users SET avatar_blob = $1 WHERE id = $2",
+        blob,
+        session.user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update the avatar")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let event = AuditEvent {
+        action: AuditAction::ProfileUpdate,
+        actor_id: Some(session.user_id),
+        target_id: Some(session.user_id),
+        diff: json_diff(
+            &serde_json::json!({ "avatar": previous }),
+            &serde_json::json!({ "avatar": blob }),
+        ),
+    };
+    record_audit(&mut *transaction, &session.origin, event)
+        .await
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    // INFO: only warn about a leftover avatar, the garbage collector gets it otherwise
+    if let Some(previous) = previous {
+        let _ret = blob_storage
+            .post_storage_driver(&previous)
+            .post_clear_all()
+            .await
+            .context("Failed to delete the previous avatar")
+            .inspect_err(|e| tracing::warn!("{e:?}"));
+    }
+    Ok(())
+}
+
+/// Sets a JPEG, PNG, WebP or GIF avatar, stripped of its photo metadata.
+#[tracing::instrument(
+    name = "Upload avatar",
+    skip(session, payload, pool, blob_storage),
+    fields(user = %session.username)
+)]
+pub async fn upload_avatar(
+    session: Session,
+    payload: Multipart,
+    pool: web::Data<PgPool>,
+    blob_storage: web::Data<BlobStorage>,
+) -> Result<HttpResponse, UsersError> {
+    let bytes = read_avatar(payload).await?;
+    let extension = match sniff_bytes(&bytes) {
+        "image/jpeg" => "jpg",
+        "image/png" => "png",
+        "image/webp" => "webp",
+        "image/gif" => "gif",
+        other => {
+            return Err(UsersError::BadRequestError(format!(
+                "An avatar cannot be {other}"
+            )))
+        }
+    };
+
+    // INFO: an avatar is a blob of one file, one the database never came to refer to is
+    // left to the garbage collector
+    let blob = Uuid::new_v4().to_string();
+    let mut driver = blob_storage.post_storage_driver(&blob);
+    driver
+        .post_save_attachment(format!("avatar.{extension}"), bytes)
+        .await
+        .context("Failed to save the avatar")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+    driver
+        .confirm_saved()
+        .await
+        .context("Failed to save the avatar")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    replace_avatar(&session, &pool, &blob_storage, Some(&blob)).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[tracing::instrument(
+    name = "Remove avatar",
+    skip(session, pool, blob_storage),
+    fields(user = %session.username)
+)]
+pub async fn remove_avatar(
+    session: Session,
+    pool: web::Data<PgPool>,
+    blob_storage: web::Data<BlobStorage>,
+) -> Result<HttpResponse, UsersError> {
+    replace_avatar(&session, &pool, &blob_storage, None).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,157 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use secrecy::SecretBox;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::UsersError;
+use crate::components::confirmations::{purge_pending, send_confirmation, ConfirmationKind};
+use crate::components::email_delivery::EmailClient;
+use crate::configuration::UserSettings;
+use crate::domain::credentials::hash_password;
+use crate::domain::users::{NewUser, UserEmail, UserName, UserPassword};
+use crate::startup::engine::WebBaseUrl;
+use crate::telemetry::spawn_blocking_with_tracing;
+
+// INFO: the limits of the users table
+const MAX_USERNAME_LEN: usize = 64;
+const MAX_EMAIL_LEN: usize = 128;
+
+#[derive(serde::Deserialize)]
+pub struct SignUpForm {
+    username: String,
+    email: String,
+    password: SecretBox<String>,
+}
+
+impl TryFrom<SignUpForm> for (NewUser, UserPassword) {
+    type Error = String;
+
+    fn try_from(form: SignUpForm) -> Result<Self, Self::Error> {
+        if form.username.len() > MAX_USERNAME_LEN {
+            return Err("username is too long".into());
+        }
+        if form.email.len() > MAX_EMAIL_LEN {
+            return Err("email is too long".into());
+        }
+
+        let user = NewUser {
+            name: UserName::try_from(form.username)?,
+            email: UserEmail::try_from(form.email)?,
+        };
+        Ok((user, UserPassword::try_from(form.password)?))
+    }
+}
+
+#[tracing::instrument(
+    name = "Sign up a user",
+    skip(form, pool, email_client, base_url, settings),
+    fields(username = %form.username, email = %form.email)
+)]
+pub async fn sign_up(
+    form: web::Form<SignUpForm>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<WebBaseUrl>,
+    settings: web::Data<UserSettings>,
+) -> Result<HttpResponse, UsersError> {
+    let (user, password) = <(NewUser, UserPassword)>::try_from(form.into_inner())
+        .map_err(UsersError::BadRequestError)?;
+
+    // INFO: stale pending accounts give their username and email back before checking for conflicts
+    let _ret = purge_pending(
+        pool.get_ref(),
+        &settings.confirmation,
+        ConfirmationKind::User,
+    )
+    .await
+    .context("Failed to purge pending users")
+    .inspect_err(|e| tracing::warn!("{e:?}"));
+
+    let iterations = settings.password_hash_iterations;
+    let password_hash =
+        spawn_blocking_with_tracing(move || hash_password(password.expose(), iterations))
+            .await
+            .context("Failed to spawn the password hashing")?
+            .context("Failed to hash the password")
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let id = Uuid::new_v4();
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO users (id, username, email, password)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT DO NOTHING
+        "#,
+        id,
+        user.name.as_ref(),
+        user.email.as_ref(),
+        password_hash,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to insert user")
+    .inspect_err(|e| tracing::error!("{e:?}"))?
+    .rows_affected();
+    if inserted == 0 {
+        // INFO: usernames are public, but whether an email is registered must not be told
+        let email_taken =
+            sqlx::query!("SELECT id FROM users WHERE email = $1", user.email.as_ref())
+                .fetch_optional(&mut *transaction)
+                .await
+                .context("Failed to fetch the user of the email")
+                .inspect_err(|e| tracing::error!("{e:?}"))?
+                .is_some();
+        if !email_taken {
+            return Err(UsersError::ConflictError(
+                "The username is already taken".to_string(),
+            ));
+        }
+
+        let _ret = send_taken_email_notice(&email_client, &user.email)
+            .await
+            .inspect_err(|e| tracing::error!("{e:?}"));
+        return Ok(HttpResponse::Created().finish());
+    }
+
+    send_confirmation(
+        &mut transaction,
+        &email_client,
+        &base_url,
+        ConfirmationKind::User,
+        id,
+        &user.email,
+    )
+    .await
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    tracing::info!(%id, "User signed up, waiting for confirmation");
+    Ok(HttpResponse::Created().finish())
+}
+
+/// Tells the owner of `email` that someone tried to sign up with it, in place of a confirmation.
+async fn send_taken_email_notice(
+    email_client: &EmailClient,
+    email: &UserEmail,
+) -> anyhow::Result<()> {
+    email_client
+        .send_email(
+            email,
+            "Someone tried to sign up with your email",
+            "Someone tried to sign up with this email, which already has an account. If it was you, log in or reset your password instead. Otherwise, you can ignore this email.",
+            "Someone tried to sign up with this email, which already has an account. If it was you, log in or reset your password instead. Otherwise, you can ignore this email.",
+        )
+        .await
+        .context("Failed to send the taken email notice")
+}
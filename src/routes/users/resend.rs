@@ -0,0 +1,44 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+use super::UsersError;
+use crate::components::confirmations::{resend_confirmation as resend, ConfirmationKind};
+use crate::components::email_delivery::EmailClient;
+use crate::configuration::UserSettings;
+use crate::domain::users::UserEmail;
+use crate::startup::engine::WebBaseUrl;
+
+#[derive(serde::Deserialize)]
+pub struct ResendForm {
+    email: String,
+}
+
+/// Sends a new confirmation link to a pending account, see
+/// [`crate::components::confirmations::resend_confirmation`].
+#[tracing::instrument(
+    name = "Resend a confirmation email",
+    skip(form, pool, email_client, base_url, settings),
+    fields(email = %form.email)
+)]
+pub async fn resend_confirmation(
+    form: web::Form<ResendForm>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<WebBaseUrl>,
+    settings: web::Data<UserSettings>,
+) -> Result<HttpResponse, UsersError> {
+    let email =
+        UserEmail::try_from(form.into_inner().email).map_err(UsersError::BadRequestError)?;
+
+    resend(
+        pool.get_ref(),
+        &email_client,
+        &base_url,
+        &settings.confirmation,
+        ConfirmationKind::User,
+        &email,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
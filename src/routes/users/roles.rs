@@ -0,0 +1,140 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{UsersError, CONFIRMED};
+use crate::components::audit::{record_audit, AuditEvent, RequestOrigin};
+use crate::components::sessions::Session;
+use crate::domain::audit::{json_diff, AuditAction};
+use crate::domain::roles::{Permission, Role};
+
+#[derive(Debug, serde::Serialize)]
+pub struct UserSummary {
+    pub id: Uuid,
+    pub username: String,
+    pub email: String,
+    pub status: String,
+    pub role: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "List users", skip(session, pool), fields(user = %session.username))]
+pub async fn list_users(
+    session: Session,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, UsersError> {
+    session.require(Permission::ManageUsers)?;
+
+    let users = sqlx::query_as!(
+        UserSummary,
+        r#"
+        SELECT id, username, email, status, role, created_at
+        FROM users
+        ORDER BY created_at, username
+        "#
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch the users")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    Ok(HttpResponse::Ok().json(users))
+}
+
+#[derive(serde::Deserialize)]
+pub struct RoleForm {
+    role: String,
+}
+
+/// Sets the role of a user, keeping at least one confirmed admin around. `actor_id` is who asked
+/// for it, for the audit log; there is nobody to name from the CLI.
+pub async fn change_role(
+    pool: &PgPool,
+    user_id: Uuid,
+    role: Role,
+    actor_id: Option<Uuid>,
+    origin: &RequestOrigin,
+) -> Result<(), UsersError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    // INFO: locking the admins keeps two demotions from both seeing another admin left
+    let admins = sqlx::query_scalar!(
+        "SELECT id FROM users WHERE role = $1 AND status = $2 FOR UPDATE",
+        Role::Admin.as_str(),
+        CONFIRMED,
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to fetch the admins")?;
+    if role != Role::Admin && admins == [user_id] {
+        return Err(UsersError::ConflictError(
+            "The last admin cannot be demoted".to_string(),
+        ));
+    }
+
+    let previous = sqlx::query_scalar!("SELECT role FROM users WHERE id = $1 FOR UPDATE", user_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("Failed to fetch the role")?
+        .ok_or_else(|| UsersError::NotFoundError(format!("User {user_id} not found")))?;
+
+    sqlx::query!(
+        "UPDATE users SET role = $1 WHERE id = $2",
+        role.as_str(),
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update the role")?;
+
+    let event = AuditEvent {
+        action: AuditAction::RoleChange,
+        actor_id,
+        target_id: Some(user_id),
+        diff: json_diff(
+            &serde_json::json!({ "role": previous }),
+            &serde_json::json!({ "role": role.as_str() }),
+        ),
+    };
+    record_audit(&mut *transaction, origin, event).await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Set the role of a user",
+    skip(session, form, pool),
+    fields(user = %session.username, role = %form.role)
+)]
+pub async fn set_user_role(
+    session: Session,
+    user_id: web::Path<Uuid>,
+    form: web::Json<RoleForm>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, UsersError> {
+    session.require(Permission::ManageUsers)?;
+
+    let role: Role = form.role.parse().map_err(UsersError::BadRequestError)?;
+    let user_id = user_id.into_inner();
+    change_role(
+        pool.get_ref(),
+        user_id,
+        role,
+        Some(session.user_id),
+        &session.origin,
+    )
+    .await
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    tracing::info!(%user_id, %role, "Role changed");
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,524 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use secrecy::{ExposeSecret, SecretBox};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use super::{check_login_throttle, count_failed_login, LoginResponse, UsersError};
+use crate::components::audit::{record_audit, AuditEvent, RequestOrigin};
+use crate::components::email_delivery::EmailClient;
+use crate::components::login_throttle::clear_login_failures;
+use crate::components::secrets::SecretSealer;
+use crate::components::sessions::{create_session, Session};
+use crate::configuration::UserSettings;
+use crate::domain::audit::{json_diff, AuditAction};
+use crate::domain::credentials::{hash_token, new_token};
+use crate::domain::login_throttle::ThrottleKind;
+use crate::domain::totp;
+
+#[derive(serde::Serialize)]
+pub struct TotpEnrollment {
+    /// Base32, for typing into an authenticator app.
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct RecoveryCodes {
+    /// Shown this once, only their hashes are kept.
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct SecondFactorForm {
+    /// A TOTP code, or a recovery code.
+    code: SecretBox<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TwoFactorLoginForm {
+    challenge: SecretBox<String>,
+    code: SecretBox<String>,
+}
+
+fn unix_now() -> u64 {
+    Utc::now().timestamp().max(0) as u64
+}
+
+/// Opens a login waiting for the second factor of `user_id`, returning its token.
+pub async fn create_login_challenge(
+    connection: &mut PgConnection,
+    user_id: Uuid,
+    expiry: Duration,
+) -> anyhow::Result<(SecretBox<String>, DateTime<Utc>)> {
+    let (challenge, challenge_hash) = new_token()?;
+    let expires_at = Utc::now() + expiry;
+
+    sqlx::query!(
+        "DELETE FROM login_challenges WHERE user_id = $1 AND expires_at <= now()",
+        user_id
+    )
+    .execute(&mut *connection)
+    .await
+    .context("Failed to remove the expired login challenges")?;
+    sqlx::query!(
+        "INSERT INTO login_challenges (token_hash, user_id, expires_at) VALUES ($1, $2, $3)",
+        challenge_hash,
+        user_id,
+        expires_at,
+    )
+    .execute(&mut *connection)
+    .await
+    .context("Failed to store the login challenge")?;
+
+    Ok((challenge, expires_at))
+}
+
+/// Checks a TOTP or recovery code of `user_id`, using it up. TOTP codes are recognized by being
+/// all digits, anything else is taken for a recovery code.
+async fn check_second_factor(
+    connection: &mut PgConnection,
+    sealer: &SecretSealer,
+    user_id: Uuid,
+    code: &str,
+) -> anyhow::Result<bool> {
+    let code = code.trim();
+    if !code.is_empty() && code.chars().all(|c| c.is_ascii_digit()) {
+        let user = sqlx::query!(
+            "SELECT totp_secret, totp_last_step FROM users WHERE id = $1 FOR UPDATE",
+            user_id
+        )
+        .fetch_one(&mut *connection)
+        .await
+        .context("Failed to fetch the TOTP secret")?;
+        let Some(secret) = user.totp_secret else {
+            return Ok(false);
+        };
+        let secret = sealer.open(&secret)?;
+
+        let last_step = user.totp_last_step.map(|step| step as u64);
+        let Some(step) = totp::verify(&secret, code, unix_now(), last_step) else {
+            return Ok(false);
+        };
+        sqlx::query!(
+            "UPDATE users SET totp_last_step = $1 WHERE id = $2",
+            step as i64,
+            user_id
+        )
+        .execute(&mut *connection)
+        .await
+        .context("Failed to record the TOTP step")?;
+        return Ok(true);
+    }
+
+    let used = sqlx::query!(
+        "DELETE FROM recovery_codes WHERE user_id = $1 AND code_hash = $2",
+        user_id,
+        hash_token(&totp::normalize_recovery_code(code)),
+    )
+    .execute(&mut *connection)
+    .await
+    .context("Failed to use the recovery code")?
+    .rows_affected();
+    Ok(used > 0)
+}
+
+/// Starts enrolling an authenticator app. The second factor is only enforced once a code from
+/// it was verified at [`confirm_totp`].
+#[tracing::instrument(
+    name = "Enroll TOTP",
+    skip(session, pool, settings, sealer),
+    fields(user = %session.username)
+)]
+pub async fn enroll_totp(
+    session: Session,
+    pool: web::Data<PgPool>,
+    settings: web::Data<UserSettings>,
+    sealer: web::Data<SecretSealer>,
+) -> Result<HttpResponse, UsersError> {
+    let secret = totp::new_secret().context("Failed to generate the TOTP secret")?;
+    let sealed = sealer
+        .seal(&secret)
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let enrolled = sqlx::query!(
+        r#"
+        UPDATE users SET totp_pending_secret = $1
+        WHERE id = $2 AND totp_secret IS NULL
+        "#,
+        sealed,
+        session.user_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to store the TOTP secret")
+    .inspect_err(|e| tracing::error!("{e:?}"))?
+    .rows_affected();
+    if enrolled == 0 {
+        return Err(UsersError::ConflictError(
+            "Two-factor authentication is already enabled".to_string(),
+        ));
+    }
+
+    Ok(HttpResponse::Ok().json(TotpEnrollment {
+        otpauth_uri: totp::otpauth_uri(&settings.totp_issuer, &session.username, &secret),
+        secret,
+    }))
+}
+
+/// Enables the second factor with a code from the enrolled app, answering with recovery codes.
+#[tracing::instrument(
+    name = "Confirm TOTP",
+    skip(session, form, pool, sealer),
+    fields(user = %session.username)
+)]
+pub async fn confirm_totp(
+    session: Session,
+    form: web::Form<SecondFactorForm>,
+    pool: web::Data<PgPool>,
+    sealer: web::Data<SecretSealer>,
+) -> Result<HttpResponse, UsersError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let pending = sqlx::query_scalar!(
+        "SELECT totp_pending_secret FROM users WHERE id = $1 FOR UPDATE",
+        session.user_id
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to fetch the TOTP secret")
+    .inspect_err(|e| tracing::error!("{e:?}"))?
+    .ok_or_else(|| UsersError::ConflictError("No TOTP enrollment is pending".to_string()))?;
+    let pending = sealer
+        .open(&pending)
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let step = totp::verify(&pending, form.code.expose_secret(), unix_now(), None)
+        .ok_or_else(|| UsersError::BadRequestError("The code is invalid".to_string()))?;
+
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_secret = totp_pending_secret, totp_pending_secret = NULL, totp_last_step = $1
+        WHERE id = $2
+        "#,
+        step as i64,
+        session.user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to enable TOTP")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let recovery_codes =
+        totp::new_recovery_codes().context("Failed to generate the recovery codes")?;
+    sqlx::query!(
+        "DELETE FROM recovery_codes WHERE user_id = $1",
+        session.user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to remove the previous recovery codes")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+    for code in &recovery_codes {
+        sqlx::query!(
+            "INSERT INTO recovery_codes (user_id, code_hash) VALUES ($1, $2)",
+            session.user_id,
+            hash_token(&totp::normalize_recovery_code(code)),
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to store a recovery code")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+    }
+
+    let event = AuditEvent {
+        action: AuditAction::TotpEnable,
+        actor_id: Some(session.user_id),
+        target_id: Some(session.user_id),
+        diff: json_diff(
+            &serde_json::json!({ "two_factor": false }),
+            &serde_json::json!({ "two_factor": true }),
+        ),
+    };
+    record_audit(&mut *transaction, &session.origin, event)
+        .await
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    tracing::info!("Two-factor authentication enabled");
+    Ok(HttpResponse::Ok().json(RecoveryCodes { recovery_codes }))
+}
+
+/// Turns the second factor off, which takes one more code so a stolen session cannot. Wrong
+/// codes count as failed logins of the account, so they cannot be guessed either.
+#[tracing::instrument(
+    name = "Disable TOTP",
+    skip(session, form, pool, settings, email_client, sealer),
+    fields(user = %session.username)
+)]
+pub async fn disable_totp(
+    session: Session,
+    form: web::Form<SecondFactorForm>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<UserSettings>,
+    email_client: web::Data<EmailClient>,
+    sealer: web::Data<SecretSealer>,
+) -> Result<HttpResponse, UsersError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    check_login_throttle(&mut transaction, &session.username, &session.origin).await?;
+
+    let verified = check_second_factor(
+        &mut transaction,
+        &sealer,
+        session.user_id,
+        form.code.expose_secret(),
+    )
+    .await
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+    if !verified {
+        let email = sqlx::query_scalar!("SELECT email FROM users WHERE id = $1", session.user_id)
+            .fetch_one(&mut *transaction)
+            .await
+            .context("Failed to fetch the email of the user")
+            .inspect_err(|e| tracing::error!("{e:?}"))?;
+        count_failed_login(
+            &mut transaction,
+            &settings,
+            email_client,
+            &session.origin,
+            &session.username,
+            Some(email),
+        )
+        .await?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit transaction")?;
+        return Err(UsersError::BadRequestError(
+            "The code is invalid".to_string(),
+        ));
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE users SET totp_secret = NULL, totp_pending_secret = NULL, totp_last_step = NULL
+        WHERE id = $1
+        "#,
+        session.user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to disable TOTP")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+    sqlx::query!(
+        "DELETE FROM recovery_codes WHERE user_id = $1",
+        session.user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to remove the recovery codes")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    let event = AuditEvent {
+        action: AuditAction::TotpDisable,
+        actor_id: Some(session.user_id),
+        target_id: Some(session.user_id),
+        diff: json_diff(
+            &serde_json::json!({ "two_factor": true }),
+            &serde_json::json!({ "two_factor": false }),
+        ),
+    };
+    record_audit(&mut *transaction, &session.origin, event)
+        .await
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    tracing::info!("Two-factor authentication disabled");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Completes a login with the second factor, within `settings.two_factor_attempts` tries.
+/// Wrong codes count as failed logins of the account, so new challenges do not give an
+/// attacker who knows the password endless tries.
+#[tracing::instrument(
+    name = "Complete a two-factor login",
+    skip(form, origin, pool, settings, email_client, sealer)
+)]
+pub async fn complete_two_factor_login(
+    form: web::Form<TwoFactorLoginForm>,
+    origin: RequestOrigin,
+    pool: web::Data<PgPool>,
+    settings: web::Data<UserSettings>,
+    email_client: web::Data<EmailClient>,
+    sealer: web::Data<SecretSealer>,
+) -> Result<HttpResponse, UsersError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let challenge_hash = hash_token(form.challenge.expose_secret());
+    let challenge = sqlx::query!(
+        r#"
+        SELECT login_challenges.user_id, login_challenges.attempts, users.username, users.email
+        FROM login_challenges JOIN users ON users.id = login_challenges.user_id
+        WHERE login_challenges.token_hash = $1 AND login_challenges.expires_at > now()
+        FOR UPDATE OF login_challenges
+        "#,
+        challenge_hash
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to fetch the login challenge")
+    .inspect_err(|e| tracing::error!("{e:?}"))?
+    .ok_or_else(|| UsersError::UnauthorizedError("The login expired".to_string()))?;
+    check_login_throttle(&mut transaction, &challenge.username, &origin).await?;
+
+    let verified = check_second_factor(
+        &mut transaction,
+        &sealer,
+        challenge.user_id,
+        form.code.expose_secret(),
+    )
+    .await
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    // INFO: the challenge goes once used or out of attempts, so codes cannot be guessed forever
+    if verified || challenge.attempts + 1 >= settings.two_factor_attempts as i32 {
+        sqlx::query!(
+            "DELETE FROM login_challenges WHERE token_hash = $1",
+            challenge_hash
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to remove the login challenge")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+    } else {
+        sqlx::query!(
+            "UPDATE login_challenges SET attempts = attempts + 1 WHERE token_hash = $1",
+            challenge_hash
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to count the attempt")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+    }
+
+    let event = AuditEvent {
+        action: match verified {
+            true => AuditAction::Login,
+            false => AuditAction::LoginFailed,
+        },
+        actor_id: verified.then_some(challenge.user_id),
+        target_id: Some(challenge.user_id),
+        diff: serde_json::json!({ "second_factor": true }),
+    };
+    record_audit(&mut *transaction, &origin, event)
+        .await
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    if !verified {
+        count_failed_login(
+            &mut transaction,
+            &settings,
+            email_client,
+            &origin,
+            &challenge.username,
+            Some(challenge.email),
+        )
+        .await?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit transaction")?;
+        return Err(UsersError::UnauthorizedError(
+            "Invalid second factor".to_string(),
+        ));
+    }
+
+    let expiry = Duration::seconds(settings.session_expiry_secs as i64);
+    let (token, expires_at) = create_session(&mut transaction, challenge.user_id, expiry)
+        .await
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+    clear_login_failures(
+        &mut *transaction,
+        ThrottleKind::Account,
+        &challenge.username,
+    )
+    .await
+    .context("Failed to forget the failed logins")
+    .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")
+        .inspect_err(|e| tracing::error!("{e:?}"))?;
+
+    tracing::info!(user_id = %challenge.user_id, "User logged in with a second factor");
+    Ok(HttpResponse::Ok().json(LoginResponse::Session {
+        token: token.expose_secret().clone(),
+        expires_at,
+    }))
+}
+
+/// Seals every TOTP secret again with the current key, such as after rotating it, returning how
+/// many there were. Secrets stored in clear get sealed on the way.
+pub async fn reseal_totp_secrets(pool: &PgPool, sealer: &SecretSealer) -> anyhow::Result<usize> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let users = sqlx::query!(
+        r#"
+        SELECT id, totp_secret, totp_pending_secret FROM users
+        WHERE totp_secret IS NOT NULL OR totp_pending_secret IS NOT NULL
+        FOR UPDATE
+        "#
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to fetch the TOTP secrets")?;
+
+    let reseal = |stored: Option<String>| {
+        stored
+            .map(|stored| sealer.seal(&sealer.open(&stored)?))
+            .transpose()
+    };
+    for user in &users {
+        sqlx::query!(
+            "UPDATE users SET totp_secret = $1, totp_pending_secret = $2 WHERE id = $3",
+            reseal(user.totp_secret.clone())?,
+            reseal(user.totp_pending_secret.clone())?,
+            user.id
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to store the resealed TOTP secrets")?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+    Ok(users.len())
+}
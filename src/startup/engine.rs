@@ -1,6 +1,6 @@
 use actix_cors::Cors;
-use actix_multipart::form::MultipartFormConfig;
 use actix_web::dev::Server;
+use actix_web::http::Method;
 use actix_web::middleware::{self, TrailingSlash};
 use actix_web::{web, App, HttpServer};
 use anyhow::Result;
@@ -8,7 +8,10 @@ use tracing_actix_web::TracingLogger;
 
 use nn_rs::prelude::*;
 
+use crate::components::secrets::SecretSealer;
 use crate::configuration::Settings;
+use crate::domain::attachments::AttachmentPolicy;
+use crate::domain::storage_usage::StorageQuota;
 use crate::routes::*;
 
 use super::prepare::Kits;
@@ -17,14 +20,32 @@ pub struct Engine {
     web_server: Server,
 }
 
+/// The scope every API route is served under.
+pub const API_SCOPE: &str = "/api";
+
+/// The root the site is served from, as configured in `application.base_url`.
 pub struct WebBaseUrl(pub String);
 
+impl WebBaseUrl {
+    /// The absolute URL of the API route `path`, for links sent out of the server.
+    pub fn api_url(&self, path: &str) -> String {
+        format!("{}{API_SCOPE}{path}", self.0.trim_end_matches('/'))
+    }
+}
+
 impl Engine {
     pub fn build(config: Settings, kits: Kits) -> Result<Self> {
         let db_pool = web::Data::new(kits.db_pool);
         let email_client = web::Data::new(kits.email_client);
         let blob_storage = web::Data::new(kits.blob_storage);
         let base_url = web::Data::new(WebBaseUrl(config.application.base_url));
+        let attachment_policy =
+            web::Data::new(AttachmentPolicy::from(&config.blob_storage.attachments));
+        let resumable_uploads = web::Data::new(config.blob_storage.resumable_uploads.clone());
+        let storage_quota = web::Data::new(StorageQuota::from(&config.blob_storage.quotas));
+        let user_settings = web::Data::new(config.users.clone());
+        let newsletter_settings = web::Data::new(config.newsletter.clone());
+        let secret_sealer = web::Data::new(SecretSealer::try_from(&config.blob_storage)?);
         let nn = NNBuilder::new_from_model_file(config.application.model_path)?.build()?;
         let nn = web::Data::new(nn);
 
@@ -38,34 +59,132 @@ impl Engine {
                         .allowed_origin("http://localhost:3000") // Replace with your frontend origin
                         .allow_any_method()
                         .allow_any_header()
+                        .expose_headers([
+                            "Location",
+                            "Upload-Offset",
+                            "Upload-Length",
+                            "Upload-Expires",
+                            "Tus-Resumable",
+                            "Tus-Version",
+                            "Tus-Extension",
+                            "Tus-Max-Size",
+                        ])
                         .max_age(3600),
                 )
                 .service(
-                    web::scope("/api")
+                    web::scope(API_SCOPE)
                         .service(
                             web::scope("/posts")
                                 .route("", web::get().to(get_all_posts))
                                 .route("", web::post().to(upload_post))
                                 .route("/{id}", web::put().to(update_post))
                                 .route("/{id}", web::delete().to(delete_post))
+                                .route("/{id}/review", web::get().to(get_post_review))
+                                .route("/{id}/review/reviewer", web::put().to(assign_reviewer))
+                                .route("/{id}/review/comments", web::post().to(add_review_comment))
+                                .route("/{id}/review/approve", web::post().to(approve_post))
+                                .route(
+                                    "/{id}/review/request_changes",
+                                    web::post().to(request_post_changes),
+                                )
                                 .route("/slug/{slug}", web::get().to(get_post_by_slug))
                                 .route(
-                                    "/slug/{slug}/{attachment}",
+                                    "/slug/{slug}/{attachment:.*}",
                                     web::get().to(get_post_attachment),
                                 )
+                                // INFO: after the slug routes, so a post slugged `review` keeps its
+                                // attachments
+                                .route(
+                                    "/{id}/review/attachments/{attachment:.*}",
+                                    web::get().to(get_review_attachment),
+                                )
                                 .route("/count", web::get().to(posts_count)),
                         )
+                        .service(
+                            web::scope("/uploads")
+                                .route("", web::method(Method::OPTIONS).to(describe_uploads))
+                                .route("", web::post().to(create_upload))
+                                .route("/{id}", web::head().to(upload_status))
+                                .route("/{id}", web::patch().to(patch_upload))
+                                .route("/{id}", web::delete().to(terminate_upload)),
+                        )
+                        .service(
+                            web::scope("/subscriptions")
+                                .route("", web::get().to(list_subscriptions))
+                                .route("", web::post().to(subscribe))
+                                .route("/confirm", web::get().to(confirm_subscription))
+                                .route(
+                                    "/resend_confirmation",
+                                    web::post().to(resend_subscription_confirmation),
+                                )
+                                .route("/unsubscribe", web::get().to(show_unsubscribe))
+                                .route("/unsubscribe", web::post().to(unsubscribe))
+                                .route("/{id}", web::delete().to(remove_subscription)),
+                        )
+                        .service(
+                            web::scope("/users")
+                                .route("", web::get().to(list_users))
+                                .route("", web::post().to(sign_up))
+                                .route("/confirm", web::get().to(confirm_user))
+                                .route("/resend_confirmation", web::post().to(resend_confirmation))
+                                .route("/login", web::post().to(login))
+                                .route(
+                                    "/login/two_factor",
+                                    web::post().to(complete_two_factor_login),
+                                )
+                                .route("/me/totp", web::post().to(enroll_totp))
+                                .route("/me/totp", web::delete().to(disable_totp))
+                                .route("/me/totp/confirm", web::post().to(confirm_totp))
+                                .route("/logout", web::post().to(logout))
+                                .route("/password_reset", web::post().to(request_password_reset))
+                                .route(
+                                    "/password_reset/complete",
+                                    web::get().to(show_password_reset),
+                                )
+                                .route(
+                                    "/password_reset/complete",
+                                    web::post().to(complete_password_reset),
+                                )
+                                .route("/me/profile", web::put().to(update_profile))
+                                .route("/me/avatar", web::put().to(upload_avatar))
+                                .route("/me/avatar", web::delete().to(remove_avatar))
+                                .route("/{id}/role", web::put().to(set_user_role)),
+                        )
+                        .service(
+                            web::scope("/authors")
+                                .route("/{username}", web::get().to(get_author))
+                                .route("/{username}/avatar", web::get().to(get_author_avatar))
+                                .route("/{username}/posts", web::get().to(get_author_posts)),
+                        )
+                        .service(
+                            web::scope("/admin")
+                                .route("/audit", web::get().to(get_audit_log))
+                                .route("/gc", web::post().to(collect_blob_garbage))
+                                .route("/login_locks", web::get().to(list_login_locks))
+                                .route(
+                                    "/login_locks/{kind}/{key}",
+                                    web::delete().to(clear_login_lock),
+                                )
+                                .route("/scrub", web::post().to(start_blob_scrub))
+                                .route("/scrub", web::get().to(last_blob_scrub))
+                                .route("/storage", web::get().to(storage_usage)),
+                        )
                         .service(
                             web::scope("/playground")
                                 .route("digit_recognition", web::post().to(recognize_digit)),
                         )
                         .route("/health_check", web::get().to(health_check)),
                 )
-                .app_data(MultipartFormConfig::default().total_limit(100 * 1024 * 1024))
                 .app_data(db_pool.clone())
                 .app_data(email_client.clone())
                 .app_data(blob_storage.clone())
                 .app_data(base_url.clone())
+                .app_data(attachment_policy.clone())
+                .app_data(resumable_uploads.clone())
+                .app_data(storage_quota.clone())
+                .app_data(user_settings.clone())
+                .app_data(newsletter_settings.clone())
+                .app_data(secret_sealer.clone())
                 .app_data(nn.clone())
         })
         .listen(kits.listener)?
@@ -67,7 +67,7 @@ pub fn prepare_email_client(config: &Settings) -> EmailClient {
 }
 
 pub fn prepare_blob_storage(config: &Settings) -> std::io::Result<BlobStorage> {
-    let bs = BlobStorage::new(config.blob_storage.base_dir.clone());
+    let bs = BlobStorage::try_from(&config.blob_storage)?;
     bs.try_init_blob_storage()?;
     Ok(bs)
 }
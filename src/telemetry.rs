@@ -6,7 +6,7 @@
 use is_terminal::IsTerminal;
 use tokio::task::JoinHandle;
 use tracing::subscriber::set_global_default;
-use tracing::Subscriber;
+use tracing::{Instrument, Subscriber};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
 use tracing_subscriber::fmt::MakeWriter;
@@ -16,6 +16,8 @@ use tracing_subscriber::{
     EnvFilter, Registry,
 };
 
+use std::future::Future;
+
 // TODO: figure out the difference between these format
 #[derive(serde::Deserialize, Debug, Clone, Copy)]
 #[serde(rename_all = "lowercase")]
@@ -144,3 +146,11 @@ where
     let current_span = tracing::Span::current();
     tokio::task::spawn_blocking(move || current_span.in_scope(f))
 }
+
+pub fn spawn_with_tracing<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::spawn(future.instrument(tracing::Span::current()))
+}
@@ -0,0 +1,309 @@
+use reqwest::multipart::{Form, Part};
+
+use std::collections::HashMap;
+
+use crate::utils::TestApp;
+
+#[tokio::test]
+async fn gc_collects_orphan_blobs_and_reports_posts_missing_their_blob() {
+    let app = TestApp::spawn_server().await;
+    let api_addr = format!("{}/admin/gc", app.address);
+
+    let kept = uuid::Uuid::new_v4();
+    let missing = uuid::Uuid::new_v4();
+    for id in [kept, missing] {
+        sqlx::query!(
+            "INSERT INTO posts (id, slug, title, blob, date) VALUES ($1, $2, $3, $4, $5)",
+            id,
+            id.to_string(),
+            "Some post",
+            id.to_string(),
+            chrono::Utc::now(),
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+    for blob in [kept.to_string(), "orphan".to_string()] {
+        let mut driver = app.blob_storage.post_storage_driver(&blob);
+        driver.post_save_content("post.md", &blob).await.unwrap();
+        driver.confirm_saved().await.unwrap();
+    }
+
+    let report: serde_json::Value = app
+        .client
+        .post(&api_addr)
+        .query(&[("dry_run", "true"), ("grace_period_secs", "0")])
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(report["orphan_blobs"], serde_json::json!(["orphan"]));
+    assert_eq!(report["missing_blobs"], serde_json::json!([missing]));
+    assert!(app.blob_storage.post_manifest("orphan").await.is_ok());
+
+    let report: serde_json::Value = app
+        .client
+        .post(&api_addr)
+        .query(&[("grace_period_secs", "0")])
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(report["action"], "quarantine");
+    assert_eq!(report["orphan_blobs"], serde_json::json!(["orphan"]));
+    assert!(app.blob_storage.post_manifest("orphan").await.is_err());
+    assert!(app
+        .blob_storage
+        .post_manifest(&kept.to_string())
+        .await
+        .is_ok());
+
+    let report: serde_json::Value = app
+        .client
+        .post(&api_addr)
+        .query(&[("action", "delete"), ("grace_period_secs", "0")])
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        report["purged"],
+        serde_json::json!(["quarantine/posts/orphan/manifest.json"])
+    );
+    assert_eq!(report["unreferenced_objects"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn scrub_reports_posts_whose_files_were_corrupted_on_disk() {
+    let dir = tempfile::tempdir().unwrap();
+    let base_dir = dir.path().to_path_buf();
+    let app = TestApp::spawn_server_with(move |config| {
+        config.blob_storage.ephemeral = false;
+        config.blob_storage.base_dir = base_dir;
+    })
+    .await;
+    let api_addr = format!("{}/admin/scrub", app.address);
+
+    let response = app
+        .client
+        .get(&api_addr)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 404);
+
+    let content = Part::file("tests/data/travel/journal.md").await.unwrap();
+    let body: HashMap<String, String> = app
+        .client
+        .post(format!("{}/posts", app.address))
+        .multipart(Form::new().part("file", content))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .unwrap();
+    let blob = &body["id"];
+    let manifest = app.blob_storage.post_manifest(blob).await.unwrap();
+    let object = &manifest.content_entry().unwrap().object;
+    let path = app.blob_storage.local_object_path(object).unwrap();
+    std::fs::write(path, "edited by hand").unwrap();
+
+    let response = app
+        .client
+        .post(&api_addr)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 202);
+
+    let mut report = serde_json::Value::Null;
+    for _ in 0..50 {
+        let response = app
+            .client
+            .get(&api_addr)
+            .send()
+            .await
+            .expect("Failed to send request");
+        if response.status().is_success() {
+            report = response.json().await.unwrap();
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    assert_eq!(report["posts_checked"], 1);
+    assert_eq!(report["damaged_posts"][0]["slug"], body["slug"]);
+    assert_eq!(
+        report["damaged_posts"][0]["files"][0]["damage"],
+        "corrupted"
+    );
+}
+
+fn post_form(title: &str, notes: usize) -> Form {
+    let content = format!("---\ntitle: {title}\ndate: 2024-10-26T00:00:00Z\n---\n\n# {title}\n");
+    Form::new()
+        .part(
+            "file",
+            Part::bytes(content.into_bytes()).file_name(format!("{title}.md")),
+        )
+        .part(
+            "file",
+            Part::bytes(vec![b'n'; notes]).file_name("notes.txt"),
+        )
+}
+
+#[tokio::test]
+async fn storage_report_breaks_usage_down_by_post_and_type() {
+    let app = TestApp::spawn_server().await;
+
+    let journal = Form::new()
+        .part(
+            "file",
+            Part::file("tests/data/travel/journal.md").await.unwrap(),
+        )
+        .part(
+            "file",
+            Part::file("tests/data/travel/image.jpeg").await.unwrap(),
+        );
+    for form in [post_form("small", 10), journal] {
+        let response = app
+            .client
+            .post(format!("{}/posts", app.address))
+            .multipart(form)
+            .send()
+            .await
+            .expect("Failed to send request");
+        assert_eq!(response.status().as_u16(), 201);
+    }
+
+    let report: serde_json::Value = app
+        .client
+        .get(format!("{}/admin/storage", app.address))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .unwrap();
+
+    let posts = report["posts"].as_array().unwrap();
+    assert_eq!(posts.len(), 2);
+    assert_eq!(posts[1]["slug"], "small");
+    let small_types: Vec<_> = posts[1]["by_type"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|usage| usage["mime"].as_str().unwrap())
+        .collect();
+    assert_eq!(small_types, vec!["text/markdown", "text/plain"]);
+    assert_eq!(posts[1]["by_type"][1]["bytes"], 10);
+
+    let images = report["by_type"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|usage| usage["mime"] == "image/jpeg")
+        .unwrap();
+    assert_eq!(images["files"], 1);
+    assert!(images["bytes"].as_u64().unwrap() > 0);
+
+    let total: u64 = posts
+        .iter()
+        .map(|post| post["bytes"].as_u64().unwrap())
+        .sum();
+    assert!(posts[0]["bytes"].as_u64().unwrap() > posts[1]["bytes"].as_u64().unwrap());
+    assert_eq!(report["total_bytes"], total);
+    assert_eq!(report["quota"]["per_post"], serde_json::Value::Null);
+}
+
+#[tokio::test]
+async fn posts_over_the_per_post_quota_are_rejected() {
+    let app = TestApp::spawn_server_with(|config| {
+        config.blob_storage.quotas.per_post = Some(4096);
+    })
+    .await;
+
+    let response = app
+        .client
+        .post(format!("{}/posts", app.address))
+        .multipart(post_form("large", 8192))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 413);
+
+    let response = app
+        .client
+        .post(format!("{}/posts", app.address))
+        .multipart(post_form("small", 1024))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 201);
+
+    let report: serde_json::Value = app
+        .client
+        .get(format!("{}/admin/storage", app.address))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(report["posts"].as_array().unwrap().len(), 1);
+    assert_eq!(report["quota"]["per_post"], 4096);
+}
+
+#[tokio::test]
+async fn posts_over_the_global_quota_are_rejected_but_may_replace_their_own_files() {
+    let app = TestApp::spawn_server_with(|config| {
+        config.blob_storage.quotas.global = Some(5000);
+    })
+    .await;
+
+    let body: HashMap<String, String> = app
+        .client
+        .post(format!("{}/posts", app.address))
+        .multipart(post_form("first", 3000))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .unwrap();
+
+    let response = app
+        .client
+        .post(format!("{}/posts", app.address))
+        .multipart(post_form("second", 3000))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 413);
+
+    let response = app
+        .client
+        .put(format!("{}/posts/{}", app.address, body["id"]))
+        .multipart(post_form("first", 4000))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app
+        .client
+        .put(format!("{}/posts/{}", app.address, body["id"]))
+        .multipart(post_form("first", 6000))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 413);
+}
@@ -1,4 +1,14 @@
+mod admin;
+mod audit;
+mod authors;
+mod blob_migration;
 mod health_check;
 mod playground;
 mod posts;
+mod review;
+mod roles;
+mod staged_writes;
+mod subscriptions;
+mod uploads;
+mod users;
 mod utils;
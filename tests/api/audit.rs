@@ -0,0 +1,140 @@
+use reqwest::multipart::{Form, Part};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+use crate::utils::TestApp;
+
+fn post_form(title: &str) -> Form {
+    let content = format!("---\ntitle: {title}\ndate: 2024-10-26T00:00:00Z\n---\n\n# {title}\n");
+    Form::new().part(
+        "file",
+        Part::bytes(content.into_bytes()).file_name(format!("{title}.md")),
+    )
+}
+
+async fn audit_log(app: &TestApp, query: &str) -> Value {
+    let response = app
+        .client
+        .get(format!("{}/admin/audit?{query}", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    response.json().await.unwrap()
+}
+
+fn actions(log: &Value) -> Vec<&str> {
+    log["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["action"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn post_changes_are_audited_with_who_where_and_what() {
+    let app = TestApp::spawn_server().await;
+
+    let response = app
+        .client
+        .post(format!("{}/posts", app.address))
+        .multipart(post_form("Before"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let id = response.json::<Value>().await.unwrap()["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let response = app
+        .client
+        .put(format!("{}/posts/{id}", app.address))
+        .multipart(post_form("After"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app
+        .client
+        .delete(format!("{}/posts/{id}", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let log = audit_log(&app, &format!("target_id={id}")).await;
+    assert_eq!(log["total"], 3);
+    assert_eq!(actions(&log), ["post.delete", "post.update", "post.upload"]);
+    for entry in log["entries"].as_array().unwrap() {
+        assert_eq!(entry["actor"], "admin");
+        assert_eq!(entry["ip"], "127.0.0.1");
+        assert!(entry["request_id"].is_string());
+    }
+
+    let update = &log["entries"][1]["diff"];
+    assert_eq!(update["title"], json!({ "from": "Before", "to": "After" }));
+    assert!(update["blob"]["from"] != update["blob"]["to"]);
+    assert_eq!(log["entries"][2]["diff"]["title"]["to"], "Before");
+    assert_eq!(log["entries"][0]["diff"]["title"]["from"], "After");
+}
+
+#[tokio::test]
+async fn the_audit_log_is_filtered_paged_and_kept_from_non_admins() {
+    let app = TestApp::spawn_server().await;
+    let editor = app.user_with_role("editor", "editor").await;
+    app.user_with_role("viewer", "viewer").await;
+
+    for username in ["viewer", "somebody", "anybody"] {
+        let response = reqwest::Client::new()
+            .post(format!("{}/users/login", app.address))
+            .form(&[("username", username), ("password", "a wrong password")])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+    let editor_id: uuid::Uuid =
+        sqlx::query_scalar!("SELECT id FROM users WHERE username = 'editor'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    let response = app
+        .client
+        .put(format!("{}/users/{editor_id}/role", app.address))
+        .json(&json!({ "role": "author" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let log = audit_log(&app, "action=user.login_failed&page=2&page_size=2").await;
+    assert_eq!(log["total"], 3);
+    assert_eq!(actions(&log), ["user.login_failed"]);
+    assert_eq!(log["entries"][0]["diff"]["username"], "viewer");
+    assert!(log["entries"][0]["actor_id"].is_null());
+    // INFO: what was typed for unknown accounts is not kept
+    let log = audit_log(&app, "action=user.login_failed&page_size=2").await;
+    assert_eq!(log["entries"][0]["diff"], json!({}));
+    assert!(log["entries"][0]["target_id"].is_null());
+
+    let log = audit_log(&app, &format!("target_id={editor_id}")).await;
+    assert_eq!(actions(&log), ["user.role_change"]);
+    assert_eq!(
+        log["entries"][0]["diff"],
+        json!({ "role": { "from": "editor", "to": "author" } })
+    );
+
+    let log = audit_log(&app, "since=2999-01-01T00:00:00Z").await;
+    assert_eq!(log["total"], 0);
+
+    // INFO: the editor is an author by now, neither may read the log
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/audit", app.address))
+        .bearer_auth(&editor)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
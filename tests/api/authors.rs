@@ -0,0 +1,193 @@
+use reqwest::multipart::{Form, Part};
+use reqwest::StatusCode;
+use serde_json::Value;
+
+use crate::utils::TestApp;
+
+fn post_form(front_matter: &str) -> Form {
+    let content = format!("---\n{front_matter}date: 2024-10-26T00:00:00Z\n---\n\n# Hello\n");
+    Form::new().part(
+        "file",
+        Part::bytes(content.into_bytes()).file_name("post.md"),
+    )
+}
+
+async fn upload_as(app: &TestApp, token: &str, front_matter: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/posts", app.address))
+        .bearer_auth(token)
+        .multipart(post_form(front_matter))
+        .send()
+        .await
+        .expect("Failed to send request")
+}
+
+async fn get_json(app: &TestApp, path: &str) -> (StatusCode, Value) {
+    let response = reqwest::get(format!("{}{path}", app.address))
+        .await
+        .unwrap();
+    let status = response.status();
+    (status, response.json().await.unwrap_or_default())
+}
+
+#[tokio::test]
+async fn posts_are_credited_to_the_uploader_or_the_author_an_editor_names() {
+    let app = TestApp::spawn_server().await;
+    let author = app.user_with_role("author", "author").await;
+    let editor = app.user_with_role("editor", "editor").await;
+    app.user_with_role("reader", "viewer").await;
+
+    let response = upload_as(&app, &author, "title: Own words\n").await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let own_words: Value = response.json().await.unwrap();
+    let response = upload_as(&app, &editor, "title: Ghostwritten\nauthor: author\n").await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let ghostwritten: Value = response.json().await.unwrap();
+
+    let response = upload_as(&app, &author, "title: Impersonated\nauthor: editor\n").await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = upload_as(&app, &editor, "title: Unknown\nauthor: nobody\n").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // INFO: the post of the author is only listed once an editor approved it
+    let (_, posts) = get_json(&app, "/authors/author/posts").await;
+    assert_eq!(posts.as_array().unwrap().len(), 1);
+    let response = app
+        .client
+        .post(format!(
+            "{}/posts/{}/review/approve",
+            app.address,
+            own_words["id"].as_str().unwrap()
+        ))
+        .form(&[("revision", "1")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let (status, posts) = get_json(&app, "/authors/author/posts").await;
+    assert_eq!(status, StatusCode::OK);
+    let titles: Vec<&str> = posts
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|post| post["title"].as_str().unwrap())
+        .collect();
+    assert_eq!(titles.len(), 2);
+    assert!(titles.contains(&"Own words") && titles.contains(&"Ghostwritten"));
+    let (_, posts) = get_json(&app, "/authors/author/posts?page=2&page_size=1").await;
+    assert_eq!(posts.as_array().unwrap().len(), 1);
+
+    let slug = ghostwritten["slug"].as_str().unwrap();
+    let (_, post) = get_json(&app, &format!("/posts/slug/{slug}")).await;
+    assert_eq!(post["author"]["username"], "author");
+
+    // INFO: editors have a profile without a post yet, readers none at all
+    let (status, _) = get_json(&app, "/authors/editor/posts").await;
+    assert_eq!(status, StatusCode::OK);
+    for path in ["/authors/reader", "/authors/nobody/posts"] {
+        let (status, _) = get_json(&app, path).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
+
+#[tokio::test]
+async fn authors_fill_in_a_public_profile_with_an_avatar() {
+    let app = TestApp::spawn_server().await;
+    let author = app.user_with_role("author", "author").await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .put(format!("{}/users/me/profile", app.address))
+        .bearer_auth(&author)
+        .form(&[("display_name", " Neil "), ("bio", "Writes about trips.")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let set_avatar = |part: Part| {
+        client
+            .put(format!("{}/users/me/avatar", app.address))
+            .bearer_auth(&author)
+            .multipart(Form::new().part("avatar", part))
+            .send()
+    };
+    let response = set_avatar(Part::bytes(b"not an image".to_vec()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let photo = Part::file("tests/data/travel/image.jpeg").await.unwrap();
+    let response = set_avatar(photo).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let (status, profile) = get_json(&app, "/authors/author").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(profile["display_name"], "Neil");
+    assert_eq!(profile["bio"], "Writes about trips.");
+    assert_eq!(profile["posts"], 0);
+    let avatar_url = profile["avatar_url"].as_str().unwrap().to_string();
+
+    // INFO: the avatar is live storage, not garbage
+    let response = app
+        .client
+        .post(format!("{}/admin/gc", app.address))
+        .query(&[("action", "delete"), ("grace_period_secs", "0")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = reqwest::get(&avatar_url).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "image/jpeg");
+    let avatar = response.bytes().await.unwrap();
+    assert!(image::load_from_memory(&avatar).is_ok());
+
+    let response = client
+        .delete(format!("{}/users/me/avatar", app.address))
+        .bearer_auth(&author)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let (_, profile) = get_json(&app, "/authors/author").await;
+    assert!(profile["avatar_url"].is_null());
+    let response = reqwest::get(&avatar_url).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn revisions_may_keep_crediting_the_current_author() {
+    let app = TestApp::spawn_server().await;
+    let author = app.user_with_role("author", "author").await;
+    let editor = app.user_with_role("editor", "editor").await;
+    app.user_with_role("coauthor", "author").await;
+
+    let response = upload_as(&app, &author, "title: Shared trip\n").await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let post: Value = response.json().await.unwrap();
+    let id = post["id"].as_str().unwrap();
+
+    let update_as = |token: &str, front_matter: &str| {
+        reqwest::Client::new()
+            .put(format!("{}/posts/{id}", app.address))
+            .bearer_auth(token)
+            .multipart(post_form(front_matter))
+            .send()
+    };
+    let response = update_as(&editor, "title: Shared trip\nauthor: coauthor\n")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // INFO: the uploader keeps the credit the editor gave, but may not hand it on
+    let response = update_as(&author, "title: Shared trip\nauthor: coauthor\n")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = update_as(&author, "title: Shared trip\nauthor: editor\n")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
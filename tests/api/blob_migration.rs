@@ -0,0 +1,101 @@
+use reqwest::multipart::{Form, Part};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use pine_tails::components::blob_migration::{follow_blob_migration, migrate_blobs};
+use pine_tails::components::blob_storage::{BlobStorage, LocalBackend};
+use pine_tails::configuration::{StorageBackendSettings, StorageMigrationSettings};
+
+use crate::utils::TestApp;
+
+#[tokio::test]
+async fn migration_copies_older_blobs_resumes_and_switches_reads_to_the_target() {
+    let source_dir = tempfile::tempdir().unwrap();
+    let target_dir = tempfile::tempdir().unwrap();
+    let (source, target) = (
+        source_dir.path().to_path_buf(),
+        target_dir.path().to_path_buf(),
+    );
+    let app = TestApp::spawn_server_with(move |config| {
+        config.blob_storage.ephemeral = false;
+        config.blob_storage.base_dir = source;
+        config.blob_storage.migrate_to = Some(StorageMigrationSettings {
+            base_dir: Some(target),
+            backend: StorageBackendSettings::Local,
+        });
+    })
+    .await;
+
+    // INFO: written before the server mirrored anything, so only the source has it
+    let old_post = uuid::Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO posts (id, slug, title, blob, date, published_at)
+        VALUES ($1, $2, $3, $4, $5, $5)
+        "#,
+        old_post,
+        "old-post",
+        "Old post",
+        old_post.to_string(),
+        chrono::Utc::now(),
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let before = BlobStorage::new(Arc::new(LocalBackend::new(source_dir.path().to_path_buf())));
+    let mut driver = before.post_storage_driver(&old_post.to_string());
+    driver
+        .post_save_content("post.md", "# Old post")
+        .await
+        .unwrap();
+    driver.confirm_saved().await.unwrap();
+
+    let content = Part::file("tests/data/travel/journal.md").await.unwrap();
+    let image = Part::file("tests/data/travel/image.jpeg").await.unwrap();
+    let body: HashMap<String, String> = app
+        .client
+        .post(format!("{}/posts", app.address))
+        .multipart(Form::new().part("file", content).part("file", image))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .unwrap();
+
+    let migration = app.blob_storage.migration().unwrap();
+    let first = migrate_blobs(&app.db_pool, migration, false).await.unwrap();
+    assert!(first.copied > 0);
+    assert!(first.failed.is_empty());
+    assert!(!first.switched);
+
+    let second = migrate_blobs(&app.db_pool, migration, true).await.unwrap();
+    assert_eq!(second.copied, 0);
+    assert_eq!(second.already_copied, first.copied);
+    assert!(second.switched);
+    assert!(follow_blob_migration(&app.db_pool, &app.blob_storage)
+        .await
+        .unwrap());
+
+    std::fs::remove_dir_all(source_dir.path()).unwrap();
+    for slug in ["old-post", body["slug"].as_str()] {
+        let response = app
+            .client
+            .get(format!("{}/posts/slug/{slug}", app.address))
+            .send()
+            .await
+            .expect("Failed to send request");
+        assert!(response.status().is_success(), "{slug} is not served");
+    }
+    let response = app
+        .client
+        .get(format!(
+            "{}/posts/slug/{}/image.jpeg",
+            app.address, body["slug"]
+        ))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert!(response.status().is_success());
+}
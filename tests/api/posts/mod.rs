@@ -1,10 +1,12 @@
 use chrono::{Duration, Utc};
 use futures::stream::{self, StreamExt};
 use reqwest::multipart::{Form, Part};
+use secrecy::SecretBox;
 use sqlx::PgPool;
 
 use std::{collections::HashMap, sync::Arc};
 
+use pine_tails::configuration::EncryptionSettings;
 use pine_tails::domain::posts::{Post, PostBuilder};
 
 use crate::utils::TestApp;
@@ -12,7 +14,10 @@ use crate::utils::TestApp;
 async fn insert_post(pool: &PgPool, post: &Post) -> uuid::Uuid {
     let id = uuid::Uuid::new_v4();
     sqlx::query!(
-        "INSERT INTO posts (id, slug, title, content, date, blob) VALUES ($1, $2, $3, $4, $5, $6)",
+        r#"
+        INSERT INTO posts (id, slug, title, content, date, blob, published_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $5)
+        "#,
         id,
         post.metadata.slug,
         post.metadata.title,
@@ -38,12 +43,12 @@ async fn get_post_with_existing_slug_should_return_ok() {
 
     let id = insert_post(&app.db_pool, &post).await;
 
-    let mut local_driver = app.blob_storage.post_storage_driver(&id.to_string());
-    local_driver.try_init().unwrap();
-    local_driver
+    let mut driver = app.blob_storage.post_storage_driver(&id.to_string());
+    driver
         .post_save_content("hello-there.md", &post.content)
+        .await
         .unwrap();
-    local_driver.confirm_saved();
+    driver.confirm_saved().await.unwrap();
 
     let response = app
         .client
@@ -53,10 +58,10 @@ async fn get_post_with_existing_slug_should_return_ok() {
         .expect("Failed to send request");
 
     assert_eq!(response.status().as_u16(), 200);
-    let body: HashMap<String, String> = response.json().await.unwrap();
+    let body: serde_json::Value = response.json().await.unwrap();
 
-    assert_eq!(body.get("title").unwrap(), "hello there");
-    assert_eq!(body.get("content").unwrap(), "some content");
+    assert_eq!(body["title"], "hello there");
+    assert_eq!(body["content"], "some content");
 }
 
 #[tokio::test]
@@ -180,3 +185,770 @@ async fn upload_post_returns_201_and_persists_data() {
 
     println!("{:?}", post);
 }
+
+#[tokio::test]
+async fn upload_post_keeps_nested_attachment_paths() {
+    let app = TestApp::spawn_server().await;
+    let api_addr = format!("{}/posts", app.address);
+    let image_path = std::path::Path::new("tests/data/travel/image.jpeg");
+    let content = Part::file("tests/data/travel/journal.md")
+        .await
+        .unwrap()
+        .file_name("travel/journal.md");
+    let image = Part::file(image_path)
+        .await
+        .unwrap()
+        .file_name("travel/images/2024/image.jpeg");
+    let form = Form::new().part("file", content).part("file", image);
+
+    let response = app
+        .client
+        .post(&api_addr)
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 201);
+    let body: HashMap<String, String> = response.json().await.unwrap();
+    let slug = body.get("slug").unwrap();
+
+    let response = app
+        .client
+        .get(format!("{api_addr}/slug/{slug}/images/2024/image.jpeg"))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 200);
+    let served = response.bytes().await.unwrap();
+    assert_eq!(image::load_from_memory(&served).unwrap().width(), 1024);
+}
+
+#[tokio::test]
+async fn upload_post_with_escaping_attachment_path_returns_400() {
+    let app = TestApp::spawn_server().await;
+    let api_addr = format!("{}/posts", app.address);
+    let content = Part::file("tests/data/travel/journal.md").await.unwrap();
+    let image = Part::file("tests/data/travel/image.jpeg")
+        .await
+        .unwrap()
+        .file_name("../../image.jpeg");
+    let form = Form::new().part("file", content).part("file", image);
+
+    let response = app
+        .client
+        .post(&api_addr)
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 400);
+
+    let count = sqlx::query!("SELECT COUNT(*) as count FROM posts")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(count.count, Some(0));
+}
+
+#[tokio::test]
+async fn upload_post_with_disallowed_attachment_type_returns_415() {
+    let app = TestApp::spawn_server().await;
+    let api_addr = format!("{}/posts", app.address);
+    let content = Part::file("tests/data/travel/journal.md").await.unwrap();
+    // A windows executable disguised behind an image extension
+    let disguised = Part::bytes(b"MZ\x90\x00\x03\x00\x00\x00\x04\x00\x00\x00\xff\xff".to_vec())
+        .file_name("image.jpeg");
+    let form = Form::new().part("file", content).part("file", disguised);
+
+    let response = app
+        .client
+        .post(&api_addr)
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 415);
+
+    let count = sqlx::query!("SELECT COUNT(*) as count FROM posts")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(count.count, Some(0));
+}
+
+#[tokio::test]
+async fn attachments_named_after_another_type_are_rejected() {
+    let app = TestApp::spawn_server().await;
+    let api_addr = format!("{}/posts", app.address);
+
+    // INFO: markup passes the sniffing as plain text, but would be rendered after its extension
+    for (name, bytes) in [
+        (
+            "page.html",
+            b"packing list <script>alert(document.cookie)</script>".as_slice(),
+        ),
+        (
+            "logo.svg",
+            b"<svg onload=\"alert(document.cookie)\"/>".as_slice(),
+        ),
+        (
+            "photo.png",
+            b"packing list <script>alert(document.cookie)</script>".as_slice(),
+        ),
+    ] {
+        let content = Part::file("tests/data/travel/journal.md").await.unwrap();
+        let disguised = Part::bytes(bytes.to_vec()).file_name(name);
+        let form = Form::new().part("file", content).part("file", disguised);
+
+        let response = app
+            .client
+            .post(&api_addr)
+            .multipart(form)
+            .send()
+            .await
+            .expect("Failed to send request");
+
+        assert_eq!(response.status().as_u16(), 415, "{name}");
+    }
+}
+
+#[tokio::test]
+async fn attachments_are_served_as_their_sniffed_type_and_only_media_inline() {
+    let app = TestApp::spawn_server().await;
+    let api_addr = format!("{}/posts", app.address);
+    let content = Part::file("tests/data/travel/journal.md").await.unwrap();
+    let notes = Part::bytes(b"packing list <script>alert(document.cookie)</script>".to_vec())
+        .file_name("notes.txt");
+    let image = Part::file("tests/data/travel/image.jpeg").await.unwrap();
+    let form = Form::new()
+        .part("file", content)
+        .part("file", notes)
+        .part("file", image);
+
+    let response = app
+        .client
+        .post(&api_addr)
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 201);
+    let body: HashMap<String, String> = response.json().await.unwrap();
+    let slug = body.get("slug").unwrap();
+
+    for (name, mime, disposition) in [
+        ("notes.txt", "text/plain", "attachment"),
+        ("image.jpeg", "image/jpeg", "inline"),
+    ] {
+        let response = app
+            .client
+            .get(format!("{api_addr}/slug/{slug}/{name}"))
+            .send()
+            .await
+            .expect("Failed to send request");
+
+        assert_eq!(response.status().as_u16(), 200);
+        let headers = response.headers();
+        assert_eq!(headers["content-type"], mime);
+        assert!(headers["content-disposition"]
+            .to_str()
+            .unwrap()
+            .starts_with(disposition));
+        assert_eq!(headers["x-content-type-options"], "nosniff");
+    }
+}
+
+#[tokio::test]
+async fn uploaded_images_are_served_in_responsive_variants() {
+    let app = TestApp::spawn_server().await;
+    let api_addr = format!("{}/posts", app.address);
+    let image_path = std::path::Path::new("tests/data/travel/image.jpeg");
+    let content = Part::file("tests/data/travel/journal.md").await.unwrap();
+    let image = Part::file(image_path).await.unwrap();
+    let form = Form::new().part("file", content).part("file", image);
+
+    let response = app
+        .client
+        .post(&api_addr)
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 201);
+    let body: HashMap<String, String> = response.json().await.unwrap();
+    let slug = body.get("slug").unwrap();
+
+    let post: serde_json::Value = app
+        .client
+        .get(format!("{api_addr}/slug/{slug}"))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(post["attachments"][0]["path"], "image.jpeg");
+    assert_eq!(
+        post["attachments"][0]["widths"],
+        serde_json::json!([480, 960, 1024])
+    );
+
+    let original = std::fs::read(image_path).unwrap();
+    let response = app
+        .client
+        .get(format!("{api_addr}/slug/{slug}/image.jpeg"))
+        .query(&[("w", "400")])
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 200);
+    let variant = response.bytes().await.unwrap();
+    assert!(variant.len() < original.len());
+    assert_eq!(image::load_from_memory(&variant).unwrap().width(), 480);
+    assert_eq!(
+        image::guess_format(&variant).unwrap(),
+        image::ImageFormat::Jpeg
+    );
+
+    // INFO: clients accepting WebP get the WebP copy of the same width
+    let response = app
+        .client
+        .get(format!("{api_addr}/slug/{slug}/image.jpeg"))
+        .query(&[("w", "400")])
+        .header("Accept", "image/avif,image/webp,*/*;q=0.8")
+        .send()
+        .await
+        .expect("Failed to send request");
+    let vary = response.headers()["vary"].to_str().unwrap();
+    assert!(vary.split(", ").any(|header| header == "Accept"));
+    let variant = response.bytes().await.unwrap();
+    assert_eq!(
+        image::guess_format(&variant).unwrap(),
+        image::ImageFormat::WebP
+    );
+    assert_eq!(image::load_from_memory(&variant).unwrap().width(), 480);
+
+    let response = app
+        .client
+        .get(format!("{api_addr}/slug/{slug}/image.jpeg"))
+        .query(&[("w", "4000")])
+        .send()
+        .await
+        .expect("Failed to send request");
+    let served = response.bytes().await.unwrap();
+    assert_eq!(image::load_from_memory(&served).unwrap().width(), 1024);
+}
+
+#[tokio::test]
+async fn uploaded_images_carry_dimensions_and_placeholders() {
+    let app = TestApp::spawn_server().await;
+    let api_addr = format!("{}/posts", app.address);
+    let content = Part::file("tests/data/travel/journal.md").await.unwrap();
+    let image = Part::file("tests/data/travel/image.jpeg").await.unwrap();
+    let form = Form::new().part("file", content).part("file", image);
+
+    let response = app
+        .client
+        .post(&api_addr)
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 201);
+    let body: HashMap<String, String> = response.json().await.unwrap();
+    let slug = body.get("slug").unwrap();
+
+    let post: serde_json::Value = app
+        .client
+        .get(format!("{api_addr}/slug/{slug}"))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .unwrap();
+
+    let attachment = &post["attachments"][0];
+    assert_eq!(attachment["width"], 1024);
+    assert_eq!(attachment["height"], 768);
+    let color = attachment["dominant_color"].as_str().unwrap();
+    assert!(color.len() == 7 && color.starts_with('#'));
+    let blurhash = attachment["blurhash"].as_str().unwrap();
+    assert!(!blurhash.is_empty());
+
+    let content = post["content"].as_str().unwrap();
+    assert!(!content.contains("![img](./image.jpeg)"));
+    assert!(content.contains(&format!(
+        r#"<img src="./image.jpeg" alt="img" width="1024" height="768" data-blurhash="{blurhash}" data-dominant-color="{color}""#
+    )));
+}
+
+#[tokio::test]
+async fn identical_attachments_are_stored_once_and_outlive_a_deleted_post() {
+    let app = TestApp::spawn_server().await;
+    let api_addr = format!("{}/posts", app.address);
+
+    let mut uploaded = Vec::new();
+    for _ in 0..2 {
+        let content = Part::file("tests/data/travel/journal.md").await.unwrap();
+        let image = Part::file("tests/data/travel/image.jpeg").await.unwrap();
+        let form = Form::new().part("file", content).part("file", image);
+        let response = app
+            .client
+            .post(&api_addr)
+            .multipart(form)
+            .send()
+            .await
+            .expect("Failed to send request");
+        assert_eq!(response.status().as_u16(), 201);
+        let body: HashMap<String, String> = response.json().await.unwrap();
+        uploaded.push((body["id"].clone(), body["slug"].clone()));
+    }
+
+    let mut objects = Vec::new();
+    for (id, _) in &uploaded {
+        let manifest = app.blob_storage.post_manifest(id).await.unwrap();
+        objects.push(manifest.files[std::path::Path::new("image.jpeg")].clone());
+    }
+    assert_eq!(objects[0].object, objects[1].object);
+
+    let response = app
+        .client
+        .delete(format!("{api_addr}/{}", uploaded[0].0))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app
+        .client
+        .get(format!("{api_addr}/slug/{}/image.jpeg", uploaded[1].1))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["content-type"].to_str().unwrap(),
+        "image/jpeg"
+    );
+    let served = response.bytes().await.unwrap();
+    assert_eq!(served.len() as u64, objects[1].object.size);
+}
+
+async fn upload_photo_post(app: &TestApp, front_matter: &str) -> Vec<u8> {
+    let api_addr = format!("{}/posts", app.address);
+    let content = Part::bytes(format!("---\n{front_matter}---\n![img](image.jpeg)\n").into_bytes())
+        .file_name("photo.md");
+    let image = Part::file("tests/data/travel/image.jpeg").await.unwrap();
+    let form = Form::new().part("file", content).part("file", image);
+
+    let response = app
+        .client
+        .post(&api_addr)
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 201);
+    let body: HashMap<String, String> = response.json().await.unwrap();
+    let slug = body.get("slug").unwrap();
+
+    app.client
+        .get(format!("{api_addr}/slug/{slug}/image.jpeg"))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .bytes()
+        .await
+        .unwrap()
+        .to_vec()
+}
+
+#[tokio::test]
+async fn uploaded_photos_are_stripped_of_exif_by_default() {
+    let app = TestApp::spawn_server().await;
+    let original = std::fs::read("tests/data/travel/image.jpeg").unwrap();
+    assert!(exif::Reader::new()
+        .read_from_container(&mut std::io::Cursor::new(&original))
+        .is_ok());
+
+    let served = upload_photo_post(&app, "title: Stripped\n").await;
+
+    assert!(exif::Reader::new()
+        .read_from_container(&mut std::io::Cursor::new(&served))
+        .is_err());
+    assert!(image::load_from_memory(&served).is_ok());
+}
+
+#[tokio::test]
+async fn posts_can_opt_out_of_exif_stripping() {
+    let app = TestApp::spawn_server().await;
+    let original = std::fs::read("tests/data/travel/image.jpeg").unwrap();
+
+    let served = upload_photo_post(&app, "title: Untouched\nstrip_metadata: false\n").await;
+
+    assert_eq!(served, original);
+}
+
+#[tokio::test]
+async fn photos_whose_metadata_cannot_be_stripped_are_rejected() {
+    let app = TestApp::spawn_server().await;
+    let content = Part::bytes(b"---\ntitle: Broken\n---\n![img](broken.jpg)\n".to_vec())
+        .file_name("broken.md");
+    let broken =
+        Part::bytes(b"\xFF\xD8\xFF\xE0 not really a photo".to_vec()).file_name("broken.jpg");
+    let form = Form::new().part("file", content).part("file", broken);
+
+    let response = app
+        .client
+        .post(format!("{}/posts", app.address))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 422);
+    let count = sqlx::query!("SELECT COUNT(*) as count FROM posts")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(count.count, Some(0));
+}
+
+#[tokio::test]
+async fn posts_can_be_stored_in_an_s3_compatible_backend() {
+    let (app, s3) = TestApp::spawn_server_with_s3().await;
+    let api_addr = format!("{}/posts", app.address);
+    let content = Part::file("tests/data/travel/journal.md").await.unwrap();
+    let image = Part::file("tests/data/travel/image.jpeg").await.unwrap();
+    let notes = Part::bytes(b"packing list".to_vec()).file_name("notes.txt");
+    let form = Form::new()
+        .part("file", content)
+        .part("file", image)
+        .part("file", notes);
+
+    let response = app
+        .client
+        .post(&api_addr)
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 201);
+    let body: HashMap<String, String> = response.json().await.unwrap();
+    let (id, slug) = (&body["id"], &body["slug"]);
+
+    let keys = s3.keys();
+    assert!(keys.contains(&format!("posts/{id}/manifest.json")));
+    assert!(keys.iter().any(|key| key.starts_with("objects/")));
+    assert!(!keys.iter().any(|key| key.starts_with("incoming/")));
+
+    let notes = app
+        .client
+        .get(format!("{api_addr}/slug/{slug}/notes.txt"))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .bytes()
+        .await
+        .unwrap();
+    assert_eq!(notes.as_ref(), b"packing list");
+
+    let post: serde_json::Value = app
+        .client
+        .get(format!("{api_addr}/slug/{slug}"))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(post["title"], "A Wonderful Journey");
+
+    let response = app
+        .client
+        .get(format!("{api_addr}/slug/{slug}/image.jpeg"))
+        .query(&[("w", "400")])
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 200);
+    let variant = response.bytes().await.unwrap();
+    assert_eq!(image::load_from_memory(&variant).unwrap().width(), 480);
+
+    let response = app
+        .client
+        .delete(format!("{api_addr}/{id}"))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(s3.keys().is_empty());
+}
+
+#[tokio::test]
+async fn attachments_are_served_from_a_local_directory_when_not_ephemeral() {
+    let dir = tempfile::tempdir().unwrap();
+    let base_dir = dir.path().to_path_buf();
+    let app = TestApp::spawn_server_with(move |config| {
+        config.blob_storage.ephemeral = false;
+        config.blob_storage.base_dir = base_dir;
+    })
+    .await;
+    let api_addr = format!("{}/posts", app.address);
+    let image_path = std::path::Path::new("tests/data/travel/image.jpeg");
+    let content = Part::file("tests/data/travel/journal.md").await.unwrap();
+    let image = Part::file(image_path).await.unwrap();
+    let form = Form::new().part("file", content).part("file", image);
+
+    let response = app
+        .client
+        .post(&api_addr)
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 201);
+    let body: HashMap<String, String> = response.json().await.unwrap();
+    let (id, slug) = (&body["id"], &body["slug"]);
+    assert!(dir
+        .path()
+        .join(format!("posts/{id}/manifest.json"))
+        .is_file());
+
+    let response = app
+        .client
+        .get(format!("{api_addr}/slug/{slug}/image.jpeg"))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["content-type"], "image/jpeg");
+}
+
+/// A PDF of `size` bytes, its body made of bytes that do not compress nor repeat.
+fn pdf_of_size(size: usize) -> Vec<u8> {
+    let mut pdf = b"%PDF-1.7\n".to_vec();
+    let mut state = 0x2545_f491_u32;
+    while pdf.len() < size {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        pdf.push(state as u8);
+    }
+    pdf
+}
+
+#[tokio::test]
+async fn large_attachments_are_streamed_into_storage_unchanged() {
+    let dir = tempfile::tempdir().unwrap();
+    let base_dir = dir.path().to_path_buf();
+    let app = TestApp::spawn_server_with(move |config| {
+        config.blob_storage.ephemeral = false;
+        config.blob_storage.base_dir = base_dir;
+    })
+    .await;
+    let api_addr = format!("{}/posts", app.address);
+    let pdf = pdf_of_size(3 * 1024 * 1024);
+    let content = Part::file("tests/data/travel/journal.md").await.unwrap();
+    let attachment = Part::bytes(pdf.clone()).file_name("guide.pdf");
+    let form = Form::new().part("file", content).part("file", attachment);
+
+    let response = app
+        .client
+        .post(&api_addr)
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 201);
+    let body: HashMap<String, String> = response.json().await.unwrap();
+    let slug = body.get("slug").unwrap();
+
+    let served = app
+        .client
+        .get(format!("{api_addr}/slug/{slug}/guide.pdf"))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .bytes()
+        .await
+        .unwrap();
+    assert!(served.as_ref() == pdf.as_slice());
+    assert!(!dir.path().join("incoming").exists());
+    assert_eq!(
+        std::fs::read_dir(dir.path().join(".staging"))
+            .unwrap()
+            .count(),
+        0
+    );
+}
+
+fn files_under(dir: &std::path::Path) -> Vec<std::path::PathBuf> {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .flat_map(|entry| {
+            let path = entry.unwrap().path();
+            match path.is_dir() {
+                true => files_under(&path),
+                false => vec![path],
+            }
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn encrypted_attachments_are_unreadable_on_disk_but_served_in_clear() {
+    let dir = tempfile::tempdir().unwrap();
+    let base_dir = dir.path().to_path_buf();
+    let app = TestApp::spawn_server_with(move |config| {
+        config.blob_storage.ephemeral = false;
+        config.blob_storage.base_dir = base_dir;
+        config.blob_storage.encryption = Some(EncryptionSettings {
+            key: SecretBox::new(Box::new(
+                "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=".to_string(),
+            )),
+            previous_keys: Vec::new(),
+        });
+    })
+    .await;
+    let api_addr = format!("{}/posts", app.address);
+    let secret = "the spare key is under the third pine tree";
+    let pdf = pdf_of_size(300 * 1024);
+    let form = Form::new()
+        .part(
+            "file",
+            Part::file("tests/data/travel/journal.md").await.unwrap(),
+        )
+        .part(
+            "file",
+            Part::bytes(secret.as_bytes().to_vec()).file_name("private.txt"),
+        )
+        .part("file", Part::bytes(pdf.clone()).file_name("guide.pdf"));
+
+    let response = app
+        .client
+        .post(&api_addr)
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 201);
+    let body: HashMap<String, String> = response.json().await.unwrap();
+    let slug = body.get("slug").unwrap();
+
+    for path in files_under(dir.path()) {
+        let stored = std::fs::read(&path).unwrap();
+        assert!(
+            !stored
+                .windows(secret.len())
+                .any(|window| window == secret.as_bytes()),
+            "{path:?} holds the attachment in clear"
+        );
+        assert!(!stored.windows(64).any(|window| window == &pdf[1024..1088]));
+    }
+
+    for (name, expected) in [("private.txt", secret.as_bytes()), ("guide.pdf", &pdf)] {
+        let served = app
+            .client
+            .get(format!("{api_addr}/slug/{slug}/{name}"))
+            .send()
+            .await
+            .expect("Failed to send request")
+            .bytes()
+            .await
+            .unwrap();
+        assert!(served.as_ref() == expected);
+    }
+
+    let response = app
+        .client
+        .get(format!("{api_addr}/slug/{slug}"))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert!(response.status().is_success());
+}
+
+#[tokio::test]
+async fn attachment_over_its_type_limit_is_rejected_while_streaming() {
+    let app = TestApp::spawn_server_with(|config| {
+        for rule in &mut config.blob_storage.attachments.allowed {
+            if rule.mime == "application/pdf" {
+                rule.max_size = 1024 * 1024;
+            }
+        }
+    })
+    .await;
+    let api_addr = format!("{}/posts", app.address);
+    let content = Part::file("tests/data/travel/journal.md").await.unwrap();
+    let attachment = Part::bytes(pdf_of_size(3 * 1024 * 1024)).file_name("guide.pdf");
+    let form = Form::new().part("file", content).part("file", attachment);
+
+    let response = app
+        .client
+        .post(&api_addr)
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 413);
+
+    let options = pine_tails::components::blob_storage::GcOptions {
+        dry_run: true,
+        grace_period_secs: 0,
+        ..Default::default()
+    };
+    let report = app
+        .blob_storage
+        .collect_garbage(&Default::default(), &options)
+        .await
+        .unwrap();
+    assert!(report.abandoned_writes.is_empty());
+    assert!(report.dangling_refs.is_empty());
+    assert!(report.unreferenced_objects.is_empty());
+}
+
+#[tokio::test]
+async fn attachments_sent_before_the_content_keep_their_nested_paths() {
+    let app = TestApp::spawn_server().await;
+    let api_addr = format!("{}/posts", app.address);
+    let image = Part::file("tests/data/travel/image.jpeg")
+        .await
+        .unwrap()
+        .file_name("travel/images/image.jpeg");
+    let content = Part::file("tests/data/travel/journal.md")
+        .await
+        .unwrap()
+        .file_name("travel/journal.md");
+    let form = Form::new().part("file", image).part("file", content);
+
+    let response = app
+        .client
+        .post(&api_addr)
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 201);
+    let body: HashMap<String, String> = response.json().await.unwrap();
+    let slug = body.get("slug").unwrap();
+
+    let served = app
+        .client
+        .get(format!("{api_addr}/slug/{slug}/images/image.jpeg"))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .bytes()
+        .await
+        .unwrap();
+    assert_eq!(image::load_from_memory(&served).unwrap().width(), 1024);
+    assert!(exif::Reader::new()
+        .read_from_container(&mut std::io::Cursor::new(&served))
+        .is_err());
+}
@@ -0,0 +1,353 @@
+use base64::prelude::*;
+use mail_parser::MessageParser;
+use reqwest::multipart::{Form, Part};
+use reqwest::{Method, StatusCode};
+use serde_json::Value;
+
+use crate::utils::TestApp;
+
+fn post_form(body: &str) -> Form {
+    let content = format!("---\ntitle: Draft\ndate: 2024-10-26T00:00:00Z\n---\n\n{body}\n");
+    Form::new().part(
+        "file",
+        Part::bytes(content.into_bytes()).file_name("draft.md"),
+    )
+}
+
+async fn send_as(
+    app: &TestApp,
+    token: &str,
+    method: Method,
+    path: &str,
+    form: &[(&str, &str)],
+) -> reqwest::Response {
+    reqwest::Client::new()
+        .request(method, format!("{}{path}", app.address))
+        .bearer_auth(token)
+        .form(form)
+        .send()
+        .await
+        .expect("Failed to send request")
+}
+
+async fn send_post_as(app: &TestApp, token: &str, method: Method, path: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .request(method, format!("{}{path}", app.address))
+        .bearer_auth(token)
+        .multipart(post_form("# Draft\n\nA first try."))
+        .send()
+        .await
+        .expect("Failed to send request")
+}
+
+/// The recipient and subject of the `index`th email sent.
+async fn email(app: &TestApp, index: usize) -> (String, String) {
+    let emails = app.wait_for_emails(index + 1).await;
+    let body: Value = serde_json::from_slice(&emails[index].body).unwrap();
+    let raw_mail = BASE64_STANDARD
+        .decode(body["raw"].as_str().unwrap())
+        .unwrap();
+    let message = MessageParser::default().parse(&raw_mail).unwrap();
+    (
+        message
+            .to()
+            .unwrap()
+            .first()
+            .unwrap()
+            .address()
+            .unwrap()
+            .to_string(),
+        message.subject().unwrap().to_string(),
+    )
+}
+
+#[tokio::test]
+async fn author_posts_are_published_once_a_reviewer_approves_them() {
+    let app = TestApp::spawn_server().await;
+    app.mocking_refresh_ok(1).await;
+    app.mocking_send_mail_ok(6).await;
+    let author = app.user_with_role("author", "author").await;
+    let editor = app.user_with_role("editor", "editor").await;
+    let reader = app.user_with_role("reader", "viewer").await;
+
+    let response = send_post_as(&app, &author, Method::POST, "/posts").await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let uploaded: Value = response.json().await.unwrap();
+    assert_eq!(uploaded["status"], "in_review");
+    let id = uploaded["id"].as_str().unwrap();
+    let slug = uploaded["slug"].as_str().unwrap();
+    let review = format!("/posts/{id}/review");
+    let approve = format!("{review}/approve");
+
+    // INFO: readers do not see the post yet, while every editor and admin is asked to review it
+    let response = reqwest::get(format!("{}/posts/slug/{slug}", app.address))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = send_as(&app, &reader, Method::GET, &review, &[]).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let mut asked = vec![email(&app, 0).await, email(&app, 1).await];
+    asked.sort();
+    assert_eq!(
+        asked,
+        [
+            (
+                "admin@pine-tails.test".to_string(),
+                "Draft waits for review".to_string()
+            ),
+            (
+                "editor@pine-tails.test".to_string(),
+                "Draft waits for review".to_string()
+            ),
+        ]
+    );
+
+    let path = format!("{review}/reviewer");
+    let response = send_as(&app, &author, Method::PUT, &path, &[("username", "editor")]).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = send_as(&app, &editor, Method::PUT, &path, &[("username", "author")]).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = send_as(&app, &editor, Method::PUT, &path, &[("username", "editor")]).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(
+        email(&app, 2).await,
+        (
+            "editor@pine-tails.test".to_string(),
+            "You were asked to review Draft".to_string()
+        )
+    );
+
+    let comments = format!("{review}/comments");
+    let comment = [("body", "A bland title"), ("line", "1")];
+    let response = send_as(&app, &editor, Method::POST, &comments, &comment).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let off_the_post = [("body", "Nothing here"), ("line", "99")];
+    let response = send_as(&app, &editor, Method::POST, &comments, &off_the_post).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let reply = [("body", "Fixing it")];
+    let response = send_as(&app, &author, Method::POST, &comments, &reply).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let path = format!("{review}/request_changes");
+    let first = [("revision", "1")];
+    let response = send_as(&app, &author, Method::POST, &path, &first).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = send_as(&app, &editor, Method::POST, &path, &first).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(
+        email(&app, 3).await,
+        (
+            "author@pine-tails.test".to_string(),
+            "Changes were requested on Draft".to_string()
+        )
+    );
+    let response = send_as(&app, &editor, Method::POST, &approve, &first).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    // INFO: a new revision goes back to the assigned reviewer only
+    let response = send_post_as(&app, &author, Method::PUT, &format!("/posts/{id}")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        email(&app, 4).await,
+        (
+            "editor@pine-tails.test".to_string(),
+            "Draft waits for review".to_string()
+        )
+    );
+
+    let response = send_as(&app, &author, Method::GET, &review, &[]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let state: Value = response.json().await.unwrap();
+    assert_eq!(state["status"], "in_review");
+    assert_eq!(state["revision"], 2);
+    assert_eq!(state["owner"], "author");
+    assert_eq!(state["reviewer"], "editor");
+    assert!(state["content"].as_str().unwrap().contains("A first try."));
+    let written = state["comments"].as_array().unwrap();
+    assert_eq!(written.len(), 2);
+    assert_eq!(written[0]["revision"], 1);
+    assert_eq!(written[0]["outdated"], true);
+    assert_eq!(written[0]["line"], 1);
+    assert_eq!(written[0]["author"], "editor");
+    assert_eq!(written[1]["line"], Value::Null);
+
+    // INFO: a decision on a revision the reviewer did not read is refused
+    let response = send_as(&app, &editor, Method::POST, &approve, &first).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = send_as(&app, &editor, Method::POST, &approve, &[("revision", "2")]).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(
+        email(&app, 5).await,
+        (
+            "author@pine-tails.test".to_string(),
+            "Draft was published".to_string()
+        )
+    );
+    let response = reqwest::get(format!("{}/posts/slug/{slug}", app.address))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = send_as(&app, &author, Method::POST, &comments, &reply).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn editors_publish_without_a_review() {
+    let app = TestApp::spawn_server().await;
+    let editor = app.user_with_role("editor", "editor").await;
+
+    let response = send_post_as(&app, &editor, Method::POST, "/posts").await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let uploaded: Value = response.json().await.unwrap();
+    assert_eq!(uploaded["status"], "published");
+
+    let response = reqwest::get(format!("{}/posts/count", app.address))
+        .await
+        .unwrap();
+    let count: Value = response.json().await.unwrap();
+    assert_eq!(count["count"], 1);
+
+    let id = uploaded["id"].as_str().unwrap();
+    let path = format!("/posts/{id}/review/approve");
+    let response = send_as(&app, &editor, Method::POST, &path, &[("revision", "1")]).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn published_posts_stay_served_while_their_next_revision_is_reviewed() {
+    let app = TestApp::spawn_server().await;
+    app.mocking_refresh_ok(1).await;
+    app.mocking_send_mail_ok(5).await;
+    let author = app.user_with_role("author", "author").await;
+    let editor = app.user_with_role("editor", "editor").await;
+
+    let response = send_post_as(&app, &author, Method::POST, "/posts").await;
+    let uploaded: Value = response.json().await.unwrap();
+    let id = uploaded["id"].as_str().unwrap();
+    let slug = uploaded["slug"].as_str().unwrap();
+    let review = format!("/posts/{id}/review");
+    let approve = format!("{review}/approve");
+    let response = send_as(&app, &editor, Method::POST, &approve, &[("revision", "1")]).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let stored_bytes = || async {
+        let response = app
+            .client
+            .get(format!("{}/admin/storage", app.address))
+            .send()
+            .await
+            .unwrap();
+        let report: Value = response.json().await.unwrap();
+        report["total_bytes"].as_u64().unwrap()
+    };
+    let published_bytes = stored_bytes().await;
+
+    let response = reqwest::Client::new()
+        .put(format!("{}/posts/{id}", app.address))
+        .bearer_auth(&author)
+        .multipart(post_form("# Draft\n\nA second try."))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let published_content = || async {
+        let response = reqwest::get(format!("{}/posts/slug/{slug}", app.address))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let post: Value = response.json().await.unwrap();
+        post["content"].as_str().unwrap().to_string()
+    };
+    assert!(published_content().await.contains("A first try."));
+    // INFO: both revisions are stored until the review is over
+    let both_bytes = stored_bytes().await;
+    assert_eq!(both_bytes, 2 * published_bytes + 1);
+    let response = reqwest::get(format!("{}/posts/count", app.address))
+        .await
+        .unwrap();
+    let count: Value = response.json().await.unwrap();
+    assert_eq!(count["count"], 1);
+
+    let response = send_as(&app, &author, Method::GET, &review, &[]).await;
+    let state: Value = response.json().await.unwrap();
+    assert_eq!(state["status"], "in_review");
+    assert_eq!(state["revision"], 2);
+    assert!(state["content"].as_str().unwrap().contains("A second try."));
+
+    let response = send_as(&app, &editor, Method::POST, &approve, &[("revision", "2")]).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(published_content().await.contains("A second try."));
+    assert_eq!(stored_bytes().await, published_bytes + 1);
+}
+
+#[tokio::test]
+async fn attachments_of_posts_under_review_are_shown_to_their_review_only() {
+    let app = TestApp::spawn_server().await;
+    let author = app.user_with_role("author", "author").await;
+    let editor = app.user_with_role("editor", "editor").await;
+    let reader = app.user_with_role("reader", "viewer").await;
+
+    let content = Part::file("tests/data/travel/journal.md").await.unwrap();
+    let image = Part::file("tests/data/travel/image.jpeg").await.unwrap();
+    let response = reqwest::Client::new()
+        .post(format!("{}/posts", app.address))
+        .bearer_auth(&author)
+        .multipart(Form::new().part("file", content).part("file", image))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let uploaded: Value = response.json().await.unwrap();
+    let id = uploaded["id"].as_str().unwrap();
+    let slug = uploaded["slug"].as_str().unwrap();
+
+    let response = reqwest::get(format!("{}/posts/slug/{slug}/image.jpeg", app.address))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let path = format!("/posts/{id}/review/attachments/image.jpeg");
+    let response = send_as(&app, &reader, Method::GET, &path, &[]).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    for token in [&author, &editor] {
+        let response = send_as(&app, token, Method::GET, &path, &[]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "image/jpeg");
+    }
+    let missing = format!("/posts/{id}/review/attachments/missing.jpeg");
+    let response = send_as(&app, &author, Method::GET, &missing, &[]).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn titles_are_escaped_in_the_html_of_review_emails() {
+    let app = TestApp::spawn_server().await;
+    app.mocking_refresh_ok(1).await;
+    app.mocking_send_mail_ok(1).await;
+    let author = app.user_with_role("author", "author").await;
+
+    let content = "---\ntitle: \"Tom & <b>Jerry</b>\"\ndate: 2024-10-26T00:00:00Z\n---\n\nHi\n";
+    let response = reqwest::Client::new()
+        .post(format!("{}/posts", app.address))
+        .bearer_auth(&author)
+        .multipart(Form::new().part(
+            "file",
+            Part::bytes(content.as_bytes().to_vec()).file_name("draft.md"),
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let emails = app.wait_for_emails(1).await;
+    let body: Value = serde_json::from_slice(&emails[0].body).unwrap();
+    let raw_mail = BASE64_STANDARD
+        .decode(body["raw"].as_str().unwrap())
+        .unwrap();
+    let message = MessageParser::default().parse(&raw_mail).unwrap();
+    let html = message.body_html(0).unwrap();
+    assert!(html.contains("Tom &amp; &lt;b&gt;Jerry&lt;/b&gt;"));
+    assert!(!html.contains("<b>"));
+    assert!(message.body_text(0).unwrap().contains("Tom & <b>Jerry</b>"));
+}
@@ -0,0 +1,196 @@
+use reqwest::multipart::{Form, Part};
+use reqwest::{Method, StatusCode};
+use serde_json::json;
+
+use crate::utils::TestApp;
+
+fn post_form(title: &str) -> Form {
+    let content = format!("---\ntitle: {title}\ndate: 2024-10-26T00:00:00Z\n---\n\n# {title}\n");
+    Form::new().part(
+        "file",
+        Part::bytes(content.into_bytes()).file_name(format!("{title}.md")),
+    )
+}
+
+/// Sends a request as the holder of `token`, or anonymously without one.
+async fn send_as(
+    app: &TestApp,
+    token: Option<&str>,
+    method: Method,
+    path: &str,
+    form: Option<Form>,
+) -> reqwest::Response {
+    let mut request = reqwest::Client::new().request(method, format!("{}{path}", app.address));
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    if let Some(form) = form {
+        request = request.multipart(form);
+    }
+    request.send().await.expect("Failed to send request")
+}
+
+async fn upload_as(app: &TestApp, token: Option<&str>, title: &str) -> reqwest::Response {
+    send_as(app, token, Method::POST, "/posts", Some(post_form(title))).await
+}
+
+async fn uploaded_id(response: reqwest::Response) -> String {
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["id"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn writing_posts_needs_a_role_allowing_it() {
+    let app = TestApp::spawn_server().await;
+    let viewer = app.user_with_role("viewer", "viewer").await;
+    let author = app.user_with_role("author", "author").await;
+
+    let response = upload_as(&app, None, "Anonymous").await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = upload_as(&app, Some("not a token"), "Forged").await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = upload_as(&app, Some(&viewer), "Viewed").await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    uploaded_id(upload_as(&app, Some(&author), "Authored").await).await;
+
+    // INFO: reading stays open to everyone
+    let response = send_as(&app, None, Method::GET, "/posts/count", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+async fn update(app: &TestApp, token: &str, id: &str, title: &str) -> reqwest::Response {
+    let path = format!("/posts/{id}");
+    send_as(app, Some(token), Method::PUT, &path, Some(post_form(title))).await
+}
+
+async fn delete(app: &TestApp, token: &str, id: &str) -> reqwest::Response {
+    send_as(
+        app,
+        Some(token),
+        Method::DELETE,
+        &format!("/posts/{id}"),
+        None,
+    )
+    .await
+}
+
+#[tokio::test]
+async fn authors_only_edit_their_own_posts_and_editors_delete_any() {
+    let app = TestApp::spawn_server().await;
+    let author = app.user_with_role("author", "author").await;
+    let other = app.user_with_role("other", "author").await;
+    let editor = app.user_with_role("editor", "editor").await;
+
+    let own = uploaded_id(upload_as(&app, Some(&author), "Own post").await).await;
+    let foreign = uploaded_id(upload_as(&app, Some(&other), "Foreign post").await).await;
+
+    assert_eq!(
+        update(&app, &author, &own, "Own post, revised")
+            .await
+            .status(),
+        StatusCode::OK
+    );
+    assert_eq!(
+        update(&app, &author, &foreign, "Hijacked").await.status(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        update(&app, &editor, &foreign, "Copy edited")
+            .await
+            .status(),
+        StatusCode::OK
+    );
+
+    assert_eq!(
+        delete(&app, &author, &own).await.status(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(delete(&app, &editor, &own).await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn admin_and_subscriber_endpoints_are_limited_by_role() {
+    let app = TestApp::spawn_server().await;
+    let editor = app.user_with_role("editor", "editor").await;
+    let author = app.user_with_role("author", "author").await;
+
+    for path in ["/admin/storage", "/admin/scrub"] {
+        let response = send_as(&app, Some(&editor), Method::GET, path, None).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+    let response = send_as(
+        &app,
+        Some(&app.admin_token),
+        Method::GET,
+        "/admin/storage",
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // INFO: subscribing stays open to everyone, listing the subscribers does not
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .form(&[("email", "not-an-email"), ("name", "Reader")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = send_as(&app, Some(&author), Method::GET, "/subscriptions", None).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = send_as(&app, Some(&editor), Method::GET, "/subscriptions", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn only_admins_manage_roles_and_the_last_admin_stays() {
+    let app = TestApp::spawn_server().await;
+    let editor = app.user_with_role("editor", "editor").await;
+    let users: Vec<serde_json::Value> = app
+        .client
+        .get(format!("{}/users", app.address))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let id_of = |username: &str| {
+        users
+            .iter()
+            .find(|user| user["username"] == username)
+            .unwrap()["id"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    };
+
+    let set_role = |token: &str, id: String, role: &str| {
+        reqwest::Client::new()
+            .put(format!("{}/users/{id}/role", app.address))
+            .bearer_auth(token)
+            .json(&json!({ "role": role }))
+            .send()
+    };
+
+    let response = set_role(&editor, id_of("editor"), "admin").await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = set_role(&app.admin_token, id_of("editor"), "owner")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = set_role(&app.admin_token, id_of("admin"), "viewer")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let response = set_role(&app.admin_token, id_of("editor"), "admin")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = send_as(&app, Some(&editor), Method::GET, "/admin/storage", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = set_role(&editor, id_of("admin"), "viewer").await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
@@ -0,0 +1,190 @@
+use chrono::{Duration, Utc};
+use futures::future::join_all;
+use reqwest::multipart::{Form, Part};
+use reqwest::StatusCode;
+use uuid::Uuid;
+
+use pine_tails::components::staged_writes::{recover_staged_writes, StagedWrite};
+
+use crate::utils::TestApp;
+
+async fn journal_len(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM blob_journal"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn recovery_rolls_back_a_write_whose_transaction_never_committed() {
+    let app = TestApp::spawn_server().await;
+
+    // INFO: the process dies before settling
+    let _staged = StagedWrite::begin(&app.db_pool, Uuid::new_v4(), "crashed")
+        .await
+        .unwrap();
+    let mut driver = app.blob_storage.post_storage_driver("crashed");
+    driver.post_save_content("post.md", "lost").await.unwrap();
+    driver.stage_saved().await.unwrap();
+    let object = app
+        .blob_storage
+        .post_manifest("crashed")
+        .await
+        .unwrap()
+        .content_entry()
+        .unwrap()
+        .object
+        .clone();
+
+    // INFO: another instance may still be writing it
+    recover_staged_writes(&app.db_pool, &app.blob_storage, Duration::hours(1))
+        .await
+        .unwrap();
+    assert!(app.blob_storage.post_manifest("crashed").await.is_ok());
+    assert_eq!(journal_len(&app).await, 1);
+
+    recover_staged_writes(&app.db_pool, &app.blob_storage, Duration::zero())
+        .await
+        .unwrap();
+
+    assert!(app.blob_storage.post_manifest("crashed").await.is_err());
+    assert!(app.blob_storage.read_object(&object).await.is_err());
+    assert_eq!(journal_len(&app).await, 0);
+}
+
+#[tokio::test]
+async fn recovery_rolls_forward_a_committed_write_and_removes_the_replaced_blob() {
+    let app = TestApp::spawn_server().await;
+    let post_id = Uuid::new_v4();
+    let (old_blob, new_blob) = (Uuid::new_v4().to_string(), Uuid::new_v4().to_string());
+
+    sqlx::query!(
+        r#"
+        INSERT INTO posts (id, slug, title, blob, date, published_at)
+        VALUES ($1, $2, $3, $4, $5, $5)
+        "#,
+        post_id,
+        "journey",
+        "Journey",
+        old_blob,
+        Utc::now(),
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let mut driver = app.blob_storage.post_storage_driver(&old_blob);
+    driver
+        .post_save_content("journey.md", "first draft")
+        .await
+        .unwrap();
+    driver.confirm_saved().await.unwrap();
+
+    let staged = StagedWrite::begin(&app.db_pool, post_id, &new_blob)
+        .await
+        .unwrap();
+    let mut driver = app.blob_storage.post_storage_driver(&new_blob);
+    driver
+        .post_save_content("journey.md", "second draft")
+        .await
+        .unwrap();
+    driver.stage_saved().await.unwrap();
+
+    let mut transaction = app.db_pool.begin().await.unwrap();
+    sqlx::query!(
+        "UPDATE posts SET blob = $1 WHERE id = $2",
+        new_blob,
+        post_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .unwrap();
+    staged
+        .commit(&mut transaction, Some(&old_blob))
+        .await
+        .unwrap();
+    transaction.commit().await.unwrap();
+
+    // INFO: the process dies before settling, the committed post is still served
+    let post: serde_json::Value = app
+        .client
+        .get(format!("{}/posts/slug/journey", app.address))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(post["content"], "second draft");
+
+    recover_staged_writes(&app.db_pool, &app.blob_storage, Duration::zero())
+        .await
+        .unwrap();
+
+    assert!(app.blob_storage.post_manifest(&old_blob).await.is_err());
+    assert!(app.blob_storage.post_manifest(&new_blob).await.is_ok());
+    assert_eq!(journal_len(&app).await, 0);
+}
+
+fn journey_form(content: &str) -> Form {
+    let content = format!("---\ntitle: Journey\ndate: 2024-10-26T00:00:00Z\n---\n\n{content}\n");
+    Form::new().part(
+        "file",
+        Part::bytes(content.into_bytes()).file_name("journey.md"),
+    )
+}
+
+#[tokio::test]
+async fn concurrent_updates_each_replace_the_blob_the_previous_one_left() {
+    let app = TestApp::spawn_server().await;
+    let response = app
+        .client
+        .post(format!("{}/posts", app.address))
+        .multipart(journey_form("first draft"))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let post_id: Uuid = body["id"].as_str().unwrap().parse().unwrap();
+
+    let updates = (0..4).map(|draft| {
+        app.client
+            .put(format!("{}/posts/{post_id}", app.address))
+            .multipart(journey_form(&format!("draft {draft}")))
+            .send()
+    });
+    for response in join_all(updates).await {
+        assert_eq!(response.unwrap().status(), StatusCode::OK);
+    }
+
+    let post = sqlx::query!("SELECT blob, revision FROM posts WHERE id = $1", post_id)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(post.revision, 5);
+    assert_eq!(journal_len(&app).await, 0);
+
+    // INFO: every update replaced a different blob, none of them is left behind but the last one
+    let diffs = sqlx::query_scalar!(
+        "SELECT diff FROM audit_log WHERE action = 'post.update' AND target_id = $1",
+        post_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    let mut replaced: Vec<String> = diffs
+        .iter()
+        .map(|diff| diff["blob"]["from"].as_str().unwrap().to_string())
+        .collect();
+    replaced.sort();
+    replaced.dedup();
+    assert_eq!(replaced.len(), 4);
+    for blob in diffs
+        .iter()
+        .map(|diff| diff["blob"]["to"].as_str().unwrap())
+        .chain(replaced.iter().map(String::as_str))
+    {
+        let kept = app.blob_storage.post_manifest(blob).await.is_ok();
+        assert_eq!(kept, blob == post.blob, "blob {blob}");
+    }
+}
@@ -0,0 +1,188 @@
+use base64::prelude::*;
+use mail_parser::MessageParser;
+use reqwest::multipart::{Form, Part};
+use reqwest::StatusCode;
+
+use std::time::Duration;
+
+use crate::utils::TestApp;
+
+async fn subscribe(app: &TestApp, email: &str, name: &str) -> reqwest::Response {
+    app.client
+        .post(format!("{}/subscriptions", app.address))
+        .form(&[("email", email), ("name", name)])
+        .send()
+        .await
+        .expect("Failed to send request")
+}
+
+async fn confirmed_subscriber(app: &TestApp, email: &str, name: &str) {
+    let sent = app.sent_emails().await.len();
+    subscribe(app, email, name).await;
+    let emails = app.sent_emails().await;
+    let links = app.get_confirmation_links(&emails[sent]);
+    let response = app.client.get(links.html).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+async fn publish_post(app: &TestApp, title: &str) -> String {
+    let content = format!("---\ntitle: {title}\ndate: 2024-10-26T00:00:00Z\n---\n\n# {title}\n");
+    let form = Form::new().part(
+        "file",
+        Part::bytes(content.into_bytes()).file_name(format!("{title}.md")),
+    );
+    let response: serde_json::Value = app
+        .client
+        .post(format!("{}/posts", app.address))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .unwrap();
+    response["slug"].as_str().unwrap().to_string()
+}
+
+async fn status_of(app: &TestApp, email: &str) -> Option<String> {
+    sqlx::query_scalar!("SELECT status FROM subscriptions WHERE email = $1", email)
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn subscribing_needs_the_emailed_link_to_be_followed() {
+    let app = TestApp::spawn_server().await;
+    app.mocking_refresh_ok(1).await;
+    app.mocking_send_mail_ok(1).await;
+
+    let response = subscribe(&app, "reader@example.com", "Reader").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        status_of(&app, "reader@example.com").await.as_deref(),
+        Some("pending_confirmation")
+    );
+
+    // INFO: subscribing again sends nothing and gives nothing away
+    let response = subscribe(&app, "reader@example.com", "Reader").await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let emails = app.sent_emails().await;
+    let links = app.get_confirmation_links(&emails[0]);
+    assert_eq!(links.html.path(), "/api/subscriptions/confirm");
+    let response = app.client.get(links.html.clone()).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        status_of(&app, "reader@example.com").await.as_deref(),
+        Some("confirmed")
+    );
+
+    let response = app.client.get(links.html).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn subscribing_rejects_invalid_fields() {
+    let app = TestApp::spawn_server().await;
+
+    for (email, name) in [
+        ("not-an-email", "Reader"),
+        ("reader@example.com", ""),
+        ("reader@example.com", "<script>"),
+    ] {
+        let response = subscribe(&app, email, name).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
+
+#[tokio::test]
+async fn resending_the_confirmation_is_rate_limited() {
+    let app = TestApp::spawn_server().await;
+    app.mocking_refresh_ok(1).await;
+    app.mocking_send_mail_ok(2).await;
+
+    subscribe(&app, "reader@example.com", "Reader").await;
+    let response = app.request_resend_email("reader@example.com").await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    sqlx::query!("UPDATE confirmation_tokens SET created_at = now() - interval '1 hour'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let response = app.request_resend_email("reader@example.com").await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let emails = app.sent_emails().await;
+    let response = app
+        .client
+        .get(app.get_confirmation_links(&emails[1]).html)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app.request_resend_email("nobody@example.com").await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn publishing_a_post_emails_confirmed_subscribers_only() {
+    let app = TestApp::spawn_server().await;
+    app.mocking_refresh_ok(1).await;
+    app.mocking_send_mail_ok(3).await;
+
+    confirmed_subscriber(&app, "reader@example.com", "Reader").await;
+    subscribe(&app, "pending@example.com", "Pending").await;
+
+    let slug = publish_post(&app, "Fresh news").await;
+    let emails = app.wait_for_emails(3).await;
+
+    let body: serde_json::Value = serde_json::from_slice(&emails[2].body).unwrap();
+    let raw_mail = BASE64_STANDARD
+        .decode(body["raw"].as_str().unwrap())
+        .unwrap();
+    let message = MessageParser::default().parse(&raw_mail).unwrap();
+    assert_eq!(message.subject(), Some("Fresh news"));
+    assert_eq!(
+        message.to().unwrap().first().unwrap().address(),
+        Some("reader@example.com")
+    );
+    let text = message.body_text(0).unwrap();
+    assert!(text.contains(&format!("/posts/slug/{slug}")));
+
+    // INFO: one click on the link from the header of the newsletter is enough to unsubscribe
+    let unsubscribe_link = message
+        .header_raw("List-Unsubscribe")
+        .unwrap()
+        .trim()
+        .trim_start_matches('<')
+        .trim_end_matches('>')
+        .to_string();
+    assert!(text.contains(&unsubscribe_link));
+    assert_eq!(
+        message.header_raw("List-Unsubscribe-Post").unwrap().trim(),
+        "List-Unsubscribe=One-Click"
+    );
+    // INFO: following the link only asks to confirm, link scanners fetch it too
+    let response = app.client.get(&unsubscribe_link).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains(r#"<form method="post">"#));
+    assert_eq!(
+        status_of(&app, "reader@example.com").await.as_deref(),
+        Some("confirmed")
+    );
+    for _ in 0..2 {
+        let response = app.client.post(&unsubscribe_link).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    assert_eq!(status_of(&app, "reader@example.com").await, None);
+
+    publish_post(&app, "Nobody listens").await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(app.sent_emails().await.len(), 3);
+}
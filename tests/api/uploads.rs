@@ -0,0 +1,333 @@
+use base64::prelude::*;
+use reqwest::multipart::{Form, Part};
+
+use std::collections::HashMap;
+
+use crate::utils::TestApp;
+
+const TUS_RESUMABLE: (&str, &str) = ("Tus-Resumable", "1.0.0");
+
+impl TestApp {
+    async fn create_upload(&self, file_name: &str, length: usize) -> reqwest::Response {
+        let metadata = format!("filename {}", BASE64_STANDARD.encode(file_name));
+        self.client
+            .post(format!("{}/uploads", self.address))
+            .header(TUS_RESUMABLE.0, TUS_RESUMABLE.1)
+            .header("Upload-Length", length)
+            .header("Upload-Metadata", metadata)
+            .send()
+            .await
+            .expect("Failed to send request")
+    }
+
+    async fn patch_upload(&self, location: &str, offset: usize, bytes: &[u8]) -> reqwest::Response {
+        self.client
+            .patch(location)
+            .header(TUS_RESUMABLE.0, TUS_RESUMABLE.1)
+            .header("Content-Type", "application/offset+octet-stream")
+            .header("Upload-Offset", offset)
+            .body(bytes.to_vec())
+            .send()
+            .await
+            .expect("Failed to send request")
+    }
+
+    async fn upload_offset(&self, location: &str) -> reqwest::Response {
+        self.client
+            .head(location)
+            .header(TUS_RESUMABLE.0, TUS_RESUMABLE.1)
+            .send()
+            .await
+            .expect("Failed to send request")
+    }
+}
+
+fn header<'a>(response: &'a reqwest::Response, name: &str) -> &'a str {
+    response.headers()[name].to_str().unwrap()
+}
+
+fn location(app: &TestApp, response: &reqwest::Response) -> String {
+    let address = app.address.trim_end_matches("/api");
+    format!("{address}{}", header(response, "Location"))
+}
+
+#[tokio::test]
+async fn resumable_upload_is_attached_to_a_post_once_complete() {
+    let app = TestApp::spawn_server().await;
+    let image = std::fs::read("tests/data/travel/image.jpeg").unwrap();
+    let (head, tail) = image.split_at(image.len() / 2);
+
+    let response = app.create_upload("images/image.jpeg", image.len()).await;
+    assert_eq!(response.status().as_u16(), 201);
+    assert_eq!(header(&response, "Upload-Offset"), "0");
+    let upload = location(&app, &response);
+
+    let response = app.patch_upload(&upload, 0, head).await;
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(header(&response, "Upload-Offset"), head.len().to_string());
+
+    // INFO: a client resuming from a stale offset is told to ask for the current one
+    let response = app.patch_upload(&upload, 0, head).await;
+    assert_eq!(response.status().as_u16(), 409);
+
+    let response = app.upload_offset(&upload).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(header(&response, "Upload-Offset"), head.len().to_string());
+    assert_eq!(header(&response, "Upload-Length"), image.len().to_string());
+
+    let content = Part::file("tests/data/travel/journal.md").await.unwrap();
+    let upload_id = upload.rsplit('/').next().unwrap().to_string();
+    let form = Form::new()
+        .part("file", content)
+        .text("upload", upload_id.clone());
+    let response = app
+        .client
+        .post(format!("{}/posts", app.address))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = app.patch_upload(&upload, head.len(), tail).await;
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(header(&response, "Upload-Offset"), image.len().to_string());
+
+    let content = Part::file("tests/data/travel/journal.md").await.unwrap();
+    let form = Form::new().part("file", content).text("upload", upload_id);
+    let response = app
+        .client
+        .post(format!("{}/posts", app.address))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 201);
+    let body: HashMap<String, String> = response.json().await.unwrap();
+    let slug = body.get("slug").unwrap();
+
+    let response = app
+        .client
+        .get(format!(
+            "{}/posts/slug/{slug}/images/image.jpeg",
+            app.address
+        ))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        image::load_from_memory(&response.bytes().await.unwrap())
+            .unwrap()
+            .width(),
+        1024
+    );
+
+    let response = app.upload_offset(&upload).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn upload_larger_than_its_declared_length_is_rejected() {
+    let app = TestApp::spawn_server().await;
+
+    let response = app.create_upload("notes.txt", 4).await;
+    let upload = location(&app, &response);
+
+    let response = app.patch_upload(&upload, 0, b"too long").await;
+    assert_eq!(response.status().as_u16(), 413);
+
+    let response = app.upload_offset(&upload).await;
+    assert_eq!(header(&response, "Upload-Offset"), "4");
+}
+
+#[tokio::test]
+async fn upload_requests_without_the_tus_version_return_412() {
+    let app = TestApp::spawn_server().await;
+
+    let response = app
+        .client
+        .post(format!("{}/uploads", app.address))
+        .header("Upload-Length", 4)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 412);
+    assert_eq!(header(&response, "Tus-Version"), "1.0.0");
+}
+
+#[tokio::test]
+async fn upload_creation_with_an_escaping_file_name_returns_400() {
+    let app = TestApp::spawn_server().await;
+
+    let response = app.create_upload("../../etc/passwd", 4).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn terminated_upload_is_gone() {
+    let app = TestApp::spawn_server().await;
+    let response = app.create_upload("notes.txt", 4).await;
+    let upload = location(&app, &response);
+    app.patch_upload(&upload, 0, b"to").await;
+
+    let response = app
+        .client
+        .delete(&upload)
+        .header(TUS_RESUMABLE.0, TUS_RESUMABLE.1)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 204);
+
+    let response = app.upload_offset(&upload).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn expired_uploads_are_purged() {
+    let app = TestApp::spawn_server_with(|config| {
+        config.blob_storage.resumable_uploads.expiry_secs = 0;
+    })
+    .await;
+    let response = app.create_upload("notes.txt", 4).await;
+    let upload = location(&app, &response);
+
+    let response = app.patch_upload(&upload, 0, b"note").await;
+    assert_eq!(response.status().as_u16(), 404);
+
+    // INFO: creating another upload purges the expired ones
+    app.create_upload("other.txt", 4).await;
+
+    let remaining = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM uploads"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining, 1);
+}
+
+#[tokio::test]
+async fn uploads_are_checked_against_their_type_when_created() {
+    let app = TestApp::spawn_server().await;
+
+    let response = app
+        .client
+        .request(reqwest::Method::OPTIONS, format!("{}/uploads", app.address))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(header(&response, "Tus-Max-Size"), "1073741824");
+
+    let response = app.create_upload("tool.exe", 4).await;
+    assert_eq!(response.status().as_u16(), 415);
+
+    let response = app.create_upload("huge.png", 21 * 1024 * 1024).await;
+    assert_eq!(response.status().as_u16(), 413);
+
+    let response = app.create_upload("trip.mp4", 900 * 1024 * 1024).await;
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn uploads_larger_than_a_whole_post_form_are_attached() {
+    let app = TestApp::spawn_server_with(|config| {
+        config.blob_storage.attachments.total_limit = 4096;
+    })
+    .await;
+    let notes = "a line of notes\n".repeat(512);
+
+    let response = app.create_upload("notes.txt", notes.len()).await;
+    let upload = location(&app, &response);
+    let response = app.patch_upload(&upload, 0, notes.as_bytes()).await;
+    assert_eq!(response.status().as_u16(), 204);
+
+    let content = Part::file("tests/data/travel/journal.md").await.unwrap();
+    let upload_id = upload.rsplit('/').next().unwrap().to_string();
+    let form = Form::new().part("file", content).text("upload", upload_id);
+    let response = app
+        .client
+        .post(format!("{}/posts", app.address))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn upload_claimed_by_another_request_returns_409() {
+    let app = TestApp::spawn_server().await;
+    let response = app.create_upload("notes.txt", 4).await;
+    let upload = location(&app, &response);
+    let upload_id: uuid::Uuid = upload.rsplit('/').next().unwrap().parse().unwrap();
+
+    // INFO: as if another request were still receiving its body
+    sqlx::query!(
+        "UPDATE uploads SET claimed_by = $1, claimed_until = now() + interval '1 minute' WHERE id = $2",
+        uuid::Uuid::new_v4(),
+        upload_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let response = app.patch_upload(&upload, 0, b"note").await;
+    assert_eq!(response.status().as_u16(), 409);
+
+    sqlx::query!(
+        "UPDATE uploads SET claimed_until = now() - interval '1 second' WHERE id = $1",
+        upload_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let response = app.patch_upload(&upload, 0, b"note").await;
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(header(&response, "Upload-Offset"), "4");
+}
+
+#[tokio::test]
+async fn uploads_of_another_user_are_missing() {
+    let app = TestApp::spawn_server().await;
+    let editor = app.user_with_role("editor", "editor").await;
+    let response = app.create_upload("notes.txt", 4).await;
+    let upload = location(&app, &response);
+    app.patch_upload(&upload, 0, b"note").await;
+
+    let client = reqwest::Client::new();
+    let requests = [
+        client.head(&upload),
+        client
+            .patch(&upload)
+            .header("Content-Type", "application/offset+octet-stream")
+            .header("Upload-Offset", 4)
+            .body(Vec::new()),
+        client.delete(&upload),
+    ];
+    for request in requests {
+        let response = request
+            .bearer_auth(&editor)
+            .header(TUS_RESUMABLE.0, TUS_RESUMABLE.1)
+            .send()
+            .await
+            .expect("Failed to send request");
+        assert_eq!(response.status().as_u16(), 404);
+    }
+
+    let content = Part::file("tests/data/travel/journal.md").await.unwrap();
+    let upload_id = upload.rsplit('/').next().unwrap().to_string();
+    let form = Form::new().part("file", content).text("upload", upload_id);
+    let response = client
+        .post(format!("{}/posts", app.address))
+        .bearer_auth(&editor)
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 404);
+
+    let response = app.upload_offset(&upload).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(header(&response, "Upload-Offset"), "4");
+}
@@ -0,0 +1,687 @@
+use base64::prelude::*;
+use reqwest::StatusCode;
+use secrecy::SecretBox;
+
+use std::num::NonZeroU32;
+
+use pine_tails::configuration::{EncryptionSettings, Settings};
+use pine_tails::domain::totp;
+
+use crate::utils::TestApp;
+
+const PASSWORD: &str = "correct horse battery staple";
+
+/// Hashing with the production iterations would only slow the tests down.
+async fn spawn_server_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
+    TestApp::spawn_server_with(|config| {
+        config.users.password_hash_iterations = NonZeroU32::new(1000).unwrap();
+        configure(config);
+    })
+    .await
+}
+
+async fn sign_up(app: &TestApp, username: &str, email: &str, password: &str) -> reqwest::Response {
+    app.client
+        .post(format!("{}/users", app.address))
+        .form(&[
+            ("username", username),
+            ("email", email),
+            ("password", password),
+        ])
+        .send()
+        .await
+        .expect("Failed to send request")
+}
+
+async fn resend(app: &TestApp, email: &str) -> reqwest::Response {
+    app.client
+        .post(format!("{}/users/resend_confirmation", app.address))
+        .form(&[("email", email)])
+        .send()
+        .await
+        .expect("Failed to send request")
+}
+
+async fn status_of(app: &TestApp, username: &str) -> Option<String> {
+    sqlx::query_scalar!("SELECT status FROM users WHERE username = $1", username)
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn sign_up_creates_a_pending_user_confirmed_once_by_the_emailed_link() {
+    let app = spawn_server_with(|_| {}).await;
+    app.mocking_refresh_ok(1).await;
+    app.mocking_send_mail_ok(1).await;
+
+    let response = sign_up(&app, "neil", "neil@example.com", PASSWORD).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(
+        status_of(&app, "neil").await.as_deref(),
+        Some("pending_confirmation")
+    );
+
+    let password = sqlx::query_scalar!("SELECT password FROM users WHERE username = 'neil'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(!password.contains(PASSWORD));
+
+    let emails = app.sent_emails().await;
+    let links = app.get_confirmation_links(&emails[0]);
+    assert_eq!(links.html, links.plain_text);
+    assert_eq!(links.html.path(), "/api/users/confirm");
+
+    let response = app.client.get(links.html.clone()).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(status_of(&app, "neil").await.as_deref(), Some("confirmed"));
+
+    let response = app.client.get(links.html).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn sign_up_rejects_invalid_fields_and_taken_names() {
+    let app = spawn_server_with(|_| {}).await;
+    app.mocking_refresh_ok(1).await;
+    app.mocking_send_mail_ok(1).await;
+
+    for (username, email, password) in [
+        ("", "neil@example.com", PASSWORD),
+        ("neil", "not-an-email", PASSWORD),
+        ("neil", "neil@example.com", "too short"),
+        (&"n".repeat(65), "neil@example.com", PASSWORD),
+    ] {
+        let response = sign_up(&app, username, email, password).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    let response = sign_up(&app, "neil", "neil@example.com", PASSWORD).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = sign_up(&app, "neil", "other@example.com", PASSWORD).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn sign_up_with_a_taken_email_answers_alike_and_notifies_its_owner() {
+    let app = spawn_server_with(|_| {}).await;
+    app.mocking_refresh_ok(1).await;
+    app.mocking_send_mail_ok(2).await;
+
+    let response = sign_up(&app, "neil", "neil@example.com", PASSWORD).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = sign_up(&app, "other", "neil@example.com", PASSWORD).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(status_of(&app, "other").await, None);
+
+    let emails = app.sent_emails().await;
+    let (subject, text) = app.email_text(&emails[1]);
+    assert_eq!(subject, "Someone tried to sign up with your email");
+    assert!(!text.contains("/users/confirm"));
+}
+
+#[tokio::test]
+async fn failing_to_send_the_confirmation_leaves_no_user_behind() {
+    let app = spawn_server_with(|_| {}).await;
+    app.mocking_refresh_ok(1).await;
+    app.mocking_send_mail_err(1).await;
+
+    let response = sign_up(&app, "neil", "neil@example.com", PASSWORD).await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(status_of(&app, "neil").await, None);
+}
+
+#[tokio::test]
+async fn resend_is_rate_limited_and_replaces_the_previous_link() {
+    let app = spawn_server_with(|_| {}).await;
+    app.mocking_refresh_ok(1).await;
+    app.mocking_send_mail_ok(2).await;
+
+    sign_up(&app, "neil", "neil@example.com", PASSWORD).await;
+    let response = resend(&app, "neil@example.com").await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // INFO: pretend the first email went out long ago
+    sqlx::query!("UPDATE confirmation_tokens SET created_at = now() - interval '1 hour'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let response = resend(&app, "neil@example.com").await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let emails = app.sent_emails().await;
+    assert_eq!(emails.len(), 2);
+    let first = app.get_confirmation_links(&emails[0]);
+    let second = app.get_confirmation_links(&emails[1]);
+
+    let response = app.client.get(first.html).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = app.client.get(second.html).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // INFO: confirmed and unknown addresses are answered the same, without an email
+    for email in ["neil@example.com", "nobody@example.com"] {
+        let response = resend(&app, email).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
+
+#[tokio::test]
+async fn expired_links_are_refused_and_stale_pending_users_are_purged() {
+    let app =
+        spawn_server_with(|config| config.users.confirmation.confirmation_expiry_secs = 60).await;
+    app.mocking_refresh_ok(1).await;
+    app.mocking_send_mail_ok(2).await;
+
+    sign_up(&app, "neil", "neil@example.com", PASSWORD).await;
+    sqlx::query!(
+        r#"
+        UPDATE confirmation_tokens SET created_at = now() - interval '2 minutes';
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let links = app.get_confirmation_links(&app.sent_emails().await[0]);
+    let response = app.client.get(links.html).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        status_of(&app, "neil").await.as_deref(),
+        Some("pending_confirmation")
+    );
+
+    // INFO: a stale pending account gives its username and email back on the next sign-up
+    sqlx::query!("UPDATE users SET created_at = now() - interval '30 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let response = sign_up(&app, "neil", "neil@example.com", PASSWORD).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let users = sqlx::query_scalar!("SELECT count(*) FROM users WHERE username = 'neil'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(users, Some(1));
+}
+
+/// Signs up `username` and follows the confirmation link.
+async fn confirmed_user(app: &TestApp, username: &str, email: &str) {
+    let sent = app.sent_emails().await.len();
+    let response = sign_up(app, username, email, PASSWORD).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let links = app.get_confirmation_links(&app.sent_emails().await[sent]);
+    app.client.get(links.html).send().await.unwrap();
+}
+
+async fn login(app: &TestApp, username: &str, password: &str) -> reqwest::Response {
+    app.client
+        .post(format!("{}/users/login", app.address))
+        .form(&[("username", username), ("password", password)])
+        .send()
+        .await
+        .expect("Failed to send request")
+}
+
+async fn login_token(app: &TestApp, username: &str, password: &str) -> String {
+    let response = login(app, username, password).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["token"].as_str().unwrap().to_string()
+}
+
+async fn logout(app: &TestApp, token: &str) -> reqwest::Response {
+    app.client
+        .post(format!("{}/users/logout", app.address))
+        .bearer_auth(token)
+        .send()
+        .await
+        .expect("Failed to send request")
+}
+
+async fn request_password_reset(app: &TestApp, email: &str) -> reqwest::Response {
+    app.client
+        .post(format!("{}/users/password_reset", app.address))
+        .form(&[("email", email)])
+        .send()
+        .await
+        .expect("Failed to send request")
+}
+
+#[tokio::test]
+async fn only_confirmed_users_log_in_and_logging_out_ends_the_session() {
+    let app = spawn_server_with(|_| {}).await;
+    app.mocking_refresh_ok(1).await;
+    app.mocking_send_mail_ok(2).await;
+
+    confirmed_user(&app, "neil", "neil@example.com").await;
+    sign_up(&app, "pending", "pending@example.com", PASSWORD).await;
+
+    for (username, password) in [
+        ("neil", "wrong password!"),
+        ("nobody", PASSWORD),
+        ("pending", PASSWORD),
+    ] {
+        let response = login(&app, username, password).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    let token = login_token(&app, "neil", PASSWORD).await;
+    let response = logout(&app, &token).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = logout(&app, &token).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        response.headers().get("WWW-Authenticate").unwrap(),
+        "Bearer"
+    );
+}
+
+#[tokio::test]
+async fn password_reset_replaces_the_password_and_ends_every_session() {
+    let app = spawn_server_with(|_| {}).await;
+    app.mocking_refresh_ok(1).await;
+    app.mocking_send_mail_ok(2).await;
+
+    confirmed_user(&app, "neil", "neil@example.com").await;
+    let token = login_token(&app, "neil", PASSWORD).await;
+
+    // INFO: unknown addresses are answered the same, without an email
+    let response = request_password_reset(&app, "nobody@example.com").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = request_password_reset(&app, "neil@example.com").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let emails = app.wait_for_emails(2).await;
+    let link = app.get_confirmation_links(&emails[1]).html;
+    assert_eq!(link.path(), "/api/users/password_reset/complete");
+
+    let stored = sqlx::query_scalar!("SELECT token_hash FROM password_reset_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(!link.as_str().contains(&stored));
+
+    // INFO: following the link only asks for the password, link scanners fetch it too
+    for _ in 0..2 {
+        let response = reqwest::get(link.clone()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let page = response.text().await.unwrap();
+        assert!(page.contains(r#"<form method="post">"#));
+        assert!(page.contains(r#"name="password""#));
+    }
+
+    let complete = |password: &'static str| {
+        app.client
+            .post(link.clone())
+            .form(&[("password", password)])
+            .send()
+    };
+    let response = complete("too short").await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = complete("a brand new password").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = complete("yet another password").await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = logout(&app, &token).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = login(&app, "neil", PASSWORD).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    login_token(&app, "neil", "a brand new password").await;
+}
+
+#[tokio::test]
+async fn password_reset_links_expire_and_requests_are_rate_limited() {
+    let app = spawn_server_with(|config| config.users.password_reset_expiry_secs = 60).await;
+    app.mocking_refresh_ok(1).await;
+    app.mocking_send_mail_ok(2).await;
+
+    confirmed_user(&app, "neil", "neil@example.com").await;
+    request_password_reset(&app, "neil@example.com").await;
+    // INFO: within the cooldown the request is silently dropped
+    let response = request_password_reset(&app, "neil@example.com").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let emails = app.wait_for_emails(2).await;
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+    assert_eq!(app.sent_emails().await.len(), 2);
+
+    sqlx::query!("UPDATE password_reset_tokens SET created_at = now() - interval '2 minutes'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let link = app.get_confirmation_links(&emails[1]).html;
+    let page = reqwest::get(link.clone())
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(page.contains("expired"));
+    assert!(!page.contains("<form"));
+    let response = app
+        .client
+        .post(link)
+        .form(&[("password", "a brand new password")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    login_token(&app, "neil", PASSWORD).await;
+}
+
+fn unix_now() -> u64 {
+    chrono::Utc::now().timestamp() as u64
+}
+
+/// Enrolls TOTP for the holder of `token`, returning the secret and the recovery codes.
+async fn enable_totp(app: &TestApp, token: &str) -> (String, Vec<String>) {
+    let enrollment: serde_json::Value = app
+        .client
+        .post(format!("{}/users/me/totp", app.address))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let secret = enrollment["secret"].as_str().unwrap().to_string();
+    assert!(enrollment["otpauth_uri"]
+        .as_str()
+        .unwrap()
+        .starts_with(&format!("otpauth://totp/pine-tails:neil?secret={secret}")));
+
+    let confirm = |code: String| {
+        app.client
+            .post(format!("{}/users/me/totp/confirm", app.address))
+            .bearer_auth(token)
+            .form(&[("code", code)])
+            .send()
+    };
+    let response = confirm("000000".to_string()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = confirm(totp::code(&secret, unix_now()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let codes = body["recovery_codes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|code| code.as_str().unwrap().to_string())
+        .collect();
+
+    (secret, codes)
+}
+
+async fn login_challenge(app: &TestApp) -> String {
+    let response = login(app, "neil", PASSWORD).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "two_factor_required");
+    assert!(body.get("token").is_none());
+    body["challenge"].as_str().unwrap().to_string()
+}
+
+async fn complete_login(app: &TestApp, challenge: &str, code: &str) -> reqwest::Response {
+    app.client
+        .post(format!("{}/users/login/two_factor", app.address))
+        .form(&[("challenge", challenge), ("code", code)])
+        .send()
+        .await
+        .expect("Failed to send request")
+}
+
+#[tokio::test]
+async fn enabled_totp_is_required_to_log_in_and_codes_work_once() {
+    let app = spawn_server_with(|_| {}).await;
+    app.mocking_refresh_ok(1).await;
+    app.mocking_send_mail_ok(1).await;
+
+    confirmed_user(&app, "neil", "neil@example.com").await;
+    let token = login_token(&app, "neil", PASSWORD).await;
+    let (secret, recovery_codes) = enable_totp(&app, &token).await;
+    assert_eq!(recovery_codes.len(), 10);
+
+    // INFO: the code confirming the enrollment was used up, the next one is still in the skew
+    let challenge = login_challenge(&app).await;
+    let last_step = sqlx::query_scalar!("SELECT totp_last_step FROM users WHERE username = 'neil'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .unwrap() as u64;
+    let used = totp::code(&secret, last_step * 30).unwrap();
+    let response = complete_login(&app, &challenge, &used).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let next = totp::code(&secret, (last_step + 1) * 30).unwrap();
+    let response = complete_login(&app, &challenge, &next).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["token"].is_string());
+    let response = complete_login(&app, &challenge, &next).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let recovery_code = recovery_codes[0].to_uppercase();
+    let response = complete_login(&app, &login_challenge(&app).await, &recovery_code).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = complete_login(&app, &login_challenge(&app).await, &recovery_code).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let stored = sqlx::query_scalar!("SELECT code_hash FROM recovery_codes")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(stored.len(), 9);
+    assert!(!stored.contains(&recovery_codes[1].replace('-', "")));
+}
+
+#[tokio::test]
+async fn totp_secrets_are_sealed_with_the_encryption_at_rest_key() {
+    let app = spawn_server_with(|config| {
+        let key = BASE64_STANDARD.encode([7; 32]);
+        config.blob_storage.encryption = Some(EncryptionSettings {
+            key: SecretBox::new(Box::new(key)),
+            previous_keys: Vec::new(),
+        });
+    })
+    .await;
+    app.mocking_refresh_ok(1).await;
+    app.mocking_send_mail_ok(1).await;
+
+    confirmed_user(&app, "neil", "neil@example.com").await;
+    let token = login_token(&app, "neil", PASSWORD).await;
+    let (secret, _) = enable_totp(&app, &token).await;
+
+    let stored = sqlx::query_scalar!("SELECT totp_secret FROM users WHERE username = 'neil'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(stored.starts_with("sealed:"));
+    assert!(!stored.contains(&secret));
+
+    let challenge = login_challenge(&app).await;
+    let code = totp::code(&secret, unix_now() + 30).unwrap();
+    let response = complete_login(&app, &challenge, &code).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn two_factor_logins_take_few_attempts_and_disabling_takes_a_code() {
+    let app = spawn_server_with(|config| {
+        config.users.two_factor_attempts = 3;
+        // INFO: the wrong codes below are failed logins too, this test is not about throttling
+        config.users.login_free_attempts = 5;
+    })
+    .await;
+    app.mocking_refresh_ok(1).await;
+    app.mocking_send_mail_ok(1).await;
+
+    confirmed_user(&app, "neil", "neil@example.com").await;
+    let token = login_token(&app, "neil", PASSWORD).await;
+    let (_, recovery_codes) = enable_totp(&app, &token).await;
+
+    let challenge = login_challenge(&app).await;
+    for _ in 0..3 {
+        let response = complete_login(&app, &challenge, "123456").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+    let response = complete_login(&app, &challenge, &recovery_codes[0]).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let disable = |code: &str| {
+        app.client
+            .delete(format!("{}/users/me/totp", app.address))
+            .bearer_auth(&token)
+            .form(&[("code", code)])
+            .send()
+    };
+    let response = disable("123456").await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = disable(&recovery_codes[0]).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    login_token(&app, "neil", PASSWORD).await;
+}
+
+#[tokio::test]
+async fn wrong_codes_to_disable_totp_are_throttled_like_failed_logins() {
+    let app = spawn_server_with(|config| {
+        config.users.login_free_attempts = 1;
+        config.users.login_backoff_secs = 60;
+    })
+    .await;
+    app.mocking_refresh_ok(1).await;
+    app.mocking_send_mail_ok(1).await;
+
+    confirmed_user(&app, "neil", "neil@example.com").await;
+    let token = login_token(&app, "neil", PASSWORD).await;
+    let (_, recovery_codes) = enable_totp(&app, &token).await;
+
+    let disable = |code: &str| {
+        app.client
+            .delete(format!("{}/users/me/totp", app.address))
+            .bearer_auth(&token)
+            .form(&[("code", code)])
+            .send()
+    };
+    for _ in 0..2 {
+        let response = disable("123456").await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+    let response = disable(&recovery_codes[0]).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    let enabled = sqlx::query_scalar!("SELECT totp_secret FROM users WHERE username = 'neil'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(enabled.is_some());
+}
+
+#[tokio::test]
+async fn failed_logins_back_off_then_lock_the_account_until_an_admin_clears_it() {
+    let app = spawn_server_with(|config| {
+        config.users.login_free_attempts = 1;
+        config.users.login_backoff_secs = 60;
+        config.users.login_lockout_attempts = 3;
+    })
+    .await;
+    app.mocking_refresh_ok(1).await;
+    app.mocking_send_mail_ok(2).await;
+    confirmed_user(&app, "neil", "neil@example.com").await;
+
+    let response = login(&app, "neil", "wrong password!").await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = login(&app, "neil", "wrong password!").await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    // INFO: waiting refuses even the right password, without trying it
+    let response = login(&app, "neil", PASSWORD).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // INFO: pretend the backoff is over
+    sqlx::query!("UPDATE login_throttles SET blocked_until = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let response = login(&app, "neil", "wrong password!").await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = login(&app, "neil", PASSWORD).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    let emails = app.wait_for_emails(2).await;
+    let (subject, text) = app.email_text(&emails[1]);
+    assert_eq!(subject, "Your account was locked");
+    assert!(text.contains("After 3 failed logins in a row, logging in as neil is locked"));
+
+    let locks: serde_json::Value = app
+        .client
+        .get(format!("{}/admin/login_locks", app.address))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let locks = locks.as_array().unwrap();
+    assert_eq!(locks.len(), 2);
+    let account = locks.iter().find(|lock| lock["kind"] == "account").unwrap();
+    assert_eq!(account["key"], "neil");
+    assert_eq!(account["failures"], 3);
+    assert_eq!(account["locked"], true);
+    // INFO: addresses take more failures before a lockout, this one only backs off
+    let address = locks.iter().find(|lock| lock["kind"] == "ip").unwrap();
+    assert_eq!(address["key"], "127.0.0.1");
+    assert_eq!(address["locked"], false);
+
+    for path in ["account/neil", "ip/127.0.0.1"] {
+        let clear = || {
+            app.client
+                .delete(format!("{}/admin/login_locks/{path}", app.address))
+                .send()
+        };
+        assert_eq!(clear().await.unwrap().status(), StatusCode::NO_CONTENT);
+        assert_eq!(clear().await.unwrap().status(), StatusCode::NOT_FOUND);
+    }
+    login_token(&app, "neil", PASSWORD).await;
+}
+
+#[tokio::test]
+async fn concurrent_failed_logins_are_counted_one_after_the_other() {
+    let app = spawn_server_with(|config| {
+        config.users.login_free_attempts = 3;
+        config.users.login_lockout_attempts = 3;
+    })
+    .await;
+    app.mocking_refresh_ok(1).await;
+    app.mocking_send_mail_ok(2).await;
+    confirmed_user(&app, "neil", "neil@example.com").await;
+
+    // INFO: guesses sent at once must not all be tried before the first ones are counted
+    let guesses = (0..8).map(|_| login(&app, "neil", "wrong password!"));
+    let mut statuses: Vec<_> = futures::future::join_all(guesses)
+        .await
+        .iter()
+        .map(|response| response.status())
+        .collect();
+    statuses.sort();
+    assert_eq!(
+        statuses,
+        [
+            [StatusCode::UNAUTHORIZED; 3].as_slice(),
+            [StatusCode::TOO_MANY_REQUESTS; 5].as_slice()
+        ]
+        .concat()
+    );
+
+    let account = sqlx::query!(
+        "SELECT failures, locked FROM login_throttles WHERE kind = 'account' AND key = 'neil'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(account.failures, 3);
+    assert!(account.locked);
+    let response = login(&app, "neil", PASSWORD).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+}
@@ -1,6 +1,8 @@
 use serde_json::json;
 use wiremock::matchers::{method, path};
-use wiremock::{Mock, ResponseTemplate, Times};
+use wiremock::{Mock, Request, ResponseTemplate, Times};
+
+use std::time::Duration;
 
 use super::TestApp;
 
@@ -46,4 +48,27 @@ impl TestApp {
             .mount(&self.email_server)
             .await;
     }
+
+    /// The emails sent so far, leaving out the token refreshes.
+    pub async fn sent_emails(&self) -> Vec<Request> {
+        self.email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|request| request.url.path().ends_with(&self.email_api))
+            .collect()
+    }
+
+    /// Waits for `count` emails, some being sent in the background after a response.
+    pub async fn wait_for_emails(&self, count: usize) -> Vec<Request> {
+        for _ in 0..50 {
+            let emails = self.sent_emails().await;
+            if emails.len() >= count {
+                return emails;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("Expected {count} emails to be sent");
+    }
 }
@@ -1,4 +1,5 @@
 pub mod email_service_mocking;
+pub mod s3_mocking;
 
 use base64::prelude::*;
 use mail_parser::MessageParser;
@@ -10,7 +11,12 @@ use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
 use wiremock::MockServer;
 
-use pine_tails::configuration::{get_configurations, DatabaseSettings};
+use s3_mocking::FakeS3;
+
+use pine_tails::configuration::{
+    get_configurations, DatabaseSettings, Settings, StorageBackendSettings,
+};
+use pine_tails::domain::credentials::new_token;
 use pine_tails::startup::engine::Engine as WebEngine;
 use pine_tails::startup::prepare::{
     prepare_blob_storage, prepare_db_pool, prepare_email_client, Kits,
@@ -44,6 +50,8 @@ pub struct TestApp {
     pub db_pool: PgPool,
     pub email_server: MockServer,
     pub blob_storage: BlobStorage,
+    /// The bearer token `client` sends unless told otherwise, of a signed-in admin.
+    pub admin_token: String,
 }
 
 pub struct ConfirmationLinks {
@@ -53,11 +61,30 @@ pub struct ConfirmationLinks {
 
 impl TestApp {
     pub async fn spawn_server() -> TestApp {
+        Self::spawn_server_with(|_| {}).await
+    }
+
+    /// Spawns a server storing its blobs in an in-memory S3 stand-in instead of the local disk.
+    pub async fn spawn_server_with_s3() -> (TestApp, FakeS3) {
+        let s3 = FakeS3::start().await;
+        let settings = s3.settings();
+        let app = Self::spawn_server_with(move |config| {
+            config.blob_storage.ephemeral = false;
+            config.blob_storage.backend = StorageBackendSettings::S3(settings);
+        })
+        .await;
+
+        (app, s3)
+    }
+
+    /// Spawns a server after letting `configure` adjust the test configuration.
+    pub async fn spawn_server_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
         Lazy::force(&TRACING);
         let listener =
             std::net::TcpListener::bind("127.0.0.1:0").expect("Failed to create listener");
         let port = listener.local_addr().unwrap().port();
-        let address = format!("http://127.0.0.1:{}/api", port);
+        let root = format!("http://127.0.0.1:{}", port);
+        let address = format!("{root}/api");
 
         let email_server = MockServer::start().await;
 
@@ -71,9 +98,9 @@ impl TestApp {
             temp_config.database.database_name = test_id.to_string();
             temp_config.gmail_service.email_api = format!("{}/{}", api_root, email_api);
             temp_config.gmail_service.token_api = format!("{}/{}", api_root, token_api);
-            temp_config.application.base_url = address.clone();
-            temp_config.blob_storage.base_dir =
-                std::path::PathBuf::from("/tmp").join(test_id.to_string());
+            temp_config.application.base_url = root;
+            temp_config.blob_storage.ephemeral = true;
+            configure(&mut temp_config);
             temp_config
         };
 
@@ -82,6 +109,14 @@ impl TestApp {
         tracing::info!("Spawning server with configuration: {configuration:#?}");
 
         let db_pool = Self::pool_to_uniq_database(&configuration.database).await;
+        let admin_token = Self::signed_in_user(&db_pool, "admin", "admin").await;
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::AUTHORIZATION,
+            format!("Bearer {admin_token}").parse().unwrap(),
+        );
+        // INFO: the server and the test share one storage, an ephemeral one only lives in memory
+        let blob_storage = prepare_blob_storage(&configuration).unwrap();
         let test_app = TestApp {
             address,
             // this is the extra db pool we used to access directly
@@ -90,15 +125,19 @@ impl TestApp {
             email_server,
             email_api,
             refresh_api: token_api,
-            client: reqwest::Client::new(),
-            blob_storage: prepare_blob_storage(&configuration).unwrap(),
+            client: reqwest::Client::builder()
+                .default_headers(headers)
+                .build()
+                .unwrap(),
+            blob_storage: blob_storage.clone(),
+            admin_token,
         };
 
         let kits = Kits::new(
             listener,
             prepare_db_pool(&configuration),
             prepare_email_client(&configuration),
-            prepare_blob_storage(&configuration).unwrap(),
+            blob_storage,
         );
         let engine = WebEngine::build(configuration, kits).unwrap();
         tokio::spawn(engine.spinup());
@@ -106,6 +145,43 @@ impl TestApp {
         test_app
     }
 
+    /// Creates a confirmed user with `role` and a session, returning its bearer token.
+    pub async fn user_with_role(&self, username: &str, role: &str) -> String {
+        Self::signed_in_user(&self.db_pool, username, role).await
+    }
+
+    async fn signed_in_user(db_pool: &PgPool, username: &str, role: &str) -> String {
+        let id = Uuid::new_v4();
+        sqlx::query!(
+            r#"
+            INSERT INTO users (id, username, email, password, status, role)
+            VALUES ($1, $2, $3, 'not a hash', 'confirmed', $4)
+            "#,
+            id,
+            username,
+            format!("{username}@pine-tails.test"),
+            role,
+        )
+        .execute(db_pool)
+        .await
+        .expect("Failed to create user");
+
+        let (token, token_hash) = new_token().unwrap();
+        sqlx::query!(
+            r#"
+            INSERT INTO user_sessions (token_hash, user_id, expires_at)
+            VALUES ($1, $2, now() + interval '1 day')
+            "#,
+            token_hash,
+            id,
+        )
+        .execute(db_pool)
+        .await
+        .expect("Failed to create session");
+
+        token.expose_secret().clone()
+    }
+
     async fn pool_to_uniq_database(config: &DatabaseSettings) -> PgPool {
         let mut connection =
             PgConnection::connect(config.connection_string_without_db().expose_secret())
@@ -172,4 +248,17 @@ impl TestApp {
             plain_text: plain_confirmation_link,
         }
     }
+
+    /// The subject and plain text body of a sent email.
+    pub fn email_text(&self, email_request: &wiremock::Request) -> (String, String) {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let raw_mail = BASE64_STANDARD
+            .decode(body["raw"].as_str().unwrap())
+            .unwrap();
+        let message = MessageParser::default().parse(&raw_mail).unwrap();
+        (
+            message.subject().unwrap().to_string(),
+            message.body_text(0).unwrap().to_string(),
+        )
+    }
 }
@@ -0,0 +1,163 @@
+use secrecy::SecretBox;
+use wiremock::matchers::any;
+use wiremock::{Mock, MockServer, Request, Respond, ResponseTemplate};
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use pine_tails::configuration::S3Settings;
+
+const BUCKET: &str = "pine-tails-test";
+const LAST_MODIFIED: &str = "Sun, 06 Oct 2024 09:30:00 GMT";
+const LAST_MODIFIED_ISO: &str = "2024-10-06T09:30:00.000Z";
+
+type Objects = Arc<Mutex<BTreeMap<String, Vec<u8>>>>;
+
+/// A minimal S3 stand-in keeping objects in memory, enough for put, conditional put, copy, get,
+/// head, delete and `ListObjectsV2` with a prefix.
+pub struct FakeS3 {
+    pub server: MockServer,
+    objects: Objects,
+}
+
+impl FakeS3 {
+    pub async fn start() -> Self {
+        let server = MockServer::start().await;
+        let objects = Objects::default();
+
+        Mock::given(any())
+            .respond_with(FakeS3Responder {
+                objects: objects.clone(),
+            })
+            .mount(&server)
+            .await;
+
+        Self { server, objects }
+    }
+
+    pub fn settings(&self) -> S3Settings {
+        S3Settings {
+            bucket: BUCKET.to_string(),
+            region: "us-east-1".to_string(),
+            endpoint: Some(self.server.uri()),
+            access_key_id: "test".to_string(),
+            secret_access_key: SecretBox::new(Box::new("test".to_string())),
+            allow_http: true,
+        }
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.objects.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+struct FakeS3Responder {
+    objects: Objects,
+}
+
+impl Respond for FakeS3Responder {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let path = request.url.path().trim_start_matches('/');
+        let key = path
+            .strip_prefix(BUCKET)
+            .unwrap_or(path)
+            .trim_start_matches('/')
+            .to_string();
+        let mut objects = self.objects.lock().unwrap();
+
+        match (request.method.as_str(), key.as_str()) {
+            ("GET", "") => {
+                let prefix = request
+                    .url
+                    .query_pairs()
+                    .find(|(name, _)| name == "prefix")
+                    .map(|(_, value)| value.into_owned())
+                    .unwrap_or_default();
+                let contents = objects
+                    .iter()
+                    .filter(|(key, _)| key.starts_with(&prefix))
+                    .map(|(key, bytes)| {
+                        format!(
+                            "<Contents><Key>{key}</Key><Size>{}</Size>\
+                             <LastModified>{LAST_MODIFIED_ISO}</LastModified>\
+                             <ETag>\"{}\"</ETag></Contents>",
+                            bytes.len(),
+                            bytes.len()
+                        )
+                    })
+                    .collect::<String>();
+
+                ResponseTemplate::new(200).set_body_string(format!(
+                    "<ListBucketResult><Name>{BUCKET}</Name><Prefix>{prefix}</Prefix>\
+                     <IsTruncated>false</IsTruncated>{contents}</ListBucketResult>"
+                ))
+            }
+            ("PUT", key) if request.headers.contains_key("x-amz-copy-source") => {
+                let source = request.headers["x-amz-copy-source"].to_str().unwrap();
+                let source = percent_decode(source.trim_start_matches('/'));
+                let source = source.strip_prefix(BUCKET).unwrap_or(&source);
+                match objects.get(source.trim_start_matches('/')).cloned() {
+                    Some(bytes) => {
+                        objects.insert(key.to_string(), bytes);
+                        ResponseTemplate::new(200).set_body_string(format!(
+                            "<CopyObjectResult><ETag>\"etag\"</ETag>\
+                             <LastModified>{LAST_MODIFIED_ISO}</LastModified></CopyObjectResult>"
+                        ))
+                    }
+                    None => ResponseTemplate::new(404)
+                        .set_body_string("<Error><Code>NoSuchKey</Code></Error>"),
+                }
+            }
+            ("PUT", key)
+                if request
+                    .headers
+                    .get("if-none-match")
+                    .is_some_and(|v| v == "*")
+                    && objects.contains_key(key) =>
+            {
+                ResponseTemplate::new(412)
+                    .set_body_string("<Error><Code>PreconditionFailed</Code></Error>")
+            }
+            ("PUT", key) => {
+                objects.insert(key.to_string(), request.body.clone());
+                ResponseTemplate::new(200).insert_header("ETag", "\"etag\"")
+            }
+            ("GET" | "HEAD", key) => match objects.get(key) {
+                Some(bytes) => ResponseTemplate::new(200)
+                    .insert_header("ETag", "\"etag\"")
+                    .insert_header("Last-Modified", LAST_MODIFIED)
+                    .set_body_bytes(bytes.clone()),
+                None => ResponseTemplate::new(404)
+                    .set_body_string("<Error><Code>NoSuchKey</Code></Error>"),
+            },
+            ("DELETE", key) => {
+                objects.remove(key);
+                ResponseTemplate::new(204)
+            }
+            _ => ResponseTemplate::new(400),
+        }
+    }
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).unwrap()
+}